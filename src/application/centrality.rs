@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::domain::{ports::KGRepository, errors::AppError};
+
+/// Parámetros de PageRank (se leen del entorno en main.rs).
+#[derive(Debug, Clone)]
+pub struct CentralityConfig {
+    /// Probabilidad de seguir una relación (vs. saltar a un nodo aleatorio)
+    pub damping: f64,
+    pub max_iterations: usize,
+    /// Convergencia: suma de cambios absolutos entre iteraciones
+    pub tolerance: f64,
+}
+
+impl Default for CentralityConfig {
+    fn default() -> Self {
+        Self { damping: 0.85, max_iterations: 100, tolerance: 1e-6 }
+    }
+}
+
+/// PageRank sobre la lista de aristas dirigidas. Los nodos sin salidas (dangling)
+/// reparten su puntuación entre todos. Devuelve puntuaciones que suman 1.0.
+pub fn pagerank(nodes: &[String], edges: &[(String, String)], config: &CentralityConfig) -> HashMap<String, f64> {
+    let n = nodes.len();
+    if n == 0 {
+        return HashMap::new();
+    }
+
+    let index: HashMap<&str, usize> = nodes.iter().enumerate().map(|(i, name)| (name.as_str(), i)).collect();
+    let mut outgoing: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for (source, target) in edges {
+        if let (Some(&s), Some(&t)) = (index.get(source.as_str()), index.get(target.as_str())) {
+            outgoing[s].push(t);
+        }
+    }
+
+    let uniform = 1.0 / n as f64;
+    let mut ranks = vec![uniform; n];
+
+    for _ in 0..config.max_iterations {
+        let dangling: f64 = (0..n).filter(|&i| outgoing[i].is_empty()).map(|i| ranks[i]).sum();
+        let base = (1.0 - config.damping) * uniform + config.damping * dangling * uniform;
+        let mut next = vec![base; n];
+
+        for (i, targets) in outgoing.iter().enumerate() {
+            if targets.is_empty() {
+                continue;
+            }
+            let share = config.damping * ranks[i] / targets.len() as f64;
+            for &t in targets {
+                next[t] += share;
+            }
+        }
+
+        let delta: f64 = next.iter().zip(&ranks).map(|(a, b)| (a - b).abs()).sum();
+        ranks = next;
+        if delta < config.tolerance {
+            break;
+        }
+    }
+
+    nodes.iter().cloned().zip(ranks).collect()
+}
+
+pub struct CentralityService {
+    repo: Arc<dyn KGRepository>,
+    config: CentralityConfig,
+}
+
+impl CentralityService {
+    pub fn new(repo: Arc<dyn KGRepository>, config: CentralityConfig) -> Self {
+        Self { repo, config }
+    }
+
+    /// Calcula PageRank para todas las entidades y lo guarda en `e.centrality`,
+    /// normalizado a 0.0 - 1.0 (1.0 = entidad más importante) para dimensionar nodos en la UI.
+    pub async fn compute_with_progress(&self, progress_tx: tokio::sync::mpsc::Sender<String>) -> Result<usize, AppError> {
+        let _ = progress_tx.send("📥 Cargando entidades y relaciones...".to_string()).await;
+        let (nodes, edges) = self.repo.get_entity_graph().await?;
+
+        let _ = progress_tx.send(format!("🧮 Calculando PageRank sobre {} entidades y {} relaciones...", nodes.len(), edges.len())).await;
+        let scores = pagerank(&nodes, &edges, &self.config);
+
+        let max = scores.values().cloned().fold(0.0_f64, f64::max);
+        let normalized: HashMap<String, f64> = scores.into_iter()
+            .map(|(name, score)| (name, if max > 0.0 { score / max } else { 0.0 }))
+            .collect();
+
+        let _ = progress_tx.send("💾 Guardando puntuaciones...".to_string()).await;
+        self.repo.save_centrality(&normalized).await?;
+
+        Ok(normalized.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn edges(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(s, t)| (s.to_string(), t.to_string())).collect()
+    }
+
+    #[test]
+    fn the_hub_node_gets_the_highest_centrality() {
+        let nodes = names(&["Lugo", "Muralla", "Puerta de Santiago", "Catedral", "Romanos", "Miño"]);
+        let edges = edges(&[
+            ("Muralla", "Lugo"),
+            ("Puerta de Santiago", "Lugo"),
+            ("Catedral", "Lugo"),
+            ("Romanos", "Muralla"),
+            ("Miño", "Lugo"),
+        ]);
+
+        let scores = pagerank(&nodes, &edges, &CentralityConfig::default());
+
+        let (top, _) = scores.iter().max_by(|a, b| a.1.total_cmp(b.1)).unwrap();
+        assert_eq!(top, "Lugo");
+        assert!((scores.values().sum::<f64>() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn centrality_is_transitive_not_just_degree() {
+        // "Miño" y "Muralla" tienen una sola entrada cada uno, pero la de "Miño" viene del hub
+        let nodes = names(&["Lugo", "Muralla", "Catedral", "Romanos", "Miño"]);
+        let edges = edges(&[
+            ("Catedral", "Lugo"),
+            ("Romanos", "Lugo"),
+            ("Muralla", "Lugo"),
+            ("Lugo", "Miño"),
+            ("Romanos", "Muralla"),
+        ]);
+
+        let scores = pagerank(&nodes, &edges, &CentralityConfig::default());
+
+        assert!(scores["Miño"] > scores["Muralla"]);
+    }
+
+    #[tokio::test]
+    async fn the_job_saves_scores_normalised_to_the_top_entity() {
+        use crate::domain::models::{GraphEntity, GraphFilter, GraphRelation, KnowledgeExtraction};
+        use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+        let repo = Arc::new(MemoryRepo::new());
+        let entities = ["Lugo", "Muralla", "Catedral"].iter()
+            .map(|name| GraphEntity { name: name.to_string(), category: "Place".to_string(), attributes: Default::default() })
+            .collect();
+        let relations = [("Muralla", "Lugo"), ("Catedral", "Lugo")].iter()
+            .map(|(s, t)| GraphRelation { source: s.to_string(), target: t.to_string(), relation_type: "IN".to_string(), confidence: None })
+            .collect();
+        repo.save_graph(uuid::Uuid::new_v4(), KnowledgeExtraction { entities, relations, events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+
+        let count = CentralityService::new(repo.clone(), CentralityConfig::default())
+            .compute_with_progress(tx).await.unwrap();
+
+        assert_eq!(count, 3);
+        let graph = repo.get_full_graph(&GraphFilter::default()).await.unwrap();
+        let centrality = |name: &str| graph.nodes.iter().find(|n| n.id == name).unwrap().centrality.unwrap();
+        assert_eq!(centrality("Lugo"), 1.0);
+        assert!(centrality("Muralla") < 1.0);
+        assert_eq!(centrality("Muralla"), centrality("Catedral"));
+    }
+}
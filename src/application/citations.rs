@@ -0,0 +1,85 @@
+use crate::domain::models::{Footnote, SourceReference};
+
+/// Índices citados en la respuesta con el formato `[n]` (también `[1][3]` y `[1, 3]`),
+/// en orden de primera aparición y sin repetir.
+pub fn extract_citation_indices(answer: &str) -> Vec<usize> {
+    let mut indices = Vec::new();
+    let mut rest = answer;
+
+    while let Some(open) = rest.find('[') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find(']') else { break };
+        let inner = &rest[..close];
+
+        // Solo marcadores numéricos: se ignoran enlaces y casillas markdown
+        let numbers: Option<Vec<usize>> = inner.split(',')
+            .map(|part| part.trim().parse::<usize>().ok())
+            .collect();
+        if let Some(numbers) = numbers {
+            for n in numbers {
+                if !indices.contains(&n) {
+                    indices.push(n);
+                }
+            }
+            rest = &rest[close + 1..];
+        }
+    }
+    indices
+}
+
+/// Notas al pie para cada cita de la respuesta que corresponde a una fuente recuperada.
+/// Las citas a fuentes inexistentes (el modelo inventó el número) se descartan.
+pub fn build_footnotes(answer: &str, sources: &[SourceReference]) -> Vec<Footnote> {
+    extract_citation_indices(answer)
+        .into_iter()
+        .filter_map(|index| {
+            let source = sources.iter().find(|s| s.index == index);
+            if source.is_none() {
+                tracing::warn!("⚠️ La respuesta cita [{}] pero no existe esa fuente", index);
+            }
+            source
+        })
+        .map(|source| Footnote {
+            marker: format!("[{}]", source.index),
+            index: source.index,
+            chunk_id: source.chunk_id.clone(),
+            preview: source.short_content.clone(),
+            score: source.relevance,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(index: usize) -> SourceReference {
+        SourceReference {
+            index,
+            chunk_id: format!("chunk-{}", index),
+            short_content: format!("Fragmento {}", index),
+            relevance: 0.5,
+            concepts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn citations_are_read_in_order_without_repeats() {
+        assert_eq!(extract_citation_indices("La muralla [2] rodea Lugo [1][3] y [1, 2]."), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn links_and_checkboxes_are_not_citations() {
+        assert_eq!(extract_citation_indices("Ver [la web](https://x) y - [x] hecho [4]"), vec![4]);
+        assert!(extract_citation_indices("Sin citas [").is_empty());
+    }
+
+    #[test]
+    fn footnotes_link_markers_to_sources_and_drop_invented_ones() {
+        let footnotes = build_footnotes("Romana [3] y del siglo III [9] [1].", &[source(1), source(2), source(3)]);
+
+        let markers: Vec<(&str, &str)> = footnotes.iter().map(|f| (f.marker.as_str(), f.chunk_id.as_str())).collect();
+        assert_eq!(markers, [("[3]", "chunk-3"), ("[1]", "chunk-1")]);
+        assert_eq!(footnotes[0].preview, "Fragmento 3");
+    }
+}
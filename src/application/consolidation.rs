@@ -0,0 +1,221 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, Notify};
+use crate::domain::{
+    ports::{KGRepository, AIService},
+    errors::AppError
+};
+use super::centrality::{CentralityConfig, CentralityService};
+use super::reasoning::{ReasoningConfig, ReasoningService};
+
+/// Tareas del mantenimiento periódico (se leen del entorno en main.rs).
+#[derive(Debug, Clone)]
+pub struct ConsolidationConfig {
+    pub interval: Duration,
+    /// Borrar entidades sin ninguna relación
+    pub prune_orphans: bool,
+    /// Recalcular PageRank (`e.centrality`)
+    pub recompute_centrality: bool,
+    /// Razonar si el grafo cambió desde el ciclo anterior (cuesta una llamada LLM)
+    pub run_reasoning: bool,
+}
+
+/// Lo que hizo un ciclo; `None` = tarea desactivada u omitida.
+#[derive(Debug, Default)]
+pub struct ConsolidationReport {
+    pub orphans_pruned: Option<usize>,
+    pub centrality_updated: Option<usize>,
+    pub relations_inferred: Option<usize>,
+}
+
+/// Mantenimiento del grafo fuera del camino de las peticiones.
+pub struct ConsolidationService {
+    repo: Arc<dyn KGRepository>,
+    ai: Arc<dyn AIService>,
+    config: ConsolidationConfig,
+    centrality: CentralityConfig,
+    reasoning: ReasoningConfig,
+    reasoning_cancel: Arc<Notify>,
+    // Marca de tiempo de Neo4j (epoch ms) hasta la que ya se razonó
+    reasoned_until: Mutex<Option<i64>>,
+}
+
+impl ConsolidationService {
+    pub fn new(
+        repo: Arc<dyn KGRepository>,
+        ai: Arc<dyn AIService>,
+        config: ConsolidationConfig,
+        centrality: CentralityConfig,
+        reasoning: ReasoningConfig,
+    ) -> Self {
+        Self { repo, ai, config, centrality, reasoning, reasoning_cancel: Arc::new(Notify::new()), reasoned_until: Mutex::new(None) }
+    }
+
+    /// El razonamiento del ciclo se puede abortar con el mismo `Notify` que `/api/reasoning/cancel`.
+    pub fn with_reasoning_cancellation(mut self, cancel: Arc<Notify>) -> Self {
+        self.reasoning_cancel = cancel;
+        self
+    }
+
+    /// Ejecuta las tareas activas en orden. Un fallo en una no impide las siguientes.
+    pub async fn run_cycle(&self) -> ConsolidationReport {
+        let mut report = ConsolidationReport::default();
+
+        // 1. Huérfanas primero: no deben contar en el PageRank
+        if self.config.prune_orphans {
+            report.orphans_pruned = self.step("prune_orphans", self.repo.prune_orphan_entities()).await;
+        }
+
+        if self.config.recompute_centrality {
+            // El progreso solo interesa en la llamada HTTP: aquí se descarta
+            let (progress_tx, _) = mpsc::channel(1);
+            let service = CentralityService::new(self.repo.clone(), self.centrality.clone());
+            report.centrality_updated = self.step("centrality", service.compute_with_progress(progress_tx)).await;
+        }
+
+        if self.config.run_reasoning {
+            report.relations_inferred = self.step("reasoning", self.reason_if_changed()).await.flatten();
+        }
+
+        report
+    }
+
+    /// Razona solo si hay entidades o relaciones nuevas desde la última vez (`None` si no hizo falta).
+    async fn reason_if_changed(&self) -> Result<Option<usize>, AppError> {
+        let mut reasoned_until = self.reasoned_until.lock().await;
+
+        let changed = match *reasoned_until {
+            Some(since) => {
+                let delta = self.repo.get_graph_since(since).await?;
+                !delta.nodes.is_empty() || !delta.edges.is_empty()
+            },
+            // Primer ciclo: sin referencia, se razona sobre el grafo actual
+            None => true,
+        };
+        if !changed {
+            return Ok(None);
+        }
+
+        let service = ReasoningService::new(self.repo.clone(), self.ai.clone(), self.reasoning.clone())
+            .with_cancellation(self.reasoning_cancel.clone());
+        let inferred = service.infer_new_knowledge().await?.len();
+
+        // Marca tomada después de guardar: las relaciones recién inferidas no disparan otro ciclo
+        *reasoned_until = Some(self.repo.get_graph_since(i64::MAX).await?.timestamp);
+        Ok(Some(inferred))
+    }
+
+    async fn step<T>(&self, name: &str, task: impl std::future::Future<Output = Result<T, AppError>>) -> Option<T> {
+        match task.await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::warn!("⚠️ Consolidation step '{}' failed: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::domain::models::{DocumentInput, GraphEntity, GraphRelation, InferenceResult, InferredRelation, KnowledgeExtraction};
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+    use uuid::Uuid;
+
+    fn entity(name: &str) -> GraphEntity {
+        GraphEntity { name: name.to_string(), category: "Place".to_string(), attributes: HashMap::new() }
+    }
+
+    fn config(prune_orphans: bool, recompute_centrality: bool, run_reasoning: bool) -> ConsolidationConfig {
+        ConsolidationConfig { interval: Duration::from_secs(60), prune_orphans, recompute_centrality, run_reasoning }
+    }
+
+    fn service(repo: Arc<MemoryRepo>, ai: Arc<MockAIService>, config: ConsolidationConfig) -> ConsolidationService {
+        ConsolidationService::new(repo, ai, config, CentralityConfig::default(), ReasoningConfig::default())
+    }
+
+    /// Lugo -> Muralla en un chunk guardado, más "Suelta" en una extracción sin chunk (huérfana).
+    async fn graph() -> Arc<MemoryRepo> {
+        let repo = Arc::new(MemoryRepo::new());
+        let (document_id, chunk_id) = (Uuid::new_v4(), Uuid::new_v4());
+        repo.save_document(document_id, &DocumentInput { name: "muralla.txt".to_string(), metadata: HashMap::new(), ..Default::default() }).await.unwrap();
+        repo.save_chunk(document_id, chunk_id, "Lugo y su muralla", vec![0.1; 8]).await.unwrap();
+        let linked = KnowledgeExtraction {
+            entities: vec![entity("Lugo"), entity("Muralla")],
+            relations: vec![GraphRelation { source: "Lugo".to_string(), target: "Muralla".to_string(), relation_type: "HAS".to_string(), confidence: None }],
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        };
+        repo.save_graph(chunk_id, linked, None).await.unwrap();
+        let loose = KnowledgeExtraction { entities: vec![entity("Suelta")], relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() };
+        repo.save_graph(Uuid::new_v4(), loose, None).await.unwrap();
+        repo
+    }
+
+    fn inference() -> InferenceResult {
+        InferenceResult { new_relations: vec![InferredRelation {
+            source: "Lugo".to_string(),
+            target: "Muralla".to_string(),
+            relation: "PROTECTED_BY".to_string(),
+            reasoning: "La muralla rodea la ciudad".to_string(),
+            inference_type: None,
+            confidence_level: None,
+            confidence: None,
+        }] }
+    }
+
+    #[tokio::test]
+    async fn a_cycle_prunes_orphans_before_ranking_the_rest() {
+        let repo = graph().await;
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+
+        let report = service(repo.clone(), ai.clone(), config(true, true, false)).run_cycle().await;
+
+        assert_eq!(report.orphans_pruned, Some(1));
+        assert_eq!(report.centrality_updated, Some(2));
+        assert_eq!(report.relations_inferred, None);
+        let state = repo.state();
+        assert!(!state.centrality.contains_key("Suelta"));
+        assert!(state.graphs.iter().all(|(_, g)| g.entities.iter().all(|e| e.name != "Suelta")));
+        assert_eq!(ai.completion_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn disabled_tasks_are_not_run() {
+        let repo = graph().await;
+        let report = service(repo.clone(), Arc::new(MockAIService::new(mock_config(8))), config(false, false, false)).run_cycle().await;
+
+        assert_eq!((report.orphans_pruned, report.centrality_updated, report.relations_inferred), (None, None, None));
+        assert!(repo.state().centrality.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reasoning_only_runs_again_when_the_graph_changed() {
+        let repo = graph().await;
+        let ai = Arc::new(MockAIService::new(mock_config(8)).with_inference(inference()));
+        let service = service(repo.clone(), ai.clone(), config(false, false, true));
+
+        assert_eq!(service.run_cycle().await.relations_inferred, Some(1));
+        // Sin cambios (las relaciones recién inferidas no cuentan): no se llama al LLM
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(service.run_cycle().await.relations_inferred, None);
+        assert_eq!(ai.completion_calls(), 1);
+
+        let more = KnowledgeExtraction { entities: vec![entity("Miño")], relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() };
+        repo.save_graph(Uuid::new_v4(), more, None).await.unwrap();
+        assert_eq!(service.run_cycle().await.relations_inferred, Some(1));
+        assert_eq!(ai.completion_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_failing_step_does_not_stop_the_cycle() {
+        let repo = graph().await;
+        repo.set_down(true);
+        let report = service(repo, Arc::new(MockAIService::new(mock_config(8))), config(true, true, true)).run_cycle().await;
+
+        assert_eq!((report.orphans_pruned, report.centrality_updated, report.relations_inferred), (None, None, None));
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
-use crate::domain::models::AIConfig;
+use crate::domain::models::{AIConfig, AIConfigPatch};
 
 #[derive(Deserialize, ToSchema)]
 pub struct AdminConfigPayload {
@@ -8,6 +8,31 @@ pub struct AdminConfigPayload {
     pub force_reset: bool,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct AdminConfigPatchPayload {
+    pub config: AIConfigPatch,
+    /// Necesario solo si cambia el modelo o la dimensión de embeddings
+    #[serde(default)]
+    pub force_reset: bool,
+}
+
+/// Configuración activa para leer desde fuera: la API key nunca se devuelve, solo si está puesta.
+#[derive(Serialize, ToSchema)]
+pub struct AdminConfigView {
+    #[serde(flatten)]
+    pub config: AIConfig,
+    /// `********` si hay API key configurada, vacío si no
+    pub api_key: String,
+}
+
+impl From<AIConfig> for AdminConfigView {
+    fn from(config: AIConfig) -> Self {
+        use secrecy::ExposeSecret;
+        let api_key = if config.api_key.expose_secret().is_empty() { String::new() } else { "********".to_string() };
+        Self { config, api_key }
+    }
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct IngestionResponse {
     pub id: String,
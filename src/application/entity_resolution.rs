@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use crate::domain::{
+    ports::{KGRepository, AIService},
+    models::MergeProposal,
+    errors::AppError
+};
+
+/// Umbral por defecto: nombres casi sinónimos ("car" / "automobile")
+pub const DEFAULT_DEDUP_THRESHOLD: f32 = 0.92;
+pub const DEFAULT_DEDUP_LIMIT: usize = 500;
+
+/// Resolución semántica de entidades: agrupa nombres cuyos embeddings son casi idénticos.
+/// Solo propone fusiones; aplicarlas es una decisión de revisión (`merge_entities`).
+pub struct EntityResolutionService {
+    repo: Arc<dyn KGRepository>,
+    ai: Arc<dyn AIService>,
+}
+
+impl EntityResolutionService {
+    pub fn new(repo: Arc<dyn KGRepository>, ai: Arc<dyn AIService>) -> Self {
+        Self { repo, ai }
+    }
+
+    pub async fn propose_merges(&self, threshold: f32, limit: usize) -> Result<Vec<MergeProposal>, AppError> {
+        // 1. Entidades candidatas (las de mayor grado primero: serán las canónicas)
+        let names = self.repo.list_entity_names(limit).await?;
+
+        // 2. Embeddings de cada nombre
+        let mut embedded = Vec::with_capacity(names.len());
+        for name in names {
+            match self.ai.generate_embedding(&name).await {
+                Ok(vector) => embedded.push((name, vector)),
+                Err(e) => tracing::warn!("⚠️ Dedup: no se pudo vectorizar '{}': {}", name, e),
+            }
+        }
+
+        Ok(group_similar(&embedded, threshold))
+    }
+}
+
+/// Clustering voraz: cada entidad libre absorbe a las similares que vienen detrás.
+fn group_similar(embedded: &[(String, Vec<f32>)], threshold: f32) -> Vec<MergeProposal> {
+    let mut assigned = vec![false; embedded.len()];
+    let mut proposals = Vec::new();
+
+    for i in 0..embedded.len() {
+        if assigned[i] {
+            continue;
+        }
+        let mut duplicates = Vec::new();
+        let mut min_similarity = 1.0_f32;
+
+        for j in (i + 1)..embedded.len() {
+            if assigned[j] {
+                continue;
+            }
+            let similarity = cosine_similarity(&embedded[i].1, &embedded[j].1);
+            if similarity >= threshold {
+                assigned[j] = true;
+                duplicates.push(embedded[j].0.clone());
+                min_similarity = min_similarity.min(similarity);
+            }
+        }
+
+        if !duplicates.is_empty() {
+            proposals.push(MergeProposal {
+                canonical: embedded[i].0.clone(),
+                duplicates,
+                similarity: min_similarity,
+            });
+        }
+    }
+
+    proposals
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedded(name: &str, vector: &[f32]) -> (String, Vec<f32>) {
+        (name.to_string(), vector.to_vec())
+    }
+
+    #[test]
+    fn near_identical_names_are_grouped_under_the_first() {
+        let names = vec![
+            embedded("Coche", &[1.0, 0.0, 0.0]),
+            embedded("Rueda", &[0.0, 1.0, 0.0]),
+            embedded("Automóvil", &[0.99, 0.05, 0.0]),
+            embedded("Auto", &[0.97, 0.0, 0.1]),
+        ];
+
+        let proposals = group_similar(&names, 0.95);
+
+        assert_eq!(proposals.len(), 1);
+        assert_eq!(proposals[0].canonical, "Coche");
+        assert_eq!(proposals[0].duplicates, ["Automóvil", "Auto"]);
+        assert!(proposals[0].similarity >= 0.95 && proposals[0].similarity < 1.0);
+        assert!(group_similar(&names, 0.999).is_empty());
+    }
+
+    #[test]
+    fn cosine_similarity_ignores_mismatched_and_zero_vectors() {
+        assert!((cosine_similarity(&[1.0, 2.0], &[2.0, 4.0]) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0, 0.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+}
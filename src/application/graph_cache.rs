@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use crate::domain::models::{GraphDataResponse, GraphFilter};
+
+/// Último grafo servido con éxito por cada filtro.
+/// Si Neo4j falla, se devuelve esta copia (marcada como obsoleta) mientras no supere el TTL.
+pub struct GraphCache {
+    ttl: Option<Duration>,
+    entries: RwLock<HashMap<GraphFilter, (Instant, GraphDataResponse)>>,
+}
+
+impl GraphCache {
+    /// `ttl = None` desactiva la caché (los errores de BD se propagan tal cual).
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self { ttl, entries: RwLock::new(HashMap::new()) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ttl.is_some()
+    }
+
+    pub async fn store(&self, filter: &GraphFilter, graph: &GraphDataResponse) {
+        if self.is_enabled() {
+            self.entries.write().await.insert(filter.clone(), (Instant::now(), graph.clone()));
+        }
+    }
+
+    /// Copia en caché para `filter` y su antigüedad, si sigue dentro del TTL.
+    pub async fn last_good(&self, filter: &GraphFilter) -> Option<(GraphDataResponse, Duration)> {
+        let ttl = self.ttl?;
+        let entries = self.entries.read().await;
+        let (stored_at, graph) = entries.get(filter)?;
+        let age = stored_at.elapsed();
+        (age <= ttl).then(|| (graph.clone(), age))
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use crate::domain::models::{ExportRecord, GraphDataResponse};
+
+/// Paleta para colorear nodos por categoría (se asigna en orden de aparición).
+const PALETTE: &[&str] = &[
+    "#8dd3c7", "#ffffb3", "#bebada", "#fb8072", "#80b1d3",
+    "#fdb462", "#b3de69", "#fccde5", "#d9d9d9", "#bc80bd",
+];
+
+/// Escapa un texto para usarlo como ID/etiqueta entre comillas en DOT.
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serializa el grafo a Graphviz DOT (renderizable con `dot -Tpng`).
+pub fn to_dot(graph: &GraphDataResponse) -> String {
+    let mut colors: HashMap<&str, &str> = HashMap::new();
+    let mut dot = String::from("digraph LaMuralla {\n");
+    dot.push_str("    rankdir=LR;\n");
+    dot.push_str("    node [shape=box, style=\"rounded,filled\", fontname=\"Helvetica\"];\n");
+    dot.push_str("    edge [fontname=\"Helvetica\", fontsize=10];\n\n");
+
+    for node in &graph.nodes {
+        let next = PALETTE[colors.len() % PALETTE.len()];
+        let color = *colors.entry(node.group.as_str()).or_insert(next);
+        dot.push_str(&format!(
+            "    \"{}\" [label=\"{}\", fillcolor=\"{}\", tooltip=\"{}\"];\n",
+            escape_dot(&node.id), escape_dot(&node.label), color, escape_dot(&node.group)
+        ));
+    }
+
+    dot.push('\n');
+    for edge in &graph.edges {
+        dot.push_str(&format!(
+            "    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+            escape_dot(&edge.from), escape_dot(&edge.to), escape_dot(&edge.label)
+        ));
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// Literal de texto Cypher entre comillas simples.
+fn cypher_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('\'');
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\'' => escaped.push_str("\\'"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped.push('\'');
+    escaped
+}
+
+/// Identificador (clave o tipo de relación) entre backticks.
+fn cypher_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+/// Valor JSON como literal Cypher (`None` para null, que no se guarda como propiedad).
+fn cypher_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::String(s) => Some(cypher_string(s)),
+        serde_json::Value::Array(items) => Some(format!(
+            "[{}]",
+            items.iter().filter_map(cypher_value).collect::<Vec<_>>().join(", ")
+        )),
+        // Neo4j no admite mapas como propiedad: se conservan como JSON en texto
+        serde_json::Value::Object(_) => Some(cypher_string(&value.to_string())),
+    }
+}
+
+/// Mapa de propiedades `{clave: valor, ...}`; las claves de `temporal` se recrean con `datetime()`.
+fn cypher_properties(properties: &HashMap<String, serde_json::Value>, temporal: &[&str]) -> String {
+    let mut keys: Vec<&String> = properties.keys().collect();
+    keys.sort();
+
+    let entries: Vec<String> = keys.into_iter()
+        .filter_map(|key| {
+            let value = &properties[key];
+            let literal = match value {
+                serde_json::Value::String(s) if temporal.contains(&key.as_str()) => format!("datetime({})", cypher_string(s)),
+                _ => cypher_value(value)?,
+            };
+            Some(format!("{}: {}", cypher_identifier(key), literal))
+        })
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+/// Cabecera del script Cypher: restricciones que hacen rápidos (e idempotentes) los `MERGE`.
+pub fn cypher_preamble(include_chunks: bool) -> String {
+    let mut script = String::from("// La Muralla: exportación del grafo de conocimiento (cypher-shell -f grafo.cypher)\n");
+    script.push_str("CREATE CONSTRAINT entity_name IF NOT EXISTS FOR (e:Entity) REQUIRE e.name IS UNIQUE;\n");
+    if include_chunks {
+        script.push_str("// Los fragmentos se exportan sin embeddings: tras importar, POST /api/admin/reembed\n");
+        script.push_str("CREATE CONSTRAINT document_id IF NOT EXISTS FOR (d:Document) REQUIRE d.id IS UNIQUE;\n");
+        script.push_str("CREATE CONSTRAINT chunk_id IF NOT EXISTS FOR (c:DocumentChunk) REQUIRE c.id IS UNIQUE;\n");
+    }
+    script.push('\n');
+    script
+}
+
+/// Sentencia `MERGE` (idempotente) que recrea un elemento exportado.
+/// Las claves de `temporal` se recrean como `datetime()`.
+pub fn cypher_statement(record: &ExportRecord, temporal: &[&str]) -> String {
+    match record {
+        ExportRecord::Entity(entity) => format!(
+            "MERGE (e:Entity {{name: {}}}) SET e += {};\n",
+            cypher_string(&entity.name),
+            cypher_properties(&entity.properties, temporal)
+        ),
+        ExportRecord::Relation(relation) => format!(
+            "MATCH (a:Entity {{name: {}}}), (b:Entity {{name: {}}}) MERGE (a)-[r:{}]->(b) SET r += {};\n",
+            cypher_string(&relation.source),
+            cypher_string(&relation.target),
+            cypher_identifier(&relation.relation_type),
+            cypher_properties(&relation.properties, temporal)
+        ),
+        ExportRecord::Document(document) => format!(
+            "MERGE (d:Document {{id: {}}}) SET d += {};\n",
+            cypher_string(&document.id),
+            cypher_properties(&document.properties, temporal)
+        ),
+        ExportRecord::Chunk(chunk) => {
+            let node = format!(
+                "MERGE (c:DocumentChunk {{id: {}}}) SET c += {}",
+                cypher_string(&chunk.id),
+                cypher_properties(&chunk.properties, temporal)
+            );
+            match &chunk.document_id {
+                Some(document_id) => format!(
+                    "MERGE (d:Document {{id: {}}}) {} MERGE (d)-[:HAS_CHUNK]->(c);\n",
+                    cypher_string(document_id),
+                    node
+                ),
+                None => format!("{};\n", node),
+            }
+        },
+        ExportRecord::Mention { chunk_id, entity } => format!(
+            "MATCH (c:DocumentChunk {{id: {}}}), (e:Entity {{name: {}}}) MERGE (c)-[:MENTIONS]->(e);\n",
+            cypher_string(chunk_id),
+            cypher_string(entity)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{ExportedEntity, ExportedNode, ExportedRelation, VisEdge, VisNode};
+
+    fn node(id: &str, group: &str) -> VisNode {
+        VisNode { id: id.to_string(), label: id.to_string(), group: group.to_string(), centrality: None }
+    }
+
+    #[test]
+    fn dot_lists_nodes_coloured_by_category_and_labelled_edges() {
+        let graph = GraphDataResponse {
+            nodes: vec![node("Muralla", "Monument"), node("Lugo", "Place"), node("Catedral", "Monument")],
+            edges: vec![VisEdge { from: "Muralla".to_string(), to: "Lugo".to_string(), label: "LOCATED_IN".to_string(), sources: Vec::new(), confidence: None, reasoning: None, inferred: false }],
+            limits_reached: Vec::new(),
+        };
+
+        let dot = to_dot(&graph);
+
+        assert!(dot.starts_with("digraph LaMuralla {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(r##""Muralla" [label="Muralla", fillcolor="#8dd3c7", tooltip="Monument"];"##));
+        assert!(dot.contains(r##""Lugo" [label="Lugo", fillcolor="#ffffb3", tooltip="Place"];"##));
+        assert!(dot.contains(r##""Catedral" [label="Catedral", fillcolor="#8dd3c7", tooltip="Monument"];"##));
+        assert!(dot.contains(r#""Muralla" -> "Lugo" [label="LOCATED_IN"];"#));
+    }
+
+    #[test]
+    fn quotes_backslashes_and_newlines_are_escaped() {
+        assert_eq!(escape_dot("Puerta \"Miñá\"\nC:\\"), r#"Puerta \"Miñá\"\nC:\\"#);
+    }
+
+    #[test]
+    fn cypher_literals_escape_quotes_and_control_characters() {
+        assert_eq!(cypher_string("L'Hospitalet\n\\"), r"'L\'Hospitalet\n\\'");
+        assert_eq!(cypher_identifier("PART`OF"), "`PART``OF`");
+    }
+
+    #[test]
+    fn cypher_statements_merge_entities_and_relations_with_their_properties() {
+        let entity = ExportRecord::Entity(ExportedEntity {
+            name: "Muralla".to_string(),
+            properties: HashMap::from([
+                ("category".to_string(), serde_json::json!("Monument")),
+                ("created_at".to_string(), serde_json::json!("2024-01-15T10:00:00Z")),
+                ("length_m".to_string(), serde_json::json!(2117)),
+                ("gates".to_string(), serde_json::json!(["Miñá", "Nova"])),
+                ("missing".to_string(), serde_json::Value::Null),
+            ]),
+        });
+        let relation = ExportRecord::Relation(ExportedRelation {
+            source: "Muralla".to_string(),
+            target: "Lugo".to_string(),
+            relation_type: "LOCATED_IN".to_string(),
+            properties: HashMap::from([("confidence".to_string(), serde_json::json!(0.9))]),
+        });
+
+        assert_eq!(
+            cypher_statement(&entity, &["created_at"]),
+            "MERGE (e:Entity {name: 'Muralla'}) SET e += {`category`: 'Monument', `created_at`: datetime('2024-01-15T10:00:00Z'), `gates`: ['Miñá', 'Nova'], `length_m`: 2117};\n"
+        );
+        assert_eq!(
+            cypher_statement(&relation, &[]),
+            "MATCH (a:Entity {name: 'Muralla'}), (b:Entity {name: 'Lugo'}) MERGE (a)-[r:`LOCATED_IN`]->(b) SET r += {`confidence`: 0.9};\n"
+        );
+    }
+
+    #[test]
+    fn chunks_are_merged_under_their_document_and_linked_to_mentions() {
+        let chunk = ExportRecord::Chunk(ExportedNode {
+            id: "c1".to_string(),
+            document_id: Some("d1".to_string()),
+            properties: HashMap::from([("content".to_string(), serde_json::json!("La muralla"))]),
+        });
+        assert_eq!(
+            cypher_statement(&chunk, &[]),
+            "MERGE (d:Document {id: 'd1'}) MERGE (c:DocumentChunk {id: 'c1'}) SET c += {`content`: 'La muralla'} MERGE (d)-[:HAS_CHUNK]->(c);\n"
+        );
+
+        let orphan = ExportRecord::Chunk(ExportedNode { id: "c2".to_string(), document_id: None, properties: HashMap::new() });
+        assert_eq!(cypher_statement(&orphan, &[]), "MERGE (c:DocumentChunk {id: 'c2'}) SET c += {};\n");
+
+        let mention = ExportRecord::Mention { chunk_id: "c1".to_string(), entity: "Muralla".to_string() };
+        assert_eq!(
+            cypher_statement(&mention, &[]),
+            "MATCH (c:DocumentChunk {id: 'c1'}), (e:Entity {name: 'Muralla'}) MERGE (c)-[:MENTIONS]->(e);\n"
+        );
+    }
+
+    #[test]
+    fn the_preamble_adds_chunk_constraints_only_when_needed() {
+        assert!(!cypher_preamble(false).contains("DocumentChunk"));
+        let full = cypher_preamble(true);
+        assert!(full.contains("FOR (c:DocumentChunk) REQUIRE c.id IS UNIQUE"));
+        assert!(full.contains("/api/admin/reembed"));
+    }
+}
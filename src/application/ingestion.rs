@@ -1,101 +1,442 @@
 use uuid::Uuid;
+use tiktoken_rs::{tokenizer::{get_tokenizer, Tokenizer}, CoreBPE};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use crate::domain::{
-    ports::{KGRepository, AIService},
-    // models::IngestionRequest, // Comentado para evitar warning
+    ports::{KGRepository, AIService, RetryNotifier, RETRY_NOTIFIER},
+    models::{DocumentInput, EmbeddingRecord, EmbeddingSlot, ExtractionProvenance, IngestionPlan, PlannedChunk},
     errors::AppError
 };
+use super::redaction::Redactor;
 
 // Reducir drásticamente para mejorar la precisión vectorial
 // 1500 caracteres ~= 300-400 tokens (Sweet spot para embeddings)
-const CHUNK_SIZE: usize = 1500; 
-const CHUNK_OVERLAP: usize = 200;
+pub const DEFAULT_CHUNK_SIZE: usize = 1500;
+pub const DEFAULT_CHUNK_OVERLAP: usize = 200;
+
+/// Cómo se miden los chunks.
+#[derive(Debug, Clone)]
+pub enum ChunkingMode {
+    /// Ventanas de `chunk_size` caracteres que comparten `overlap` con la anterior (heurística histórica)
+    Characters {
+        chunk_size: usize,
+        overlap: usize,
+    },
+    /// Ventanas de tokens reales: garantiza que cada chunk cabe en el contexto del modelo
+    Tokens {
+        max_tokens: usize,
+        overlap_tokens: usize,
+        /// Tokenizer forzado (`cl100k_base`, `o200k_base`...); si no, se deduce del modelo de embeddings
+        tokenizer: Option<String>,
+    },
+}
+
+impl Default for ChunkingMode {
+    fn default() -> Self {
+        ChunkingMode::Characters { chunk_size: DEFAULT_CHUNK_SIZE, overlap: DEFAULT_CHUNK_OVERLAP }
+    }
+}
+
+/// Tokenizer para un nombre de codificación o de modelo; `cl100k_base` si no se reconoce
+/// (modelos locales tipo Ollama: es una aproximación razonable).
+fn resolve_tokenizer(name: &str) -> &'static CoreBPE {
+    let tokenizer = match name {
+        "o200k_base" => Some(Tokenizer::O200kBase),
+        "cl100k_base" => Some(Tokenizer::Cl100kBase),
+        "p50k_base" => Some(Tokenizer::P50kBase),
+        "p50k_edit" => Some(Tokenizer::P50kEdit),
+        "r50k_base" | "gpt2" => Some(Tokenizer::R50kBase),
+        model => get_tokenizer(model),
+    };
+
+    match tokenizer {
+        Some(Tokenizer::O200kBase) => tiktoken_rs::o200k_base_singleton(),
+        Some(Tokenizer::P50kBase) => tiktoken_rs::p50k_base_singleton(),
+        Some(Tokenizer::P50kEdit) => tiktoken_rs::p50k_edit_singleton(),
+        Some(Tokenizer::R50kBase) | Some(Tokenizer::Gpt2) => tiktoken_rs::r50k_base_singleton(),
+        Some(Tokenizer::Cl100kBase) | None => tiktoken_rs::cl100k_base_singleton(),
+    }
+}
+
+/// Divide en ventanas de `max_tokens` tokens con `overlap_tokens` de solape.
+/// Los cortes se ajustan a límites de carácter UTF-8 (un token puede partir un carácter CJK).
+pub fn split_text_by_tokens(text: &str, bpe: &CoreBPE, max_tokens: usize, overlap_tokens: usize) -> Vec<String> {
+    let max_tokens = max_tokens.max(1);
+    let tokens = bpe.encode_ordinary(text);
+
+    // offsets[i] = byte donde empieza el token i en `text`
+    let mut offsets = Vec::with_capacity(tokens.len() + 1);
+    let mut position = 0;
+    for piece in bpe._decode_native_and_split(tokens.clone()) {
+        offsets.push(position);
+        position += piece.len();
+    }
+    offsets.push(position);
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < tokens.len() {
+        let end = std::cmp::min(start + max_tokens, tokens.len());
+
+        let mut byte_start = offsets[start].min(text.len());
+        while !text.is_char_boundary(byte_start) {
+            byte_start += 1;
+        }
+        let mut byte_end = offsets[end].min(text.len());
+        while !text.is_char_boundary(byte_end) {
+            byte_end -= 1;
+        }
+
+        if byte_end > byte_start {
+            chunks.push(text[byte_start..byte_end].to_string());
+        }
+
+        start += std::cmp::max(1, (end - start).saturating_sub(overlap_tokens));
+    }
+    chunks
+}
+
+/// Ventana deslizante de `chunk_size` caracteres: cada chunk repite los últimos `overlap`
+/// caracteres del anterior, para no perder entidades mencionadas justo en un corte.
+/// Tanto el final como el inicio del solape se ajustan a espacios para no partir palabras.
+pub fn split_text_by_chars(text: &str, chunk_size: usize, overlap: usize) -> Vec<String> {
+    let chunk_size = chunk_size.max(1);
+    // Un solape >= tamaño no avanzaría: se acota a la mitad de la ventana
+    let overlap = overlap.min(chunk_size / 2);
+    let chars: Vec<char> = text.chars().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let end = std::cmp::min(start + chunk_size, chars.len());
+
+        // Ajuste para no cortar palabras (buscar espacio hacia atrás)
+        let mut actual_end = end;
+        if actual_end < chars.len() {
+            while actual_end > start && !chars[actual_end].is_whitespace() {
+                actual_end -= 1;
+            }
+        }
+        if actual_end == start { actual_end = end; } // Fallback si la palabra es gigante
+
+        chunks.push(chars[start..actual_end].iter().collect());
+        if actual_end >= chars.len() {
+            break;
+        }
+
+        // Retroceder `overlap` y avanzar hasta el inicio de una palabra
+        let mut next = std::cmp::max(start + 1, actual_end.saturating_sub(overlap));
+        while next < actual_end && !chars[next - 1].is_whitespace() {
+            next += 1;
+        }
+        while next < chars.len() && chars[next].is_whitespace() {
+            next += 1;
+        }
+        start = next;
+    }
+    chunks
+}
+
+/// Ajustes globales de ingesta (se leen del entorno en main.rs).
+#[derive(Debug, Clone, Default)]
+pub struct IngestionConfig {
+    /// Máximo de chunks a vectorizar/extraer por documento (None = sin límite).
+    /// Se puede sobreescribir por petición.
+    pub max_chunks: Option<usize>,
+    /// Separación mínima entre llamadas a la IA (cero = sin throttle).
+    /// Útil en planes con rate limit bajo para evitar 429.
+    pub ai_call_interval: Duration,
+    /// Conserva títulos/listas/negritas como marcas markdown al convertir documentos.
+    pub preserve_formatting: bool,
+    /// PDF con menos caracteres que esto: OCR de sus imágenes (feature `ocr`; None = desactivado)
+    pub ocr_min_chars: Option<usize>,
+    /// Chunking por caracteres (por defecto) o por tokens
+    pub chunking: ChunkingMode,
+    /// Guardar en cada chunk el modelo/versión de prompt de la extracción y su fecha
+    pub record_provenance: bool,
+    /// Guardar en cada chunk el JSON de la extracción para poder reconstruir el grafo sin el LLM
+    pub store_extractions: bool,
+    /// Guardar también redactado el texto de los chunks (None = se guarda el original)
+    pub stored_text_redactor: Option<Arc<Redactor>>,
+    /// Tokens estimados (embeddings + extracción) que puede gastar un documento (None = sin límite).
+    /// Se puede sobreescribir por petición.
+    pub token_budget: Option<usize>,
+    /// Embeddings adicionales por chunk con otros modelos (EMBEDDING_SLOTS); un fallo no detiene la ingesta
+    pub embedding_slots: Vec<EmbeddingSlot>,
+}
+
+/// Tokens fijos de cada extracción además del fragmento: instrucciones del prompt y JSON de respuesta.
+/// Aproximado a propósito; el presupuesto es una estimación, no una factura.
+pub const EXTRACTION_OVERHEAD_TOKENS: usize = 600;
+
+/// Límites de los metadatos de documento.
+const MAX_METADATA_KEYS: usize = 32;
+const MAX_METADATA_KEY_LEN: usize = 64;
+const MAX_METADATA_BYTES: usize = 8 * 1024;
+
+/// Valida los metadatos de un documento: claves `[A-Za-z0-9_]`, valores escalares
+/// o listas de escalares, y un tamaño total acotado.
+pub fn validate_metadata(metadata: &HashMap<String, serde_json::Value>) -> Result<(), AppError> {
+    if metadata.len() > MAX_METADATA_KEYS {
+        return Err(AppError::ValidationError(format!("metadata admite como máximo {} claves", MAX_METADATA_KEYS)));
+    }
+
+    for (key, value) in metadata {
+        let valid_key = !key.is_empty()
+            && key.len() <= MAX_METADATA_KEY_LEN
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            return Err(AppError::ValidationError(format!("Clave de metadata no válida: '{}'", key)));
+        }
+
+        let is_scalar = |v: &serde_json::Value| v.is_string() || v.is_number() || v.is_boolean();
+        let valid_value = match value {
+            serde_json::Value::Array(items) => items.iter().all(is_scalar),
+            other => is_scalar(other),
+        };
+        if !valid_value {
+            return Err(AppError::ValidationError(format!("El valor de '{}' debe ser texto, número, booleano o lista de ellos", key)));
+        }
+    }
+
+    let size = serde_json::to_string(metadata).map(|s| s.len()).unwrap_or(usize::MAX);
+    if size > MAX_METADATA_BYTES {
+        return Err(AppError::ValidationError(format!("metadata supera {} bytes", MAX_METADATA_BYTES)));
+    }
+
+    Ok(())
+}
+
+/// Embeddings importados (hash de contenido -> vector) que la ingesta reutiliza
+/// en lugar de llamar al proveedor.
+pub type EmbeddingImports = Arc<RwLock<HashMap<String, Vec<f32>>>>;
 
 pub struct IngestionService {
     repo: Arc<dyn KGRepository>,
-    ai: Arc<RwLock<dyn AIService>>,
+    ai: Arc<dyn AIService>,
+    config: IngestionConfig,
+    embedding_imports: Option<EmbeddingImports>,
 }
 
 impl IngestionService {
-    pub fn new(repo: Arc<dyn KGRepository>, ai: Arc<RwLock<dyn AIService>>) -> Self {
-        Self { repo, ai }
+    pub fn new(repo: Arc<dyn KGRepository>, ai: Arc<dyn AIService>, config: IngestionConfig) -> Self {
+        Self { repo, ai, config, embedding_imports: None }
     }
 
-    /// Función auxiliar para dividir texto preservando palabras completas
-    // En split_text_into_chunks:
-    // Implementar lógica de ventana deslizante (sliding window)
-    fn split_text_into_chunks(&self, text: &str) -> Vec<String> {
-        let mut chunks = Vec::new();
-        let chars: Vec<char> = text.chars().collect();
-        let mut start = 0;
+    /// Reutiliza embeddings importados cuando el hash del chunk coincide.
+    pub fn with_embedding_imports(mut self, imports: EmbeddingImports) -> Self {
+        self.embedding_imports = Some(imports);
+        self
+    }
 
-        while start < chars.len() {
-            let end = std::cmp::min(start + CHUNK_SIZE, chars.len());
-        
-            // Ajuste para no cortar palabras (buscar espacio hacia atrás)
-            let mut actual_end = end;
-            if actual_end < chars.len() {
-                while actual_end > start && !chars[actual_end].is_whitespace() {
-                    actual_end -= 1;
-                }
+    async fn imported_embedding(&self, chunk_text: &str) -> Option<Vec<f32>> {
+        let imports = self.embedding_imports.as_ref()?;
+        let hash = EmbeddingRecord::hash_content(chunk_text);
+        imports.read().await.get(&hash).cloned()
+    }
+
+    /// Espera lo necesario para respetar `ai_call_interval` desde la última llamada.
+    async fn throttle(&self, last_call: &mut Option<Instant>) {
+        let interval = self.config.ai_call_interval;
+        if interval.is_zero() {
+            return;
+        }
+        if let Some(previous) = *last_call {
+            let elapsed = previous.elapsed();
+            if elapsed < interval {
+                tokio::time::sleep(interval - elapsed).await;
             }
-            if actual_end == start { actual_end = end; } // Fallback si la palabra es gigante
+        }
+        *last_call = Some(Instant::now());
+    }
 
-            let chunk_str: String = chars[start..actual_end].iter().collect();
-            chunks.push(chunk_str);
+    /// Divide el texto según el modo de chunking configurado.
+    fn split_text(&self, text: &str) -> Vec<String> {
+        match &self.config.chunking {
+            ChunkingMode::Characters { chunk_size, overlap } => split_text_by_chars(text, *chunk_size, *overlap),
+            ChunkingMode::Tokens { max_tokens, overlap_tokens, tokenizer } => {
+                let name = tokenizer.clone().unwrap_or_else(|| self.ai.get_config().embedding_model);
+                split_text_by_tokens(text, resolve_tokenizer(&name), *max_tokens, *overlap_tokens)
+            },
+        }
+    }
 
-            // Avanzar restando el overlap para mantener contexto
-            start +=  std::cmp::max(1, (actual_end - start).saturating_sub(CHUNK_OVERLAP));
+    /// Conteo de tokens con el tokenizer que usaría el chunking por tokens.
+    fn tokenizer(&self) -> &'static CoreBPE {
+        match &self.config.chunking {
+            ChunkingMode::Tokens { tokenizer: Some(name), .. } => resolve_tokenizer(name),
+            _ => resolve_tokenizer(&self.ai.get_config().embedding_model),
+        }
+    }
+
+    /// Plan de ingesta sin coste: mismo chunking y límite que `ingest_with_progress`,
+    /// sin llamadas a la IA ni escrituras en la base de datos.
+    pub async fn plan(&self, content: &str, document_name: String, max_chunks: Option<usize>) -> IngestionPlan {
+        let chunks = self.split_text(content);
+        let chunk_count = chunks.len();
+        let processed_chunks = max_chunks.or(self.config.max_chunks)
+            .map_or(chunk_count, |cap| chunk_count.min(cap));
+
+        let bpe = self.tokenizer();
+        let mut planned = Vec::with_capacity(chunk_count);
+        let mut imported = 0;
+        for (index, chunk_text) in chunks.iter().enumerate() {
+            if index < processed_chunks && self.imported_embedding(chunk_text).await.is_some() {
+                imported += 1;
+            }
+            planned.push(PlannedChunk {
+                index,
+                chars: chunk_text.chars().count(),
+                estimated_tokens: bpe.encode_ordinary(chunk_text).len(),
+            });
+        }
+
+        IngestionPlan {
+            document: document_name,
+            char_count: content.chars().count(),
+            chunk_count,
+            processed_chunks,
+            estimated_tokens: planned.iter().take(processed_chunks).map(|c| c.estimated_tokens).sum(),
+            chunks: planned,
+            embedding_calls: processed_chunks - imported,
+            extraction_calls: processed_chunks,
         }
-        chunks
     }
 
     pub async fn ingest_with_progress(
         &self, 
         content: String,
+        document: DocumentInput,
+        max_chunks: Option<usize>,
+        token_budget: Option<usize>,
         progress_tx: tokio::sync::mpsc::Sender<String>
     ) -> Result<Uuid, AppError> {
+        validate_metadata(&document.metadata)?;
         
         // 1. Dividir el contenido en trozos (Chunks)
-        let chunks = self.split_text_into_chunks(&content);
+        let mut chunks = self.split_text(&content);
+        let original_chunks = chunks.len();
+        let doc_group_id = Uuid::new_v4(); // Nodo :Document que agrupa los chunks
+
+        self.repo.save_document(doc_group_id, &document).await?;
+
+        let _ = progress_tx.send(format!("🔪 Documento largo detectado. Dividido en {} fragmentos.", original_chunks)).await;
+
+        // Límite de coste: el de la petición tiene prioridad sobre el global
+        if let Some(cap) = max_chunks.or(self.config.max_chunks) {
+            if original_chunks > cap {
+                chunks.truncate(cap);
+                let _ = progress_tx.send(format!(
+                    "✂️ Límite de {} fragmentos por documento: se omiten los {} restantes.",
+                    cap, original_chunks - cap
+                )).await;
+            }
+        }
         let total_chunks = chunks.len();
-        let doc_group_id = Uuid::new_v4(); // ID para agrupar (opcional en lógica futura)
+        let mut last_ai_call: Option<Instant> = None;
+
+        // Presupuesto de tokens: antes de cada fragmento se estima su coste y, si no cabe, se para
+        let token_budget = token_budget.or(self.config.token_budget);
+        let bpe = self.tokenizer();
+        let mut tokens_spent = 0usize;
+        let mut processed_chunks = 0usize;
+        let mut processed_chars = 0usize;
 
-        let _ = progress_tx.send(format!("🔪 Documento largo detectado. Dividido en {} fragmentos.", total_chunks)).await;
+        // Procedencia de la extracción (igual para todo el documento)
+        let provenance = self.config.record_provenance.then(|| ExtractionProvenance {
+            model: self.ai.get_config().model_name,
+            prompt_version: self.ai.extraction_prompt_version(),
+        });
 
         // 2. Procesar cada chunk
         for (index, chunk_text) in chunks.iter().enumerate() {
             let current_step = index + 1;
             let chunk_id = Uuid::new_v4();
 
+            let imported = self.imported_embedding(chunk_text).await;
+            let chunk_tokens = bpe.encode_ordinary(chunk_text).len();
+            // Embedding principal (salvo importado) + uno por slot + extracción
+            let estimated_cost = if imported.is_some() { 0 } else { chunk_tokens }
+                + chunk_tokens * self.config.embedding_slots.len()
+                + chunk_tokens + EXTRACTION_OVERHEAD_TOKENS;
+            if let Some(budget) = token_budget {
+                if tokens_spent + estimated_cost > budget {
+                    let _ = progress_tx.send(format!(
+                        "💰 Presupuesto de {} tokens alcanzado (~{} gastados; el fragmento {} necesitaría ~{}). Se detiene la ingesta.",
+                        budget, tokens_spent, current_step, estimated_cost
+                    )).await;
+                    break;
+                }
+            }
+            tokens_spent += estimated_cost;
+            processed_chunks += 1;
+            processed_chars += chunk_text.chars().count();
+
+            // Reintentos de la IA (errores transitorios) visibles en el progreso
+            let retry_tx = progress_tx.clone();
+            let notifier: RetryNotifier = Arc::new(move |attempt, max, e| {
+                let _ = retry_tx.try_send(format!("🔁 Reintentando fragmento {} (intento {}/{}): {}", current_step, attempt, max, e));
+            });
+
             // A. Vectorizar
             let _ = progress_tx.send(format!("🧠 [{}/{}] Generando Embeddings...", current_step, total_chunks)).await;
             
-            // Obtenemos lock para IA
-            let ai_guard = self.ai.read().await;
-            
-            // Manejo de error específico de Embeddings para no detener todo el proceso si uno falla
-            let embedding = match ai_guard.generate_embedding(chunk_text).await {
-                Ok(emb) => emb,
-                Err(e) => {
-                    let _ = progress_tx.send(format!("⚠️ Error embedding chunk {}: {}. Saltando...", current_step, e)).await;
-                    continue; 
+            // Si el chunk ya tiene un embedding importado (mismo contenido), no llamamos al proveedor
+            let embedding = if let Some(emb) = imported {
+                let _ = progress_tx.send(format!("♻️ [{}/{}] Reutilizando embedding importado.", current_step, total_chunks)).await;
+                emb
+            } else {
+                // Manejo de error específico de Embeddings para no detener todo el proceso si uno falla
+                self.throttle(&mut last_ai_call).await;
+                match RETRY_NOTIFIER.scope(notifier.clone(), self.ai.generate_embedding(chunk_text)).await {
+                    Ok(emb) => emb,
+                    Err(e) => {
+                        let _ = progress_tx.send(format!("⚠️ Error embedding chunk {}: {}. Saltando...", current_step, e)).await;
+                        continue; 
+                    }
                 }
             };
 
             // B. Guardar Chunk
             // let _ = progress_tx.send(format!("💾 [{}/{}] Guardando datos...", current_step, total_chunks)).await;
-            self.repo.save_chunk(chunk_id, chunk_text, embedding).await?;
+            let stored_text = match &self.config.stored_text_redactor {
+                Some(redactor) => redactor.redact(chunk_text),
+                None => chunk_text.as_str().into(),
+            };
+            self.repo.save_chunk(doc_group_id, chunk_id, &stored_text, embedding).await?;
+
+            // Embeddings adicionales (slots): uno por modelo configurado
+            for slot in &self.config.embedding_slots {
+                self.throttle(&mut last_ai_call).await;
+                match RETRY_NOTIFIER.scope(notifier.clone(), self.ai.generate_slot_embedding(slot, chunk_text)).await {
+                    Ok(emb) if emb.len() == slot.dim => self.repo.save_chunk_slot_embedding(chunk_id, slot, emb).await?,
+                    Ok(emb) => {
+                        let _ = progress_tx.send(format!(
+                            "⚠️ [{}/{}] Slot '{}': {} dimensiones (esperadas {}). Sin embedding adicional.",
+                            current_step, total_chunks, slot.name, emb.len(), slot.dim
+                        )).await;
+                    },
+                    Err(e) => {
+                        let _ = progress_tx.send(format!("⚠️ [{}/{}] Slot '{}': {}. Sin embedding adicional.", current_step, total_chunks, slot.name, e)).await;
+                    },
+                }
+            }
 
             // C. Extracción Simbólica (LLM)
             let _ = progress_tx.send(format!("🕵️ [{}/{}] Extrayendo conocimiento...", current_step, total_chunks)).await;
             
-            match ai_guard.extract_knowledge(chunk_text).await {
+            self.throttle(&mut last_ai_call).await;
+            match RETRY_NOTIFIER.scope(notifier, self.ai.extract_knowledge(chunk_text)).await {
                 Ok(extraction) => {
                     let count = extraction.entities.len();
+                    if self.config.store_extractions {
+                        let json = serde_json::to_string(&extraction).map_err(|e| AppError::ParseError(e.to_string()))?;
+                        self.repo.save_chunk_extraction(chunk_id, &json).await?;
+                    }
                     let _ = progress_tx.send(format!("🕸️ [{}/{}] Conectando {} entidades al grafo...", current_step, total_chunks, count)).await;
-                    self.repo.save_graph(chunk_id, extraction).await?;
+                    self.repo.save_graph(chunk_id, extraction, provenance.as_ref()).await?;
                 },
                 Err(e) => {
                     let _ = progress_tx.send(format!("⚠️ Error extrayendo entidades en parte {}: {}", current_step, e)).await;
@@ -104,9 +445,348 @@ impl IngestionService {
             };
         }
 
-        let _ = progress_tx.send("✅ ¡Todo el documento ha sido procesado!".to_string()).await;
+        if processed_chunks < total_chunks {
+            let total_chars = content.chars().count().max(1);
+            let _ = progress_tx.send(format!(
+                "✅ Documento procesado parcialmente (presupuesto): {} de {} fragmentos, ~{}% del texto, ~{} tokens estimados.",
+                processed_chunks, original_chunks, processed_chars * 100 / total_chars, tokens_spent
+            )).await;
+        } else if total_chunks < original_chunks {
+            let _ = progress_tx.send(format!(
+                "✅ Documento procesado parcialmente (truncado): {} de {} fragmentos.",
+                total_chunks, original_chunks
+            )).await;
+        } else {
+            let _ = progress_tx.send("✅ ¡Todo el documento ha sido procesado!".to_string()).await;
+        }
 
         // Retornamos el ID del último chunk procesado (o uno nuevo genérico)
         Ok(doc_group_id)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    /// Documento de ~8000 caracteres: varios fragmentos de `CHUNK_SIZE`
+    fn long_document() -> String {
+        "palabra ".repeat(1000)
+    }
+
+    async fn ingest(config: IngestionConfig, max_chunks: Option<usize>) -> (usize, usize, Vec<String>) {
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let service = IngestionService::new(repo.clone(), ai.clone(), config);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10_000);
+
+        service.ingest_with_progress(long_document(), DocumentInput::default(), max_chunks, None, tx).await.unwrap();
+
+        let mut messages = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            messages.push(message);
+        }
+        let embeddings = ai.embedding_calls();
+        let graphs = repo.state().graphs.len();
+        (embeddings, graphs, messages)
+    }
+
+    #[tokio::test]
+    async fn without_a_cap_every_chunk_is_processed() {
+        let chunks = split_text_by_chars(&long_document(), DEFAULT_CHUNK_SIZE, DEFAULT_CHUNK_OVERLAP).len();
+        assert!(chunks > 3);
+
+        let (embeddings, graphs, messages) = ingest(IngestionConfig::default(), None).await;
+        assert_eq!((embeddings, graphs), (chunks, chunks));
+        assert_eq!(messages.last().unwrap(), "✅ ¡Todo el documento ha sido procesado!");
+    }
+
+    #[tokio::test]
+    async fn the_global_cap_truncates_the_document() {
+        let (embeddings, graphs, messages) = ingest(IngestionConfig { max_chunks: Some(2), ..Default::default() }, None).await;
+
+        assert_eq!((embeddings, graphs), (2, 2));
+        assert!(messages.iter().any(|m| m.starts_with("✂️ Límite de 2 fragmentos")));
+        assert!(messages.last().unwrap().contains("truncado"));
+    }
+
+    #[tokio::test]
+    async fn the_request_cap_overrides_the_global_one() {
+        let (embeddings, graphs, _) = ingest(IngestionConfig { max_chunks: Some(2), ..Default::default() }, Some(3)).await;
+        assert_eq!((embeddings, graphs), (3, 3));
+
+        let (embeddings, _, messages) = ingest(IngestionConfig::default(), Some(1)).await;
+        assert_eq!(embeddings, 1);
+        assert!(messages.last().unwrap().contains("1 de "));
+    }
+
+    #[tokio::test]
+    async fn ingestion_stops_before_the_chunk_that_exceeds_the_token_budget() {
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let repo = Arc::new(MemoryRepo::new());
+        let probe = IngestionService::new(repo.clone(), ai.clone(), IngestionConfig::default());
+        let chunks = probe.split_text(&long_document());
+        let cost = |chunk: &str| 2 * probe.tokenizer().encode_ordinary(chunk).len() + EXTRACTION_OVERHEAD_TOKENS;
+        let budget = cost(&chunks[0]) + cost(&chunks[1]);
+
+        let config = IngestionConfig { token_budget: Some(budget), ..Default::default() };
+        let service = IngestionService::new(repo.clone(), ai.clone(), config);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10_000);
+        service.ingest_with_progress(long_document(), DocumentInput::default(), None, None, tx).await.unwrap();
+        let mut messages = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            messages.push(message);
+        }
+        assert_eq!((ai.embedding_calls(), repo.state().graphs.len()), (2, 2));
+        assert!(messages.iter().any(|m| m.starts_with(&format!("💰 Presupuesto de {} tokens alcanzado", budget))));
+        assert!(messages.last().unwrap().contains(&format!("2 de {} fragmentos", chunks.len())));
+
+        // El presupuesto de la petición sustituye al global
+        let (tx, _rx) = tokio::sync::mpsc::channel(10_000);
+        service.ingest_with_progress(long_document(), DocumentInput::default(), None, Some(budget + cost(&chunks[2])), tx).await.unwrap();
+        assert_eq!(ai.embedding_calls(), 2 + 3);
+    }
+
+    #[tokio::test]
+    async fn ai_calls_are_spaced_by_the_configured_interval() {
+        let interval = Duration::from_millis(40);
+        let config = IngestionConfig { ai_call_interval: interval, ..Default::default() };
+
+        // 2 fragmentos = 4 llamadas (embedding + extracción): al menos 3 esperas
+        let started = Instant::now();
+        let (embeddings, graphs, _) = ingest(config, Some(2)).await;
+        assert_eq!((embeddings, graphs), (2, 2));
+        assert!(started.elapsed() >= interval * 3);
+    }
+
+    fn metadata(value: serde_json::Value) -> HashMap<String, serde_json::Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn metadata_accepts_scalars_and_lists_of_scalars() {
+        let valid = metadata(serde_json::json!({ "author": "Ana", "year": 2024, "public": true, "tags": ["legal", 3] }));
+        assert!(validate_metadata(&valid).is_ok());
+    }
+
+    #[test]
+    fn metadata_rejects_bad_keys_nested_values_and_oversized_maps() {
+        for invalid in [
+            serde_json::json!({ "bad key": "x" }),
+            serde_json::json!({ "": "x" }),
+            serde_json::json!({ "nested": { "a": 1 } }),
+            serde_json::json!({ "tags": [["a"]] }),
+            serde_json::json!({ "empty": null }),
+            serde_json::json!({ "big": "x".repeat(MAX_METADATA_BYTES) }),
+        ] {
+            assert!(matches!(validate_metadata(&metadata(invalid.clone())), Err(AppError::ValidationError(_))), "{}", invalid);
+        }
+
+        let too_many: HashMap<String, serde_json::Value> = (0..=MAX_METADATA_KEYS)
+            .map(|i| (format!("k{}", i), serde_json::json!(i)))
+            .collect();
+        assert!(validate_metadata(&too_many).is_err());
+    }
+
+    #[tokio::test]
+    async fn chunks_are_grouped_under_the_saved_document() {
+        let repo = Arc::new(MemoryRepo::new());
+        let service = IngestionService::new(repo.clone(), Arc::new(MockAIService::new(mock_config(8))), IngestionConfig::default());
+        let document = DocumentInput { name: "muralla.txt".to_string(), metadata: metadata(serde_json::json!({ "author": "Ana" })), ..Default::default() };
+        let (tx, _rx) = tokio::sync::mpsc::channel(10_000);
+
+        let document_id = service.ingest_with_progress(long_document(), document, Some(2), None, tx).await.unwrap();
+
+        let state = repo.state();
+        assert_eq!(state.documents.len(), 1);
+        assert_eq!(state.documents[0].0, document_id);
+        assert_eq!(state.documents[0].1.metadata["author"], "Ana");
+        assert_eq!(state.chunks.len(), 2);
+        assert!(state.chunks.iter().all(|c| c.document_id == document_id));
+    }
+
+    #[tokio::test]
+    async fn invalid_metadata_stops_the_ingestion_before_any_ai_call() {
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let service = IngestionService::new(repo.clone(), ai.clone(), IngestionConfig::default());
+        let document = DocumentInput { name: "x".to_string(), metadata: metadata(serde_json::json!({ "bad key": 1 })), ..Default::default() };
+        let (tx, _rx) = tokio::sync::mpsc::channel(10_000);
+
+        let result = service.ingest_with_progress(long_document(), document, None, None, tx).await;
+
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+        assert_eq!(ai.embedding_calls(), 0);
+        assert!(repo.state().documents.is_empty());
+    }
+
+    #[test]
+    fn token_windows_respect_the_limit_and_overlap() {
+        let bpe = resolve_tokenizer("cl100k_base");
+        let text = long_document();
+
+        let chunks = split_text_by_tokens(&text, bpe, 100, 20);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| bpe.encode_ordinary(c).len() <= 100));
+        // Cada ventana empieza 80 tokens después de la anterior: solapan 20
+        let first = bpe.encode_ordinary(&chunks[0]);
+        let second = bpe.encode_ordinary(&chunks[1]);
+        assert_eq!(first[80..], second[..20]);
+    }
+
+    #[test]
+    fn token_windows_never_split_a_multibyte_character() {
+        let bpe = resolve_tokenizer("cl100k_base");
+        let text = "長城は中国の古代の防御施設です。".repeat(20);
+
+        let chunks = split_text_by_tokens(&text, bpe, 7, 2);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| !c.is_empty() && text.contains(c.as_str())));
+    }
+
+    #[test]
+    fn the_tokenizer_follows_the_model_name() {
+        let ptr = |bpe: &CoreBPE| bpe as *const CoreBPE;
+        assert_eq!(ptr(resolve_tokenizer("gpt-4o")), ptr(tiktoken_rs::o200k_base_singleton()));
+        assert_eq!(ptr(resolve_tokenizer("text-embedding-3-small")), ptr(tiktoken_rs::cl100k_base_singleton()));
+        assert_eq!(ptr(resolve_tokenizer("nomic-embed-text")), ptr(tiktoken_rs::cl100k_base_singleton()));
+    }
+
+    #[tokio::test]
+    async fn token_mode_drives_the_ingestion_chunks() {
+        let chunking = ChunkingMode::Tokens { max_tokens: 500, overlap_tokens: 0, tokenizer: None };
+        let (embeddings, graphs, _) = ingest(IngestionConfig { chunking, ..Default::default() }, None).await;
+
+        // Una ventana por cada 500 tokens del documento (sin solape)
+        let tokens = tiktoken_rs::cl100k_base_singleton().encode_ordinary(&long_document()).len();
+        assert_eq!(embeddings, tokens.div_ceil(500));
+        assert_eq!(graphs, embeddings);
+    }
+
+    #[tokio::test]
+    async fn provenance_is_recorded_only_when_enabled() {
+        let run = |record_provenance: bool| async move {
+            let repo = Arc::new(MemoryRepo::new());
+            let ai = Arc::new(MockAIService::new(mock_config(8)).with_seed(7));
+            let config = IngestionConfig { record_provenance, ..Default::default() };
+            let (tx, _rx) = tokio::sync::mpsc::channel(10_000);
+            IngestionService::new(repo.clone(), ai, config)
+                .ingest_with_progress(long_document(), DocumentInput::default(), Some(2), None, tx).await.unwrap();
+            repo
+        };
+
+        assert!(run(false).await.state().provenance.is_empty());
+
+        let recorded = run(true).await;
+        let state = recorded.state();
+        assert_eq!(state.provenance.len(), 2);
+        assert!(state.chunks.iter().all(|c| {
+            let provenance = &state.provenance[&c.id];
+            provenance.model == "mock-llm" && provenance.prompt_version == "mock-7"
+        }));
+    }
+
+    const TEXT: &str = "Los romanos levantaron en el siglo III la Muralla de Lugo, que todavía rodea el casco antiguo de la ciudad.";
+    const ENTITY: &str = "Muralla de Lugo";
+
+    #[test]
+    fn overlap_keeps_an_entity_straddling_a_boundary_whole() {
+        // Sin solape el corte cae dentro del nombre: ningún chunk lo contiene entero
+        let plain = split_text_by_chars(TEXT, 50, 0);
+        assert!(plain.iter().all(|chunk| !chunk.contains(ENTITY)), "{:?}", plain);
+
+        let overlapping = split_text_by_chars(TEXT, 50, 20);
+        let mentions: Vec<usize> = overlapping.iter().enumerate()
+            .filter(|(_, chunk)| chunk.contains("Muralla"))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(mentions, vec![0, 1], "{:?}", overlapping);
+        assert!(overlapping[1].contains(ENTITY));
+    }
+
+    #[test]
+    fn chunks_start_and_end_on_word_boundaries() {
+        for overlap in [0, 7, 20, 200] {
+            for chunk in split_text_by_chars(TEXT, 50, overlap) {
+                assert!(chunk.chars().count() <= 50);
+                let start = TEXT.find(&chunk).unwrap();
+                let before = TEXT[..start].chars().next_back();
+                let after = TEXT[start + chunk.len()..].chars().next();
+                assert!(before.is_none_or(char::is_whitespace), "{:?} starts mid-word", chunk);
+                assert!(after.is_none_or(char::is_whitespace), "{:?} ends mid-word", chunk);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn the_plan_counts_chunks_tokens_and_calls_without_side_effects() {
+        let document: String = (0..60).map(|i| format!("palabra{} ", i)).collect();
+        let chunks = split_text_by_chars(&document, 100, 0);
+        assert!(chunks.len() > 3);
+        let imports = EmbeddingImports::default();
+        imports.write().await.insert(EmbeddingRecord::hash_content(&chunks[0]), vec![0.1; 8]);
+
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let config = IngestionConfig {
+            chunking: ChunkingMode::Characters { chunk_size: 100, overlap: 0 },
+            max_chunks: Some(3),
+            ..Default::default()
+        };
+        let service = IngestionService::new(repo.clone(), ai.clone(), config).with_embedding_imports(imports);
+
+        let plan = service.plan(&document, "palabras.txt".to_string(), None).await;
+        assert_eq!(plan.char_count, document.chars().count());
+        assert_eq!((plan.chunk_count, plan.chunks.len(), plan.processed_chunks), (chunks.len(), chunks.len(), 3));
+        assert_eq!(plan.chunks[1].chars, chunks[1].chars().count());
+        let processed_tokens: usize = plan.chunks.iter().take(3).map(|c| c.estimated_tokens).sum();
+        assert_eq!(plan.estimated_tokens, processed_tokens);
+        // El primer fragmento reutiliza su embedding importado
+        assert_eq!((plan.embedding_calls, plan.extraction_calls), (2, 3));
+
+        // El límite de la petición manda sobre el global
+        assert_eq!(service.plan(&document, "palabras.txt".to_string(), Some(1)).await.processed_chunks, 1);
+
+        assert_eq!((ai.embedding_calls(), ai.completion_calls()), (0, 0));
+        let state = repo.state();
+        assert!(state.documents.is_empty() && state.chunks.is_empty() && state.graphs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_stored_text_redactor_keeps_personal_data_out_of_the_chunks() {
+        let repo = Arc::new(MemoryRepo::new());
+        let redactor = Arc::new(Redactor::new(&HashMap::new()).unwrap());
+        let config = IngestionConfig { stored_text_redactor: Some(redactor), ..Default::default() };
+        let service = IngestionService::new(repo.clone(), Arc::new(MockAIService::new(mock_config(8))), config);
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+
+        service.ingest_with_progress("Contacto: ana@example.com".to_string(), DocumentInput::default(), None, None, tx).await.unwrap();
+
+        assert_eq!(repo.state().chunks[0].content, "Contacto: [EMAIL]");
+    }
+
+    #[tokio::test]
+    async fn each_chunk_gets_one_embedding_per_slot_with_the_slot_dimension() {
+        let repo = Arc::new(MemoryRepo::new());
+        let config = IngestionConfig {
+            max_chunks: Some(2),
+            embedding_slots: vec!["fast:small-embedder:4".parse().unwrap(), "quality:large-embedder:16".parse().unwrap()],
+            ..Default::default()
+        };
+        let service = IngestionService::new(repo.clone(), Arc::new(MockAIService::new(mock_config(8))), config);
+        let (tx, _rx) = tokio::sync::mpsc::channel(10_000);
+        service.ingest_with_progress(long_document(), DocumentInput::default(), None, None, tx).await.unwrap();
+
+        let state = repo.state();
+        assert_eq!(state.slot_embeddings.len(), 4);
+        for chunk in &state.chunks {
+            assert_eq!(chunk.embedding.len(), 8);
+            assert_eq!(state.slot_embeddings[&(chunk.id, "fast".to_string())].len(), 4);
+            assert_eq!(state.slot_embeddings[&(chunk.id, "quality".to_string())].len(), 16);
+        }
+    }
+}
@@ -1,3 +1,14 @@
 pub mod dtos;
 pub mod ingestion;
-pub mod reasoning; // <-- NUEVO
\ No newline at end of file
+pub mod reasoning; // <-- NUEVO
+pub mod entity_resolution;
+pub mod retrieval;
+pub mod graph_cache;
+pub mod graph_export;
+pub mod centrality;
+pub mod reindex;
+pub mod citations;
+pub mod consolidation;
+pub mod timeline;
+pub mod rebuild;
+pub mod redaction;
\ No newline at end of file
@@ -1,22 +1,122 @@
+use std::future::Future;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::Notify;
 use crate::domain::{
     ports::{KGRepository, AIService},
-    models::InferredRelation,
+    models::{ConfidenceLevel, InferredRelation, ReasoningExplanation},
     errors::AppError
 };
 
+/// Ajustes del razonamiento (se leen del entorno en main.rs).
+#[derive(Debug, Clone, Default)]
+pub struct ReasoningConfig {
+    /// Máximo de relaciones inferidas que se guardan por ejecución (None = sin límite)
+    pub max_relations: Option<usize>,
+    /// Antigüedad máxima de las relaciones inferidas antes de borrarlas (None = no caducan)
+    pub inferred_max_age: Option<Duration>,
+    /// Cada cuánto se ejecuta el barrido automático de caducadas (None = solo bajo petición)
+    pub expiry_sweep_interval: Option<Duration>,
+    /// Tiempo máximo para obtener las inferencias (contexto + LLM); None = sin límite
+    pub timeout: Option<Duration>,
+}
+
+/// Formato antiguo "(Confianza: Alta) Explicación...": separa el nivel de la explicación.
+fn split_legacy_reasoning(relation: &mut InferredRelation) {
+    let Some(rest) = relation.reasoning.trim_start().strip_prefix("(Confianza:") else { return };
+    let Some((label, explanation)) = rest.split_once(')') else { return };
+    let level = match label.trim().to_lowercase().as_str() {
+        "alta" => ConfidenceLevel::High,
+        "media" => ConfidenceLevel::Medium,
+        "baja" => ConfidenceLevel::Low,
+        _ => return,
+    };
+    relation.confidence_level.get_or_insert(level);
+    relation.reasoning = explanation.trim().to_string();
+}
+
 pub struct ReasoningService {
     repo: Arc<dyn KGRepository>,
-    ai: Arc<RwLock<dyn AIService>>,
+    ai: Arc<dyn AIService>,
+    config: ReasoningConfig,
+    cancel: Arc<Notify>,
 }
 
 impl ReasoningService {
-    pub fn new(repo: Arc<dyn KGRepository>, ai: Arc<RwLock<dyn AIService>>) -> Self {
-        Self { repo, ai }
+    pub fn new(repo: Arc<dyn KGRepository>, ai: Arc<dyn AIService>, config: ReasoningConfig) -> Self {
+        Self { repo, ai, config, cancel: Arc::new(Notify::new()) }
+    }
+
+    /// `notify_waiters()` sobre `cancel` aborta las ejecuciones en curso.
+    pub fn with_cancellation(mut self, cancel: Arc<Notify>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Aplica el timeout configurado y la cancelación a la fase de consulta.
+    async fn bounded<T>(&self, operation: impl Future<Output = Result<T, AppError>>) -> Result<T, AppError> {
+        let cancelled = self.cancel.notified();
+        let limited = async {
+            match self.config.timeout {
+                Some(limit) => tokio::time::timeout(limit, operation).await
+                    .map_err(|_| AppError::Timeout(format!("reasoning run exceeded {}s", limit.as_secs())))?,
+                None => operation.await,
+            }
+        };
+
+        tokio::select! {
+            result = limited => result,
+            _ = cancelled => Err(AppError::Cancelled("reasoning run cancelled".to_string())),
+        }
     }
 
     pub async fn infer_new_knowledge(&self) -> Result<Vec<InferredRelation>, AppError> {
+        Ok(self.infer_with_explanation().await?.new_relations)
+    }
+
+    /// Igual que `infer_new_knowledge`, pero conserva el contexto y el prompt usados
+    /// (para `?explain=true` y el ajuste de prompts).
+    pub async fn infer_with_explanation(&self) -> Result<ReasoningExplanation, AppError> {
+        // 1-3. Contexto + LLM con límite de tiempo: si se corta aquí, aún no se ha guardado nada
+        let (context, prompt, mut new_relations) = self.bounded(async {
+            let (context, prompt) = self.build_prompt().await?;
+            let relations = self.request_inference(&prompt).await?;
+            Ok((context, prompt, relations))
+        }).await?;
+
+        // Campos tipados; la confianza numérica (para filtrar el grafo) se deduce del nivel si falta
+        for relation in new_relations.iter_mut() {
+            split_legacy_reasoning(relation);
+            if relation.confidence.is_none() {
+                relation.confidence = relation.confidence_level.map(ConfidenceLevel::score);
+            }
+        }
+
+        // 4. Acotar el impacto de una ejecución mala: solo las N más confiables
+        if let Some(cap) = self.config.max_relations {
+            if new_relations.len() > cap {
+                // sort estable: a igual confianza se respeta el orden del modelo
+                new_relations.sort_by_key(|r| std::cmp::Reverse(r.confidence_level));
+                let discarded = new_relations.split_off(cap);
+                tracing::warn!(
+                    "🧹 Razonamiento: se descartan {} relaciones por encima del límite de {}: {:?}",
+                    discarded.len(),
+                    cap,
+                    discarded.iter().map(|r| format!("{} -[{}]-> {}", r.source, r.relation, r.target)).collect::<Vec<_>>()
+                );
+            }
+        }
+        
+        // 5. Guardar en Base de Datos
+        if !new_relations.is_empty() {
+            self.repo.save_inferred_relations(new_relations.clone()).await?;
+        }
+
+        Ok(ReasoningExplanation { new_relations, context, prompt })
+    }
+
+    /// Contexto del grafo y prompt que se enviará al LLM.
+    async fn build_prompt(&self) -> Result<(String, String), AppError> {
         // 1. Obtener contexto más amplio
         let graph_context = self.repo.get_graph_context_for_reasoning(500).await?;
 
@@ -41,7 +141,10 @@ impl ReasoningService {
                         "source": "NombreExactoOrigen", 
                         "target": "NombreExactoDestino", 
                         "relation": "TIPO_RELACION_INFERIDA", 
-                        "reasoning": "(Confianza: Alta/Media) Explicación breve de por qué dedujiste esto." 
+                        "inference_type": "transitivity | causality | same_as",
+                        "confidence_level": "high | medium | low",
+                        "confidence": 0.85,
+                        "reasoning": "Explicación breve de por qué dedujiste esto (sin repetir la confianza)."
                     }}
                 ]
             }}
@@ -54,17 +157,209 @@ impl ReasoningService {
             graph_context
         );
 
+        Ok((graph_context, prompt))
+    }
+
+    /// Consulta al LLM (sin guardar nada).
+    async fn request_inference(&self, prompt: &str) -> Result<Vec<InferredRelation>, AppError> {
         // 3. Consultar IA
-        let ai_guard = self.ai.read().await;
-        
         // Usamos generate_inference que ya maneja la limpieza de JSON
-        let response_json = ai_guard.generate_inference(&prompt).await?;
-        
-        // 4. Guardar en Base de Datos
-        if !response_json.new_relations.is_empty() {
-            self.repo.save_inferred_relations(response_json.new_relations.clone()).await?;
+        Ok(self.ai.generate_inference(prompt).await?.new_relations)
+    }
+
+    /// Borra las relaciones inferidas más antiguas que `max_age` (o la configurada)
+    /// para que la siguiente ejecución las vuelva a deducir sobre el grafo actual.
+    pub async fn expire_inferred(&self, max_age: Option<Duration>) -> Result<usize, AppError> {
+        let max_age = max_age.or(self.config.inferred_max_age)
+            .ok_or_else(|| AppError::ValidationError("No max age given and INFERRED_MAX_AGE_HOURS is not set".to_string()))?;
+
+        let removed = self.repo.delete_inferred_relations_older_than(max_age).await?;
+        if removed > 0 {
+            tracing::info!("🧹 {} relaciones inferidas caducadas (> {}h)", removed, max_age.as_secs() / 3600);
         }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{InferenceResult, InferenceType};
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    fn relation(target: &str, confidence: &str) -> InferredRelation {
+        InferredRelation {
+            source: "Lugo".to_string(),
+            target: target.to_string(),
+            relation: "RELATED_TO".to_string(),
+            reasoning: format!("(Confianza: {}) prueba", confidence),
+            inference_type: None,
+            confidence_level: None,
+            confidence: None,
+        }
+    }
+
+    async fn run(relations: Vec<InferredRelation>, max_relations: Option<usize>) -> (Vec<String>, Vec<String>) {
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = MockAIService::new(mock_config(8)).with_inference(InferenceResult { new_relations: relations });
+        let service = ReasoningService::new(repo.clone(), Arc::new(ai), ReasoningConfig { max_relations, ..Default::default() });
+
+        let returned = service.infer_new_knowledge().await.unwrap();
+        let saved = repo.state().inferred.iter().map(|r| r.target.clone()).collect();
+        (returned.into_iter().map(|r| r.target).collect(), saved)
+    }
+
+    #[tokio::test]
+    async fn the_cap_keeps_the_most_confident_relations_in_model_order() {
+        let relations = vec![
+            relation("Baja", "Baja"),
+            relation("Media1", "Media"),
+            relation("Alta1", "Alta"),
+            relation("SinConfianza", "?"),
+            relation("Alta2", "alta"),
+            relation("Media2", "Media"),
+        ];
+
+        let (returned, saved) = run(relations, Some(3)).await;
+
+        assert_eq!(returned, vec!["Alta1", "Alta2", "Media1"]);
+        assert_eq!(saved, returned);
+    }
+
+    #[tokio::test]
+    async fn without_a_cap_or_under_it_every_relation_is_saved() {
+        let relations = vec![relation("Baja", "Baja"), relation("Alta", "Alta")];
+
+        let (returned, saved) = run(relations.clone(), None).await;
+        assert_eq!(returned, vec!["Baja", "Alta"]);
+        assert_eq!(saved, returned);
+
+        let (returned, _) = run(relations, Some(5)).await;
+        assert_eq!(returned, vec!["Baja", "Alta"]);
+    }
+
+    #[tokio::test]
+    async fn a_missing_confidence_is_derived_from_the_reasoning_label() {
+        let scored = InferredRelation { confidence: Some(0.42), ..relation("Puntuada", "Baja") };
+        let relations = vec![relation("Alta", "Alta"), relation("Baja", "Baja"), relation("SinConfianza", "?"), scored];
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = MockAIService::new(mock_config(8)).with_inference(InferenceResult { new_relations: relations });
+
+        ReasoningService::new(repo.clone(), Arc::new(ai), ReasoningConfig::default())
+            .infer_new_knowledge().await.unwrap();
+
+        let confidences: Vec<Option<f32>> = repo.state().inferred.iter().map(|r| r.confidence).collect();
+        assert_eq!(confidences, [Some(0.9), Some(0.3), None, Some(0.42)]);
+    }
+
+    async fn repo_with_inferred(targets: &[&str]) -> Arc<MemoryRepo> {
+        let repo = Arc::new(MemoryRepo::new());
+        repo.save_inferred_relations(targets.iter().map(|t| relation(t, "Alta")).collect()).await.unwrap();
+        repo
+    }
+
+    #[tokio::test]
+    async fn expire_removes_only_relations_older_than_the_max_age() {
+        let repo = repo_with_inferred(&["Antigua", "Reciente"]).await;
+        // La primera se infirió hace tres horas
+        repo.state().inferred_times[0] -= 3 * 3600 * 1000;
+        let config = ReasoningConfig { inferred_max_age: Some(Duration::from_secs(2 * 3600)), ..Default::default() };
+        let service = ReasoningService::new(repo.clone(), Arc::new(MockAIService::new(mock_config(8))), config);
+
+        assert_eq!(service.expire_inferred(None).await.unwrap(), 1);
+        let remaining: Vec<String> = repo.state().inferred.iter().map(|r| r.target.clone()).collect();
+        assert_eq!(remaining, ["Reciente"]);
+
+        // Una antigüedad explícita manda sobre la configurada
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(service.expire_inferred(Some(Duration::ZERO)).await.unwrap(), 1);
+        assert!(repo.state().inferred.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expire_without_any_max_age_is_a_validation_error() {
+        let repo = repo_with_inferred(&["Antigua"]).await;
+        let service = ReasoningService::new(repo.clone(), Arc::new(MockAIService::new(mock_config(8))), ReasoningConfig::default());
+
+        assert!(matches!(service.expire_inferred(None).await, Err(AppError::ValidationError(_))));
+        assert_eq!(repo.state().inferred.len(), 1);
+    }
+
+    #[test]
+    fn typed_fields_are_read_and_unknown_values_are_ignored() {
+        let result: InferenceResult = serde_json::from_value(serde_json::json!({ "new_relations": [
+            { "source": "A", "target": "C", "relation": "PART_OF", "reasoning": "A está en B y B en C",
+              "inference_type": "transitivity", "confidence_level": "high", "confidence": 0.8 },
+            { "source": "A", "target": "D", "relation": "SAME_AS", "reasoning": "Alias",
+              "inference_type": "analogy", "confidence_level": "Media" }
+        ]})).unwrap();
+
+        let [typed, lenient] = &result.new_relations[..] else { panic!("two relations expected") };
+        assert_eq!(typed.inference_type, Some(InferenceType::Transitivity));
+        assert_eq!(typed.confidence_level, Some(ConfidenceLevel::High));
+        assert_eq!(lenient.inference_type, None);
+        assert_eq!(lenient.confidence_level, Some(ConfidenceLevel::Medium));
+    }
+
+    #[tokio::test]
+    async fn the_legacy_label_becomes_a_level_and_leaves_only_the_explanation() {
+        let typed = InferredRelation { confidence_level: Some(ConfidenceLevel::Low), reasoning: "Sin etiqueta".to_string(), ..relation("Tipada", "?") };
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = MockAIService::new(mock_config(8))
+            .with_inference(InferenceResult { new_relations: vec![relation("Antigua", "Media"), typed] });
+
+        ReasoningService::new(repo.clone(), Arc::new(ai), ReasoningConfig::default())
+            .infer_new_knowledge().await.unwrap();
+
+        let saved = &repo.state().inferred;
+        assert_eq!(saved[0].confidence_level, Some(ConfidenceLevel::Medium));
+        assert_eq!(saved[0].reasoning, "prueba");
+        assert_eq!(saved[0].confidence, Some(0.6));
+        assert_eq!(saved[1].confidence_level, Some(ConfidenceLevel::Low));
+        assert_eq!(saved[1].reasoning, "Sin etiqueta");
+        assert_eq!(saved[1].confidence, Some(0.3));
+    }
+
+    fn slow_service(repo: Arc<MemoryRepo>, timeout: Option<Duration>) -> ReasoningService {
+        let ai = MockAIService::new(mock_config(8))
+            .with_inference(InferenceResult { new_relations: vec![relation("Tarde", "Alta")] })
+            .with_latency(Duration::from_millis(500));
+        ReasoningService::new(repo, Arc::new(ai), ReasoningConfig { timeout, ..Default::default() })
+    }
+
+    #[tokio::test]
+    async fn a_run_over_the_timeout_fails_without_saving() {
+        let repo = Arc::new(MemoryRepo::new());
+        let result = slow_service(repo.clone(), Some(Duration::from_millis(50))).infer_new_knowledge().await;
+
+        assert!(matches!(result, Err(AppError::Timeout(_))), "{:?}", result);
+        assert!(repo.state().inferred.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelling_aborts_the_run_in_progress() {
+        let repo = Arc::new(MemoryRepo::new());
+        let cancel = Arc::new(Notify::new());
+        let service = slow_service(repo.clone(), None).with_cancellation(cancel.clone());
+        let run = tokio::spawn(async move { service.infer_new_knowledge().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        cancel.notify_waiters();
+
+        let result = tokio::time::timeout(Duration::from_millis(200), run).await
+            .expect("the run should stop as soon as it is cancelled")
+            .unwrap();
+        assert!(matches!(result, Err(AppError::Cancelled(_))), "{:?}", result);
+        assert!(repo.state().inferred.is_empty());
+    }
+
+    #[tokio::test]
+    async fn within_the_timeout_the_run_completes() {
+        let repo = Arc::new(MemoryRepo::new());
+        let relations = slow_service(repo.clone(), Some(Duration::from_secs(5))).infer_new_knowledge().await.unwrap();
 
-        Ok(response_json.new_relations)
+        assert_eq!(relations.len(), 1);
+        assert_eq!(repo.state().inferred.len(), 1);
     }
 }
\ No newline at end of file
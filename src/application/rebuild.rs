@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use crate::domain::{
+    ports::KGRepository,
+    models::KnowledgeExtraction,
+    errors::AppError
+};
+
+/// Resultado de una reconstrucción del grafo.
+#[derive(Debug, Default)]
+pub struct RebuildSummary {
+    pub replayed: usize,
+    pub failed: usize,
+    /// Chunks ingeridos sin `STORE_RAW_EXTRACTIONS` (no se pueden reconstruir)
+    pub without_extraction: usize,
+    pub elapsed: Duration,
+}
+
+/// Reconstruye entidades y relaciones a partir de las extracciones guardadas en los chunks,
+/// sin llamar al LLM: separa la extracción (cara) de la construcción del grafo (barata).
+pub struct GraphRebuildService {
+    repo: Arc<dyn KGRepository>,
+}
+
+impl GraphRebuildService {
+    pub fn new(repo: Arc<dyn KGRepository>) -> Self {
+        Self { repo }
+    }
+
+    pub async fn rebuild_with_progress(
+        &self,
+        reset: bool,
+        progress_tx: tokio::sync::mpsc::Sender<String>
+    ) -> Result<RebuildSummary, AppError> {
+        let started = Instant::now();
+        let (stored, without_extraction) = self.repo.list_stored_extractions().await?;
+        let total = stored.len();
+        let _ = progress_tx.send(format!(
+            "📦 {} fragmentos con extracción guardada ({} sin ella).",
+            total, without_extraction
+        )).await;
+
+        if reset {
+            let deleted = self.repo.clear_extracted_graph().await?;
+            let _ = progress_tx.send(format!("🧹 {} entidades y sucesos borrados.", deleted)).await;
+        }
+
+        let mut summary = RebuildSummary { without_extraction, ..Default::default() };
+        for (index, (chunk_id, json)) in stored.into_iter().enumerate() {
+            let current_step = index + 1;
+            let parsed = Uuid::parse_str(&chunk_id)
+                .map_err(|e| e.to_string())
+                .and_then(|id| serde_json::from_str::<KnowledgeExtraction>(&json).map(|data| (id, data)).map_err(|e| e.to_string()));
+            match parsed {
+                Ok((id, extraction)) => {
+                    // Sin procedencia: se conserva la de la extracción original
+                    self.repo.save_graph(id, extraction, None).await?;
+                    summary.replayed += 1;
+                    let _ = progress_tx.send(format!("🕸️ [{}/{}] Grafo reconstruido.", current_step, total)).await;
+                },
+                Err(e) => {
+                    summary.failed += 1;
+                    let _ = progress_tx.send(format!("⚠️ [{}/{}] Extracción ilegible en {}: {}. Saltando...", current_step, total, chunk_id, e)).await;
+                }
+            }
+        }
+        summary.elapsed = started.elapsed();
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::ingestion::{IngestionConfig, IngestionService};
+    use crate::domain::models::DocumentInput;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    const TEXT: &str = "La Muralla de Lugo fue construida por los Romanos. Rodea el casco antiguo de Lugo.";
+
+    async fn ingest(repo: Arc<MemoryRepo>, store_extractions: bool) {
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let config = IngestionConfig { store_extractions, ..Default::default() };
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        IngestionService::new(repo, ai, config)
+            .ingest_with_progress(TEXT.to_string(), DocumentInput::default(), Some(1), None, tx).await.unwrap();
+    }
+
+    fn entity_names(repo: &MemoryRepo) -> Vec<String> {
+        let mut names: Vec<String> = repo.state().graphs.iter()
+            .flat_map(|(_, g)| g.entities.iter().map(|e| e.name.clone()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    async fn rebuild(repo: Arc<MemoryRepo>, reset: bool) -> (RebuildSummary, Vec<String>) {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let summary = GraphRebuildService::new(repo).rebuild_with_progress(reset, tx).await.unwrap();
+        let mut messages = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            messages.push(message);
+        }
+        (summary, messages)
+    }
+
+    #[tokio::test]
+    async fn the_graph_is_rebuilt_from_the_stored_extractions() {
+        let repo = Arc::new(MemoryRepo::new());
+        ingest(repo.clone(), true).await;
+        let original = entity_names(&repo);
+        assert!(!original.is_empty());
+        assert_eq!(repo.state().extractions.len(), 1);
+
+        let (summary, messages) = rebuild(repo.clone(), true).await;
+
+        assert_eq!((summary.replayed, summary.failed, summary.without_extraction), (1, 0, 0));
+        assert_eq!(entity_names(&repo), original);
+        assert_eq!(repo.state().graphs.len(), 1);
+        assert!(messages.iter().any(|m| m.starts_with("🧹")));
+    }
+
+    #[tokio::test]
+    async fn chunks_without_a_stored_extraction_are_counted_and_unreadable_ones_skipped() {
+        let repo = Arc::new(MemoryRepo::new());
+        ingest(repo.clone(), false).await;
+        assert!(repo.state().extractions.is_empty());
+        ingest(repo.clone(), true).await;
+        let broken = repo.state().chunks[1].id;
+        repo.save_chunk_extraction(broken, "{no es json").await.unwrap();
+
+        let (summary, messages) = rebuild(repo.clone(), true).await;
+
+        assert_eq!((summary.replayed, summary.failed, summary.without_extraction), (0, 1, 1));
+        assert!(messages.iter().any(|m| m.starts_with("⚠️") && m.contains(&broken.to_string())));
+        // Con `reset` el chunk sin extracción guardada pierde sus entidades
+        assert!(entity_names(&repo).is_empty());
+    }
+}
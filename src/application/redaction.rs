@@ -0,0 +1,155 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use regex::Regex;
+use crate::domain::{
+    ports::AIService,
+    models::{AIConfig, ChatMessage, EmbeddingSlot, InferenceResult, KnowledgeExtraction},
+    errors::AppError
+};
+
+/// Patrones por defecto (etiqueta, regex). El orden importa: las tarjetas antes que los teléfonos.
+const DEFAULT_PATTERNS: &[(&str, &str)] = &[
+    ("EMAIL", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+    ("IBAN", r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){3,7}\b"),
+    ("CARD", r"\b(?:\d{4}[ -]?){3}\d{4}\b"),
+    ("ID", r"\b[XYZ]?\d{7,8}-?[A-Z]\b"),
+    ("PHONE", r"(?:\+\d{1,3}[\s.-]?)?\b(?:\d{3}[\s.-]?\d{3}[\s.-]?\d{3,4}|\d{2}[\s.-]\d{3}[\s.-]\d{2}[\s.-]\d{2})\b"),
+];
+
+/// Sustituye datos personales (emails, teléfonos, documentos de identidad...) por `[ETIQUETA]`.
+#[derive(Debug)]
+pub struct Redactor {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Redactor {
+    /// Patrones por defecto más los `extra` (etiqueta -> regex).
+    pub fn new(extra: &HashMap<String, String>) -> Result<Self, AppError> {
+        let mut patterns = Vec::with_capacity(DEFAULT_PATTERNS.len() + extra.len());
+        for (label, pattern) in DEFAULT_PATTERNS {
+            patterns.push((label.to_string(), Regex::new(pattern).map_err(|e| AppError::ConfigError(e.to_string()))?));
+        }
+        for (label, pattern) in extra {
+            let regex = Regex::new(pattern)
+                .map_err(|e| AppError::ConfigError(format!("Invalid redaction pattern '{}': {}", label, e)))?;
+            patterns.push((label.to_uppercase(), regex));
+        }
+        Ok(Self { patterns })
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut result = Cow::Borrowed(text);
+        for (label, regex) in &self.patterns {
+            if let Cow::Owned(replaced) = regex.replace_all(&result, format!("[{}]", label).as_str()) {
+                result = Cow::Owned(replaced);
+            }
+        }
+        result
+    }
+}
+
+/// Decorador de `AIService`: todo texto que sale hacia el proveedor pasa antes por el `Redactor`,
+/// así los datos personales nunca salen del despliegue (embeddings, extracción, chat...).
+pub struct RedactingAIService {
+    inner: Arc<dyn AIService>,
+    redactor: Arc<Redactor>,
+}
+
+impl RedactingAIService {
+    pub fn new(inner: Arc<dyn AIService>, redactor: Arc<Redactor>) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+#[async_trait]
+impl AIService for RedactingAIService {
+    async fn extract_knowledge_raw(&self, text: &str) -> Result<(KnowledgeExtraction, String), AppError> {
+        self.inner.extract_knowledge_raw(&self.redactor.redact(text)).await
+    }
+
+    async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>, AppError> {
+        self.inner.generate_embedding(&self.redactor.redact(text)).await
+    }
+
+    async fn generate_slot_embedding(&self, slot: &EmbeddingSlot, text: &str) -> Result<Vec<f32>, AppError> {
+        self.inner.generate_slot_embedding(slot, &self.redactor.redact(text)).await
+    }
+
+    fn update_config(&self, config: AIConfig) -> Result<(), AppError> {
+        self.inner.update_config(config)
+    }
+
+    fn get_config(&self) -> AIConfig {
+        self.inner.get_config()
+    }
+
+    fn extraction_prompt_version(&self) -> String {
+        format!("{}-redacted", self.inner.extraction_prompt_version())
+    }
+
+    async fn check_connectivity(&self) -> Result<(), AppError> {
+        self.inner.check_connectivity().await
+    }
+
+    async fn generate_inference(&self, prompt: &str) -> Result<InferenceResult, AppError> {
+        self.inner.generate_inference(&self.redactor.redact(prompt)).await
+    }
+
+    async fn generate_answer(&self, system_prompt: &str, history: &[ChatMessage], message: &str) -> Result<String, AppError> {
+        // El contexto RAG puede contener texto original guardado sin redactar
+        let history: Vec<ChatMessage> = history.iter()
+            .map(|m| ChatMessage { role: m.role, content: self.redactor.redact(&m.content).into_owned() })
+            .collect();
+        self.inner.generate_answer(&self.redactor.redact(system_prompt), &history, &self.redactor.redact(message)).await
+    }
+
+    async fn decompose_query(&self, query: &str, max: usize) -> Result<Vec<String>, AppError> {
+        self.inner.decompose_query(&self.redactor.redact(query), max).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService, DEFAULT_MOCK_SEED};
+
+    fn redactor() -> Redactor {
+        Redactor::new(&HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn default_patterns_cover_emails_phones_ids_and_cards() {
+        let redacted = redactor().redact("Ana (ana.lopez@example.com, +34 612 345 678, DNI 12345678Z) pagó con 4111 1111 1111 1111");
+        assert_eq!(redacted, "Ana ([EMAIL], [PHONE], DNI [ID]) pagó con [CARD]");
+    }
+
+    #[test]
+    fn text_without_personal_data_is_borrowed_untouched() {
+        let text = "La muralla de Lugo tiene 71 torres";
+        assert!(matches!(redactor().redact(text), Cow::Borrowed(t) if t == text));
+    }
+
+    #[test]
+    fn extra_patterns_are_labelled_in_uppercase_and_invalid_ones_rejected() {
+        let extra = HashMap::from([("expediente".to_string(), r"EXP-\d{4}".to_string())]);
+        assert_eq!(Redactor::new(&extra).unwrap().redact("Ver EXP-2024"), "Ver [EXPEDIENTE]");
+
+        let invalid = HashMap::from([("roto".to_string(), "(".to_string())]);
+        assert!(matches!(Redactor::new(&invalid), Err(AppError::ConfigError(msg)) if msg.contains("roto")));
+    }
+
+    #[tokio::test]
+    async fn the_provider_only_sees_redacted_text() {
+        let inner = Arc::new(MockAIService::new(mock_config(8)).with_answer("Escribe a [EMAIL]", "Hecho"));
+        let service = RedactingAIService::new(inner, Arc::new(redactor()));
+
+        assert_eq!(service.generate_answer("", &[], "Escribe a ana@example.com").await.unwrap(), "Hecho");
+        assert_eq!(
+            service.generate_embedding("ana@example.com").await.unwrap(),
+            service.generate_embedding("[EMAIL]").await.unwrap(),
+        );
+        assert_eq!(service.extraction_prompt_version(), format!("mock-{}-redacted", DEFAULT_MOCK_SEED));
+    }
+}
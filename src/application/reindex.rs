@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::domain::{
+    ports::{KGRepository, AIService},
+    errors::AppError
+};
+
+/// Resultado de una re-vectorización.
+#[derive(Debug, Default)]
+pub struct ReembedSummary {
+    pub embedded: usize,
+    pub failed: usize,
+    pub elapsed: Duration,
+}
+
+/// Ritmo observado (fragmentos/s) y tiempo restante estimado tras procesar `processed` de `total`.
+/// Sin datos todavía (`processed == 0`) no hay estimación.
+pub fn throughput(processed: usize, total: usize, elapsed: Duration) -> (f64, Option<Duration>) {
+    let secs = elapsed.as_secs_f64();
+    if processed == 0 || secs <= 0.0 {
+        return (0.0, None);
+    }
+    let rate = processed as f64 / secs;
+    let remaining = total.saturating_sub(processed) as f64 / rate;
+    (rate, Some(Duration::from_secs_f64(remaining)))
+}
+
+/// Duración legible: `1h 02m`, `3m 07s`, `12s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, (secs % 3600) / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+/// Sufijo de progreso: `[12/340] 3.2 frag/s · ETA 1m 42s`.
+fn progress_label(processed: usize, total: usize, started: Instant) -> String {
+    let (rate, eta) = throughput(processed, total, started.elapsed());
+    let eta = eta.map(format_duration).unwrap_or_else(|| "--".to_string());
+    format!("[{}/{}] {:.1} frag/s · ETA {}", processed, total, rate, eta)
+}
+
+/// Re-vectoriza los chunks del grafo con el modelo de embeddings actual.
+/// Por defecto es incremental: solo los chunks sin embedding o con dimensión distinta.
+pub struct ReindexService {
+    repo: Arc<dyn KGRepository>,
+    ai: Arc<dyn AIService>,
+}
+
+impl ReindexService {
+    pub fn new(repo: Arc<dyn KGRepository>, ai: Arc<dyn AIService>) -> Self {
+        Self { repo, ai }
+    }
+
+    pub async fn reembed_with_progress(
+        &self,
+        force: bool,
+        progress_tx: tokio::sync::mpsc::Sender<String>
+    ) -> Result<ReembedSummary, AppError> {
+        let config = self.ai.get_config();
+        let dim = config.embedding_dim;
+
+        // 1. Chunks pendientes (todos si force)
+        let pending = self.repo.list_chunks_needing_embedding(dim, force).await?;
+        let total = pending.len();
+        let _ = progress_tx.send(if force {
+            format!("🔁 Re-vectorizando los {} fragmentos (force).", total)
+        } else {
+            format!("🔎 {} fragmentos sin embedding válido de {} dimensiones.", total, dim)
+        }).await;
+
+        // 2. Vectorizar y guardar cada uno (cada mensaje lleva ritmo y ETA según lo observado)
+        let started = Instant::now();
+        let mut summary = ReembedSummary::default();
+        for (index, (chunk_id, content)) in pending.into_iter().enumerate() {
+            let result = self.ai.generate_embedding(&content).await;
+            let progress = progress_label(index + 1, total, started);
+            match result {
+                Ok(embedding) if embedding.len() == dim => {
+                    self.repo.update_chunk_embedding(&chunk_id, embedding).await?;
+                    summary.embedded += 1;
+                    let _ = progress_tx.send(format!("🧠 {} Embedding actualizado.", progress)).await;
+                },
+                Ok(embedding) => {
+                    summary.failed += 1;
+                    let _ = progress_tx.send(format!(
+                        "⚠️ {} El modelo devolvió {} dimensiones (esperadas {}). Saltando...",
+                        progress, embedding.len(), dim
+                    )).await;
+                },
+                Err(e) => {
+                    summary.failed += 1;
+                    let _ = progress_tx.send(format!("⚠️ {} Error embedding: {}. Saltando...", progress, e)).await;
+                }
+            }
+        }
+        summary.elapsed = started.elapsed();
+
+        // 3. Asegurar índices
+        self.repo.create_indexes(dim, config.similarity_function).await?;
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    use crate::domain::models::VectorSimilarity;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    /// Un chunk válido (8 dimensiones), uno con otra dimensión y uno sin embedding.
+    async fn repo() -> Arc<MemoryRepo> {
+        let repo = Arc::new(MemoryRepo::new());
+        let document = Uuid::new_v4();
+        for embedding in [vec![0.5; 8], vec![0.5; 4], Vec::new()] {
+            repo.save_chunk(document, Uuid::new_v4(), "La Muralla de Lugo", embedding).await.unwrap();
+        }
+        repo
+    }
+
+    async fn reembed(repo: Arc<MemoryRepo>, force: bool) -> (ReembedSummary, usize) {
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let (tx, _rx) = tokio::sync::mpsc::channel(64);
+        let summary = ReindexService::new(repo, ai.clone()).reembed_with_progress(force, tx).await.unwrap();
+        (summary, ai.embedding_calls())
+    }
+
+    #[tokio::test]
+    async fn only_chunks_without_a_valid_embedding_are_reembedded() {
+        let repo = repo().await;
+        let (summary, calls) = reembed(repo.clone(), false).await;
+
+        assert_eq!((summary.embedded, summary.failed, calls), (2, 0, 2));
+        let state = repo.state();
+        assert!(state.chunks.iter().all(|c| c.embedding.len() == 8));
+        assert_eq!(state.chunks[0].embedding, vec![0.5; 8]);
+        assert_eq!(state.indexes, vec![(8, VectorSimilarity::Cosine)]);
+    }
+
+    #[tokio::test]
+    async fn force_reembeds_every_chunk() {
+        let repo = repo().await;
+        let (summary, calls) = reembed(repo.clone(), true).await;
+
+        assert_eq!((summary.embedded, calls), (3, 3));
+        assert_ne!(repo.state().chunks[0].embedding, vec![0.5; 8]);
+
+        // Sin pendientes, la siguiente pasada incremental no llama al proveedor
+        let (summary, calls) = reembed(repo, false).await;
+        assert_eq!((summary.embedded, calls), (0, 0));
+    }
+
+    #[test]
+    fn throughput_estimates_the_remaining_time_from_the_observed_rate() {
+        let (rate, eta) = throughput(10, 40, Duration::from_secs(5));
+        assert!((rate - 2.0).abs() < 1e-9);
+        assert_eq!(eta, Some(Duration::from_secs(15)));
+
+        assert_eq!(throughput(0, 40, Duration::from_secs(5)), (0.0, None));
+        assert_eq!(throughput(40, 40, Duration::from_secs(5)).1, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn durations_are_shown_with_two_units_at_most() {
+        assert_eq!(format_duration(Duration::from_secs(12)), "12s");
+        assert_eq!(format_duration(Duration::from_secs(187)), "3m 07s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h 02m");
+    }
+
+    #[tokio::test]
+    async fn every_progress_message_carries_the_rate_and_eta() {
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+        ReindexService::new(repo().await, ai).reembed_with_progress(false, tx).await.unwrap();
+
+        let mut progress = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            if message.starts_with("🧠") {
+                progress.push(message);
+            }
+        }
+        assert_eq!(progress.len(), 2);
+        assert!(progress[0].starts_with("🧠 [1/2] "));
+        assert!(progress.iter().all(|m| m.contains(" frag/s · ETA ")));
+    }
+}
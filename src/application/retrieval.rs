@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use crate::domain::{
+    ports::{KGRepository, AIService},
+    models::{EmbeddingSlot, HybridContext, RetrievalStrategy},
+    errors::AppError
+};
+
+/// Constante estándar de Reciprocal Rank Fusion (amortigua el peso de las primeras posiciones)
+const RRF_K: f64 = 60.0;
+
+/// Orden en que los fragmentos recuperados se colocan en el prompt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContextOrder {
+    /// De mayor a menor relevancia (tal como llegan de la recuperación)
+    #[default]
+    ScoreDesc,
+    /// Los más relevantes en los extremos y los peores en el centro ("lost in the middle")
+    EdgesFirst,
+}
+
+impl std::str::FromStr for ContextOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "score" | "score_desc" => Ok(Self::ScoreDesc),
+            "edges" | "edges_first" | "lost_in_the_middle" => Ok(Self::EdgesFirst),
+            other => Err(format!("Unknown context order: {}", other)),
+        }
+    }
+}
+
+impl ContextOrder {
+    /// Posiciones (índices en orden de relevancia) en el orden en que deben aparecer en el prompt.
+    /// `EdgesFirst` alterna principio y final: 1º, 3º, 5º ... 4º, 2º.
+    pub fn arrange(self, len: usize) -> Vec<usize> {
+        match self {
+            Self::ScoreDesc => (0..len).collect(),
+            Self::EdgesFirst => {
+                let front = (0..len).step_by(2);
+                let back = (1..len).step_by(2).rev();
+                front.chain(back).collect()
+            },
+        }
+    }
+}
+
+/// Parámetros de una recuperación de contexto.
+#[derive(Debug, Clone, Copy)]
+pub struct RetrievalOptions<'a> {
+    pub strategy: RetrievalStrategy,
+    /// Fragmentos a devolver
+    pub limit: usize,
+    /// Peso de la centralidad al reordenar (0 = solo similitud)
+    pub centrality_boost: f64,
+    /// Saltos en el grafo para las entidades de cada fragmento vectorial (1 = solo las mencionadas)
+    pub hops: usize,
+    /// Similitud vectorial mínima (None = sin filtro). Se aplica antes de fusionar con RRF,
+    /// cuyas puntuaciones ya no son comparables con la similitud.
+    pub min_score: Option<f64>,
+    /// Slot de embeddings con el que vectorizar y buscar (None = embedding principal)
+    pub embedding_slot: Option<&'a EmbeddingSlot>,
+}
+
+/// Embedding de la consulta con el modelo del slot elegido (o el principal).
+async fn embed_query(ai: &dyn AIService, query: &str, slot: Option<&EmbeddingSlot>) -> Result<Vec<f32>, AppError> {
+    match slot {
+        Some(slot) => ai.generate_slot_embedding(slot, query).await,
+        None => ai.generate_embedding(query).await,
+    }
+}
+
+/// Recupera el contexto para una consulta según la estrategia elegida.
+/// Con `centrality_boost > 0` se reordena además por la centralidad de las entidades conectadas.
+pub async fn retrieve_context(
+    repo: &dyn KGRepository,
+    ai: &dyn AIService,
+    query: &str,
+    options: RetrievalOptions<'_>,
+) -> Result<Vec<HybridContext>, AppError> {
+    let RetrievalOptions { strategy, limit, centrality_boost, hops, min_score, embedding_slot } = options;
+    let contexts = match strategy {
+        RetrievalStrategy::Vector => {
+            let embedding = embed_query(ai, query, embedding_slot).await?;
+            repo.find_hybrid_context(embedding, limit, hops, min_score, embedding_slot).await?
+        },
+        RetrievalStrategy::Keyword => repo.find_keyword_context(query, limit).await?,
+        RetrievalStrategy::Hybrid => {
+            let embedding = embed_query(ai, query, embedding_slot).await?;
+            let vector_hits = repo.find_hybrid_context(embedding, limit, hops, min_score, embedding_slot).await?;
+            let keyword_hits = repo.find_keyword_context(query, limit).await?;
+            fuse_rrf(vec![vector_hits, keyword_hits], limit)
+        },
+    };
+
+    Ok(rerank_by_centrality(contexts, centrality_boost))
+}
+
+/// Recuperación multi-consulta: el LLM divide la pregunta en facetas, se recupera para la
+/// original y para cada faceta y se fusiona con RRF ponderado (la original pesa 1.0 y las
+/// facetas se reparten otro 1.0, para que ninguna domine).
+pub async fn retrieve_multi_query(
+    repo: &dyn KGRepository,
+    ai: &dyn AIService,
+    query: &str,
+    options: RetrievalOptions<'_>,
+    max_subqueries: usize,
+) -> Result<Vec<HybridContext>, AppError> {
+    let subqueries: Vec<String> = ai.decompose_query(query, max_subqueries).await?
+        .into_iter()
+        .filter(|q| q.trim() != query.trim())
+        .collect();
+    if subqueries.is_empty() {
+        return retrieve_context(repo, ai, query, options).await;
+    }
+    tracing::info!("🔀 Multi-query: {:?}", subqueries);
+
+    let facet_weight = 1.0 / subqueries.len() as f64;
+    // La centralidad se aplica una sola vez, tras fusionar
+    let per_query = RetrievalOptions { centrality_boost: 0.0, ..options };
+    let searches = std::iter::once((1.0, query))
+        .chain(subqueries.iter().map(|q| (facet_weight, q.as_str())))
+        .map(|(weight, q)| async move {
+            retrieve_context(repo, ai, q, per_query).await.map(|hits| (weight, hits))
+        });
+    let lists = futures::future::try_join_all(searches).await?;
+
+    Ok(rerank_by_centrality(fuse_weighted_rrf(lists, options.limit), options.centrality_boost))
+}
+
+/// Multiplica cada puntuación por `1 + boost * centralidad` y reordena: a igual similitud,
+/// gana el fragmento ligado a conceptos importantes del grafo. `boost <= 0` no cambia nada.
+pub fn rerank_by_centrality(mut contexts: Vec<HybridContext>, boost: f64) -> Vec<HybridContext> {
+    if boost <= 0.0 {
+        return contexts;
+    }
+
+    for ctx in &mut contexts {
+        ctx.score *= 1.0 + boost * ctx.entity_centrality;
+    }
+    contexts.sort_by(|a, b| b.score.total_cmp(&a.score));
+    contexts
+}
+
+/// Fusiona varias listas ordenadas con Reciprocal Rank Fusion.
+/// La puntuación resultante se normaliza a 0.0 - 1.0 (1.0 = primero en todas las listas).
+pub fn fuse_rrf(lists: Vec<Vec<HybridContext>>, limit: usize) -> Vec<HybridContext> {
+    fuse_weighted_rrf(lists.into_iter().map(|list| (1.0, list)).collect(), limit)
+}
+
+/// RRF con un peso por lista (la contribución de cada posición se multiplica por el peso).
+pub fn fuse_weighted_rrf(lists: Vec<(f64, Vec<HybridContext>)>, limit: usize) -> Vec<HybridContext> {
+    let total_weight = lists.iter().map(|(weight, _)| weight).sum::<f64>();
+    let mut fused: HashMap<String, (f64, HybridContext)> = HashMap::new();
+
+    for (weight, list) in lists {
+        for (rank, ctx) in list.into_iter().enumerate() {
+            let contribution = weight / (RRF_K + rank as f64 + 1.0);
+            fused.entry(ctx.chunk_id.clone())
+                .and_modify(|(score, existing)| {
+                    *score += contribution;
+                    existing.entity_centrality = existing.entity_centrality.max(ctx.entity_centrality);
+                    // Unimos las entidades vistas por cada estrategia
+                    for entity in &ctx.connected_entities {
+                        if !existing.connected_entities.contains(entity) {
+                            existing.connected_entities.push(entity.clone());
+                        }
+                    }
+                })
+                .or_insert((contribution, ctx));
+        }
+    }
+
+    let max_score = total_weight.max(f64::EPSILON) / (RRF_K + 1.0);
+    let mut results: Vec<HybridContext> = fused.into_values()
+        .map(|(score, mut ctx)| {
+            ctx.score = score / max_score;
+            ctx
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(limit);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    fn options(strategy: RetrievalStrategy, limit: usize, centrality_boost: f64) -> RetrievalOptions<'static> {
+        RetrievalOptions { strategy, limit, centrality_boost, hops: 1, min_score: None, embedding_slot: None }
+    }
+
+    fn context(chunk_id: &str, entity: &str) -> HybridContext {
+        HybridContext {
+            chunk_id: chunk_id.to_string(),
+            content: format!("contenido de {}", chunk_id),
+            connected_entities: vec![entity.to_string()],
+            score: 0.5,
+            entity_centrality: 0.0,
+        }
+    }
+
+    fn ids(contexts: &[HybridContext]) -> Vec<&str> {
+        contexts.iter().map(|c| c.chunk_id.as_str()).collect()
+    }
+
+    #[test]
+    fn rrf_ranks_chunks_found_by_both_lists_first() {
+        let vector = vec![context("a", "Lugo"), context("b", "Lugo")];
+        let keyword = vec![context("b", "Muralla"), context("c", "Muralla")];
+
+        let fused = fuse_rrf(vec![vector, keyword], 10);
+
+        assert_eq!(ids(&fused), vec!["b", "a", "c"]);
+        assert_eq!(fused[0].connected_entities, vec!["Lugo", "Muralla"]);
+        assert!(fused.iter().all(|c| c.score > 0.0 && c.score <= 1.0));
+    }
+
+    #[test]
+    fn rrf_scores_one_for_the_top_of_every_list_and_honours_the_limit() {
+        let fused = fuse_rrf(vec![vec![context("a", "Lugo")], vec![context("a", "Lugo"), context("b", "Lugo")]], 1);
+
+        assert_eq!(ids(&fused), vec!["a"]);
+        assert!((fused[0].score - 1.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn vector_hits_come_by_similarity_and_below_min_score_are_dropped() {
+        let scored = |chunk_id: &str, score: f64| HybridContext { score, ..context(chunk_id, "Lugo") };
+        let repo = MemoryRepo::new().with_contexts(vec![scored("a", 0.6), scored("b", 0.9), scored("c", 0.3)]);
+        let ai = MockAIService::new(mock_config(8));
+
+        let all = retrieve_context(&repo, &ai, "muralla", options(RetrievalStrategy::Vector, 5, 0.0)).await.unwrap();
+        assert_eq!(ids(&all), vec!["b", "a", "c"]);
+
+        let strict = RetrievalOptions { min_score: Some(0.5), ..options(RetrievalStrategy::Vector, 5, 0.0) };
+        let strong = retrieve_context(&repo, &ai, "muralla", strict).await.unwrap();
+        assert_eq!(ids(&strong), vec!["b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn each_strategy_queries_only_the_indexes_it_needs() {
+        let repo = MemoryRepo::new()
+            .with_contexts(vec![context("v", "Lugo")])
+            .with_keyword_contexts(vec![context("k", "Lugo")]);
+        let ai = MockAIService::new(mock_config(8));
+
+        let keyword = retrieve_context(&repo, &ai, "muralla", options(RetrievalStrategy::Keyword, 5, 0.0)).await.unwrap();
+        assert_eq!(ids(&keyword), vec!["k"]);
+        assert_eq!(ai.embedding_calls(), 0);
+
+        let vector = retrieve_context(&repo, &ai, "muralla", options(RetrievalStrategy::Vector, 5, 0.0)).await.unwrap();
+        assert_eq!(ids(&vector), vec!["v"]);
+        assert_eq!(ai.embedding_calls(), 1);
+
+        let mut hybrid = ids(&retrieve_context(&repo, &ai, "muralla", options(RetrievalStrategy::Hybrid, 5, 0.0)).await.unwrap())
+            .into_iter().map(String::from).collect::<Vec<_>>();
+        hybrid.sort();
+        assert_eq!(hybrid, vec!["k", "v"]);
+        assert_eq!(ai.embedding_calls(), 2);
+    }
+
+    #[test]
+    fn the_strategy_defaults_to_hybrid_and_reads_lowercase() {
+        let request: crate::domain::models::ChatRequest = serde_json::from_value(serde_json::json!({ "message": "hola" })).unwrap();
+        assert_eq!(request.retrieval, RetrievalStrategy::Hybrid);
+
+        let strategy: RetrievalStrategy = serde_json::from_value(serde_json::json!("keyword")).unwrap();
+        assert_eq!(strategy, RetrievalStrategy::Keyword);
+    }
+
+    fn central(chunk_id: &str, score: f64, entity_centrality: f64) -> HybridContext {
+        HybridContext { score, entity_centrality, ..context(chunk_id, "Lugo") }
+    }
+
+    #[test]
+    fn a_central_entity_lifts_a_slightly_less_similar_chunk() {
+        let contexts = vec![central("periferia", 0.80, 0.0), central("nucleo", 0.75, 0.9)];
+
+        let reranked = rerank_by_centrality(contexts.clone(), 0.5);
+        assert_eq!(ids(&reranked), vec!["nucleo", "periferia"]);
+        assert!((reranked[0].score - 0.75 * 1.45).abs() < 1e-9);
+        assert!((reranked[1].score - 0.80).abs() < 1e-9);
+
+        // Sin boost el orden y las puntuaciones no cambian
+        let untouched = rerank_by_centrality(contexts, 0.0);
+        assert_eq!(ids(&untouched), vec!["periferia", "nucleo"]);
+        assert!((untouched[1].score - 0.75).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn retrieval_applies_the_boost_after_fusion() {
+        let repo = MemoryRepo::new()
+            .with_contexts(vec![central("a", 0.9, 0.0), central("b", 0.8, 1.0)])
+            .with_keyword_contexts(vec![central("a", 3.0, 0.0), central("b", 2.0, 1.0)]);
+        let ai = MockAIService::new(mock_config(8));
+
+        let plain = retrieve_context(&repo, &ai, "muralla", options(RetrievalStrategy::Hybrid, 5, 0.0)).await.unwrap();
+        assert_eq!(ids(&plain), vec!["a", "b"]);
+
+        let boosted = retrieve_context(&repo, &ai, "muralla", options(RetrievalStrategy::Hybrid, 5, 1.0)).await.unwrap();
+        assert_eq!(ids(&boosted), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn the_weight_decides_how_much_each_list_pulls() {
+        let main = || vec![context("a", "Lugo"), context("b", "Lugo")];
+
+        let light_facet = fuse_weighted_rrf(vec![(1.0, main()), (0.0, vec![context("b", "Lugo")])], 10);
+        assert_eq!(ids(&light_facet), vec!["a", "b"]);
+
+        let heavy_facet = fuse_weighted_rrf(vec![(1.0, main()), (0.5, vec![context("b", "Lugo")])], 10);
+        assert_eq!(ids(&heavy_facet), vec!["b", "a"]);
+
+        // Primero en todas las listas sigue valiendo 1.0 con cualquier reparto de pesos
+        let top = fuse_weighted_rrf(vec![(1.0, vec![context("a", "Lugo")]), (0.25, vec![context("a", "Lugo")])], 10);
+        assert!((top[0].score - 1.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn multi_query_searches_the_question_and_each_facet() {
+        let repo = MemoryRepo::new().with_contexts(vec![context("a", "Lugo"), context("b", "Muralla")]);
+        let ai = MockAIService::new(mock_config(8));
+
+        let hits = retrieve_multi_query(&repo, &ai, "la muralla y los romanos", options(RetrievalStrategy::Vector, 5, 0.0), 3)
+            .await.unwrap();
+
+        assert_eq!(ids(&hits), vec!["a", "b"]);
+        assert!((hits[0].score - 1.0).abs() < 1e-9);
+        assert_eq!(ai.completion_calls(), 1);
+        // La pregunta original más las dos facetas
+        assert_eq!(ai.embedding_calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_single_facet_falls_back_to_plain_retrieval() {
+        let repo = MemoryRepo::new().with_contexts(vec![context("a", "Lugo")]);
+        let ai = MockAIService::new(mock_config(8));
+
+        let hits = retrieve_multi_query(&repo, &ai, "muralla", options(RetrievalStrategy::Vector, 5, 0.0), 3).await.unwrap();
+
+        assert_eq!(ids(&hits), vec!["a"]);
+        assert_eq!(ai.embedding_calls(), 1);
+    }
+
+    #[test]
+    fn edges_first_puts_the_best_fragments_at_both_ends() {
+        assert_eq!(ContextOrder::ScoreDesc.arrange(5), vec![0, 1, 2, 3, 4]);
+        assert_eq!(ContextOrder::EdgesFirst.arrange(5), vec![0, 2, 4, 3, 1]);
+        assert_eq!(ContextOrder::EdgesFirst.arrange(4), vec![0, 2, 3, 1]);
+        assert!(ContextOrder::EdgesFirst.arrange(0).is_empty());
+    }
+
+    #[test]
+    fn context_order_parses_its_aliases() {
+        assert_eq!("score".parse::<ContextOrder>(), Ok(ContextOrder::ScoreDesc));
+        assert_eq!(" Edges_First ".parse::<ContextOrder>(), Ok(ContextOrder::EdgesFirst));
+        assert_eq!("lost_in_the_middle".parse::<ContextOrder>(), Ok(ContextOrder::EdgesFirst));
+        assert!("random".parse::<ContextOrder>().is_err());
+    }
+
+    #[tokio::test]
+    async fn extra_hops_bring_the_entities_around_the_mentioned_ones() {
+        use crate::domain::models::{GraphEntity, GraphRelation, KnowledgeExtraction};
+        let repo = MemoryRepo::new().with_contexts(vec![context("a", "Muralla")]);
+        let entities = ["Muralla", "Lugo", "Galicia", "España"].iter()
+            .map(|name| GraphEntity { name: name.to_string(), category: "Place".to_string(), attributes: Default::default() })
+            .collect();
+        let relation = |source: &str, target: &str| GraphRelation { source: source.to_string(), target: target.to_string(), relation_type: "PART_OF".to_string(), confidence: None };
+        let relations = vec![relation("Muralla", "Lugo"), relation("Lugo", "Galicia"), relation("Galicia", "España")];
+        repo.save_graph(uuid::Uuid::new_v4(), KnowledgeExtraction { entities, relations, events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+        let ai = MockAIService::new(mock_config(8));
+        let entities_with = |hops| {
+            let (repo, ai) = (&repo, &ai);
+            async move {
+                let options = RetrievalOptions { hops, ..options(RetrievalStrategy::Vector, 5, 0.0) };
+                retrieve_context(repo, ai, "muralla", options).await.unwrap()[0].connected_entities.clone()
+            }
+        };
+
+        assert_eq!(entities_with(1).await, ["Muralla"]);
+        assert_eq!(entities_with(2).await, ["Muralla", "Lugo"]);
+        assert_eq!(entities_with(3).await, ["Muralla", "Lugo", "Galicia"]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use crate::domain::models::TimelineEvent;
+
+/// (sin fecha, fecha, nombre)
+type SortKey = (bool, String, String);
+
+/// Clave de desempate entre sucesos sin orden `BEFORE` entre sí: fecha (los sin fecha al final) y nombre.
+fn sort_key(event: &TimelineEvent) -> SortKey {
+    (event.date.is_none(), event.date.clone().unwrap_or_default(), event.name.clone())
+}
+
+/// Ordena los sucesos respetando las aristas `(antes, después)` (orden topológico);
+/// entre sucesos no ordenados manda la fecha. Si hay ciclos (extracciones contradictorias)
+/// los sucesos implicados se añaden al final por fecha.
+pub fn order_events(events: Vec<TimelineEvent>, before: &[(String, String)]) -> Vec<TimelineEvent> {
+    let index: HashMap<&str, usize> = events.iter().enumerate().map(|(i, ev)| (ev.name.as_str(), i)).collect();
+
+    let mut pending = vec![0usize; events.len()];
+    let mut next: Vec<Vec<usize>> = vec![Vec::new(); events.len()];
+    for (a, b) in before {
+        if let (Some(&a), Some(&b)) = (index.get(a.as_str()), index.get(b.as_str())) {
+            next[a].push(b);
+            pending[b] += 1;
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<(SortKey, usize)>> = (0..events.len())
+        .filter(|&i| pending[i] == 0)
+        .map(|i| Reverse((sort_key(&events[i]), i)))
+        .collect();
+    let mut order = Vec::with_capacity(events.len());
+    while let Some(Reverse((_, i))) = ready.pop() {
+        order.push(i);
+        for &j in &next[i] {
+            pending[j] -= 1;
+            if pending[j] == 0 {
+                ready.push(Reverse((sort_key(&events[j]), j)));
+            }
+        }
+    }
+
+    if order.len() < events.len() {
+        let mut cyclic: Vec<usize> = (0..events.len()).filter(|&i| pending[i] > 0).collect();
+        tracing::warn!("⚠️ Línea temporal: {} sucesos con orden contradictorio (ciclo BEFORE)", cyclic.len());
+        cyclic.sort_by_key(|&i| sort_key(&events[i]));
+        order.extend(cyclic);
+    }
+
+    let mut slots: Vec<Option<TimelineEvent>> = events.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| slots[i].take()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, date: Option<&str>) -> TimelineEvent {
+        TimelineEvent { name: name.to_string(), date: date.map(str::to_string), participants: Vec::new() }
+    }
+
+    fn names(events: Vec<TimelineEvent>) -> Vec<String> {
+        events.into_iter().map(|e| e.name).collect()
+    }
+
+    fn edge(before: &str, after: &str) -> (String, String) {
+        (before.to_string(), after.to_string())
+    }
+
+    #[test]
+    fn before_edges_win_over_dates() {
+        // La fecha de "Muralla" es anterior, pero el texto dice que la fundación fue antes
+        let events = vec![event("Muralla", Some("0250")), event("Fundación", Some("0300"))];
+        assert_eq!(names(order_events(events, &[edge("Fundación", "Muralla")])), ["Fundación", "Muralla"]);
+    }
+
+    #[test]
+    fn unordered_events_go_by_date_with_undated_last() {
+        let events = vec![event("Sin fecha", None), event("Patrimonio", Some("2000")), event("Fundación", Some("-0025"))];
+        assert_eq!(names(order_events(events, &[])), ["Fundación", "Patrimonio", "Sin fecha"]);
+    }
+
+    #[test]
+    fn a_cycle_is_appended_by_date_instead_of_dropping_events() {
+        let events = vec![event("B", Some("2")), event("A", Some("1")), event("Suelto", Some("0"))];
+        let before = [edge("A", "B"), edge("B", "A"), edge("A", "Desconocido")];
+        assert_eq!(names(order_events(events, &before)), ["Suelto", "A", "B"]);
+    }
+}
@@ -8,6 +8,8 @@ pub enum AppError {
     DatabaseError(String),
     #[error("AI Provider error: {0}")]
     AIError(String),
+    #[error("AI provider temporarily unavailable ({0})")]
+    AIUnavailable(String),
     #[error("Configuration error: {0}")]
     ConfigError(String),
     #[error("Validation error: {0}")]
@@ -16,6 +18,35 @@ pub enum AppError {
     ParseError(String),
     #[error("Admin operation requires force flag")]
     SafetyGuardError,
+    #[error("Server is running in read-only mode")]
+    ReadOnlyMode,
+    #[error("Authentication required")]
+    Unauthorized,
+    #[error("Operation timed out: {0}")]
+    Timeout(String),
+    #[error("Operation cancelled: {0}")]
+    Cancelled(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+}
+
+impl AppError {
+    /// Error pasajero del proveedor que merece reintento: red, timeout, 5xx o rate limit.
+    /// Los errores de parseo, validación o configuración, y el circuito abierto, no lo son.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AppError::Timeout(_) => true,
+            AppError::AIError(message) => {
+                let message = message.to_lowercase();
+                const MARKERS: &[&str] = &[
+                    "429", "rate limit", "too many requests", "timed out", "timeout", "connection",
+                    "500", "502", "503", "504", "529", "overloaded", "temporarily", "unavailable",
+                ];
+                MARKERS.iter().any(|marker| message.contains(marker))
+            },
+            _ => false,
+        }
+    }
 }
 
 impl IntoResponse for AppError {
@@ -23,6 +54,12 @@ impl IntoResponse for AppError {
         let (status, error_message) = match self {
             AppError::ValidationError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::SafetyGuardError => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::ReadOnlyMode => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
+            AppError::AIUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, self.to_string()),
+            AppError::Cancelled(_) => (StatusCode::CONFLICT, self.to_string()),
+            AppError::NotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::ConfigError(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Internal Error: {}", self)),
         };
@@ -1,7 +1,8 @@
 // FILE: src/domain/models.rs
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use secrecy::SecretString;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 // --- CONFIGURACIÓN (Sin cambios significativos) ---
@@ -11,12 +12,217 @@ pub enum AIProvider {
     OpenAI,
     Ollama,
     Groq,
+    /// Claude vía la Messages API (no compatible OpenAI, sin embeddings propios)
+    Anthropic,
+}
+
+impl AIProvider {
+    /// Indica si el proveedor puede forzar una salida que cumpla un JSON Schema
+    /// (`json_schema` en OpenAI, herramienta obligatoria en Anthropic).
+    pub fn supports_structured_output(&self) -> bool {
+        matches!(self, AIProvider::OpenAI | AIProvider::Anthropic)
+    }
+}
+
+/// Cómo se envía la API key al proveedor.
+/// Formato textual: `bearer` (por defecto), `header:<Nombre-Cabecera>` o `query:<parametro>`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <key>`
+    #[default]
+    Bearer,
+    /// Cabecera personalizada, ej. `api-key: <key>`
+    Header(String),
+    /// Parámetro de la URL, ej. `?key=<key>`
+    Query(String),
+}
+
+impl std::str::FromStr for AuthScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("bearer") {
+            return Ok(AuthScheme::Bearer);
+        }
+        let (kind, name) = match s.split_once(':') {
+            Some((kind, name)) if !name.trim().is_empty() => (kind, name.trim()),
+            _ => return Err(format!("Unknown auth scheme '{}' (expected 'bearer', 'header:<name>' or 'query:<name>')", s)),
+        };
+        // Un nombre inválido se rechaza aquí, no al construir la petición
+        if kind.eq_ignore_ascii_case("header") {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid auth header name '{}'", name))?;
+            Ok(AuthScheme::Header(name.to_string()))
+        } else if kind.eq_ignore_ascii_case("query") {
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~')) {
+                return Err(format!("Invalid auth query parameter '{}'", name));
+            }
+            Ok(AuthScheme::Query(name.to_string()))
+        } else {
+            Err(format!("Unknown auth scheme '{}' (expected 'bearer', 'header:<name>' or 'query:<name>')", s))
+        }
+    }
+}
+
+impl TryFrom<String> for AuthScheme {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AuthScheme> for String {
+    fn from(value: AuthScheme) -> Self {
+        match value {
+            AuthScheme::Bearer => "bearer".to_string(),
+            AuthScheme::Header(name) => format!("header:{}", name),
+            AuthScheme::Query(name) => format!("query:{}", name),
+        }
+    }
+}
+
+/// Nivel de detalle de las entidades extraídas.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractionGranularity {
+    /// Pocas entidades amplias (organizaciones, temas principales)
+    Coarse,
+    #[default]
+    Balanced,
+    /// Entidades específicas (subunidades, cargos, detalles)
+    Fine,
+}
+
+impl std::str::FromStr for ExtractionGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "coarse" => Ok(ExtractionGranularity::Coarse),
+            "balanced" => Ok(ExtractionGranularity::Balanced),
+            "fine" => Ok(ExtractionGranularity::Fine),
+            other => Err(format!("Unknown granularity '{}' (expected coarse, balanced or fine)", other)),
+        }
+    }
+}
+
+/// Función de similitud del índice vectorial de chunks.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorSimilarity {
+    #[default]
+    Cosine,
+    /// Algunos modelos locales (no normalizados) funcionan mejor con distancia euclídea
+    Euclidean,
+}
+
+impl VectorSimilarity {
+    /// Valor de `vector.similarity_function` en Neo4j.
+    pub fn as_neo4j(&self) -> &'static str {
+        match self {
+            VectorSimilarity::Cosine => "cosine",
+            VectorSimilarity::Euclidean => "euclidean",
+        }
+    }
+}
+
+impl std::str::FromStr for VectorSimilarity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "cosine" => Ok(VectorSimilarity::Cosine),
+            "euclidean" => Ok(VectorSimilarity::Euclidean),
+            other => Err(format!("Unknown similarity function '{}' (expected cosine or euclidean)", other)),
+        }
+    }
+}
+
+/// Embedding adicional de cada chunk con otro modelo (EMBEDDING_SLOTS), para comparar modelos
+/// o recuperar por niveles. Se guarda en `c.embedding_<name>` con su propio índice vectorial;
+/// el embedding principal (`c.embedding`, índice `chunk_embeddings`) no cambia.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct EmbeddingSlot {
+    /// Nombre del slot (`[a-z0-9_]`), p. ej. `fast`
+    pub name: String,
+    pub model: String,
+    pub dim: usize,
+}
+
+impl EmbeddingSlot {
+    /// Propiedad del chunk con el vector de este slot.
+    pub fn property(&self) -> String {
+        format!("embedding_{}", self.name)
+    }
+
+    /// Índice vectorial de este slot.
+    pub fn index_name(&self) -> String {
+        format!("chunk_embeddings_{}", self.name)
+    }
+}
+
+/// Formato `nombre:modelo:dimensión`; el modelo puede llevar tag (`fast:nomic-embed-text:latest:768`).
+impl std::str::FromStr for EmbeddingSlot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid embedding slot '{}' (expected name:model:dim)", s.trim());
+        let (name, rest) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let (model, dim) = rest.rsplit_once(':').ok_or_else(invalid)?;
+        let name = name.trim().to_lowercase();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid embedding slot name '{}' (use [a-z0-9_])", name));
+        }
+        let dim = dim.trim().parse::<usize>().ok().filter(|d| *d > 0).ok_or_else(invalid)?;
+        if model.trim().is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self { name, model: model.trim().to_string(), dim })
+    }
+}
+
+/// Dimensiones de modelos de embeddings conocidos (nombre sin tag `:latest`).
+const KNOWN_EMBEDDING_DIMS: &[(&str, usize)] = &[
+    ("text-embedding-3-small", 1536),
+    ("text-embedding-3-large", 3072),
+    ("text-embedding-ada-002", 1536),
+    ("nomic-embed-text", 768),
+    ("mxbai-embed-large", 1024),
+    ("all-minilm", 384),
+    ("bge-m3", 1024),
+    ("snowflake-arctic-embed", 1024),
+];
+
+/// Dimensión de un modelo de embeddings conocido; `None` para modelos propios o desconocidos.
+pub fn known_embedding_dim(model: &str) -> Option<usize> {
+    // Ollama admite tags (`nomic-embed-text:latest`): la dimensión no depende del tag
+    let base = model.split(':').next().unwrap_or(model).trim();
+    KNOWN_EMBEDDING_DIMS.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(base))
+        .map(|(_, dim)| *dim)
+}
+
+/// Dimensión de embeddings efectiva: `explicit` (AI_EMBEDDING_DIM) manda; si falta,
+/// se deduce del modelo, y para modelos desconocidos es obligatoria.
+pub fn resolve_embedding_dim(model: &str, explicit: Option<&str>) -> Result<usize, String> {
+    match explicit {
+        Some(v) => v.trim().parse::<usize>().map_err(|_| format!("AI_EMBEDDING_DIM must be a number, got '{}'", v)),
+        None => known_embedding_dim(model)
+            .ok_or_else(|| format!("Unknown embedding model '{}': set AI_EMBEDDING_DIM explicitly", model)),
+    }
 }
 
 fn default_api_key() -> SecretString {
     SecretString::new("".into())
 }
 
+fn default_true() -> bool {
+    true
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate, ToSchema, Clone)]
 pub struct AIConfig {
     pub provider: AIProvider,
@@ -28,10 +234,75 @@ pub struct AIConfig {
     #[serde(skip_serializing, default = "default_api_key")]
     #[schema(value_type = String)] 
     pub api_key: SecretString,
+    /// Esquema de autenticación: `bearer`, `header:<nombre>` o `query:<nombre>`
+    #[serde(default)]
+    #[schema(value_type = String, example = "bearer")]
+    pub auth_scheme: AuthScheme,
     
     pub embedding_dim: usize,
     #[validate(url)]
     pub base_url: Option<String>, 
+    /// Endpoint opcional solo para embeddings (si no, se usa `base_url`)
+    #[validate(url)]
+    #[serde(default)]
+    pub embedding_base_url: Option<String>,
+
+    /// Usa el modo JSON nativo del proveedor en la extracción (si lo soporta).
+    #[serde(default = "default_true")]
+    pub json_mode: bool,
+
+    /// Granularidad de entidades pedida al LLM en la extracción.
+    #[serde(default)]
+    pub granularity: ExtractionGranularity,
+
+    /// Función de similitud con la que se crea el índice vectorial.
+    #[serde(default)]
+    pub similarity_function: VectorSimilarity,
+}
+
+/// Actualización parcial de `AIConfig`: los campos ausentes conservan su valor actual.
+#[derive(Debug, Default, Deserialize, ToSchema, Clone)]
+pub struct AIConfigPatch {
+    pub provider: Option<AIProvider>,
+    pub model_name: Option<String>,
+    pub embedding_model: Option<String>,
+    /// Vacío u omitido: se mantiene la clave actual
+    #[schema(value_type = Option<String>)]
+    pub api_key: Option<SecretString>,
+    #[schema(value_type = Option<String>, example = "bearer")]
+    pub auth_scheme: Option<AuthScheme>,
+    pub embedding_dim: Option<usize>,
+    pub base_url: Option<String>,
+    pub embedding_base_url: Option<String>,
+    pub json_mode: Option<bool>,
+    pub granularity: Option<ExtractionGranularity>,
+    pub similarity_function: Option<VectorSimilarity>,
+}
+
+impl AIConfig {
+    /// Aplica un `AIConfigPatch` sobre esta configuración.
+    pub fn with_patch(mut self, patch: AIConfigPatch) -> Self {
+        use secrecy::ExposeSecret;
+
+        if let Some(provider) = patch.provider { self.provider = provider; }
+        if let Some(model_name) = patch.model_name { self.model_name = model_name; }
+        if let Some(embedding_model) = patch.embedding_model { self.embedding_model = embedding_model; }
+        // Una clave vacía (formulario sin rellenar) nunca sustituye a la actual
+        if let Some(api_key) = patch.api_key.filter(|k| !k.expose_secret().is_empty()) { self.api_key = api_key; }
+        if let Some(auth_scheme) = patch.auth_scheme { self.auth_scheme = auth_scheme; }
+        if let Some(embedding_dim) = patch.embedding_dim { self.embedding_dim = embedding_dim; }
+        if let Some(base_url) = patch.base_url { self.base_url = Some(base_url); }
+        if let Some(embedding_base_url) = patch.embedding_base_url { self.embedding_base_url = Some(embedding_base_url); }
+        if let Some(json_mode) = patch.json_mode { self.json_mode = json_mode; }
+        if let Some(granularity) = patch.granularity { self.granularity = granularity; }
+        if let Some(similarity_function) = patch.similarity_function { self.similarity_function = similarity_function; }
+        self
+    }
+
+    /// Cambios que invalidan los embeddings ya guardados (exigen reiniciar la base de datos).
+    pub fn is_structural_change(&self, other: &AIConfig) -> bool {
+        self.embedding_model != other.embedding_model || self.embedding_dim != other.embedding_dim
+    }
 }
 
 // --- GRAFO BÁSICO (Sin cambios) ---
@@ -40,6 +311,10 @@ pub struct AIConfig {
 pub struct GraphEntity {
     pub name: String,
     pub category: String, 
+    /// Valores tipados presentes en el texto (fechas ISO, importes, medidas...).
+    /// Se guardan como propiedades del nodo.
+    #[serde(default)]
+    pub attributes: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
@@ -47,12 +322,81 @@ pub struct GraphRelation {
     pub source: String,
     pub target: String,
     pub relation_type: String, 
+    /// Confianza del modelo en la relación (0.0 - 1.0), si la indica
+    #[serde(default)]
+    pub confidence: Option<f32>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Con qué modelo y versión de prompt se extrajo un chunk (se guarda en el `:DocumentChunk`).
+#[derive(Debug, Clone)]
+pub struct ExtractionProvenance {
+    pub model: String,
+    pub prompt_version: String,
+}
+
+/// Suceso fechable del texto (batalla, firma, lanzamiento...). Se guarda como nodo `:Event`.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct GraphEvent {
+    pub name: String,
+    /// Fecha ISO-8601 (o solo el año) si el texto la indica
+    #[serde(default)]
+    pub date: Option<String>,
+    /// Entidades que intervienen (se enlazan con `INVOLVES`)
+    #[serde(default)]
+    pub participants: Vec<String>,
+}
+
+/// Orden temporal entre dos sucesos: `(before)-[:BEFORE]->(after)`.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct TemporalRelation {
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
 pub struct KnowledgeExtraction {
     pub entities: Vec<GraphEntity>,
     pub relations: Vec<GraphRelation>,
+    /// Solo con la extracción de sucesos activada (`EXTRACT_EVENTS`)
+    #[serde(default)]
+    pub events: Vec<GraphEvent>,
+    #[serde(default)]
+    pub temporal_relations: Vec<TemporalRelation>,
+}
+
+/// Categoría de entidad presente en el grafo y cuántas entidades la tienen (filtros de la UI).
+#[derive(Debug, Serialize, ToSchema, Clone)]
+pub struct CategoryCount {
+    pub category: String,
+    pub count: usize,
+}
+
+/// Entidad con su número de relaciones (en cualquier sentido) con otras entidades.
+#[derive(Debug, Serialize, ToSchema, Clone)]
+pub struct EntityDegree {
+    pub name: String,
+    pub degree: usize,
+}
+
+/// Recuentos globales del grafo para el panel de control.
+#[derive(Debug, Serialize, ToSchema, Clone, Default)]
+pub struct GraphStats {
+    pub entities: usize,
+    /// Relaciones entre entidades (incluye las inferidas)
+    pub relations: usize,
+    /// Relaciones creadas por el motor de razonamiento
+    pub inferred_relations: usize,
+    pub chunks: usize,
+    /// Las 10 entidades con más relaciones
+    pub top_entities: Vec<EntityDegree>,
+}
+
+/// Suceso de la línea temporal, con sus participantes.
+#[derive(Debug, Serialize, ToSchema, Clone)]
+pub struct TimelineEvent {
+    pub name: String,
+    pub date: Option<String>,
+    pub participants: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema, Validate)]
@@ -62,33 +406,397 @@ pub struct IngestionRequest {
     pub metadata: serde_json::Value,
 }
 
-// --- VISUALIZACIÓN (Sin cambios) ---
+// --- DOCUMENTOS ---
+
+/// Documento que se va a ingestar: nombre de origen y metadatos libres
+/// (autor, fuente, fecha, etiquetas...).
+#[derive(Debug, Clone, Default)]
+pub struct DocumentInput {
+    pub name: String,
+    pub metadata: HashMap<String, serde_json::Value>,
+    /// Tipo MIME declarado en la subida (`None` si no vino o es texto directo sin cabecera)
+    pub content_type: Option<String>,
+    /// Caracteres del texto extraído
+    pub char_count: usize,
+}
+
+/// Documento ingestado (nodo `:Document` que agrupa sus chunks).
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DocumentSummary {
+    pub id: String,
+    /// Nombre del archivo subido ("Texto Plano" para texto directo)
+    pub name: String,
+    /// Fecha de ingesta
+    pub created_at: String,
+    pub content_type: Option<String>,
+    pub char_count: usize,
+    pub chunk_count: usize,
+    pub metadata: HashMap<String, serde_json::Value>,
+}
 
+/// Resultado del borrado de un documento.
 #[derive(Debug, Serialize, ToSchema)]
+pub struct DocumentDeletion {
+    pub document_id: String,
+    pub chunks_deleted: usize,
+    /// Entidades que solo mencionaba este documento
+    pub entities_deleted: usize,
+}
+
+/// Resultado de la comprobación previa de un archivo (solo conversión a texto, sin ingestar).
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileValidationReport {
+    pub valid: bool,
+    pub filename: String,
+    /// Formato detectado por la extensión (`None` si no está soportado)
+    pub format: Option<String>,
+    /// Caracteres de texto extraídos
+    pub char_count: usize,
+    /// Inicio del texto extraído
+    pub preview: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FileValidationParams {
+    /// Caracteres de la vista previa del texto (por defecto 500)
+    #[serde(default = "default_preview_chars")]
+    pub preview_chars: usize,
+}
+
+fn default_preview_chars() -> usize { 500 }
+
+/// Un fragmento del plan de ingesta.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PlannedChunk {
+    pub index: usize,
+    pub chars: usize,
+    /// Tokens según el tokenizer del modelo de embeddings (estimación)
+    pub estimated_tokens: usize,
+}
+
+/// Plan de una ingesta (conversión + chunking) sin llamadas a la IA ni escrituras.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IngestionPlan {
+    pub document: String,
+    pub char_count: usize,
+    /// Fragmentos resultantes del chunking
+    pub chunk_count: usize,
+    /// Fragmentos que se procesarían tras aplicar `max_chunks`
+    pub processed_chunks: usize,
+    pub chunks: Vec<PlannedChunk>,
+    pub estimated_tokens: usize,
+    /// Llamadas de embeddings (descontando los embeddings importados reutilizables)
+    pub embedding_calls: usize,
+    /// Llamadas de extracción al LLM (una por fragmento)
+    pub extraction_calls: usize,
+}
+
+/// Texto a extraer sin guardar nada en el grafo (ajuste de prompts).
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+pub struct ExtractionPreviewRequest {
+    #[validate(length(min = 10))]
+    pub content: String,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExtractionPreviewParams {
+    /// Incluir la respuesta literal del modelo (depuración)
+    #[serde(default)]
+    pub raw: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExtractionPreview {
+    pub extraction: KnowledgeExtraction,
+    /// Respuesta sin procesar del LLM (solo con `?raw=true`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_response: Option<String>,
+    /// Tokens consumidos por la extracción (solo con AI_INCLUDE_TOKEN_USAGE)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+// --- VISUALIZACIÓN (Sin cambios) ---
+
+#[derive(Debug, Serialize, ToSchema, Clone)]
 pub struct VisNode {
     pub id: String,
     pub label: String,
     pub group: String,
+    /// Importancia (PageRank normalizado 0.0 - 1.0), si ya se calculó
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub centrality: Option<f64>,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
+#[derive(Debug, Serialize, ToSchema, Clone)]
 pub struct VisEdge {
     pub from: String,
     pub to: String,
     pub label: String,
+    /// IDs de los chunks que afirman esta relación (evidencia)
+    pub sources: Vec<String>,
+    /// Confianza almacenada en la relación (extraída o inferida), si existe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+    /// Justificación del razonador (solo relaciones inferidas)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    /// Relación inferida por la IA (`is_ai_generated`) en lugar de extraída del texto
+    pub inferred: bool,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
+#[derive(Debug, Serialize, ToSchema, Clone)]
 pub struct GraphDataResponse {
     pub nodes: Vec<VisNode>,
     pub edges: Vec<VisEdge>,
+    /// Límites que han recortado el resultado (vacío = grafo completo)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub limits_reached: Vec<GraphLimit>,
+}
+
+/// Límite de la vista del grafo que se ha alcanzado.
+#[derive(Debug, Serialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphLimit {
+    MaxNodes,
+    MaxEdges,
+}
+
+/// Cambios del grafo desde un instante (actualización incremental de la vista).
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GraphDelta {
+    /// Entidades creadas después de `since` y extremos de las relaciones nuevas
+    pub nodes: Vec<VisNode>,
+    /// Relaciones creadas después de `since`
+    pub edges: Vec<VisEdge>,
+    /// Instante de la consulta (epoch ms): usar como `timestamp` en la siguiente petición
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GraphSinceParams {
+    /// Epoch en milisegundos; se devuelve solo lo creado después
+    pub timestamp: i64,
+}
+
+/// Filtros opcionales para la vista del grafo completo.
+#[derive(Debug, Default, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GraphFilter {
+    /// Solo entidades con al menos este número de relaciones (vista "backbone")
+    pub min_degree: Option<usize>,
+    /// Solo relaciones con confianza >= este valor (0.0 - 1.0)
+    pub min_confidence: Option<f64>,
+    /// Máximo de relaciones devueltas (por defecto 1000, tope 10000)
+    pub max_edges: Option<usize>,
+    /// Máximo de entidades devueltas (por defecto sin límite propio, tope 10000)
+    pub max_nodes: Option<usize>,
+}
+
+pub const DEFAULT_GRAPH_EDGE_LIMIT: usize = 1000;
+pub const MAX_GRAPH_LIMIT: usize = 10_000;
+
+impl GraphFilter {
+    fn key(&self) -> (Option<usize>, Option<u64>, usize, usize) {
+        (self.min_degree, self.min_confidence.map(f64::to_bits), self.edge_limit(), self.node_limit())
+    }
+
+    /// Límite de relaciones efectivo
+    pub fn edge_limit(&self) -> usize {
+        self.max_edges.unwrap_or(DEFAULT_GRAPH_EDGE_LIMIT).clamp(1, MAX_GRAPH_LIMIT)
+    }
+
+    /// Límite de entidades efectivo
+    pub fn node_limit(&self) -> usize {
+        self.max_nodes.unwrap_or(MAX_GRAPH_LIMIT).clamp(1, MAX_GRAPH_LIMIT)
+    }
+}
+
+// Igualdad/hash por bits del f64: el filtro se usa como clave de la caché del grafo
+impl PartialEq for GraphFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for GraphFilter {}
+
+impl std::hash::Hash for GraphFilter {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Formatos de exportación estática del grafo.
+#[derive(Debug, Deserialize, ToSchema, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphExportFormat {
+    /// Graphviz DOT
+    #[default]
+    Dot,
+    /// Script de sentencias MERGE para cypher-shell (copia de seguridad portable)
+    Cypher,
+}
+
+/// Entidad completa (todas sus propiedades) para la exportación a Cypher.
+#[derive(Debug, Clone)]
+pub struct ExportedEntity {
+    pub name: String,
+    /// Propiedades salvo `name`; las fechas llegan como texto ISO-8601
+    pub properties: HashMap<String, serde_json::Value>,
+}
+
+/// Relación completa entre dos entidades para la exportación a Cypher.
+#[derive(Debug, Clone)]
+pub struct ExportedRelation {
+    pub source: String,
+    pub target: String,
+    pub relation_type: String,
+    pub properties: HashMap<String, serde_json::Value>,
+}
+
+/// Documento o fragmento para la exportación completa (`include_chunks`).
+#[derive(Debug, Clone)]
+pub struct ExportedNode {
+    pub id: String,
+    /// Documento al que pertenece (solo fragmentos)
+    pub document_id: Option<String>,
+    /// Propiedades salvo `id` y `embedding`
+    pub properties: HashMap<String, serde_json::Value>,
+}
+
+/// Elemento de la exportación del grafo, en el orden en que debe recrearse.
+#[derive(Debug, Clone)]
+pub enum ExportRecord {
+    Entity(ExportedEntity),
+    Relation(ExportedRelation),
+    Document(ExportedNode),
+    Chunk(ExportedNode),
+    /// Arista `(chunk)-[:MENTIONS]->(entity)`
+    Mention { chunk_id: String, entity: String },
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GraphExportParams {
+    #[serde(default)]
+    #[param(inline)]
+    pub format: GraphExportFormat,
+    /// Solo `cypher`: incluye documentos, fragmentos (con su texto) y aristas MENTIONS
+    #[serde(default)]
+    pub include_chunks: bool,
+}
+
+/// Sentido de las relaciones a recorrer desde el concepto central.
+#[derive(Debug, Deserialize, ToSchema, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TraversalDirection {
+    /// Solo relaciones entrantes ("qué influye en X")
+    In,
+    /// Solo relaciones salientes ("en qué influye X")
+    Out,
+    #[default]
+    Both,
+}
+
+/// Parámetros de exploración del vecindario de un concepto.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NeighborhoodParams {
+    /// `in`, `out` o `both` (por defecto)
+    #[serde(default)]
+    #[param(inline)]
+    pub direction: TraversalDirection,
 }
 
 // --- CHAT RAG AVANZADO (MODIFICADO) ---
 
+/// Estrategia de recuperación de contexto para el chat.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetrievalStrategy {
+    /// Similitud semántica (índice vectorial)
+    Vector,
+    /// Coincidencia de términos (índice full-text)
+    Keyword,
+    /// Fusión de ambas (Reciprocal Rank Fusion)
+    #[default]
+    Hybrid,
+}
+
+/// Fragmentos recuperados por pregunta si la petición no indica `top_k`.
+pub const DEFAULT_CHAT_TOP_K: usize = 5;
+/// Tope de `top_k` (más fragmentos solo añaden ruido y tokens al prompt).
+pub const MAX_CHAT_TOP_K: usize = 20;
+/// Saltos máximos al expandir el contexto por el grafo (cada salto multiplica los caminos).
+pub const MAX_CONTEXT_HOPS: usize = 3;
+/// Tope de entidades por fragmento al expandir (las mencionadas directamente van primero).
+pub const MAX_CONTEXT_ENTITIES_PER_CHUNK: usize = 30;
+/// Turnos previos que se reenvían al modelo (los más antiguos se descartan).
+pub const MAX_CHAT_HISTORY_MESSAGES: usize = 10;
+
+/// Autor de un turno de la conversación.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    User,
+    Assistant,
+}
+
+/// Turno previo de la conversación, tal como lo mostró el cliente.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ChatRequest {
     pub message: String,
+    /// Estrategia de recuperación (por defecto `hybrid`)
+    #[serde(default)]
+    pub retrieval: RetrievalStrategy,
+    /// Incluir `footnotes` (cita -> chunk) en la respuesta
+    #[serde(default)]
+    pub footnotes: bool,
+    /// Fragmentos a recuperar (1 - 20, por defecto 5)
+    #[serde(default)]
+    #[schema(minimum = 1, maximum = 20, example = 5)]
+    pub top_k: Option<usize>,
+    /// Similitud vectorial mínima (0.0 - 1.0) de los fragmentos; los más débiles se descartan
+    #[serde(default)]
+    #[schema(minimum = 0.0, maximum = 1.0, example = 0.75)]
+    pub min_score: Option<f64>,
+    /// Turnos anteriores, del más antiguo al más reciente (sin incluir `message`).
+    /// La recuperación usa solo `message`; el historial da contexto al modelo.
+    #[serde(default)]
+    pub history: Vec<ChatMessage>,
+    /// Slot de embeddings (EMBEDDING_SLOTS) con el que buscar; por defecto el embedding principal
+    #[serde(default)]
+    #[schema(example = "fast")]
+    pub embedding: Option<String>,
+}
+
+impl ChatRequest {
+    /// `top_k` pedido, acotado a 1..=MAX_CHAT_TOP_K.
+    pub fn effective_top_k(&self) -> usize {
+        self.top_k.unwrap_or(DEFAULT_CHAT_TOP_K).clamp(1, MAX_CHAT_TOP_K)
+    }
+
+    /// Últimos MAX_CHAT_HISTORY_MESSAGES turnos con contenido.
+    pub fn recent_history(&self) -> Vec<ChatMessage> {
+        let turns: Vec<ChatMessage> = self.history.iter()
+            .filter(|m| !m.content.trim().is_empty())
+            .cloned()
+            .collect();
+        let skip = turns.len().saturating_sub(MAX_CHAT_HISTORY_MESSAGES);
+        turns.into_iter().skip(skip).collect()
+    }
 }
 
 /// Referencia a una fuente documental específica.
@@ -111,30 +819,501 @@ pub struct SourceReference {
 /// Respuesta estructurada del chat.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ChatResponse {
+    /// `false` si no hubo contexto suficiente y no se consultó al LLM (`response` vacío)
+    #[serde(default = "default_true")]
+    pub has_answer: bool,
     /// Texto generado por el LLM (Markdown)
     pub response: String,
     /// Lista de fuentes utilizadas para generar la respuesta
     pub sources: Vec<SourceReference>,
+    /// Citas realmente usadas en `response`, en orden de aparición (solo si se piden)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footnotes: Option<Vec<Footnote>>,
+    /// Tokens consumidos por la petición (solo con AI_INCLUDE_TOKEN_USAGE)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
 }
 
-#[derive(Debug, Clone)]
+/// Tokens consumidos según el proveedor (suma de todas las llamadas de una operación).
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self { prompt_tokens, completion_tokens, total_tokens: prompt_tokens + completion_tokens }
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// Nota al pie: enlaza un marcador de cita de la respuesta con su chunk.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Footnote {
+    /// Marcador tal y como aparece en la respuesta (ej: "[2]")
+    pub marker: String,
+    pub index: usize,
+    pub chunk_id: String,
+    pub preview: String,
+    pub score: f32,
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone)]
 pub struct HybridContext {
     pub chunk_id: String,
     pub content: String,
     pub connected_entities: Vec<String>, 
+    /// Similitud vectorial devuelta por el índice
+    pub score: f64,
+    /// Centralidad máxima (PageRank 0.0 - 1.0) de las entidades conectadas; 0.0 si no se calculó
+    pub entity_centrality: f64,
+}
+
+/// Respuesta del modo debug del chat: lo que vería el LLM, sin llamarlo.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChatDebugResponse {
+    /// Fragmentos recuperados por la búsqueda híbrida (con su puntuación)
+    pub contexts: Vec<HybridContext>,
+    /// System prompt final ensamblado
+    pub system_prompt: String,
+}
+
+// --- EXPORTACIÓN / IMPORTACIÓN DE EMBEDDINGS ---
+
+/// Embedding de un chunk identificado por el hash de su contenido.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct EmbeddingRecord {
+    pub chunk_id: String,
+    /// SHA-256 (hex) del contenido del chunk
+    pub content_hash: String,
+    pub embedding: Vec<f32>,
+}
+
+impl EmbeddingRecord {
+    /// Hash estable del contenido, usado para reconocer chunks idénticos entre bases de datos.
+    pub fn hash_content(content: &str) -> String {
+        use sha2::{Digest, Sha256};
+        format!("{:x}", Sha256::digest(content.as_bytes()))
+    }
+}
+
+/// Volcado de embeddings: solo es reutilizable con el mismo modelo y dimensión.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmbeddingExport {
+    pub embedding_model: String,
+    pub embedding_dim: usize,
+    pub records: Vec<EmbeddingRecord>,
+}
+
+// --- RESOLUCIÓN DE ENTIDADES (DEDUP SEMÁNTICO) ---
+
+/// Parámetros del pase de deduplicación semántica.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DedupParams {
+    /// Similitud coseno mínima para proponer una fusión (0.0 - 1.0)
+    pub threshold: Option<f32>,
+    /// Máximo de entidades a analizar (las de mayor grado primero)
+    pub limit: Option<usize>,
+}
+
+/// Opciones de la re-vectorización de chunks.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReembedParams {
+    /// Re-vectorizar también los chunks que ya tienen un embedding válido
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RebuildGraphParams {
+    /// Borrar antes entidades y sucesos (los chunks sin extracción guardada pierden sus entidades)
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Criterios para considerar obsoleta la extracción de un chunk.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StaleChunksParams {
+    /// Comparar también el modelo de extracción, no solo la versión del prompt (por defecto sí)
+    #[serde(default = "default_true")]
+    pub check_model: bool,
+    /// Incluir los chunks sin sello de procedencia (extraídos antes de registrarla; por defecto sí)
+    #[serde(default = "default_true")]
+    pub include_unversioned: bool,
+    /// Máximo de chunks listados (por defecto 100; `total` los cuenta todos)
+    pub limit: Option<usize>,
+}
+
+/// Chunk extraído con un modelo o prompt distinto del actual.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StaleChunk {
+    pub chunk_id: String,
+    pub document_id: Option<String>,
+    pub extraction_model: Option<String>,
+    pub extraction_prompt_version: Option<String>,
+    pub extracted_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StaleChunksReport {
+    pub current_model: String,
+    pub current_prompt_version: String,
+    /// Chunks obsoletos en total (la lista puede estar recortada por `limit`)
+    pub total: usize,
+    pub chunks: Vec<StaleChunk>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExpireInferredParams {
+    /// Antigüedad máxima en horas (por defecto, INFERRED_MAX_AGE_HOURS)
+    pub max_age_hours: Option<u64>,
+}
+
+/// Fragmento de origen que menciona una entidad (búsqueda inversa de MENTIONS).
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EntityChunk {
+    pub chunk_id: String,
+    /// Documento al que pertenece el chunk (None en chunks anteriores a `:Document`)
+    pub document_id: Option<String>,
+    /// Primeros caracteres del contenido
+    pub preview: String,
+    pub content: String,
+}
+
+/// Resultados por defecto y máximos de la búsqueda de entidades.
+pub const DEFAULT_ENTITY_SEARCH_LIMIT: usize = 10;
+pub const MAX_ENTITY_SEARCH_LIMIT: usize = 100;
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EntitySearchParams {
+    /// Texto a buscar en el nombre (cada palabra como prefijo, sin distinguir mayúsculas)
+    #[serde(default)]
+    pub q: String,
+    /// Máximo de resultados (por defecto 10, máximo 100)
+    pub limit: Option<usize>,
+}
+
+/// Entidad encontrada por la búsqueda (autocompletado).
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EntityMatch {
+    pub name: String,
+    pub category: String,
+    /// Relaciones con otras entidades
+    pub degree: usize,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EntityChunksParams {
+    /// Máximo de fragmentos devueltos (por defecto 50)
+    pub limit: Option<usize>,
+}
+
+/// Fila de la exportación CSV de entidades.
+#[derive(Debug, Clone)]
+pub struct EntityExportRow {
+    pub name: String,
+    pub category: String,
+    /// Relaciones con otras entidades
+    pub degree: i64,
+    pub centrality: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EntityExportParams {
+    /// Exportar como máximo N entidades (muestra); sin límite por defecto
+    pub limit: Option<usize>,
+}
+
+/// Propuesta de fusión: entidades semánticamente equivalentes a `canonical`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MergeProposal {
+    pub canonical: String,
+    pub duplicates: Vec<String>,
+    /// Similitud mínima entre `canonical` y sus duplicados
+    pub similarity: f32,
+}
+
+/// Petición para fusionar entidades en una canónica (revisión manual de propuestas).
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+pub struct MergeEntitiesRequest {
+    #[validate(length(min = 1))]
+    pub canonical: String,
+    #[validate(length(min = 1))]
+    pub duplicates: Vec<String>,
+}
+
+// --- SALUD / READINESS ---
+
+/// Estado de una dependencia externa comprobada por `/ready`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DependencyStatus {
+    pub name: String,
+    pub ok: bool,
+    /// Diagnóstico cuando la dependencia falla
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl DependencyStatus {
+    pub fn ok(name: &str) -> Self {
+        Self { name: name.to_string(), ok: true, detail: None }
+    }
+
+    pub fn failed(name: &str, detail: String) -> Self {
+        Self { name: name.to_string(), ok: false, detail: Some(detail) }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub dependencies: Vec<DependencyStatus>,
 }
 
 // --- RAZONAMIENTO E INFERENCIA ---
 
+/// Regla lógica con la que el modelo dedujo una relación.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InferenceType {
+    /// A -> B y B -> C  =>  A -> C
+    Transitivity,
+    /// A causa B y B implica C  =>  A lleva a C
+    Causality,
+    /// Dos nombres para la misma entidad
+    SameAs,
+}
+
+impl InferenceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Transitivity => "transitivity",
+            Self::Causality => "causality",
+            Self::SameAs => "same_as",
+        }
+    }
+}
+
+/// Nivel de confianza declarado por el modelo (ordenado de menor a mayor).
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfidenceLevel {
+    #[serde(alias = "baja", alias = "Baja", alias = "Low")]
+    Low,
+    #[serde(alias = "media", alias = "Media", alias = "Medium")]
+    Medium,
+    #[serde(alias = "alta", alias = "Alta", alias = "High")]
+    High,
+}
+
+impl ConfidenceLevel {
+    /// Confianza numérica equivalente a la etiqueta.
+    pub fn score(self) -> f32 {
+        match self {
+            Self::High => 0.9,
+            Self::Medium => 0.6,
+            Self::Low => 0.3,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Medium => "medium",
+            Self::Low => "low",
+        }
+    }
+}
+
+/// Un valor desconocido del LLM en un campo opcional se trata como ausente
+/// en lugar de invalidar toda la respuesta.
+fn lenient_option<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.and_then(|v| serde_json::from_value(v).ok()))
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
 pub struct InferredRelation {
     pub source: String,
     pub target: String,
     pub relation: String,
+    /// Explicación breve de la deducción
     pub reasoning: String, 
+    /// Regla aplicada (transitividad, causalidad, same_as)
+    #[serde(default, deserialize_with = "lenient_option")]
+    pub inference_type: Option<InferenceType>,
+    /// Nivel declarado por el modelo; en respuestas antiguas se extrae de "(Confianza: ...)"
+    #[serde(default, deserialize_with = "lenient_option")]
+    pub confidence_level: Option<ConfidenceLevel>,
+    /// Confianza numérica (0.0 - 1.0); si el modelo no la da se deduce del nivel
+    #[serde(default)]
+    pub confidence: Option<f32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InferenceResult {
     pub new_relations: Vec<InferredRelation>,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReasoningRunParams {
+    /// Incluye en la respuesta el contexto del grafo y el prompt enviados al LLM
+    #[serde(default)]
+    pub explain: bool,
+}
+
+/// Respuesta de `/api/reasoning/run?explain=true`: relaciones + lo que recibió el LLM.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReasoningExplanation {
+    pub new_relations: Vec<InferredRelation>,
+    /// Triplas del grafo tal como se insertaron en el prompt
+    pub context: String,
+    /// Prompt completo enviado al LLM
+    pub prompt: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedding_base_url_is_optional_and_validated() {
+        let config: AIConfig = serde_json::from_value(serde_json::json!({
+            "provider": "OpenAI",
+            "model_name": "gpt-4o",
+            "embedding_model": "text-embedding-3-small",
+            "embedding_dim": 1536,
+            "base_url": "https://api.openai.com/v1"
+        })).unwrap();
+        assert!(config.embedding_base_url.is_none());
+        assert_eq!(config.similarity_function, VectorSimilarity::Cosine);
+        assert!(config.validate().is_ok());
+
+        let config = AIConfig { embedding_base_url: Some("not a url".to_string()), ..config };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn auth_scheme_parses_each_form_and_round_trips() {
+        for (text, scheme) in [
+            ("bearer", AuthScheme::Bearer),
+            ("header:api-key", AuthScheme::Header("api-key".to_string())),
+            ("query:key", AuthScheme::Query("key".to_string())),
+        ] {
+            assert_eq!(text.parse::<AuthScheme>().unwrap(), scheme);
+            assert_eq!(String::from(scheme), text);
+        }
+        assert_eq!(" Header: X-Api-Key ".parse::<AuthScheme>().unwrap(), AuthScheme::Header("X-Api-Key".to_string()));
+    }
+
+    #[test]
+    fn auth_scheme_rejects_invalid_names_at_parse_time() {
+        for invalid in ["basic", "header:", "header:bad header", "header:caf\u{e9}", "query:", "query:a&b", "query:k=v", "cookie:session"] {
+            assert!(invalid.parse::<AuthScheme>().is_err(), "{} should be rejected", invalid);
+        }
+        let config: Result<AuthScheme, _> = serde_json::from_value(serde_json::json!("header:bad header"));
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn known_embedding_models_resolve_their_dimension() {
+        assert_eq!(known_embedding_dim("text-embedding-3-small"), Some(1536));
+        assert_eq!(known_embedding_dim("text-embedding-3-large"), Some(3072));
+        assert_eq!(known_embedding_dim("nomic-embed-text"), Some(768));
+        assert_eq!(known_embedding_dim("nomic-embed-text:latest"), Some(768));
+        assert_eq!(resolve_embedding_dim("mxbai-embed-large", None), Ok(1024));
+        // AI_EMBEDDING_DIM manda incluso sobre un modelo conocido
+        assert_eq!(resolve_embedding_dim("text-embedding-3-large", Some("256")), Ok(256));
+    }
+
+    #[test]
+    fn unknown_embedding_models_require_an_explicit_dimension() {
+        assert_eq!(known_embedding_dim("my-custom-embedder"), None);
+        assert!(resolve_embedding_dim("my-custom-embedder", None).unwrap_err().contains("AI_EMBEDDING_DIM"));
+        assert_eq!(resolve_embedding_dim("my-custom-embedder", Some("512")), Ok(512));
+        assert!(resolve_embedding_dim("my-custom-embedder", Some("auto")).is_err());
+    }
+
+    #[test]
+    fn top_k_defaults_to_five_and_is_clamped() {
+        let req = |top_k| ChatRequest { message: "hola".into(), retrieval: RetrievalStrategy::default(), footnotes: false, top_k, min_score: None, history: Vec::new(), embedding: None };
+        assert_eq!(req(None).effective_top_k(), DEFAULT_CHAT_TOP_K);
+        assert_eq!(req(Some(0)).effective_top_k(), 1);
+        assert_eq!(req(Some(8)).effective_top_k(), 8);
+        assert_eq!(req(Some(50)).effective_top_k(), MAX_CHAT_TOP_K);
+    }
+
+    #[test]
+    fn only_the_latest_non_empty_turns_are_kept_as_history() {
+        let turn = |i: usize| ChatMessage {
+            role: if i.is_multiple_of(2) { ChatRole::User } else { ChatRole::Assistant },
+            content: if i == 13 { "  ".to_string() } else { format!("turno {}", i) },
+        };
+        let req: ChatRequest = serde_json::from_value(serde_json::json!({ "message": "hola" })).unwrap();
+        assert!(req.recent_history().is_empty());
+
+        let req = ChatRequest { history: (0..15).map(turn).collect(), ..req };
+        let history = req.recent_history();
+        assert_eq!(history.len(), MAX_CHAT_HISTORY_MESSAGES);
+        assert_eq!(history[0].content, "turno 4");
+        assert_eq!(history.last().unwrap().content, "turno 14");
+        assert!(history.iter().all(|m| !m.content.trim().is_empty()));
+    }
+
+    #[test]
+    fn the_edge_limit_defaults_to_1000_and_is_capped() {
+        let filter = |max_edges| GraphFilter { max_edges, ..GraphFilter::default() };
+        assert_eq!(filter(None).edge_limit(), DEFAULT_GRAPH_EDGE_LIMIT);
+        assert_eq!(filter(Some(0)).edge_limit(), 1);
+        assert_eq!(filter(Some(50_000)).edge_limit(), MAX_GRAPH_LIMIT);
+    }
+
+    #[test]
+    fn the_node_limit_only_applies_when_asked_for() {
+        let filter = |max_nodes| GraphFilter { max_nodes, ..GraphFilter::default() };
+        assert_eq!(filter(None).node_limit(), MAX_GRAPH_LIMIT);
+        assert_eq!(filter(Some(0)).node_limit(), 1);
+        assert_eq!(filter(Some(2)).node_limit(), 2);
+    }
+
+    #[test]
+    fn similarity_function_parses_and_maps_to_neo4j() {
+        assert_eq!("cosine".parse::<VectorSimilarity>().unwrap(), VectorSimilarity::Cosine);
+        assert_eq!(" Euclidean ".parse::<VectorSimilarity>().unwrap(), VectorSimilarity::Euclidean);
+        assert!("dot".parse::<VectorSimilarity>().is_err());
+        assert_eq!(VectorSimilarity::Euclidean.as_neo4j(), "euclidean");
+    }
+
+    #[test]
+    fn embedding_slots_parse_name_model_and_dimension() {
+        let slot: EmbeddingSlot = " Fast:nomic-embed-text:latest:768 ".parse().unwrap();
+        assert_eq!(slot, EmbeddingSlot { name: "fast".to_string(), model: "nomic-embed-text:latest".to_string(), dim: 768 });
+        assert_eq!(slot.property(), "embedding_fast");
+        assert_eq!(slot.index_name(), "chunk_embeddings_fast");
+
+        // El nombre se interpola en el Cypher: solo [a-z0-9_]
+        for invalid in ["fast", "fast:768", "fa-st:model:768", "x}) DETACH DELETE c //:model:8", "fast:model:0", "fast::768", ":model:768"] {
+            assert!(invalid.parse::<EmbeddingSlot>().is_err(), "{} should be rejected", invalid);
+        }
+    }
+}
@@ -1,33 +1,174 @@
 use async_trait::async_trait;
-use crate::domain::models::{AIConfig, KnowledgeExtraction, GraphDataResponse, HybridContext, InferredRelation, InferenceResult};
+use std::collections::HashMap;
+use crate::domain::models::{DocumentDeletion, DocumentInput, DocumentSummary, AIConfig, EntityChunk, ExportRecord, ExtractionProvenance, EntityExportRow, KnowledgeExtraction, GraphDataResponse, GraphDelta, GraphFilter, TraversalDirection, HybridContext, InferredRelation, InferenceResult, StaleChunk, TimelineEvent, EmbeddingRecord, CategoryCount, ChatMessage, GraphStats, EntityMatch, VectorSimilarity, EmbeddingSlot, TokenUsage};
 use crate::domain::errors::AppError;
 use uuid::Uuid;
+use std::sync::Arc;
+use tokio::sync::mpsc;
 
 #[async_trait]
 pub trait KGRepository: Send + Sync {
-    async fn save_chunk(&self, id: Uuid, content: &str, embedding: Vec<f32>) -> Result<(), AppError>;
-    async fn save_graph(&self, chunk_id: Uuid, data: KnowledgeExtraction) -> Result<(), AppError>;
-    async fn reset_database(&self) -> Result<(), AppError>;
-    async fn create_indexes(&self, dim: usize) -> Result<(), AppError>;
+    /// Crea el nodo `:Document` que agrupa los chunks de una ingesta.
+    async fn save_document(&self, id: Uuid, document: &DocumentInput) -> Result<(), AppError>;
+    async fn save_chunk(&self, document_id: Uuid, id: Uuid, content: &str, embedding: Vec<f32>) -> Result<(), AppError>;
+    /// Documentos cuyos metadatos coinciden con todos los pares `clave = valor` del filtro.
+    async fn list_documents(&self, metadata_filter: &HashMap<String, String>) -> Result<Vec<DocumentSummary>, AppError>;
+    /// Borra el documento, sus chunks y las entidades que solo mencionaban esos chunks.
+    async fn delete_document(&self, doc_group_id: Uuid) -> Result<DocumentDeletion, AppError>;
+    /// Subgrafo inducido por el documento: entidades que mencionan sus chunks y las relaciones entre ellas.
+    async fn get_document_graph(&self, doc_group_id: Uuid) -> Result<GraphDataResponse, AppError>;
+    /// Guarda entidades/relaciones del chunk; con `provenance` marca además el chunk
+    /// con modelo, versión de prompt y fecha de extracción.
+    async fn save_graph(&self, chunk_id: Uuid, data: KnowledgeExtraction, provenance: Option<&ExtractionProvenance>) -> Result<(), AppError>;
+    /// Guarda en el chunk el JSON de la extracción tal como lo devolvió el LLM.
+    async fn save_chunk_extraction(&self, chunk_id: Uuid, extraction_json: &str) -> Result<(), AppError>;
+    /// Borra todos los nodos (y sus relaciones); devuelve cuántos nodos.
+    async fn reset_database(&self) -> Result<usize, AppError>;
+    async fn create_indexes(&self, dim: usize, similarity: VectorSimilarity) -> Result<(), AppError>;
+    /// Índice vectorial de un slot de embeddings adicional (si no existe).
+    async fn create_embedding_slot_index(&self, slot: &EmbeddingSlot, similarity: VectorSimilarity) -> Result<(), AppError>;
+    /// Consulta mínima para comprobar que la base de datos responde.
+    async fn ping(&self) -> Result<(), AppError>;
     
-    async fn get_full_graph(&self) -> Result<GraphDataResponse, AppError>;
-    async fn find_hybrid_context(&self, embedding: Vec<f32>, limit: usize) -> Result<Vec<HybridContext>, AppError>;
+    async fn get_full_graph(&self, filter: &GraphFilter) -> Result<GraphDataResponse, AppError>;
+    /// Envía por `tx` todas las entidades y relaciones con sus propiedades (copia de seguridad);
+    /// con `include_chunks` también documentos, fragmentos y aristas MENTIONS. Devuelve los enviados.
+    async fn stream_graph_records(&self, include_chunks: bool, tx: mpsc::Sender<ExportRecord>) -> Result<usize, AppError>;
+    /// Entidades y relaciones con `created_at` posterior a `since_millis` (epoch ms).
+    async fn get_graph_since(&self, since_millis: i64) -> Result<GraphDelta, AppError>;
+    /// Búsqueda vectorial de chunks con sus entidades. Con `hops > 1` añade las entidades
+    /// alcanzables desde las mencionadas en hasta `hops` saltos (acotado por fragmento).
+    /// Ordenado por similitud descendente; `min_score` descarta las coincidencias por debajo.
+    /// Con `slot` se busca en el índice de ese slot en lugar del principal.
+    async fn find_hybrid_context(&self, embedding: Vec<f32>, limit: usize, hops: usize, min_score: Option<f64>, slot: Option<&EmbeddingSlot>) -> Result<Vec<HybridContext>, AppError>;
+    /// Búsqueda por términos sobre el contenido de los chunks (índice full-text).
+    async fn find_keyword_context(&self, text: &str, limit: usize) -> Result<Vec<HybridContext>, AppError>;
     
     // --- MÉTODO NUEVO DE VECINDARIO ---
-    async fn get_concept_neighborhood(&self, concept_name: &str) -> Result<GraphDataResponse, AppError>;
+    async fn get_concept_neighborhood(&self, concept_name: &str, direction: TraversalDirection) -> Result<GraphDataResponse, AppError>;
+
+    /// Chunks (id, contenido) sin embedding o con dimensión distinta de `dim`; todos si `force`.
+    async fn list_chunks_needing_embedding(&self, dim: usize, force: bool) -> Result<Vec<(String, String)>, AppError>;
+    async fn update_chunk_embedding(&self, chunk_id: &str, embedding: Vec<f32>) -> Result<(), AppError>;
+    /// Guarda el vector de un slot adicional en el chunk (`c.embedding_<slot>`).
+    async fn save_chunk_slot_embedding(&self, chunk_id: Uuid, slot: &EmbeddingSlot, embedding: Vec<f32>) -> Result<(), AppError>;
+
+    /// Vuelca id, hash de contenido y vector de todos los chunks.
+    async fn export_embeddings(&self) -> Result<Vec<EmbeddingRecord>, AppError>;
+
+    // --- Resolución de entidades ---
+    /// Nombres de entidades ordenados por grado descendente.
+    async fn list_entity_names(&self, limit: usize) -> Result<Vec<String>, AppError>;
+    /// Chunks que mencionan la entidad `name` (relación MENTIONS en sentido inverso).
+    async fn find_chunks_by_entity(&self, name: &str, limit: usize) -> Result<Vec<EntityChunk>, AppError>;
+    /// Entidades cuyo nombre contiene las palabras de `text` (como prefijo), de mayor a menor grado.
+    /// Un texto vacío no devuelve nada.
+    async fn search_entities(&self, text: &str, limit: usize) -> Result<Vec<EntityMatch>, AppError>;
+    /// Envía las entidades por `tx` a medida que llegan del cursor (sin cargarlas todas);
+    /// para si el receptor se cierra. Devuelve cuántas se enviaron.
+    async fn stream_entities(&self, limit: Option<usize>, tx: mpsc::Sender<EntityExportRow>) -> Result<usize, AppError>;
+    /// Fusiona `duplicates` en `canonical` (re-enlaza relaciones y borra los duplicados).
+    async fn merge_entities(&self, canonical: &str, duplicates: &[String]) -> Result<usize, AppError>;
+
+    // --- Centralidad ---
+    /// Todas las entidades y las relaciones dirigidas entre ellas (origen, destino).
+    async fn get_entity_graph(&self) -> Result<(Vec<String>, Vec<(String, String)>), AppError>;
+    /// Guarda la puntuación de importancia en `e.centrality`.
+    async fn save_centrality(&self, scores: &HashMap<String, f64>) -> Result<(), AppError>;
+
+    /// Categorías distintas de las entidades con su número de entidades, de más a menos frecuente.
+    async fn list_categories(&self) -> Result<Vec<CategoryCount>, AppError>;
+
+    /// Recuentos de entidades, relaciones y chunks y las entidades con más relaciones (ceros si está vacío).
+    async fn get_stats(&self) -> Result<GraphStats, AppError>;
+
+    // --- Línea temporal ---
+    /// Todos los sucesos (`:Event`) con sus participantes y las aristas `BEFORE` (antes, después).
+    async fn get_timeline(&self) -> Result<(Vec<TimelineEvent>, Vec<(String, String)>), AppError>;
+
+    /// Chunks cuya extracción no coincide con `prompt_version` (ni con `model`, si se indica).
+    /// Devuelve el total y como mucho `limit` chunks.
+    async fn find_stale_chunks(&self, model: Option<&str>, prompt_version: &str, include_unversioned: bool, limit: usize) -> Result<(usize, Vec<StaleChunk>), AppError>;
+
+    // --- Mantenimiento ---
+    /// Borra las entidades sin ninguna relación (ni MENTIONS ni entre entidades); devuelve cuántas.
+    async fn prune_orphan_entities(&self) -> Result<usize, AppError>;
+    /// Chunks con extracción guardada (id, JSON) y cuántos chunks no la tienen.
+    async fn list_stored_extractions(&self) -> Result<(Vec<(String, String)>, usize), AppError>;
+    /// Borra entidades y sucesos (con sus relaciones) conservando documentos y chunks; devuelve cuántos nodos.
+    async fn clear_extracted_graph(&self) -> Result<usize, AppError>;
 
     // --- Métodos para razonamiento ---
     async fn get_graph_context_for_reasoning(&self, limit: usize) -> Result<String, AppError>;
     async fn save_inferred_relations(&self, relations: Vec<InferredRelation>) -> Result<(), AppError>;
+    /// Borra las relaciones `is_ai_generated` creadas hace más de `max_age`; devuelve cuántas.
+    async fn delete_inferred_relations_older_than(&self, max_age: std::time::Duration) -> Result<usize, AppError>;
+}
+
+/// Aviso de reintento de una llamada a la IA: (intento, intentos máximos, error del anterior).
+pub type RetryNotifier = Arc<dyn Fn(u32, u32, &AppError) + Send + Sync>;
+
+/// Uso de tokens de cada llamada a la IA que lo informa.
+pub type UsageRecorder = Arc<dyn Fn(&TokenUsage) + Send + Sync>;
+
+tokio::task_local! {
+    /// Quien llama a la IA puede escuchar sus reintentos sin cambiar la firma de `AIService`
+    /// (la ingesta los muestra en el progreso): `RETRY_NOTIFIER.scope(notifier, llamada)`.
+    pub static RETRY_NOTIFIER: RetryNotifier;
+
+    /// Igual para el uso de tokens: `USAGE_RECORDER.scope(recorder, llamada)` recibe el de cada
+    /// llamada al proveedor hecha dentro (reintentos incluidos).
+    pub static USAGE_RECORDER: UsageRecorder;
+}
+
+/// Ejecuta `call` sumando el uso de tokens de todas las llamadas a la IA que haga.
+/// `None` si ninguna informó de uso (proveedor sin datos o sin llamadas).
+pub async fn with_usage<T>(call: impl std::future::Future<Output = T>) -> (T, Option<TokenUsage>) {
+    let total: Arc<std::sync::Mutex<Option<TokenUsage>>> = Arc::new(std::sync::Mutex::new(None));
+    let sink = total.clone();
+    let recorder: UsageRecorder = Arc::new(move |usage| {
+        sink.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(TokenUsage::default).add(usage);
+    });
+    let result = USAGE_RECORDER.scope(recorder, call).await;
+    let usage = *total.lock().unwrap_or_else(|e| e.into_inner());
+    (result, usage)
 }
 
 #[async_trait]
 pub trait AIService: Send + Sync {
-    async fn extract_knowledge(&self, text: &str) -> Result<KnowledgeExtraction, AppError>;
+    /// Extracción junto con la respuesta literal del modelo (para depurar el parseo).
+    async fn extract_knowledge_raw(&self, text: &str) -> Result<(KnowledgeExtraction, String), AppError>;
+
+    async fn extract_knowledge(&self, text: &str) -> Result<KnowledgeExtraction, AppError> {
+        self.extract_knowledge_raw(text).await.map(|(extraction, _)| extraction)
+    }
     async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>, AppError>;
-    fn update_config(&mut self, config: AIConfig) -> Result<(), AppError>;
+    /// Embedding con el modelo de un slot adicional (mismo proveedor y endpoint de embeddings).
+    async fn generate_slot_embedding(&self, slot: &EmbeddingSlot, _text: &str) -> Result<Vec<f32>, AppError> {
+        Err(AppError::ConfigError(format!("Embedding slot '{}' is not supported by this AI service", slot.name)))
+    }
+    /// Sustituye la configuración; no espera a las llamadas en curso (usan su propia copia).
+    fn update_config(&self, config: AIConfig) -> Result<(), AppError>;
     fn get_config(&self) -> AIConfig;
 
+    /// Versión del prompt de extracción (cambia al modificar preámbulo o granularidad).
+    fn extraction_prompt_version(&self) -> String {
+        "unversioned".to_string()
+    }
+
+    /// Comprueba conectividad y credenciales con el proveedor (un embedding mínimo).
+    async fn check_connectivity(&self) -> Result<(), AppError> {
+        self.generate_embedding("ping").await.map(|_| ())
+    }
+
     // --- Método para inferencia ---
     async fn generate_inference(&self, prompt: &str) -> Result<InferenceResult, AppError>;
+
+    /// Respuesta del chat a `message` con el contexto RAG en `system_prompt`.
+    /// `history` son los turnos previos, que el modelo recibe antes de `message`.
+    async fn generate_answer(&self, system_prompt: &str, history: &[ChatMessage], message: &str) -> Result<String, AppError>;
+
+    /// Divide una pregunta compuesta en como máximo `max` sub-consultas independientes
+    /// (devuelve solo la original si no hay nada que dividir).
+    async fn decompose_query(&self, query: &str, max: usize) -> Result<Vec<String>, AppError>;
 }
\ No newline at end of file
@@ -0,0 +1,147 @@
+//! Cliente mínimo de la Messages API de Anthropic (Claude).
+//! No es compatible con OpenAI: la clave va en `x-api-key` y la respuesta son bloques de contenido.
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::domain::models::{AIConfig, AuthScheme, ChatMessage, ChatRole, TokenUsage};
+
+pub const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// La Messages API exige `max_tokens`; holgado para extracciones largas
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    #[serde(default)]
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+    /// Argumentos de un bloque `tool_use`
+    #[serde(default)]
+    input: Option<Value>,
+}
+
+// Herramienta obligatoria con la que se fuerza la salida estructurada
+const OUTPUT_TOOL: &str = "record_output";
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+/// Cabeceras de Anthropic: `x-api-key` (o la cabecera de `AuthScheme::Header`) y la versión de la API.
+fn headers(config: &AIConfig) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert("anthropic-version", HeaderValue::from_static(ANTHROPIC_VERSION));
+
+    // Con `query:` la clave va en la URL (ver `complete`)
+    let name = match &config.auth_scheme {
+        AuthScheme::Header(name) => HeaderName::from_bytes(name.as_bytes()).unwrap_or_else(|_| {
+            tracing::warn!("⚠️ Invalid auth header name '{}', falling back to x-api-key", name);
+            HeaderName::from_static("x-api-key")
+        }),
+        AuthScheme::Bearer => HeaderName::from_static("x-api-key"),
+        AuthScheme::Query(_) => return headers,
+    };
+    if let Ok(mut val) = HeaderValue::from_str(config.api_key.expose_secret()) {
+        val.set_sensitive(true);
+        headers.insert(name, val);
+    }
+    headers
+}
+
+/// Una ronda de la Messages API: devuelve el texto concatenado de los bloques `text`.
+/// Con `schema`, se obliga al modelo a llamar a una herramienta con ese `input_schema`
+/// y se devuelven sus argumentos como JSON. `history` va como turnos previos a `prompt`.
+/// Devuelve también el uso de tokens (`usage` de la respuesta).
+pub async fn complete(config: &AIConfig, system: Option<&str>, history: &[ChatMessage], prompt: &str, schema: Option<&Value>) -> Result<(String, Option<TokenUsage>), String> {
+    let base_url = config.base_url.as_deref().unwrap_or(DEFAULT_ANTHROPIC_BASE_URL);
+    let url = format!("{}/messages", base_url.trim_end_matches('/'));
+
+    let mut messages: Vec<Value> = history.iter()
+        .map(|m| {
+            let role = match m.role {
+                ChatRole::User => "user",
+                ChatRole::Assistant => "assistant",
+            };
+            json!({ "role": role, "content": m.content })
+        })
+        .collect();
+    messages.push(json!({ "role": "user", "content": prompt }));
+
+    let mut body = json!({
+        "model": config.model_name,
+        "max_tokens": DEFAULT_MAX_TOKENS,
+        "messages": messages,
+    });
+    if let Some(system) = system {
+        body["system"] = json!(system);
+    }
+    if let Some(schema) = schema {
+        body["tools"] = json!([{
+            "name": OUTPUT_TOOL,
+            "description": "Record the structured output requested in the instructions.",
+            "input_schema": schema,
+        }]);
+        body["tool_choice"] = json!({ "type": "tool", "name": OUTPUT_TOOL });
+    }
+
+    let mut request = reqwest::Client::new()
+        .post(&url)
+        .headers(headers(config));
+    if let AuthScheme::Query(name) = &config.auth_scheme {
+        request = request.query(&[(name.as_str(), config.api_key.expose_secret())]);
+    }
+    // Sin la URL en el error: con `query:` llevaría la clave
+    let response = request
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+
+    let status = response.status();
+    let raw = response.text().await.map_err(|e| e.without_url().to_string())?;
+    if !status.is_success() {
+        let message = serde_json::from_str::<ErrorResponse>(&raw)
+            .map(|e| e.error.message)
+            .unwrap_or(raw);
+        return Err(format!("Anthropic API {}: {}", status, message));
+    }
+
+    let parsed: MessagesResponse = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid Anthropic response: {}", e))?;
+    let usage = parsed.usage.as_ref().map(|u| TokenUsage::new(u.input_tokens, u.output_tokens));
+    if schema.is_some() {
+        if let Some(input) = parsed.content.iter().find(|block| block.kind == "tool_use").and_then(|block| block.input.as_ref()) {
+            return Ok((input.to_string(), usage));
+        }
+    }
+    let text = parsed.content.into_iter()
+        .filter(|block| block.kind == "text")
+        .map(|block| block.text)
+        .collect();
+    Ok((text, usage))
+}
@@ -0,0 +1,136 @@
+use std::time::Duration;
+use sha2::{Digest, Sha256};
+use crate::domain::models::{AIConfig, TokenUsage};
+
+/// Target de `tracing` para filtrar/enrutar el log de auditoría (ej. `RUST_LOG=ai_audit=info`).
+pub const AUDIT_TARGET: &str = "ai_audit";
+
+/// Ajustes del log de auditoría de prompts (se leen del entorno en main.rs).
+#[derive(Debug, Clone, Default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Sustituye el contenido del prompt por su hash SHA-256 (no sale texto de documentos al log)
+    pub redact_content: bool,
+}
+
+/// Una llamada al proveedor de IA.
+pub struct AuditRecord<'a> {
+    /// `embedding`, `extraction`, `inference` o `chat`
+    pub operation: &'a str,
+    pub model: &'a str,
+    pub prompt: &'a str,
+    /// Longitud de la respuesta en caracteres (0 si falló)
+    pub response_chars: usize,
+    pub latency: Duration,
+    pub success: bool,
+    /// Uso informado por el proveedor (None en embeddings o si no lo da)
+    pub usage: Option<TokenUsage>,
+}
+
+/// Estimación aproximada de tokens (~4 caracteres por token) cuando el proveedor no da el uso.
+fn estimate_tokens(chars: usize) -> usize {
+    chars.div_ceil(4)
+}
+
+/// Escribe un registro estructurado de la llamada si la auditoría está activa.
+pub fn record(audit: &AuditConfig, config: &AIConfig, entry: AuditRecord<'_>) {
+    if !audit.enabled {
+        return;
+    }
+
+    let prompt = if audit.redact_content {
+        format!("sha256:{:x}", Sha256::digest(entry.prompt.as_bytes()))
+    } else {
+        entry.prompt.to_string()
+    };
+
+    let (prompt_tokens, completion_tokens, tokens_estimated) = match entry.usage {
+        Some(usage) => (usage.prompt_tokens as usize, usage.completion_tokens as usize, false),
+        None => (estimate_tokens(entry.prompt.chars().count()), estimate_tokens(entry.response_chars), true),
+    };
+
+    tracing::info!(
+        target: AUDIT_TARGET,
+        operation = entry.operation,
+        provider = ?config.provider,
+        model = entry.model,
+        prompt_tokens,
+        completion_tokens,
+        tokens_estimated,
+        latency_ms = entry.latency.as_millis() as u64,
+        success = entry.success,
+        prompt = %prompt,
+        "🧾 AI call"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::infrastructure::ai::mock::mock_config;
+
+    /// Destino de `tracing` en memoria para inspeccionar el log escrito.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn logged(audit: AuditConfig, prompt: &str) -> String {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            record(&audit, &mock_config(8), AuditRecord {
+                operation: "extraction",
+                model: "mock-llm",
+                prompt,
+                response_chars: 9,
+                latency: Duration::from_millis(12),
+                success: true,
+                usage: None,
+            });
+        });
+        let bytes = captured.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn disabled_audit_writes_nothing() {
+        assert!(logged(AuditConfig::default(), "La Muralla de Lugo").is_empty());
+    }
+
+    #[test]
+    fn a_call_is_logged_with_estimated_tokens_and_latency() {
+        let line = logged(AuditConfig { enabled: true, redact_content: false }, "La Muralla de Lugo");
+
+        assert!(line.contains(AUDIT_TARGET));
+        assert!(line.contains("operation=\"extraction\""));
+        assert!(line.contains("prompt_tokens=5"));
+        assert!(line.contains("completion_tokens=3"));
+        assert!(line.contains("tokens_estimated=true"));
+        assert!(line.contains("latency_ms=12"));
+        assert!(line.contains("prompt=La Muralla de Lugo"));
+    }
+
+    #[test]
+    fn redaction_replaces_the_prompt_with_its_hash() {
+        let line = logged(AuditConfig { enabled: true, redact_content: true }, "La Muralla de Lugo");
+
+        let hash = format!("sha256:{:x}", Sha256::digest("La Muralla de Lugo".as_bytes()));
+        assert!(line.contains(&hash));
+        assert!(!line.contains("Muralla"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::domain::errors::AppError;
+
+/// Umbrales del circuit breaker (se leen del entorno en main.rs).
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Fallos consecutivos que abren el circuito (0 = desactivado)
+    pub failure_threshold: u32,
+    /// Tiempo con el circuito abierto antes de probar de nuevo (half-open)
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self { failure_threshold: 0, cooldown: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed { consecutive_failures: u32 },
+    Open { since: Instant },
+    /// Una llamada de prueba en curso; el resto falla rápido hasta conocer su resultado
+    HalfOpen,
+}
+
+/// Corta las llamadas al proveedor tras N fallos seguidos y las deja pasar de nuevo
+/// (una de prueba) pasado el cooldown.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self { config, state: Mutex::new(BreakerState::Closed { consecutive_failures: 0 }) }
+    }
+
+    /// Comprueba si se puede llamar al proveedor; con el circuito abierto falla sin llamarlo.
+    pub fn before_call(&self) -> Result<(), AppError> {
+        if self.config.failure_threshold == 0 {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { since } if since.elapsed() >= self.config.cooldown => {
+                tracing::info!("🟡 AI circuit half-open: probando el proveedor");
+                *state = BreakerState::HalfOpen;
+                Ok(())
+            },
+            BreakerState::Open { since } => {
+                let retry_in = self.config.cooldown.saturating_sub(since.elapsed());
+                Err(AppError::AIUnavailable(format!("retry in {}s", retry_in.as_secs().max(1))))
+            },
+            BreakerState::HalfOpen => Err(AppError::AIUnavailable("recovery check in progress".to_string())),
+        }
+    }
+
+    /// Registra el resultado de una llamada al proveedor.
+    pub fn record(&self, success: bool) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = match (&*state, success) {
+            (BreakerState::HalfOpen, true) => {
+                tracing::info!("🟢 AI circuit closed: el proveedor responde de nuevo");
+                BreakerState::Closed { consecutive_failures: 0 }
+            },
+            (_, true) => BreakerState::Closed { consecutive_failures: 0 },
+            (BreakerState::HalfOpen, false) => {
+                tracing::warn!("🔴 AI circuit re-opened: la llamada de prueba ha fallado");
+                BreakerState::Open { since: Instant::now() }
+            },
+            (BreakerState::Closed { consecutive_failures }, false) => {
+                let failures = consecutive_failures + 1;
+                if failures >= self.config.failure_threshold {
+                    tracing::warn!("🔴 AI circuit open tras {} fallos consecutivos ({}s de cooldown)", failures, self.config.cooldown.as_secs());
+                    BreakerState::Open { since: Instant::now() }
+                } else {
+                    BreakerState::Closed { consecutive_failures: failures }
+                }
+            },
+            // Resultado tardío de una llamada iniciada antes de abrir: no cambia nada
+            (BreakerState::Open { since }, false) => BreakerState::Open { since: *since },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig { failure_threshold: 2, cooldown })
+    }
+
+    #[test]
+    fn consecutive_failures_open_the_circuit() {
+        let breaker = breaker(Duration::from_secs(60));
+        breaker.record(false);
+        breaker.record(true);
+        breaker.record(false);
+        assert!(breaker.before_call().is_ok(), "a success resets the count");
+
+        breaker.record(false);
+        assert!(matches!(breaker.before_call(), Err(AppError::AIUnavailable(_))));
+    }
+
+    #[test]
+    fn after_the_cooldown_a_single_probe_decides_whether_it_closes() {
+        let breaker = breaker(Duration::ZERO);
+        breaker.record(false);
+        breaker.record(false);
+
+        assert!(breaker.before_call().is_ok(), "the cooldown is over: one probe goes through");
+        assert!(matches!(breaker.before_call(), Err(AppError::AIUnavailable(_))), "only one probe at a time");
+        breaker.record(false);
+        assert!(breaker.before_call().is_ok());
+        breaker.record(true);
+        assert!(breaker.before_call().is_ok());
+        assert!(breaker.before_call().is_ok(), "closed again");
+    }
+
+    #[test]
+    fn a_zero_threshold_disables_the_breaker() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig::default());
+        for _ in 0..10 {
+            breaker.record(false);
+        }
+        assert!(breaker.before_call().is_ok());
+    }
+}
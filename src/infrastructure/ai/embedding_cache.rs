@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use sha2::{Digest, Sha256};
+
+/// Aciertos/fallos acumulados de la caché de embeddings (diagnóstico).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EmbeddingCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+#[derive(Default)]
+struct Entries {
+    // clave -> (vector, último uso)
+    vectors: HashMap<String, (Vec<f32>, u64)>,
+    // último uso -> clave: la primera entrada es la menos usada recientemente
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+/// Caché LRU en memoria de embeddings por `(modelo, texto)`.
+/// Evita pagar dos veces el mismo texto al re-ingerir documentos iguales o casi iguales.
+pub struct EmbeddingCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl EmbeddingCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Hash de modelo + texto: cambiar de modelo nunca devuelve vectores del anterior.
+    fn key(model: &str, text: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(model.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(text.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn get(&self, model: &str, text: &str) -> Option<Vec<f32>> {
+        let key = Self::key(model, text);
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        let Some((vector, last_used)) = entries.vectors.get_mut(&key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let previous = std::mem::replace(last_used, tick);
+        let vector = vector.clone();
+        entries.recency.remove(&previous);
+        entries.recency.insert(tick, key);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(vector)
+    }
+
+    pub fn insert(&self, model: &str, text: &str, vector: Vec<f32>) {
+        let key = Self::key(model, text);
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        if let Some((_, previous)) = entries.vectors.insert(key.clone(), (vector, tick)) {
+            entries.recency.remove(&previous);
+        }
+        entries.recency.insert(tick, key);
+
+        while entries.vectors.len() > self.capacity {
+            let Some((_, oldest)) = entries.recency.pop_first() else { break };
+            entries.vectors.remove(&oldest);
+        }
+    }
+
+    pub fn stats(&self) -> EmbeddingCacheStats {
+        EmbeddingCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap_or_else(|e| e.into_inner()).vectors.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_cached_text_is_a_hit_only_for_the_same_model() {
+        let cache = EmbeddingCache::new(10);
+        assert_eq!(cache.get("small", "Lugo"), None);
+        cache.insert("small", "Lugo", vec![1.0, 0.0]);
+
+        assert_eq!(cache.get("small", "Lugo"), Some(vec![1.0, 0.0]));
+        assert_eq!(cache.get("large", "Lugo"), None);
+        assert_eq!(cache.stats(), EmbeddingCacheStats { hits: 1, misses: 2, entries: 1 });
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted() {
+        let cache = EmbeddingCache::new(2);
+        cache.insert("m", "a", vec![1.0]);
+        cache.insert("m", "b", vec![2.0]);
+        // Leer "a" la hace reciente: al llenarse sale "b"
+        cache.get("m", "a");
+        cache.insert("m", "c", vec![3.0]);
+
+        assert_eq!(cache.get("m", "b"), None);
+        assert_eq!(cache.get("m", "a"), Some(vec![1.0]));
+        assert_eq!(cache.get("m", "c"), Some(vec![3.0]));
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn reinserting_a_key_replaces_it_without_growing() {
+        let cache = EmbeddingCache::new(2);
+        cache.insert("m", "a", vec![1.0]);
+        cache.insert("m", "a", vec![9.0]);
+        cache.insert("m", "b", vec![2.0]);
+
+        assert_eq!(cache.get("m", "a"), Some(vec![9.0]));
+        assert_eq!(cache.stats().entries, 2);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::domain::errors::AppError;
+
+/// Comprueba que un embedding devuelto por el proveedor sea utilizable.
+/// Un vector de ceros o constante (endpoint equivocado, modelo roto) deja la similitud
+/// coseno indefinida o uniforme: mejor fallar aquí que guardarlo y romper la búsqueda en silencio.
+/// Se rechaza también cualquier vector con varianza `<= min_variance`.
+pub fn check_embedding(vector: &[f32], min_variance: f64) -> Result<(), AppError> {
+    if vector.is_empty() {
+        return Err(AppError::AIError("Degenerate embedding: empty vector".to_string()));
+    }
+    if let Some(i) = vector.iter().position(|v| !v.is_finite()) {
+        return Err(AppError::AIError(format!("Degenerate embedding: non-finite value at position {}", i)));
+    }
+
+    let n = vector.len() as f64;
+    let norm = vector.iter().map(|&v| (v as f64) * (v as f64)).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return Err(AppError::AIError(format!(
+            "Degenerate embedding: all {} components are zero (check the embedding model and endpoint)",
+            vector.len()
+        )));
+    }
+
+    let mean = vector.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let variance = vector.iter().map(|&v| (v as f64 - mean).powi(2)).sum::<f64>() / n;
+    if variance <= min_variance {
+        return Err(AppError::AIError(format!(
+            "Degenerate embedding: variance {:.3e} <= minimum {:.3e} (constant vector, mean {:.4})",
+            variance, min_variance, mean
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_normal_embedding_passes() {
+        assert!(check_embedding(&[0.12, -0.4, 0.33, 0.05], 0.0).is_ok());
+    }
+
+    #[test]
+    fn empty_zero_and_non_finite_vectors_are_rejected() {
+        for vector in [vec![], vec![0.0; 8], vec![0.1, f32::NAN, 0.2]] {
+            assert!(matches!(check_embedding(&vector, 0.0), Err(AppError::AIError(_))), "{:?}", vector);
+        }
+    }
+
+    #[test]
+    fn a_constant_vector_is_rejected_even_when_not_zero() {
+        let result = check_embedding(&[0.25; 16], 0.0);
+        assert!(matches!(result, Err(AppError::AIError(message)) if message.contains("constant vector")));
+    }
+
+    #[test]
+    fn the_minimum_variance_hardens_the_check() {
+        let nearly_flat = [0.5, 0.5001, 0.4999, 0.5];
+        assert!(check_embedding(&nearly_flat, 0.0).is_ok());
+        assert!(check_embedding(&nearly_flat, 1e-6).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serde::Deserialize;
+use crate::domain::{
+    models::{AIConfig, ChatMessage, EmbeddingSlot, GraphEntity, GraphRelation, KnowledgeExtraction, InferenceResult, TokenUsage},
+    ports::{AIService, USAGE_RECORDER},
+    errors::AppError
+};
+
+/// Semilla por defecto de los embeddings simulados.
+pub const DEFAULT_MOCK_SEED: u64 = 42;
+
+/// Configuración de IA para los tests: proveedor local ficticio con `embedding_dim` dimensiones.
+#[cfg(test)]
+pub fn mock_config(embedding_dim: usize) -> AIConfig {
+    AIConfig {
+        provider: crate::domain::models::AIProvider::Ollama,
+        model_name: "mock-llm".to_string(),
+        embedding_model: "mock-embed".to_string(),
+        api_key: secrecy::SecretString::new("".into()),
+        auth_scheme: Default::default(),
+        embedding_dim,
+        base_url: None,
+        embedding_base_url: None,
+        json_mode: true,
+        granularity: Default::default(),
+        similarity_function: Default::default(),
+    }
+}
+
+/// Respuestas grabadas (fichero JSON de `AI_MOCK_FIXTURES`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MockFixtures {
+    /// Texto exacto del chunk -> extracción
+    pub extractions: HashMap<String, KnowledgeExtraction>,
+    pub inference: Option<InferenceResult>,
+    /// Mensaje exacto del chat -> respuesta
+    pub answers: HashMap<String, String>,
+}
+
+/// `AIService` determinista y sin red, para tests y CI (`AI_MOCK_SEED`).
+///
+/// - Embeddings: vector unitario derivado de (semilla, texto); mismo texto -> mismo vector.
+/// - Extracción / inferencia / chat: respuesta registrada con `with_*` para ese texto exacto;
+///   si no hay, una respuesta simulada estable (entidades = palabras en mayúscula).
+pub struct MockAIService {
+    config: RwLock<AIConfig>,
+    seed: u64,
+    extractions: HashMap<String, KnowledgeExtraction>,
+    inference: Option<InferenceResult>,
+    answers: HashMap<String, String>,
+    /// Espera antes de cada respuesta de texto (simula un proveedor lento)
+    latency: Option<std::time::Duration>,
+    completion_calls: AtomicUsize,
+    embedding_calls: AtomicUsize,
+}
+
+impl MockAIService {
+    pub fn new(config: AIConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            seed: DEFAULT_MOCK_SEED,
+            extractions: HashMap::new(),
+            inference: None,
+            answers: HashMap::new(),
+            latency: None,
+            completion_calls: AtomicUsize::new(0),
+            embedding_calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Llamadas de texto (extracción, inferencia, chat) recibidas.
+    #[cfg(test)]
+    pub fn completion_calls(&self) -> usize {
+        self.completion_calls.load(Ordering::SeqCst)
+    }
+
+    /// Llamadas de embeddings recibidas.
+    #[cfg(test)]
+    pub fn embedding_calls(&self) -> usize {
+        self.embedding_calls.load(Ordering::SeqCst)
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Extracción devuelta cuando el texto del chunk es exactamente `text`.
+    pub fn with_extraction(mut self, text: &str, extraction: KnowledgeExtraction) -> Self {
+        self.extractions.insert(text.trim().to_string(), extraction);
+        self
+    }
+
+    /// Resultado de todas las llamadas de inferencia.
+    pub fn with_inference(mut self, result: InferenceResult) -> Self {
+        self.inference = Some(result);
+        self
+    }
+
+    /// Respuesta del chat cuando el mensaje es exactamente `message`.
+    pub fn with_answer(mut self, message: &str, answer: &str) -> Self {
+        self.answers.insert(message.trim().to_string(), answer.to_string());
+        self
+    }
+
+    /// Retrasa cada extracción, inferencia y respuesta del chat.
+    #[cfg(test)]
+    pub fn with_latency(mut self, latency: std::time::Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    async fn simulate_latency(&self) {
+        if let Some(latency) = self.latency {
+            tokio::time::sleep(latency).await;
+        }
+    }
+
+    /// Carga respuestas grabadas desde un fichero JSON (`MockFixtures`).
+    pub fn with_fixtures_file(mut self, path: &str) -> Result<Self, AppError> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| AppError::ConfigError(format!("Cannot read mock fixtures {}: {}", path, e)))?;
+        let fixtures: MockFixtures = serde_json::from_str(&raw)
+            .map_err(|e| AppError::ConfigError(format!("Invalid mock fixtures {}: {}", path, e)))?;
+
+        for (text, extraction) in fixtures.extractions {
+            self = self.with_extraction(&text, extraction);
+        }
+        for (message, answer) in fixtures.answers {
+            self = self.with_answer(&message, &answer);
+        }
+        if let Some(inference) = fixtures.inference {
+            self = self.with_inference(inference);
+        }
+        Ok(self)
+    }
+}
+
+/// Uso de tokens simulado (una palabra = un token) para probar AI_INCLUDE_TOKEN_USAGE sin proveedor.
+fn report_usage(prompt: &str, response: &str) {
+    let usage = TokenUsage::new(prompt.split_whitespace().count() as u64, response.split_whitespace().count() as u64);
+    let _ = USAGE_RECORDER.try_with(|record| record(&usage));
+}
+
+/// Vector determinista de `dim` componentes derivado de `seed` y `text`.
+fn pseudo_embedding(seed: u64, dim: usize, text: &str) -> Vec<f32> {
+    let mut state = fnv1a(seed, text);
+    let mut vector: Vec<f32> = (0..dim)
+        .map(|_| (splitmix64(&mut state) >> 40) as f32 / (1u64 << 23) as f32 - 1.0)
+        .collect();
+
+    // Normalizado: la similitud coseno del índice vectorial queda en su rango habitual
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+/// FNV-1a de 64 bits: estable entre versiones de Rust (a diferencia de `DefaultHasher`).
+fn fnv1a(seed: u64, text: &str) -> u64 {
+    let mut hash = 0xcbf29ce484222325_u64 ^ seed;
+    for byte in text.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Generador splitmix64 (rápido y determinista).
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Extracción simulada: palabras que empiezan por mayúscula como entidades,
+/// enlazadas en orden de aparición con `RELATED_TO`.
+fn heuristic_extraction(text: &str) -> KnowledgeExtraction {
+    let mut names: Vec<String> = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if word.chars().count() > 2 && word.chars().next().is_some_and(char::is_uppercase) && !names.iter().any(|n| n == word) {
+            names.push(word.to_string());
+        }
+    }
+
+    let relations = names.windows(2)
+        .map(|pair| GraphRelation {
+            source: pair[0].clone(),
+            target: pair[1].clone(),
+            relation_type: "RELATED_TO".to_string(),
+            confidence: None,
+        })
+        .collect();
+    let entities = names.into_iter()
+        .map(|name| GraphEntity { name, category: "Concept".to_string(), attributes: HashMap::new() })
+        .collect();
+
+    KnowledgeExtraction { entities, relations, events: Vec::new(), temporal_relations: Vec::new() }
+}
+
+#[async_trait]
+impl AIService for MockAIService {
+    async fn extract_knowledge_raw(&self, text: &str) -> Result<(KnowledgeExtraction, String), AppError> {
+        self.completion_calls.fetch_add(1, Ordering::SeqCst);
+        self.simulate_latency().await;
+        let extraction = self.extractions.get(text.trim())
+            .cloned()
+            .unwrap_or_else(|| heuristic_extraction(text));
+        let raw = serde_json::to_string(&extraction).map_err(|e| AppError::ParseError(e.to_string()))?;
+        report_usage(text, &raw);
+        Ok((extraction, raw))
+    }
+
+    async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>, AppError> {
+        self.embedding_calls.fetch_add(1, Ordering::SeqCst);
+        Ok(pseudo_embedding(self.seed, self.get_config().embedding_dim, text))
+    }
+
+    /// Cada slot usa su dimensión y una semilla propia (vectores distintos a los del principal).
+    async fn generate_slot_embedding(&self, slot: &EmbeddingSlot, text: &str) -> Result<Vec<f32>, AppError> {
+        Ok(pseudo_embedding(fnv1a(self.seed, &slot.name), slot.dim, text))
+    }
+
+    fn update_config(&self, config: AIConfig) -> Result<(), AppError> {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+        Ok(())
+    }
+
+    fn get_config(&self) -> AIConfig {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn extraction_prompt_version(&self) -> String {
+        format!("mock-{}", self.seed)
+    }
+
+    async fn generate_inference(&self, _prompt: &str) -> Result<InferenceResult, AppError> {
+        self.completion_calls.fetch_add(1, Ordering::SeqCst);
+        self.simulate_latency().await;
+        Ok(self.inference.clone().unwrap_or(InferenceResult { new_relations: Vec::new() }))
+    }
+
+    /// Divide por " y " / " and " (determinista, sin LLM).
+    async fn decompose_query(&self, query: &str, max: usize) -> Result<Vec<String>, AppError> {
+        self.completion_calls.fetch_add(1, Ordering::SeqCst);
+        let mut queries: Vec<String> = query.split(" y ")
+            .flat_map(|part| part.split(" and "))
+            .map(|part| part.trim().trim_end_matches('?').trim().to_string())
+            .filter(|part| !part.is_empty())
+            .collect();
+        queries.truncate(max.max(1));
+        if queries.is_empty() {
+            queries.push(query.to_string());
+        }
+        Ok(queries)
+    }
+
+    async fn generate_answer(&self, system_prompt: &str, _history: &[ChatMessage], message: &str) -> Result<String, AppError> {
+        self.completion_calls.fetch_add(1, Ordering::SeqCst);
+        self.simulate_latency().await;
+        let answer = self.answers.get(message.trim())
+            .cloned()
+            .unwrap_or_else(|| format!("Respuesta simulada para: {} [1]", message.trim()));
+        report_usage(&format!("{}\n\n{}", system_prompt, message), &answer);
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Ejemplos end-to-end con `MockAIService` y `MemoryRepo`: el pipeline completo, sin red.
+    use super::*;
+    use std::sync::Arc;
+    use crate::application::ingestion::{IngestionConfig, IngestionService};
+    use crate::application::reasoning::{ReasoningConfig, ReasoningService};
+    use crate::domain::models::DocumentInput;
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    const CORPUS: &str = "La Muralla de Lugo fue construida por los Romanos. Rodea el casco antiguo de Lugo.";
+
+    async fn ingest(ai: MockAIService) -> Arc<MemoryRepo> {
+        let repo = Arc::new(MemoryRepo::new());
+        let service = IngestionService::new(repo.clone(), Arc::new(ai), IngestionConfig::default());
+        let document = DocumentInput { name: "muralla.txt".to_string(), ..Default::default() };
+        let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        // Un solo chunk: el corpus completo
+        service.ingest_with_progress(CORPUS.to_string(), document, Some(1), None, tx).await.unwrap();
+        repo
+    }
+
+    fn embeddings(repo: &MemoryRepo) -> Vec<Vec<f32>> {
+        repo.state().chunks.iter().map(|c| c.embedding.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn ingestion_is_reproducible_for_the_same_seed() {
+        let first = ingest(MockAIService::new(mock_config(16)).with_seed(7)).await;
+        let second = ingest(MockAIService::new(mock_config(16)).with_seed(7)).await;
+        let other_seed = ingest(MockAIService::new(mock_config(16)).with_seed(8)).await;
+
+        assert!(!embeddings(&first).is_empty());
+        assert_eq!(embeddings(&first), embeddings(&second));
+        assert_ne!(embeddings(&first), embeddings(&other_seed));
+        let entities = |repo: &MemoryRepo| -> Vec<String> {
+            repo.state().graphs.iter().flat_map(|(_, g)| g.entities.iter().map(|e| e.name.clone())).collect()
+        };
+        assert_eq!(entities(&first), entities(&second));
+        assert!(entities(&first).contains(&"Lugo".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_recorded_extraction_is_saved_for_its_chunk() {
+        let recorded = KnowledgeExtraction {
+            entities: vec![
+                GraphEntity { name: "Muralla de Lugo".to_string(), category: "Monument".to_string(), attributes: HashMap::new() },
+                GraphEntity { name: "Imperio Romano".to_string(), category: "Organization".to_string(), attributes: HashMap::new() },
+            ],
+            relations: vec![GraphRelation {
+                source: "Imperio Romano".to_string(),
+                target: "Muralla de Lugo".to_string(),
+                relation_type: "BUILT".to_string(),
+                confidence: Some(0.9),
+            }],
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        };
+
+        let repo = ingest(MockAIService::new(mock_config(8)).with_extraction(CORPUS, recorded)).await;
+
+        let state = repo.state();
+        assert_eq!(state.chunks.len(), 1);
+        let (chunk_id, graph) = &state.graphs[0];
+        assert_eq!(*chunk_id, state.chunks[0].id);
+        assert_eq!(graph.relations[0].relation_type, "BUILT");
+        assert_eq!(graph.entities.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fixtures_drive_chat_answers_and_reasoning() {
+        let path = std::env::temp_dir().join(format!("mock-fixtures-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, serde_json::json!({
+            "answers": { "¿Quién construyó la muralla?": "Los romanos, en el siglo III." },
+            "inference": { "new_relations": [{
+                "source": "Imperio Romano", "target": "Lugo", "relation": "FOUNDED",
+                "reasoning": "Construyó su muralla", "confidence_level": "high"
+            }] }
+        }).to_string()).unwrap();
+        let ai = Arc::new(MockAIService::new(mock_config(8)).with_fixtures_file(path.to_str().unwrap()).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let answer = ai.generate_answer("contexto", &[], "¿Quién construyó la muralla?").await.unwrap();
+        assert_eq!(answer, "Los romanos, en el siglo III.");
+
+        let repo = Arc::new(MemoryRepo::new());
+        let relations = ReasoningService::new(repo.clone(), ai, ReasoningConfig::default())
+            .infer_new_knowledge().await.unwrap();
+        assert_eq!(relations.len(), 1);
+        assert_eq!(repo.state().inferred[0].relation, "FOUNDED");
+    }
+
+    #[test]
+    fn an_unreadable_fixtures_file_is_a_config_error() {
+        let result = MockAIService::new(mock_config(8)).with_fixtures_file("/nonexistent/fixtures.json");
+        assert!(matches!(result, Err(AppError::ConfigError(_))));
+    }
+}
@@ -1,2 +1,12 @@
 pub mod rig_client;
+pub mod anthropic;
+pub mod audit;
+pub mod circuit_breaker;
+pub mod openai_compat;
+pub mod retry;
+pub mod embedding_check;
+pub mod embedding_cache;
+// Soporte de tests; en el binario solo con la feature `mock-ai` (AI_MOCK_SEED)
+#[cfg(any(test, feature = "mock-ai"))]
+pub mod mock;
 // pub mod extractors; // Descomentar si creaste este archivo
\ No newline at end of file
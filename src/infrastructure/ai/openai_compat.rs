@@ -0,0 +1,132 @@
+//! Cliente mínimo compatible OpenAI (Chat Completions y Embeddings) para pasarelas que esperan
+//! la API key como parámetro de la URL (`AuthScheme::Query`): rig solo la envía en cabeceras.
+use reqwest::header::CONTENT_TYPE;
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::domain::models::{AIConfig, AuthScheme, ChatMessage, ChatRole, TokenUsage};
+
+pub const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    #[serde(default)]
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+/// POST a `{base_url}/{path}` con la clave en el parámetro de `AuthScheme::Query`.
+/// Devuelve el cuerpo de una respuesta 2xx; los errores nunca incluyen la URL (lleva la clave).
+async fn post(config: &AIConfig, base_url: Option<&str>, path: &str, body: &Value) -> Result<String, String> {
+    let base_url = base_url.unwrap_or(DEFAULT_OPENAI_BASE_URL);
+    let url = format!("{}/{}", base_url.trim_end_matches('/'), path);
+
+    let mut request = reqwest::Client::new()
+        .post(&url)
+        .header(CONTENT_TYPE, "application/json");
+    let api_key = config.api_key.expose_secret();
+    if let AuthScheme::Query(name) = &config.auth_scheme {
+        if !api_key.is_empty() {
+            request = request.query(&[(name.as_str(), api_key)]);
+        }
+    }
+
+    let response = request.json(body).send().await.map_err(|e| e.without_url().to_string())?;
+    let status = response.status();
+    let raw = response.text().await.map_err(|e| e.without_url().to_string())?;
+    if !status.is_success() {
+        let message = serde_json::from_str::<ErrorResponse>(&raw)
+            .map(|e| e.error.message)
+            .unwrap_or(raw);
+        return Err(format!("Provider API {}: {}", status, message));
+    }
+    Ok(raw)
+}
+
+/// Una ronda de Chat Completions. Con `schema`, se pide `response_format` con ese JSON Schema.
+/// `history` va como turnos previos a `prompt`. Devuelve también el uso de tokens (`usage`).
+pub async fn complete(config: &AIConfig, system: Option<&str>, history: &[ChatMessage], prompt: &str, schema: Option<&serde_json::Value>) -> Result<(String, Option<TokenUsage>), String> {
+    let mut messages = Vec::new();
+    if let Some(system) = system {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    for m in history {
+        let role = match m.role {
+            ChatRole::User => "user",
+            ChatRole::Assistant => "assistant",
+        };
+        messages.push(json!({ "role": role, "content": m.content }));
+    }
+    messages.push(json!({ "role": "user", "content": prompt }));
+
+    let mut body = json!({ "model": config.model_name, "messages": messages });
+    if let Some(schema) = schema {
+        body["response_format"] = json!({
+            "type": "json_schema",
+            "json_schema": { "name": "structured_output", "schema": schema, "strict": false }
+        });
+    }
+
+    let raw = post(config, config.base_url.as_deref(), "chat/completions", &body).await?;
+    let parsed: ChatResponse = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid completion response: {}", e))?;
+    let usage = parsed.usage.as_ref().map(|u| TokenUsage::new(u.prompt_tokens, u.completion_tokens));
+    let text = parsed.choices.into_iter()
+        .next()
+        .and_then(|choice| choice.message.content)
+        .unwrap_or_default();
+    Ok((text, usage))
+}
+
+/// Embedding de `text` con `model_name` en `base_url` (por defecto api.openai.com).
+pub async fn embed(config: &AIConfig, base_url: Option<&str>, model_name: &str, text: &str) -> Result<Vec<f64>, String> {
+    let body = json!({ "model": model_name, "input": text });
+    let raw = post(config, base_url, "embeddings", &body).await?;
+    let parsed: EmbeddingResponse = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid embedding response: {}", e))?;
+    parsed.data.into_iter()
+        .next()
+        .map(|d| d.embedding)
+        .ok_or_else(|| "No embedding returned".to_string())
+}
@@ -0,0 +1,121 @@
+use std::future::Future;
+use std::time::Duration;
+use crate::domain::{errors::AppError, ports::RETRY_NOTIFIER};
+
+/// Reintentos ante errores transitorios del proveedor (se leen del entorno en main.rs).
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Reintentos tras el primer intento (0 = desactivado)
+    pub max_retries: u32,
+    /// Espera antes del primer reintento; se duplica en cada uno
+    pub base_delay: Duration,
+    /// Tope de la espera entre reintentos
+    pub max_delay: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self { max_retries: 0, base_delay: Duration::from_millis(500), max_delay: Duration::from_secs(10) }
+    }
+}
+
+impl RetryConfig {
+    /// Espera antes del reintento `retry` (1, 2, ...): base, 2·base, 4·base... hasta `max_delay`.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Ejecuta `call` reintentando con backoff exponencial solo los errores transitorios
+/// (red, 5xx, rate limit). El resto (parseo, configuración, circuito abierto) sale al instante.
+/// Cada reintento se avisa al `RETRY_NOTIFIER` de la tarea, si lo hay.
+pub async fn with_retries<T, F, Fut>(config: &RetryConfig, operation: &str, mut call: F) -> Result<T, AppError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, AppError>>,
+{
+    let mut retry = 0;
+    loop {
+        match call().await {
+            Err(e) if retry < config.max_retries && e.is_transient() => {
+                retry += 1;
+                let delay = config.backoff(retry);
+                tracing::warn!("🔁 {} failed ({}), retry {}/{} in {}ms", operation, e, retry, config.max_retries, delay.as_millis());
+                let _ = RETRY_NOTIFIER.try_with(|notify| notify(retry + 1, config.max_retries + 1, &e));
+                tokio::time::sleep(delay).await;
+            },
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+    use crate::domain::ports::RetryNotifier;
+
+    fn config(max_retries: u32) -> RetryConfig {
+        RetryConfig { max_retries, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(2) }
+    }
+
+    #[test]
+    fn the_backoff_doubles_up_to_the_cap() {
+        let config = RetryConfig { max_retries: 5, base_delay: Duration::from_millis(500), max_delay: Duration::from_secs(3) };
+        let delays: Vec<u128> = (1..=5).map(|retry| config.backoff(retry).as_millis()).collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 3000, 3000]);
+    }
+
+    #[test]
+    fn only_network_server_and_rate_limit_errors_are_transient() {
+        assert!(AppError::Timeout("embedding".into()).is_transient());
+        assert!(AppError::AIError("Provider API 429 Too Many Requests: slow down".into()).is_transient());
+        assert!(AppError::AIError("Provider API 503: Service Unavailable".into()).is_transient());
+        assert!(AppError::AIError("error sending request: connection refused".into()).is_transient());
+        assert!(!AppError::AIError("Provider API 401: invalid api key".into()).is_transient());
+        assert!(!AppError::ParseError("expected value".into()).is_transient());
+        assert!(!AppError::ConfigError("missing key".into()).is_transient());
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_and_announced_to_the_notifier() {
+        let calls = AtomicU32::new(0);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let notifier: RetryNotifier = {
+            let seen = seen.clone();
+            Arc::new(move |attempt, max, _: &AppError| seen.lock().unwrap().push((attempt, max)))
+        };
+
+        let result = RETRY_NOTIFIER.scope(notifier, with_retries(&config(3), "Embedding", || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(AppError::AIError("Provider API 503: overloaded".into())),
+                _ => Ok("vector"),
+            }
+        })).await;
+
+        assert_eq!(result.unwrap(), "vector");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(*seen.lock().unwrap(), vec![(2, 4), (3, 4)]);
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_and_exhausted_retries_return_the_last_error() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = with_retries(&config(3), "Extraction", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(AppError::AIError("Provider API 400: bad request".into()))
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = with_retries(&config(2), "Extraction", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(AppError::Timeout("extraction".into()))
+        }).await;
+        assert!(matches!(result, Err(AppError::Timeout(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -2,111 +2,657 @@ use async_trait::async_trait;
 use rig::{
     providers::openai::{self, OpenAIResponsesExt},
     client::{CompletionClient, EmbeddingsClient},
-    completion::Prompt,
+    completion::{Message, Prompt},
     embeddings::EmbeddingsBuilder,
 };
+use std::collections::HashSet;
+use std::sync::RwLock;
+use std::time::Instant;
 use secrecy::ExposeSecret;
-use serde_json::from_str;
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
-use crate::domain::{models::{AIConfig, KnowledgeExtraction, InferenceResult}, ports::AIService, errors::AppError};
+use serde::de::DeserializeOwned;
+use serde_json::{from_str, json};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use crate::domain::{models::{AIConfig, AIProvider, AuthScheme, ChatMessage, ChatRole, EmbeddingSlot, ExtractionGranularity, GraphEntity, GraphEvent, GraphRelation, KnowledgeExtraction, InferenceResult, TemporalRelation, TokenUsage}, ports::{AIService, USAGE_RECORDER}, errors::AppError};
+use super::anthropic;
+use super::openai_compat;
+use super::audit::{self, AuditConfig, AuditRecord};
+use super::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use super::retry::{with_retries, RetryConfig};
+use super::embedding_check::check_embedding;
+use super::embedding_cache::{EmbeddingCache, EmbeddingCacheStats};
+
+const EXTRACTION_PREAMBLE: &str = "You are an expert Ontology Engineer. Extract entities and relationships from the text. \
+    When the text states concrete values about an entity (dates, amounts, quantities, measurements), \
+    add them to its optional \"attributes\" object using ISO-8601 dates and plain numbers (no units or currency symbols in numbers). \
+    Return strictly JSON format matching this structure: \
+    { \"entities\": [{\"name\": \"...\", \"category\": \"...\", \"attributes\": {\"date\": \"2024-01-15\", \"amount\": 50000}}], \"relations\": [{\"source\": \"...\", \"target\": \"...\", \"relation_type\": \"...\", \"confidence\": 0.9}] } \
+    where the optional \"confidence\" (0.0 to 1.0) says how explicitly the text states the relation.";
+
+/// Subir al cambiar `EXTRACTION_PREAMBLE` o las pautas de granularidad: se guarda en cada
+/// chunk para poder re-extraer selectivamente los procesados con un prompt antiguo.
+pub const EXTRACTION_PROMPT_VERSION: &str = "v2";
+
+/// Tope de re-preguntas por JSON inválido en una extracción (cada una es otra llamada al LLM).
+pub const MAX_EXTRACTION_PARSE_RETRIES: u32 = 2;
+
+/// Extracción en dos etapas, etapa 1: solo entidades (y sucesos, si están activados).
+const ENTITY_PREAMBLE: &str = "You are an expert Ontology Engineer. Extract the entities mentioned in the text. \
+    When the text states concrete values about an entity (dates, amounts, quantities, measurements), \
+    add them to its optional \"attributes\" object using ISO-8601 dates and plain numbers (no units or currency symbols in numbers). \
+    Return strictly JSON format matching this structure: \
+    { \"entities\": [{\"name\": \"...\", \"category\": \"...\", \"attributes\": {\"date\": \"2024-01-15\", \"amount\": 50000}}] }.";
+
+/// Añadido al preámbulo con la extracción de sucesos activada.
+const EVENTS_PREAMBLE: &str = "Also extract the events the text narrates (battles, signings, launches, meetings...) in an \"events\" array: \
+    [{\"name\": \"...\", \"date\": \"1492-10-12\", \"participants\": [\"entity name\"]}] (date optional, ISO-8601 or year), \
+    and their chronological order in a \"temporal_relations\" array: [{\"before\": \"earlier event\", \"after\": \"later event\"}], \
+    only when the text states or clearly implies the order. Use empty arrays if there are no events.";
+
+/// Preámbulo de extracción con la pauta de granularidad configurada.
+fn extraction_preamble(granularity: ExtractionGranularity, events: bool) -> String {
+    with_guidelines(EXTRACTION_PREAMBLE, granularity, events)
+}
+
+/// Etapa 1 de la extracción en dos etapas: solo entidades, con las mismas pautas.
+fn entity_preamble(granularity: ExtractionGranularity, events: bool) -> String {
+    with_guidelines(ENTITY_PREAMBLE, granularity, events)
+}
+
+/// Añade a `base` la pauta de granularidad y, si procede, la petición de sucesos.
+fn with_guidelines(base: &str, granularity: ExtractionGranularity, events: bool) -> String {
+    let hint = match granularity {
+        ExtractionGranularity::Coarse => "Prefer few, broad entities: keep multi-word names of organizations, places and works as a single entity \
+            (e.g. \"New York City Police Department\") and skip minor details.",
+        ExtractionGranularity::Balanced => "Keep well-known multi-word names as a single entity and only extract sub-parts when the text discusses them on their own.",
+        ExtractionGranularity::Fine => "Prefer specific entities: also extract meaningful sub-parts, units, roles and places as separate entities \
+            (e.g. \"New York City\" and \"New York City Police Department\") linked by relations.",
+    };
+    if events {
+        format!("{} {} {}", base, hint, EVENTS_PREAMBLE)
+    } else {
+        format!("{} {}", base, hint)
+    }
+}
+
+/// Etapa 2: relaciones solo entre las entidades de la etapa 1, con sus nombres exactos.
+fn relation_preamble(entities: &[&str]) -> String {
+    format!(
+        "You are an expert Ontology Engineer. Extract the relationships the text states between these entities, \
+         using their names exactly as listed: {}. Do not introduce any other entity. \
+         Return strictly JSON format matching this structure: \
+         {{ \"relations\": [{{\"source\": \"...\", \"target\": \"...\", \"relation_type\": \"...\", \"confidence\": 0.9}}] }} \
+         where the optional \"confidence\" (0.0 to 1.0) says how explicitly the text states the relation.",
+        json!(entities)
+    )
+}
+
+/// Esquema de `KnowledgeExtraction` que el proveedor hace cumplir con salida estructurada.
+/// No es estricto: `attributes` es un objeto libre.
+fn extraction_schema(events: bool) -> serde_json::Value {
+    stage_schema(true, true, events)
+}
+
+/// Esquema con solo las partes pedidas (la extracción en dos etapas pide cada una por separado).
+fn stage_schema(entities: bool, relations: bool, events: bool) -> serde_json::Value {
+    let mut properties = json!({});
+    let mut required = Vec::new();
+    if entities {
+        properties["entities"] = json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "category": { "type": "string" },
+                    "attributes": { "type": "object" }
+                },
+                "required": ["name", "category"]
+            }
+        });
+        required.push("entities");
+    }
+    if relations {
+        properties["relations"] = json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "source": { "type": "string" },
+                    "target": { "type": "string" },
+                    "relation_type": { "type": "string" },
+                    "confidence": { "type": "number", "minimum": 0.0, "maximum": 1.0 }
+                },
+                "required": ["source", "target", "relation_type"]
+            }
+        });
+        required.push("relations");
+    }
+    if events {
+        properties["events"] = json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "date": { "type": "string" },
+                    "participants": { "type": "array", "items": { "type": "string" } }
+                },
+                "required": ["name"]
+            }
+        });
+        properties["temporal_relations"] = json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "before": { "type": "string" },
+                    "after": { "type": "string" }
+                },
+                "required": ["before", "after"]
+            }
+        });
+        required.extend(["events", "temporal_relations"]);
+    }
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// Primer objeto JSON completo de una respuesta con texto alrededor
+/// ("Here is the JSON: ```json {...} ``` Hope it helps"). Sin objeto, devuelve el texto recortado.
+fn extract_json_object(raw: &str) -> &str {
+    let Some(start) = raw.find('{') else {
+        return raw.trim();
+    };
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (offset, c) in raw[start..].char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {},
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &raw[start..=start + offset];
+                }
+            },
+            _ => {},
+        }
+    }
+    raw[start..].trim()
+}
+
+/// Re-pregunta tras un JSON inválido: el texto original más el error y el esquema esperado.
+fn parse_retry_prompt(text: &str, error: &str, structure: &str) -> String {
+    format!(
+        "{}\n\nYour previous output failed to parse: {}. \
+         Return ONLY valid JSON (no markdown, no comments) with the structure \
+         {} described in the instructions.",
+        text, error, structure
+    )
+}
+
+/// Respuesta de la etapa 1 de la extracción en dos etapas.
+#[derive(serde::Deserialize)]
+struct EntityStage {
+    entities: Vec<GraphEntity>,
+    #[serde(default)]
+    events: Vec<GraphEvent>,
+    #[serde(default)]
+    temporal_relations: Vec<TemporalRelation>,
+}
+
+/// Respuesta de la etapa 2.
+#[derive(serde::Deserialize)]
+struct RelationStage {
+    relations: Vec<GraphRelation>,
+}
 
 pub struct RigAIService {
-    config: AIConfig,
+    // Lock síncrono y breve: cada llamada trabaja sobre una copia de la configuración,
+    // así `update_config` nunca espera a un embedding/extracción en curso.
+    config: RwLock<AIConfig>,
+    audit: AuditConfig,
+    breaker: CircuitBreaker,
+    // Reintentos por operación: embeddings (perder uno deja un chunk sin recuperar) y completados (chat, extracción...)
+    embedding_retry: RetryConfig,
+    completion_retry: RetryConfig,
+    // Varianza mínima de un embedding válido (ver `check_embedding`)
+    min_embedding_variance: f64,
+    // Reintentos de extracción cuando el JSON no se puede leer (0 - MAX_EXTRACTION_PARSE_RETRIES)
+    extraction_parse_retries: u32,
+    // Pedir también sucesos y su orden temporal en la extracción
+    extract_events: bool,
+    // Entidades y relaciones en dos llamadas (las relaciones solo entre entidades ya extraídas)
+    two_stage_extraction: bool,
+    // Embeddings ya calculados por (modelo, texto); None = sin caché
+    embedding_cache: Option<EmbeddingCache>,
 }
 
 impl RigAIService {
     pub fn new(config: AIConfig) -> Self {
-        Self { config }
+        Self {
+            config: RwLock::new(config),
+            audit: AuditConfig::default(),
+            breaker: CircuitBreaker::new(CircuitBreakerConfig::default()),
+            embedding_retry: RetryConfig::default(),
+            completion_retry: RetryConfig::default(),
+            min_embedding_variance: 0.0,
+            extraction_parse_retries: 0,
+            extract_events: false,
+            two_stage_extraction: false,
+            embedding_cache: None,
+        }
+    }
+
+    /// Caché LRU de hasta `capacity` embeddings: un texto repetido no vuelve a llamar al proveedor.
+    pub fn with_embedding_cache(mut self, capacity: usize) -> Self {
+        self.embedding_cache = Some(EmbeddingCache::new(capacity));
+        self
+    }
+
+    /// Aciertos y fallos de la caché de embeddings (None si está desactivada).
+    pub fn embedding_cache_stats(&self) -> Option<EmbeddingCacheStats> {
+        self.embedding_cache.as_ref().map(EmbeddingCache::stats)
+    }
+
+    /// Si la extracción devuelve JSON inválido, se repite la pregunta incluyendo el error de parseo
+    /// para que el modelo se corrija. Acotado a `MAX_EXTRACTION_PARSE_RETRIES`.
+    pub fn with_extraction_parse_retries(mut self, retries: u32) -> Self {
+        self.extraction_parse_retries = retries.min(MAX_EXTRACTION_PARSE_RETRIES);
+        self
+    }
+
+    /// Extrae además sucesos (`:Event`) y su orden temporal (`BEFORE`) para la línea temporal.
+    pub fn with_event_extraction(mut self, enabled: bool) -> Self {
+        self.extract_events = enabled;
+        self
+    }
+
+    /// Extrae primero las entidades y después, en otra llamada con esa lista, solo las relaciones
+    /// entre ellas: ninguna relación apunta a una entidad no extraída (a costa del doble de llamadas).
+    pub fn with_two_stage_extraction(mut self, enabled: bool) -> Self {
+        self.two_stage_extraction = enabled;
+        self
     }
 
+    /// Rechaza embeddings con varianza `<= min_variance` (0.0: solo ceros y vectores constantes).
+    pub fn with_min_embedding_variance(mut self, min_variance: f64) -> Self {
+        self.min_embedding_variance = min_variance;
+        self
+    }
+
+    /// Falla rápido tras `failure_threshold` errores seguidos del proveedor.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = CircuitBreaker::new(config);
+        self
+    }
+
+    /// Reintenta con backoff exponencial los errores transitorios, con ajustes distintos para
+    /// embeddings y para completados (extracción, inferencia, chat).
+    pub fn with_retries(mut self, embedding: RetryConfig, completion: RetryConfig) -> Self {
+        self.embedding_retry = embedding;
+        self.completion_retry = completion;
+        self
+    }
+
+    /// Registra cada llamada al proveedor en el log de auditoría.
+    pub fn with_audit(mut self, audit: AuditConfig) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Copia de la configuración actual (el lock se libera al instante)
+    fn snapshot(&self) -> AIConfig {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Respaldo sin salida estructurada: quita bloques ```json y la prosa alrededor del objeto.
     fn clean_json_response(&self, raw: &str) -> String {
-        raw.trim()
-            .trim_start_matches("```json")
-            .trim_start_matches("```")
-            .trim_end_matches("```")
-            .to_string()
+        extract_json_object(raw).to_string()
+    }
+
+    /// Salida estructurada (JSON Schema impuesto por el proveedor) activa para la configuración dada
+    fn structured_output_enabled(config: &AIConfig) -> bool {
+        config.json_mode && config.provider.supports_structured_output()
     }
-    
-    fn get_client(&self) -> openai::Client {
-        let base_url = self.config.base_url.as_deref().unwrap_or("https://api.openai.com/v1");
-        let api_key = self.config.api_key.expose_secret();
 
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    /// Embedding de `text` con `model_name` (el principal o el de un slot) y el proveedor de `config`.
+    async fn embed(&self, config: &AIConfig, model_name: &str, text: &str) -> Result<Vec<f32>, AppError> {
+        if matches!(config.provider, AIProvider::Anthropic) && config.embedding_base_url.is_none() {
+            return Err(AppError::ConfigError("Anthropic has no embeddings API: set AI_EMBEDDING_BASE_URL".to_string()));
+        }
+        if let Some(cached) = self.embedding_cache.as_ref().and_then(|c| c.get(model_name, text)) {
+            if let Some(stats) = self.embedding_cache_stats() {
+                tracing::debug!("♻️ Embedding en caché ({} aciertos, {} fallos, {} entradas)", stats.hits, stats.misses, stats.entries);
+            }
+            return Ok(cached);
+        }
+        let embedding = with_retries(&self.embedding_retry, "Embedding", || async {
+            self.breaker.before_call()?;
+            let started = Instant::now();
+            let result = Self::embed_once(config, model_name, text).await;
+            self.breaker.record(result.is_ok());
+            audit::record(&self.audit, config, AuditRecord {
+                operation: "embedding",
+                model: model_name,
+                prompt: text,
+                response_chars: 0,
+                latency: started.elapsed(),
+                success: result.is_ok(),
+                usage: None,
+            });
+            result.map_err(|e| AppError::AIError(format!("Embedding failed (Provider: {:?}): {}", config.provider, e)))
+        }).await?;
+
+        let embedding_f32: Vec<f32> = embedding.iter().map(|&x| x as f32).collect();
+        check_embedding(&embedding_f32, self.min_embedding_variance)?;
+        if let Some(cache) = &self.embedding_cache {
+            cache.insert(model_name, text, embedding_f32.clone());
+        }
         
-        if !api_key.is_empty() {
-            if let Ok(mut val) = HeaderValue::from_str(&format!("Bearer {}", api_key)) {
-                val.set_sensitive(true);
-                headers.insert(AUTHORIZATION, val);
+        Ok(embedding_f32)
+    }
+
+    /// Cierra una llamada de completado: circuito, auditoría y uso de tokens (al `USAGE_RECORDER`
+    /// de la tarea, si lo hay). Devuelve solo el texto.
+    fn track(&self, config: &AIConfig, operation: &str, prompt: &str, started: Instant, result: Result<(String, Option<TokenUsage>), String>) -> Result<String, String> {
+        self.breaker.record(result.is_ok());
+        let usage = result.as_ref().ok().and_then(|(_, usage)| *usage);
+        audit::record(&self.audit, config, AuditRecord {
+            operation,
+            model: &config.model_name,
+            prompt,
+            response_chars: result.as_ref().map(|(r, _)| r.chars().count()).unwrap_or(0),
+            latency: started.elapsed(),
+            success: result.is_ok(),
+            usage,
+        });
+        if let Some(usage) = usage {
+            let _ = USAGE_RECORDER.try_with(|record| record(&usage));
+        }
+        result.map(|(text, _)| text)
+    }
+
+    /// Lee como `T` la respuesta de `call(prompt, intento)`, la llamada al modelo. Si el JSON no se
+    /// puede leer, se vuelve a llamar con el error en el prompt (hasta `extraction_parse_retries` veces).
+    async fn parse_with_retries<T, F, Fut>(&self, structured: bool, text: &str, structure: &str, mut call: F) -> Result<(T, String), AppError>
+    where
+        T: DeserializeOwned,
+        F: FnMut(String, u32) -> Fut,
+        Fut: std::future::Future<Output = Result<String, AppError>>,
+    {
+        let mut prompt = text.to_string();
+        let mut attempt = 0;
+        loop {
+            let response = call(prompt.clone(), attempt).await?;
+
+            // Sin salida estructurada, se aísla el objeto JSON de la prosa y los bloques ```json
+            let cleaned_json = if structured {
+                response.clone()
+            } else {
+                self.clean_json_response(&response)
+            };
+
+            match from_str::<T>(&cleaned_json) {
+                Ok(parsed) => return Ok((parsed, response)),
+                Err(e) if attempt < self.extraction_parse_retries => {
+                    attempt += 1;
+                    tracing::warn!("🔁 Extraction JSON invalid ({}), re-prompting ({}/{})", e, attempt, self.extraction_parse_retries);
+                    prompt = parse_retry_prompt(text, &e.to_string(), structure);
+                },
+                Err(e) => return Err(AppError::ParseError(format!("Failed to parse JSON: {} - Raw: {}", e, cleaned_json))),
+            }
+        }
+    }
+    
+    /// Una llamada de extracción que devuelve `T` y la respuesta en bruto. `schema` solo se envía
+    /// si el proveedor admite salida estructurada; `structure` se recuerda al re-preguntar.
+    async fn extract_json<T: DeserializeOwned>(
+        &self,
+        config: &AIConfig,
+        operation: &str,
+        preamble: &str,
+        text: &str,
+        schema: serde_json::Value,
+        structure: &str,
+    ) -> Result<(T, String), AppError> {
+        let structured = Self::structured_output_enabled(config);
+        let schema = structured.then_some(schema);
+        let retry_operation = format!("{}_retry", operation);
+
+        // Si el JSON no se puede leer, se vuelve a preguntar con el error (hasta `extraction_parse_retries` veces)
+        self.parse_with_retries(structured, text, structure, |prompt, attempt| {
+            let (schema, retry_operation) = (schema.as_ref(), retry_operation.as_str());
+            async move {
+                with_retries(&self.completion_retry, "Extraction", || async {
+                    self.breaker.before_call()?;
+                    let started = Instant::now();
+                    let result = complete(config, Some(preamble), &[], &prompt, schema).await;
+                    let operation = if attempt == 0 { operation } else { retry_operation };
+                    let result = self.track(config, operation, &format!("{}\n\n{}", preamble, prompt), started, result);
+                    result.map_err(|e| AppError::AIError(format!("Extraction failed: {}", e)))
+                }).await
             }
+        }).await
+    }
+
+    /// Cliente para embeddings: usa `embedding_base_url` si está configurado
+    fn get_embedding_client(config: &AIConfig) -> openai::Client {
+        build_client(config, Self::embedding_base_url(config))
+    }
+
+    fn embedding_base_url(config: &AIConfig) -> Option<&str> {
+        config.embedding_base_url.as_deref()
+            .or(config.base_url.as_deref())
+    }
+
+    /// Una llamada de embedding al proveedor.
+    /// Con `AuthScheme::Query` va por el cliente propio: rig no admite la clave en la URL.
+    async fn embed_once(config: &AIConfig, model_name: &str, text: &str) -> Result<Vec<f64>, String> {
+        if matches!(config.auth_scheme, AuthScheme::Query(_)) {
+            return openai_compat::embed(config, Self::embedding_base_url(config), model_name, text).await;
         }
 
-        openai::Client::from_parts(
-            base_url.to_string(),
-            headers,
-            reqwest::Client::new(),
-            OpenAIResponsesExt,
-        )
+        let model = Self::get_embedding_client(config).embedding_model(model_name);
+        let embeddings = EmbeddingsBuilder::new(model)
+            .document(text)
+            .map_err(|e| format!("Error adding document: {}", e))?
+            .build()
+            .await
+            .map_err(|e| e.to_string())?;
+        let (_, embedding) = embeddings.first().ok_or_else(|| "No embedding returned".to_string())?;
+        Ok(embedding.first().vec)
+    }
+}
+
+/// Una llamada de completado con `config`: Anthropic va por su Messages API; el resto, por
+/// el cliente compatible OpenAI de rig (o el propio de `openai_compat` si la clave va en la URL).
+/// Con `schema`, la respuesta es el JSON que cumple ese esquema (solo si el proveedor lo admite).
+/// `history` son turnos previos de conversación (vacío salvo en el chat).
+/// Devuelve también el uso de tokens informado por el proveedor.
+pub async fn complete(config: &AIConfig, preamble: Option<&str>, history: &[ChatMessage], prompt: &str, schema: Option<&serde_json::Value>) -> Result<(String, Option<TokenUsage>), String> {
+    if matches!(config.provider, AIProvider::Anthropic) {
+        return anthropic::complete(config, preamble, history, prompt, schema).await;
+    }
+    if matches!(config.auth_scheme, AuthScheme::Query(_)) {
+        return openai_compat::complete(config, preamble, history, prompt, schema).await;
+    }
+
+    let client = build_client(config, config.base_url.as_deref());
+    let mut builder = client.agent(&config.model_name);
+    if let Some(preamble) = preamble {
+        builder = builder.preamble(preamble);
     }
+    if let Some(schema) = schema {
+        // Responses API: el proveedor garantiza un objeto que cumple el esquema
+        builder = builder.additional_params(json!({
+            "text": { "format": { "type": "json_schema", "name": "structured_output", "schema": schema, "strict": false } }
+        }));
+    }
+
+    let agent = builder.build();
+    let mut history: Vec<Message> = history.iter()
+        .map(|m| match m.role {
+            ChatRole::User => Message::user(m.content.clone()),
+            ChatRole::Assistant => Message::assistant(m.content.clone()),
+        })
+        .collect();
+    let response = agent.prompt(prompt)
+        .with_history(&mut history)
+        .extended_details()
+        .await
+        .map_err(|e| e.to_string())?;
+    let usage = response.total_usage;
+    // Proveedores compatibles que no informan del uso devuelven ceros
+    let usage = (usage.total_tokens > 0).then_some(TokenUsage {
+        prompt_tokens: usage.input_tokens,
+        completion_tokens: usage.output_tokens,
+        total_tokens: usage.total_tokens,
+    });
+    Ok((response.output, usage))
+}
+
+/// Cabeceras de autenticación según `auth_scheme`.
+/// La clave se marca como sensible para que nunca aparezca en logs/Debug.
+fn auth_headers(config: &AIConfig) -> HeaderMap {
+    let api_key = config.api_key.expose_secret();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    if api_key.is_empty() {
+        return headers;
+    }
+
+    // El nombre de la cabecera ya se validó al leer `auth_scheme`; con `query:` no va en cabeceras
+    let (name, value) = match &config.auth_scheme {
+        AuthScheme::Bearer => (AUTHORIZATION, format!("Bearer {}", api_key)),
+        AuthScheme::Header(name) => match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(header) => (header, api_key.to_string()),
+            Err(_) => return headers,
+        },
+        AuthScheme::Query(_) => return headers,
+    };
+
+    if let Ok(mut val) = HeaderValue::from_str(&value) {
+        val.set_sensitive(true);
+        headers.insert(name, val);
+    }
+    headers
+}
+
+/// Cliente compatible OpenAI para `base_url` (por defecto api.openai.com) con la auth configurada.
+pub fn build_client(config: &AIConfig, base_url: Option<&str>) -> openai::Client {
+    let base_url = base_url.unwrap_or("https://api.openai.com/v1");
+
+    openai::Client::from_parts(
+        base_url.to_string(),
+        auth_headers(config),
+        reqwest::Client::new(),
+        OpenAIResponsesExt,
+    )
 }
 
 #[async_trait]
 impl AIService for RigAIService {
-    fn update_config(&mut self, config: AIConfig) -> Result<(), AppError> {
-        self.config = config;
+    fn update_config(&self, config: AIConfig) -> Result<(), AppError> {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
         Ok(())
     }
 
     fn get_config(&self) -> AIConfig {
-        self.config.clone()
+        self.snapshot()
     }
 
-    async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>, AppError> {
-        let client = self.get_client(); 
-        let model = client.embedding_model(&self.config.embedding_model);
-        
-        let embeddings = EmbeddingsBuilder::new(model)
-            .document(text) 
-            .map_err(|e| AppError::AIError(format!("Error adding document: {}", e)))? 
-            .build()
-            .await
-            .map_err(|e| AppError::AIError(format!("Embedding failed (Provider: {:?}): {}", self.config.provider, e)))?;
+    fn extraction_prompt_version(&self) -> String {
+        let granularity = format!("{:?}", self.snapshot().granularity).to_lowercase();
+        let events = if self.extract_events { "-events" } else { "" };
+        let stages = if self.two_stage_extraction { "-2stage" } else { "" };
+        format!("{}-{}{}{}", EXTRACTION_PROMPT_VERSION, granularity, events, stages)
+    }
 
-        let (_, embedding_data) = embeddings.first()
-            .ok_or_else(|| AppError::AIError("No embedding returned".to_string()))?;
-            
-        let first_embedding = embedding_data.first();
-        let embedding_f32: Vec<f32> = first_embedding.vec.iter().map(|&x| x as f32).collect();
-        
-        Ok(embedding_f32)
+    async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>, AppError> {
+        let config = self.snapshot();
+        self.embed(&config, &config.embedding_model, text).await
     }
 
-    async fn extract_knowledge(&self, text: &str) -> Result<KnowledgeExtraction, AppError> {
-        let client = self.get_client(); 
+    async fn generate_slot_embedding(&self, slot: &EmbeddingSlot, text: &str) -> Result<Vec<f32>, AppError> {
+        let config = self.snapshot();
+        self.embed(&config, &slot.model, text).await
+    }
 
-        let agent = client.agent(&self.config.model_name)
-            .preamble("You are an expert Ontology Engineer. Extract entities and relationships from the text. \
-                       Return strictly JSON format matching this structure: \
-                       { \"entities\": [{\"name\": \"...\", \"category\": \"...\"}], \"relations\": [{\"source\": \"...\", \"target\": \"...\", \"relation_type\": \"...\"}] }")
-            .build();
+    async fn extract_knowledge_raw(&self, text: &str) -> Result<(KnowledgeExtraction, String), AppError> {
+        let config = self.snapshot();
+        if !self.two_stage_extraction {
+            let preamble = extraction_preamble(config.granularity, self.extract_events);
+            let schema = extraction_schema(self.extract_events);
+            let structure = if self.extract_events {
+                r#"{"entities": [...], "relations": [...], "events": [...], "temporal_relations": [...]}"#
+            } else {
+                r#"{"entities": [...], "relations": [...]}"#
+            };
+            return self.extract_json(&config, "extraction", &preamble, text, schema, structure).await;
+        }
 
-        let response = agent.prompt(text).await
-            .map_err(|e| AppError::AIError(format!("Extraction failed: {}", e)))?;
+        // Etapa 1: entidades (y sucesos)
+        let preamble = entity_preamble(config.granularity, self.extract_events);
+        let schema = stage_schema(true, false, self.extract_events);
+        let structure = if self.extract_events {
+            r#"{"entities": [...], "events": [...], "temporal_relations": [...]}"#
+        } else {
+            r#"{"entities": [...]}"#
+        };
+        let (stage, _) = self.extract_json::<EntityStage>(&config, "extraction_entities", &preamble, text, schema, structure).await?;
 
-        let cleaned_json = self.clean_json_response(&response);
+        // Etapa 2: relaciones entre esas entidades (sin entidades no hay nada que relacionar)
+        let mut relations = Vec::new();
+        if !stage.entities.is_empty() {
+            let names: Vec<&str> = stage.entities.iter().map(|e| e.name.as_str()).collect();
+            let preamble = relation_preamble(&names);
+            let schema = stage_schema(false, true, false);
+            let (found, _) = self.extract_json::<RelationStage>(&config, "extraction_relations", &preamble, text, schema, r#"{"relations": [...]}"#).await?;
 
-        let extraction: KnowledgeExtraction = from_str(&cleaned_json)
-            .map_err(|e| AppError::ParseError(format!("Failed to parse JSON: {} - Raw: {}", e, cleaned_json)))?;
+            // El modelo puede desobedecer: se descartan las relaciones con extremos fuera de la lista
+            let known: HashSet<String> = names.iter().map(|n| n.trim().to_lowercase()).collect();
+            let total = found.relations.len();
+            relations = found.relations.into_iter()
+                .filter(|r| known.contains(&r.source.trim().to_lowercase()) && known.contains(&r.target.trim().to_lowercase()))
+                .collect();
+            if relations.len() < total {
+                tracing::warn!("🧹 Two-stage extraction: dropped {} relations with unknown endpoints", total - relations.len());
+            }
+        }
 
-        Ok(extraction)
+        let extraction = KnowledgeExtraction {
+            entities: stage.entities,
+            relations,
+            events: stage.events,
+            temporal_relations: stage.temporal_relations,
+        };
+        let raw = serde_json::to_string(&extraction)
+            .map_err(|e| AppError::ParseError(e.to_string()))?;
+        Ok((extraction, raw))
     }
 
     async fn generate_inference(&self, prompt: &str) -> Result<InferenceResult, AppError> {
-        let client = self.get_client();
-        let agent = client.agent(&self.config.model_name).build();
-        
-        let response = agent.prompt(prompt).await
-            .map_err(|e| AppError::AIError(format!("Inference failed: {}", e)))?;
+        let config = self.snapshot();
+
+        let response = with_retries(&self.completion_retry, "Inference", || async {
+            self.breaker.before_call()?;
+            let started = Instant::now();
+            let result = complete(&config, None, &[], prompt, None).await;
+            let result = self.track(&config, "inference", prompt, started, result);
+            result.map_err(|e| AppError::AIError(format!("Inference failed: {}", e)))
+        }).await?;
             
         let cleaned = self.clean_json_response(&response);
         
@@ -115,4 +661,583 @@ impl AIService for RigAIService {
             
         Ok(result)
     }
-}
\ No newline at end of file
+
+    async fn generate_answer(&self, system_prompt: &str, history: &[ChatMessage], message: &str) -> Result<String, AppError> {
+        let config = self.snapshot();
+
+        with_retries(&self.completion_retry, "Chat", || async {
+            self.breaker.before_call()?;
+            let started = Instant::now();
+            let result = complete(&config, Some(system_prompt), history, message, None).await;
+            let result = self.track(&config, "chat", &format!("{}\n\n{}", system_prompt, message), started, result);
+            result.map_err(|e| AppError::AIError(format!("Error generando respuesta LLM: {}", e)))
+        }).await
+    }
+
+    async fn decompose_query(&self, query: &str, max: usize) -> Result<Vec<String>, AppError> {
+        let config = self.snapshot();
+        let preamble = format!(
+            "Split the user's question into at most {} self-contained search queries, one per distinct facet, \
+             in the same language as the question. If it has a single facet, return it unchanged as the only query. \
+             Return strictly JSON: {{\"queries\": [\"...\"]}}",
+            max
+        );
+
+        let response = with_retries(&self.completion_retry, "Query decomposition", || async {
+            self.breaker.before_call()?;
+            let started = Instant::now();
+            let result = complete(&config, Some(&preamble), &[], query, None).await;
+            let result = self.track(&config, "query_decomposition", &format!("{}\n\n{}", preamble, query), started, result);
+            result.map_err(|e| AppError::AIError(format!("Query decomposition failed: {}", e)))
+        }).await?;
+
+        #[derive(serde::Deserialize)]
+        struct Decomposition {
+            queries: Vec<String>,
+        }
+        let parsed: Decomposition = from_str(&self.clean_json_response(&response))
+            .map_err(|e| AppError::ParseError(format!("JSON Error: {}", e)))?;
+
+        let mut queries: Vec<String> = parsed.queries.into_iter()
+            .map(|q| q.trim().to_string())
+            .filter(|q| !q.is_empty())
+            .collect();
+        queries.truncate(max.max(1));
+        if queries.is_empty() {
+            queries.push(query.to_string());
+        }
+        Ok(queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::ai::mock::mock_config;
+
+    const PLAIN: &str = r#"{"entities": [{"name": "Ada Lovelace", "category": "Person"}], "relations": []}"#;
+    const FENCED: &str = "```json\n{\"entities\": [{\"name\": \"Ada Lovelace\", \"category\": \"Person\"}], \"relations\": []}\n```";
+
+    #[test]
+    fn structured_output_is_used_only_by_providers_that_support_it() {
+        let mut config = mock_config(8);
+        config.provider = AIProvider::OpenAI;
+        assert!(RigAIService::structured_output_enabled(&config));
+        config.provider = AIProvider::Anthropic;
+        assert!(RigAIService::structured_output_enabled(&config));
+
+        config.json_mode = false;
+        assert!(!RigAIService::structured_output_enabled(&config));
+
+        config.json_mode = true;
+        config.provider = AIProvider::Ollama;
+        assert!(!RigAIService::structured_output_enabled(&config));
+    }
+
+    #[test]
+    fn the_schema_requires_events_only_when_they_are_extracted() {
+        assert_eq!(extraction_schema(false)["required"], json!(["entities", "relations"]));
+        assert!(extraction_schema(false)["properties"].get("events").is_none());
+
+        let schema = extraction_schema(true);
+        assert_eq!(schema["required"], json!(["entities", "relations", "events", "temporal_relations"]));
+        assert_eq!(schema["properties"]["temporal_relations"]["items"]["required"], json!(["before", "after"]));
+    }
+
+    #[test]
+    fn the_fallback_isolates_the_first_json_object_from_the_prose() {
+        let raw = "Here is the JSON:\n```json\n{\"a\": {\"b\": \"} {\\\" }\"}}\n```\nHope it helps {not json}";
+        assert_eq!(extract_json_object(raw), "{\"a\": {\"b\": \"} {\\\" }\"}}");
+        assert_eq!(extract_json_object("  sin objeto  "), "sin objeto");
+        assert_eq!(extract_json_object("cortado: {\"a\": 1 "), "{\"a\": 1");
+    }
+
+    #[tokio::test]
+    async fn json_mode_parses_the_raw_response_without_cleaning() {
+        let service = RigAIService::new(mock_config(8));
+
+        let (parsed, raw): (KnowledgeExtraction, String) = service
+            .parse_with_retries(true, "texto", "{}", |_, _| async { Ok(PLAIN.to_string()) })
+            .await.unwrap();
+        assert_eq!(parsed.entities[0].name, "Ada Lovelace");
+        assert_eq!(raw, PLAIN);
+
+        // Un bloque ```json solo se lee si pasa por clean_json_response: en modo JSON no se limpia
+        let fenced = service
+            .parse_with_retries::<KnowledgeExtraction, _, _>(true, "texto", "{}", |_, _| async { Ok(FENCED.to_string()) })
+            .await;
+        assert!(matches!(fenced, Err(AppError::ParseError(_))));
+    }
+
+    #[tokio::test]
+    async fn without_json_mode_the_response_is_cleaned_before_parsing() {
+        let service = RigAIService::new(mock_config(8));
+
+        let (parsed, raw): (KnowledgeExtraction, String) = service
+            .parse_with_retries(false, "texto", "{}", |_, _| async { Ok(FENCED.to_string()) })
+            .await.unwrap();
+        assert_eq!(parsed.entities[0].name, "Ada Lovelace");
+        assert_eq!(raw, FENCED);
+    }
+
+    #[test]
+    fn embeddings_use_their_own_base_url_when_configured() {
+        let mut config = mock_config(8);
+        config.base_url = Some("http://llm.local/v1".to_string());
+        assert_eq!(RigAIService::embedding_base_url(&config), Some("http://llm.local/v1"));
+
+        config.embedding_base_url = Some("http://embeddings.local/v1".to_string());
+        assert_eq!(RigAIService::embedding_base_url(&config), Some("http://embeddings.local/v1"));
+        assert_eq!(config.base_url.as_deref(), Some("http://llm.local/v1"));
+    }
+
+    const SECRET: &str = "sk-test-secret";
+
+    fn config_with(auth_scheme: &str) -> AIConfig {
+        let mut config = mock_config(8);
+        config.api_key = secrecy::SecretString::new(SECRET.into());
+        config.auth_scheme = auth_scheme.parse().unwrap();
+        config
+    }
+
+    /// Proveedor local que responde `status` y `body` a todo y guarda la URI y las cabeceras de cada petición.
+    async fn capture_server(status: axum::http::StatusCode, body: serde_json::Value) -> (String, std::sync::Arc<std::sync::Mutex<Vec<(String, HeaderMap)>>>) {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = axum::Router::new().fallback({
+            let seen = seen.clone();
+            move |request: axum::extract::Request| async move {
+                seen.lock().unwrap().push((request.uri().to_string(), request.headers().clone()));
+                (status, axum::Json(body))
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base_url, seen)
+    }
+
+    #[test]
+    fn bearer_scheme_sends_the_key_in_the_authorization_header() {
+        let headers = auth_headers(&config_with("bearer"));
+
+        assert_eq!(headers.get(AUTHORIZATION).unwrap(), &format!("Bearer {}", SECRET));
+        assert!(headers.get(AUTHORIZATION).unwrap().is_sensitive());
+        assert!(!format!("{:?}", headers).contains(SECRET));
+    }
+
+    #[test]
+    fn header_scheme_sends_the_key_in_the_named_header() {
+        let headers = auth_headers(&config_with("header:api-key"));
+
+        assert_eq!(headers.get("api-key").unwrap(), SECRET);
+        assert!(headers.get("api-key").unwrap().is_sensitive());
+        assert!(headers.get(AUTHORIZATION).is_none());
+        assert!(!format!("{:?}", headers).contains(SECRET));
+    }
+
+    #[tokio::test]
+    async fn query_scheme_sends_the_key_as_a_url_parameter() {
+        let (base_url, seen) = capture_server(axum::http::StatusCode::OK, json!({
+            "choices": [{ "message": { "role": "assistant", "content": "hola" } }],
+            "data": [{ "embedding": [0.5, 0.25] }]
+        })).await;
+        let mut config = config_with("query:key");
+        config.base_url = Some(base_url);
+        assert!(auth_headers(&config).get(AUTHORIZATION).is_none());
+
+        let (answer, _) = complete(&config, Some("sistema"), &[], "pregunta", None).await.unwrap();
+        assert_eq!(answer, "hola");
+        let embedding = RigAIService::embed_once(&config, &config.embedding_model, "texto").await.unwrap();
+        assert_eq!(embedding, vec![0.5, 0.25]);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].0, format!("/v1/chat/completions?key={}", SECRET));
+        assert_eq!(seen[1].0, format!("/v1/embeddings?key={}", SECRET));
+        for (_, headers) in seen.iter() {
+            assert!(headers.get(AUTHORIZATION).is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn query_scheme_keeps_the_key_out_of_connection_errors() {
+        let mut config = config_with("query:key");
+        config.base_url = Some("http://127.0.0.1:1/v1".to_string());
+
+        let error = complete(&config, None, &[], "pregunta", None).await.unwrap_err();
+        assert!(!error.contains(SECRET), "{}", error);
+    }
+
+    #[test]
+    fn the_extraction_preamble_carries_the_configured_granularity() {
+        let coarse = extraction_preamble(ExtractionGranularity::Coarse, false);
+        let balanced = extraction_preamble(ExtractionGranularity::Balanced, false);
+        let fine = extraction_preamble(ExtractionGranularity::Fine, false);
+
+        for preamble in [&coarse, &balanced, &fine] {
+            assert!(preamble.starts_with(EXTRACTION_PREAMBLE));
+        }
+        assert!(coarse.contains("few, broad entities"));
+        assert!(fine.contains("sub-parts, units, roles"));
+        assert_ne!(balanced, coarse);
+        assert_ne!(balanced, fine);
+    }
+
+    #[test]
+    fn granularity_parses_case_insensitively_and_defaults_to_balanced() {
+        assert_eq!(" Fine ".parse::<ExtractionGranularity>().unwrap(), ExtractionGranularity::Fine);
+        assert_eq!("COARSE".parse::<ExtractionGranularity>().unwrap(), ExtractionGranularity::Coarse);
+        assert!("medium".parse::<ExtractionGranularity>().is_err());
+        assert_eq!(mock_config(8).granularity, ExtractionGranularity::Balanced);
+    }
+
+    #[tokio::test]
+    async fn a_config_update_neither_waits_for_nor_changes_an_in_flight_call() {
+        // Proveedor lento: la llamada sigue en curso mientras se cambia la configuración
+        let app = axum::Router::new().fallback(|| async {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            axum::Json(json!({ "data": [{ "embedding": [1.0, 0.0] }] }))
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut config = config_with("query:key");
+        config.base_url = Some(base_url);
+        let service = std::sync::Arc::new(RigAIService::new(config.clone()));
+        let in_flight = tokio::spawn({
+            let service = service.clone();
+            async move { service.generate_embedding("texto").await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let started = std::time::Instant::now();
+        config.base_url = Some("http://127.0.0.1:1/v1".to_string());
+        service.update_config(config).unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(100));
+        assert_eq!(service.get_config().base_url.as_deref(), Some("http://127.0.0.1:1/v1"));
+
+        // La llamada en curso termina con su copia de la configuración anterior
+        assert_eq!(in_flight.await.unwrap().unwrap(), vec![1.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn anthropic_uses_the_messages_api_with_its_own_headers() {
+        let (base_url, seen) = capture_server(axum::http::StatusCode::OK, json!({
+            "content": [{ "type": "text", "text": "ho" }, { "type": "tool_use", "id": "x" }, { "type": "text", "text": "la" }]
+        })).await;
+        for scheme in ["bearer", "query:key"] {
+            let mut config = config_with(scheme);
+            config.provider = AIProvider::Anthropic;
+            config.base_url = Some(base_url.clone());
+            assert_eq!(complete(&config, Some("sistema"), &[], "pregunta", None).await.unwrap().0, "hola");
+        }
+
+        let seen = seen.lock().unwrap();
+        let (uri, headers) = &seen[0];
+        assert!(uri.ends_with("/v1/messages"));
+        assert_eq!(headers["x-api-key"], SECRET);
+        assert_eq!(headers["anthropic-version"], "2023-06-01");
+        assert!(headers.get(AUTHORIZATION).is_none());
+        let (uri, headers) = &seen[1];
+        assert!(uri.contains(&format!("key={}", SECRET)));
+        assert!(headers.get("x-api-key").is_none());
+    }
+
+    #[tokio::test]
+    async fn anthropic_returns_the_forced_tool_input_as_the_structured_output() {
+        let (base_url, _) = capture_server(axum::http::StatusCode::OK, json!({
+            "content": [{ "type": "text", "text": "Registro la salida" }, { "type": "tool_use", "id": "x", "name": "record_output", "input": { "entities": [], "relations": [] } }]
+        })).await;
+        let mut config = config_with("bearer");
+        config.provider = AIProvider::Anthropic;
+        config.base_url = Some(base_url);
+
+        let schema = extraction_schema(false);
+        let (structured, _) = complete(&config, None, &[], "pregunta", Some(&schema)).await.unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&structured).unwrap(), json!({ "entities": [], "relations": [] }));
+        assert_eq!(complete(&config, None, &[], "pregunta", None).await.unwrap().0, "Registro la salida");
+    }
+
+    #[tokio::test]
+    async fn anthropic_errors_carry_the_api_message_and_embeddings_need_another_provider() {
+        let (base_url, _) = capture_server(axum::http::StatusCode::UNAUTHORIZED, json!({
+            "type": "error", "error": { "type": "authentication_error", "message": "invalid x-api-key" }
+        })).await;
+        let mut config = config_with("bearer");
+        config.provider = AIProvider::Anthropic;
+        config.base_url = Some(base_url);
+        let error = complete(&config, None, &[], "pregunta", None).await.unwrap_err();
+        assert!(error.contains("401") && error.contains("invalid x-api-key"), "{}", error);
+
+        let result = RigAIService::new(config).generate_embedding("texto").await;
+        assert!(matches!(result, Err(AppError::ConfigError(ref msg)) if msg.contains("AI_EMBEDDING_BASE_URL")));
+    }
+
+    #[tokio::test]
+    async fn a_repeated_text_is_embedded_once_with_the_cache() {
+        let (base_url, seen) = capture_server(axum::http::StatusCode::OK, json!({ "data": [{ "embedding": [1.0, 0.0] }] })).await;
+        let mut config = config_with("query:key");
+        config.base_url = Some(base_url);
+        let service = RigAIService::new(config).with_embedding_cache(10);
+
+        assert_eq!(service.generate_embedding("Lugo").await.unwrap(), vec![1.0, 0.0]);
+        assert_eq!(service.generate_embedding("Lugo").await.unwrap(), vec![1.0, 0.0]);
+        service.generate_embedding("Muralla").await.unwrap();
+
+        assert_eq!(seen.lock().unwrap().len(), 2);
+        assert_eq!(service.embedding_cache_stats(), Some(EmbeddingCacheStats { hits: 1, misses: 2, entries: 2 }));
+        assert_eq!(RigAIService::new(mock_config(8)).embedding_cache_stats(), None);
+    }
+
+    #[tokio::test]
+    async fn an_open_circuit_fails_fast_without_calling_the_provider() {
+        let (base_url, seen) = capture_server(axum::http::StatusCode::SERVICE_UNAVAILABLE, json!({
+            "error": { "message": "upstream down" }
+        })).await;
+        let mut config = config_with("query:key");
+        config.base_url = Some(base_url);
+        let service = RigAIService::new(config).with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 3,
+            cooldown: std::time::Duration::from_secs(60),
+        });
+
+        for _ in 0..3 {
+            let result = service.generate_embedding("texto").await;
+            assert!(matches!(result, Err(AppError::AIError(_))), "{:?}", result);
+        }
+        assert_eq!(seen.lock().unwrap().len(), 3);
+
+        for _ in 0..5 {
+            let result = service.generate_embedding("texto").await;
+            assert!(matches!(result, Err(AppError::AIUnavailable(_))), "{:?}", result);
+            let result = service.generate_inference("pregunta").await;
+            assert!(matches!(result, Err(AppError::AIUnavailable(_))), "{:?}", result);
+        }
+        assert_eq!(seen.lock().unwrap().len(), 3, "no request may reach the provider while the circuit is open");
+    }
+
+    #[test]
+    fn the_prompt_version_includes_the_granularity() {
+        let mut config = mock_config(8);
+        config.granularity = crate::domain::models::ExtractionGranularity::Fine;
+        let service = RigAIService::new(config);
+
+        assert_eq!(service.extraction_prompt_version(), format!("{}-fine", EXTRACTION_PROMPT_VERSION));
+    }
+
+    #[test]
+    fn event_extraction_changes_the_preamble_and_the_prompt_version() {
+        let granularity = crate::domain::models::ExtractionGranularity::default();
+        assert!(!extraction_preamble(granularity, false).contains("temporal_relations"));
+        assert!(extraction_preamble(granularity, true).ends_with(EVENTS_PREAMBLE));
+
+        let service = RigAIService::new(mock_config(8)).with_event_extraction(true);
+        assert!(service.extraction_prompt_version().ends_with("-events"));
+    }
+
+    #[test]
+    fn extractions_without_events_still_parse() {
+        let parsed: KnowledgeExtraction = serde_json::from_str(r#"{"entities": [], "relations": []}"#).unwrap();
+        assert!(parsed.events.is_empty() && parsed.temporal_relations.is_empty());
+
+        let parsed: KnowledgeExtraction = serde_json::from_str(r#"{"entities": [], "relations": [],
+            "events": [{"name": "Fundación de Lucus Augusti", "date": "-25"}, {"name": "Construcción de la muralla", "participants": ["Lugo"]}],
+            "temporal_relations": [{"before": "Fundación de Lucus Augusti", "after": "Construcción de la muralla"}]}"#).unwrap();
+        assert_eq!(parsed.events[0].date.as_deref(), Some("-25"));
+        assert!(parsed.events[0].participants.is_empty());
+        assert_eq!(parsed.temporal_relations[0].after, "Construcción de la muralla");
+    }
+
+    #[tokio::test]
+    async fn invalid_json_is_re_prompted_with_the_parse_error() {
+        let service = RigAIService::new(mock_config(8)).with_extraction_parse_retries(1);
+        let prompts = std::sync::Mutex::new(Vec::new());
+
+        let (parsed, raw): (KnowledgeExtraction, String) = service
+            .parse_with_retries(true, "texto", r#"{"entities": [...]}"#, |prompt, attempt| {
+                prompts.lock().unwrap().push(prompt);
+                let response = if attempt == 0 { r#"{"entities": [{"name": "Ada"#.to_string() } else { PLAIN.to_string() };
+                async move { Ok(response) }
+            })
+            .await.unwrap();
+
+        assert_eq!(parsed.entities[0].name, "Ada Lovelace");
+        assert_eq!(raw, PLAIN);
+        let prompts = prompts.into_inner().unwrap();
+        assert_eq!(prompts.len(), 2);
+        assert_eq!(prompts[0], "texto");
+        assert!(prompts[1].starts_with("texto"));
+        assert!(prompts[1].contains("Your previous output failed to parse: EOF while parsing"));
+        assert!(prompts[1].contains(r#"{"entities": [...]}"#));
+    }
+
+    #[tokio::test]
+    async fn parse_retries_are_bounded() {
+        // Más reintentos que el tope se limitan a MAX_EXTRACTION_PARSE_RETRIES
+        for (retries, expected_calls) in [(0, 1), (10, MAX_EXTRACTION_PARSE_RETRIES + 1)] {
+            let service = RigAIService::new(mock_config(8)).with_extraction_parse_retries(retries);
+            let calls = std::sync::atomic::AtomicU32::new(0);
+
+            let result = service
+                .parse_with_retries::<KnowledgeExtraction, _, _>(true, "texto", "{}", |_, _| {
+                    calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async { Ok("not json".to_string()) }
+                })
+                .await;
+
+            assert!(matches!(result, Err(AppError::ParseError(_))));
+            assert_eq!(calls.into_inner(), expected_calls);
+        }
+    }
+
+    #[tokio::test]
+    async fn provider_5xx_errors_are_retried_but_client_errors_are_not() {
+        let retries = RetryConfig {
+            max_retries: 2,
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(1),
+        };
+        for (status, requests) in [(axum::http::StatusCode::SERVICE_UNAVAILABLE, 3), (axum::http::StatusCode::BAD_REQUEST, 1)] {
+            let (base_url, seen) = capture_server(status, json!({ "error": { "message": "falla" } })).await;
+            let mut config = config_with("query:key");
+            config.base_url = Some(base_url);
+            let service = RigAIService::new(config).with_retries(retries.clone(), retries.clone());
+
+            assert!(service.generate_embedding("texto").await.is_err());
+            assert_eq!(seen.lock().unwrap().len(), requests, "{}", status);
+        }
+    }
+
+    #[tokio::test]
+    async fn embeddings_and_completions_retry_independently() {
+        let (base_url, seen) = capture_server(axum::http::StatusCode::SERVICE_UNAVAILABLE, json!({ "error": { "message": "falla" } })).await;
+        let mut config = config_with("query:key");
+        config.base_url = Some(base_url);
+        let retries = |max_retries| RetryConfig {
+            max_retries,
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(1),
+        };
+        let service = RigAIService::new(config).with_retries(retries(3), retries(0));
+
+        assert!(service.generate_embedding("texto").await.is_err());
+        assert!(service.generate_answer("sistema", &[], "pregunta").await.is_err());
+
+        let seen = seen.lock().unwrap();
+        let paths: Vec<&str> = seen.iter().map(|(uri, _)| uri.split('?').next().unwrap()).collect();
+        assert_eq!(paths, ["/v1/embeddings", "/v1/embeddings", "/v1/embeddings", "/v1/embeddings", "/v1/chat/completions"]);
+    }
+
+    /// Proveedor local que responde `body` a todo y guarda el JSON de cada petición.
+    async fn body_server(body: serde_json::Value) -> (String, std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>) {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = axum::Router::new().fallback({
+            let seen = seen.clone();
+            move |axum::Json(request): axum::Json<serde_json::Value>| async move {
+                seen.lock().unwrap().push(request);
+                axum::Json(body)
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base_url, seen)
+    }
+
+    #[tokio::test]
+    async fn the_history_goes_between_the_system_prompt_and_the_new_message() {
+        let history = [
+            ChatMessage { role: ChatRole::User, content: "¿Qué es la muralla?".to_string() },
+            ChatMessage { role: ChatRole::Assistant, content: "Una fortificación romana.".to_string() },
+        ];
+        let roles = |messages: &serde_json::Value| messages.as_array().unwrap().iter()
+            .map(|m| m["role"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+
+        let (base_url, seen) = body_server(json!({ "choices": [{ "message": { "role": "assistant", "content": "ok" } }] })).await;
+        let mut config = config_with("query:key");
+        config.base_url = Some(base_url);
+        complete(&config, Some("sistema"), &history, "¿Y cuándo se hizo?", None).await.unwrap();
+        let messages = seen.lock().unwrap()[0]["messages"].clone();
+        assert_eq!(roles(&messages), ["system", "user", "assistant", "user"]);
+        assert_eq!(messages[3]["content"], "¿Y cuándo se hizo?");
+
+        let (base_url, seen) = body_server(json!({ "content": [{ "type": "text", "text": "ok" }] })).await;
+        config.provider = AIProvider::Anthropic;
+        config.base_url = Some(base_url);
+        complete(&config, Some("sistema"), &history, "¿Y cuándo se hizo?", None).await.unwrap();
+        let request = seen.lock().unwrap()[0].clone();
+        assert_eq!(request["system"], "sistema");
+        assert_eq!(roles(&request["messages"]), ["user", "assistant", "user"]);
+        assert_eq!(request["messages"][1]["content"], "Una fortificación romana.");
+    }
+
+    #[tokio::test]
+    async fn two_stage_extraction_only_keeps_relations_between_the_extracted_entities() {
+        let replies = [
+            json!({ "entities": [{ "name": "Ada Lovelace", "category": "Person" }, { "name": "Charles Babbage", "category": "Person" }] }),
+            json!({ "relations": [
+                { "source": "ada lovelace", "target": "Charles Babbage", "relation_type": "WORKED_WITH" },
+                { "source": "Ada Lovelace", "target": "Lord Byron", "relation_type": "DAUGHTER_OF" }
+            ] }),
+        ];
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::<serde_json::Value>::new()));
+        let app = axum::Router::new().fallback({
+            let seen = seen.clone();
+            move |axum::Json(request): axum::Json<serde_json::Value>| async move {
+                let mut seen = seen.lock().unwrap();
+                let reply = replies[seen.len().min(1)].to_string();
+                seen.push(request);
+                axum::Json(json!({ "choices": [{ "message": { "role": "assistant", "content": reply } }] }))
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = config_with("query:key");
+        config.base_url = Some(format!("http://{}/v1", listener.local_addr().unwrap()));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let service = RigAIService::new(config).with_two_stage_extraction(true);
+        assert!(service.extraction_prompt_version().ends_with("-2stage"));
+        let (extraction, raw) = service.extract_knowledge_raw("Ada Lovelace trabajó con Charles Babbage.").await.unwrap();
+
+        assert_eq!(extraction.entities.len(), 2);
+        assert_eq!(extraction.relations.len(), 1);
+        assert_eq!(extraction.relations[0].relation_type, "WORKED_WITH");
+        assert_eq!(from_str::<KnowledgeExtraction>(&raw).unwrap().relations.len(), 1);
+
+        let requests = seen.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let relation_prompt = requests[1]["messages"][0]["content"].as_str().unwrap();
+        assert!(relation_prompt.contains(r#"["Ada Lovelace","Charles Babbage"]"#));
+    }
+
+    #[test]
+    fn each_stage_schema_only_asks_for_its_own_parts() {
+        assert_eq!(stage_schema(true, false, false)["required"], json!(["entities"]));
+        assert_eq!(stage_schema(false, true, false)["required"], json!(["relations"]));
+        assert_eq!(stage_schema(true, false, true)["required"], json!(["entities", "events", "temporal_relations"]));
+        assert_eq!(extraction_schema(false), stage_schema(true, true, false));
+    }
+
+    #[tokio::test]
+    async fn provider_token_usage_is_captured_for_each_call() {
+        let (base_url, _) = capture_server(axum::http::StatusCode::OK, json!({
+            "choices": [{ "message": { "role": "assistant", "content": "La muralla mide 2 km [1]" } }],
+            "usage": { "prompt_tokens": 120, "completion_tokens": 9 }
+        })).await;
+        let mut config = config_with("query:key");
+        config.base_url = Some(base_url);
+        let service = RigAIService::new(config);
+
+        // Dos llamadas dentro de la misma operación se suman
+        let (answers, usage) = crate::domain::ports::with_usage(async {
+            let first = service.generate_answer("sistema", &[], "¿Cuánto mide?").await.unwrap();
+            let second = service.generate_answer("sistema", &[], "¿Y de alto?").await.unwrap();
+            (first, second)
+        }).await;
+
+        assert_eq!(answers.0, "La muralla mide 2 km [1]");
+        assert_eq!(usage, Some(TokenUsage { prompt_tokens: 240, completion_tokens: 18, total_tokens: 258 }));
+    }
+}
@@ -0,0 +1,98 @@
+//! Registro de conversaciones para datasets de evaluación RAG (ej. RAGAS).
+//!
+//! Con `CHAT_EVAL_DATASET_PATH` cada respuesta de `/api/chat` añade una línea JSON al fichero:
+//!
+//! ```json
+//! {"timestamp": 1718000000000, "question": "...", "history": [{"role": "user", "content": "..."}],
+//!  "retrieval": "hybrid", "contexts": ["texto del fragmento", "..."],
+//!  "retrieved": [{"chunk_id": "...", "score": 0.87, "entities": ["..."]}],
+//!  "system_prompt": "...", "answer": "...", "has_answer": true}
+//! ```
+//!
+//! `question`, `contexts` y `answer` son los campos que espera RAGAS; `contexts` y `retrieved`
+//! siguen el mismo orden (relevancia descendente). `ground_truth` se añade a mano al curar el dataset.
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::domain::models::{ChatMessage, HybridContext, RetrievalStrategy};
+
+/// Fragmento recuperado, con su puntuación final y las entidades que aportó al prompt.
+#[derive(Debug, Serialize)]
+pub struct EvalContext {
+    pub chunk_id: String,
+    pub score: f64,
+    pub entities: Vec<String>,
+}
+
+/// Una línea del dataset.
+#[derive(Debug, Serialize)]
+pub struct EvalRecord {
+    /// Epoch en milisegundos
+    pub timestamp: i64,
+    pub question: String,
+    pub history: Vec<ChatMessage>,
+    pub retrieval: RetrievalStrategy,
+    pub contexts: Vec<String>,
+    pub retrieved: Vec<EvalContext>,
+    pub system_prompt: String,
+    /// Vacío si no se consultó al LLM (`has_answer=false`)
+    pub answer: String,
+    pub has_answer: bool,
+}
+
+impl EvalRecord {
+    pub fn new(question: &str, history: Vec<ChatMessage>, retrieval: RetrievalStrategy, contexts: &[HybridContext], system_prompt: &str, answer: &str, has_answer: bool) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+
+        Self {
+            timestamp,
+            question: question.to_string(),
+            history,
+            retrieval,
+            contexts: contexts.iter().map(|c| c.content.clone()).collect(),
+            retrieved: contexts.iter()
+                .map(|c| EvalContext { chunk_id: c.chunk_id.clone(), score: c.score, entities: c.connected_entities.clone() })
+                .collect(),
+            system_prompt: system_prompt.to_string(),
+            answer: answer.to_string(),
+            has_answer,
+        }
+    }
+}
+
+/// Fichero JSON Lines de solo añadir; el mutex evita líneas entrelazadas entre peticiones.
+pub struct EvalDatasetLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl EvalDatasetLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), lock: Mutex::new(()) }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Añade `record` como una línea al final del fichero (lo crea si no existe).
+    pub async fn append(&self, record: &EvalRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record).map_err(std::io::Error::other)?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        // tokio::fs::File escribe en segundo plano: sin flush la línea puede perderse al soltarlo
+        file.flush().await
+    }
+}
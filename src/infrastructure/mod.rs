@@ -1,3 +1,8 @@
 pub mod ai;
+pub mod eval_dataset;
 pub mod persistence;
-pub mod parsing;
\ No newline at end of file
+pub mod parsing;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+pub mod startup;
+pub mod transmutation;
\ No newline at end of file
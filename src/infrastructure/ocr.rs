@@ -0,0 +1,45 @@
+//! OCR de PDFs escaneados (feature `ocr`, requiere Tesseract instalado).
+//! Los escáneres guardan cada página como una imagen JPEG (`DCTDecode`); esos flujos ya son
+//! ficheros JPEG completos y Tesseract los lee directamente, sin rasterizar el PDF.
+use lopdf::{Document, Object};
+use tesseract::{Tesseract, TesseractError};
+use crate::domain::errors::AppError;
+
+/// Idiomas de Tesseract (los `traineddata` deben estar instalados).
+const OCR_LANGUAGES: &str = "spa+eng";
+
+/// Texto reconocido en las imágenes JPEG del documento, en orden de objeto.
+pub fn ocr_pdf_images(doc: &Document) -> Result<String, AppError> {
+    let mut text = String::new();
+    let mut images = 0;
+    for object in doc.objects.values() {
+        let Object::Stream(stream) = object else { continue };
+        let is_image = matches!(stream.dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Image");
+        if !is_image || !is_jpeg(stream.dict.get(b"Filter").ok()) {
+            continue;
+        }
+        images += 1;
+
+        let page_text = recognize(&stream.content)
+            .map_err(|e| AppError::ParseError(format!("OCR failed: {}", e)))?;
+        text.push_str(page_text.trim());
+        text.push('\n');
+    }
+
+    tracing::info!("🔍 OCR: {} imágenes procesadas", images);
+    Ok(text)
+}
+
+fn recognize(image: &[u8]) -> Result<String, TesseractError> {
+    let mut tesseract = Tesseract::new(None, Some(OCR_LANGUAGES))?.set_image_from_mem(image)?;
+    Ok(tesseract.get_text()?)
+}
+
+/// `Filter` de un flujo de imagen: un nombre o una lista de nombres.
+fn is_jpeg(filter: Option<&Object>) -> bool {
+    match filter {
+        Some(Object::Name(name)) => name == b"DCTDecode",
+        Some(Object::Array(filters)) => filters.iter().any(|f| matches!(f, Object::Name(name) if name == b"DCTDecode")),
+        _ => false,
+    }
+}
@@ -1,27 +1,139 @@
-use std::io::Read;
+use std::io::{Cursor, Read, Seek};
+use calamine::{open_workbook_from_rs, Reader, SheetType, Xls, Xlsx};
 use lopdf::Document;
 use xml::reader::{EventReader, XmlEvent};
 use crate::domain::errors::AppError;
 
-pub fn parse_text_from_bytes(filename: &str, bytes: &[u8]) -> Result<String, AppError> {
+/// Opciones de conversión a texto.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// Conserva marcas ligeras tipo markdown (`#` títulos, `-` listas, `**` negrita)
+    /// en lugar de aplanar todo el documento.
+    pub preserve_formatting: bool,
+    /// PDF con menos caracteres que este umbral: se intenta OCR (solo con la feature `ocr`; None = nunca)
+    pub ocr_min_chars: Option<usize>,
+}
+
+/// Cómo se obtuvo el texto de un documento.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextSource {
+    /// Capa de texto del propio formato
+    Native,
+    /// Reconocimiento óptico de las imágenes (PDF escaneado)
+    Ocr,
+}
+
+pub fn parse_text_from_bytes(filename: &str, bytes: &[u8], options: ParseOptions) -> Result<String, AppError> {
+    parse_document(filename, bytes, options).map(|(text, _)| text)
+}
+
+/// Como `parse_text_from_bytes`, indicando además si hizo falta OCR (para avisar en el progreso).
+pub fn parse_document(filename: &str, bytes: &[u8], options: ParseOptions) -> Result<(String, TextSource), AppError> {
     let extension = std::path::Path::new(filename)
         .extension()
         .and_then(std::ffi::OsStr::to_str)
         .unwrap_or("")
         .to_lowercase();
 
-    match extension.as_str() {
-        "pdf" => extract_text_from_pdf(bytes),
+    let text = match extension.as_str() {
+        "pdf" => return extract_text_from_pdf(bytes, options),
+        "docx" if options.preserve_formatting => extract_markdown_from_docx(bytes),
         "docx" => extract_text_from_docx(bytes),
-        "txt" | "md" | "json" | "csv" => {
+        "html" | "htm" => extract_text_from_html(bytes, options),
+        "xlsx" => {
+            let workbook: Xlsx<_> = open_workbook_from_rs(Cursor::new(bytes))
+                .map_err(|e| AppError::ValidationError(format!("Invalid .xlsx workbook: {}", e)))?;
+            extract_text_from_workbook(workbook)
+        },
+        "xls" => {
+            let workbook: Xls<_> = open_workbook_from_rs(Cursor::new(bytes))
+                .map_err(|e| AppError::ValidationError(format!("Invalid .xls workbook: {}", e)))?;
+            extract_text_from_workbook(workbook)
+        },
+        "csv" => extract_text_from_csv(bytes),
+        "txt" | "md" | "json" => {
             String::from_utf8(bytes.to_vec())
                 .map_err(|e| AppError::ParseError(format!("Invalid UTF-8: {}", e)))
         },
         _ => Err(AppError::ValidationError(format!("Unsupported file format: .{}", extension))),
+    }?;
+    Ok((text, TextSource::Native))
+}
+
+/// Hojas de cálculo: una sección por hoja y cada fila con sus celdas separadas por tabuladores.
+/// Solo se leen hojas de datos; gráficos, macros y diálogos se omiten.
+fn extract_text_from_workbook<RS, W>(mut workbook: W) -> Result<String, AppError>
+where
+    RS: Read + Seek,
+    W: Reader<RS>,
+    W::Error: std::fmt::Display,
+{
+    let sheets: Vec<(String, SheetType)> = workbook.sheets_metadata().iter()
+        .map(|sheet| (sheet.name.clone(), sheet.typ))
+        .collect();
+
+    let mut text = String::new();
+    let mut worksheets = 0;
+    for (name, typ) in sheets {
+        if typ != SheetType::WorkSheet {
+            tracing::debug!("Hoja '{}' omitida ({:?})", name, typ);
+            continue;
+        }
+        let range = workbook.worksheet_range(&name)
+            .map_err(|e| AppError::ParseError(format!("Failed to read sheet '{}': {}", name, e)))?;
+        worksheets += 1;
+
+        text.push_str(&format!("## {}\n", name));
+        for row in range.rows() {
+            let cells: Vec<String> = row.iter().map(|cell| cell.to_string()).collect();
+            // Filas vacías (formato sin datos) no aportan nada al LLM
+            if cells.iter().all(|c| c.trim().is_empty()) {
+                continue;
+            }
+            text.push_str(cells.join("\t").trim_end());
+            text.push('\n');
+        }
+        text.push('\n');
+    }
+
+    if worksheets == 0 {
+        return Err(AppError::ValidationError("Workbook has no data worksheets".to_string()));
+    }
+    Ok(text)
+}
+
+/// CSV: una línea por fila como `columna: valor; columna: valor`, para que el LLM vea a qué
+/// columna pertenece cada dato. Las celdas vacías se omiten.
+fn extract_text_from_csv(bytes: &[u8]) -> Result<String, AppError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(bytes);
+    let headers: Vec<String> = reader.headers()
+        .map_err(|e| AppError::ParseError(format!("Invalid CSV header: {}", e)))?
+        .iter()
+        .map(|h| h.trim().to_string())
+        .collect();
+
+    let mut text = String::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| AppError::ParseError(format!("Invalid CSV row: {}", e)))?;
+        let fields: Vec<String> = record.iter().enumerate()
+            .filter(|(_, value)| !value.trim().is_empty())
+            .map(|(i, value)| {
+                let column = headers.get(i).filter(|h| !h.is_empty()).cloned()
+                    .unwrap_or_else(|| format!("col{}", i + 1));
+                format!("{}: {}", column, value.trim())
+            })
+            .collect();
+        if !fields.is_empty() {
+            text.push_str(&fields.join("; "));
+            text.push('\n');
+        }
     }
+    Ok(text)
 }
 
-fn extract_text_from_pdf(bytes: &[u8]) -> Result<String, AppError> {
+fn extract_text_from_pdf(bytes: &[u8], options: ParseOptions) -> Result<(String, TextSource), AppError> {
     // Cargar PDF desde memoria
     let doc = Document::load_mem(bytes)
         .map_err(|e| AppError::ParseError(format!("Failed to load PDF: {}", e)))?;
@@ -34,15 +146,69 @@ fn extract_text_from_pdf(bytes: &[u8]) -> Result<String, AppError> {
         text.push_str(&content);
         text.push('\n');
     }
+
+    // Casi sin texto: probablemente páginas escaneadas
+    let native_chars = text.trim().chars().count();
+    let recognized = match options.ocr_min_chars {
+        Some(min_chars) if native_chars < min_chars => ocr_fallback(&doc, min_chars)?,
+        _ => None,
+    };
+    let source = match recognized {
+        Some(recognized) if recognized.trim().chars().count() > native_chars => {
+            text = recognized;
+            TextSource::Ocr
+        },
+        _ => TextSource::Native,
+    };
     
     if text.trim().is_empty() {
         return Err(AppError::ParseError("PDF appears to be empty or scanned images".to_string()));
     }
+
+    // El PDF no guarda estructura semántica: solo normalizamos las viñetas a `-`
+    if options.preserve_formatting {
+        text = normalize_pdf_bullets(&text);
+    }
     
-    Ok(text)
+    Ok((text, source))
 }
 
-fn extract_text_from_docx(bytes: &[u8]) -> Result<String, AppError> {
+#[cfg(feature = "ocr")]
+fn ocr_fallback(doc: &Document, _min_chars: usize) -> Result<Option<String>, AppError> {
+    super::ocr::ocr_pdf_images(doc).map(Some)
+}
+
+#[cfg(not(feature = "ocr"))]
+fn ocr_fallback(_doc: &Document, min_chars: usize) -> Result<Option<String>, AppError> {
+    tracing::warn!("⚠️ PDF con menos de {} caracteres: OCR no disponible (compilar con --features ocr)", min_chars);
+    Ok(None)
+}
+
+fn normalize_pdf_bullets(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            match trimmed.strip_prefix(['•', '◦', '▪', '●', '‣']) {
+                Some(rest) => format!("- {}", rest.trim_start()),
+                None => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn extract_text_from_html(bytes: &[u8], options: ParseOptions) -> Result<String, AppError> {
+    // Ancho amplio para no introducir saltos de línea artificiales
+    const WIDTH: usize = 1000;
+    let result = if options.preserve_formatting {
+        html2text::config::plain().string_from_read(bytes, WIDTH)
+    } else {
+        html2text::config::plain_no_decorate().string_from_read(bytes, WIDTH)
+    };
+    result.map_err(|e| AppError::ParseError(format!("Failed to parse HTML: {}", e)))
+}
+
+fn read_docx_xml(bytes: &[u8]) -> Result<String, AppError> {
     let cursor = std::io::Cursor::new(bytes);
     let mut zip = zip::ZipArchive::new(cursor)
         .map_err(|e| AppError::ParseError(format!("Failed to read DOCX zip: {}", e)))?;
@@ -54,6 +220,11 @@ fn extract_text_from_docx(bytes: &[u8]) -> Result<String, AppError> {
     let mut xml_content = String::new();
     xml_file.read_to_string(&mut xml_content)
         .map_err(|e| AppError::ParseError(format!("Failed to read XML: {}", e)))?;
+    Ok(xml_content)
+}
+
+fn extract_text_from_docx(bytes: &[u8]) -> Result<String, AppError> {
+    let xml_content = read_docx_xml(bytes)?;
 
     // Parsear XML simple para sacar el texto
     let parser = EventReader::from_str(&xml_content);
@@ -71,4 +242,259 @@ fn extract_text_from_docx(bytes: &[u8]) -> Result<String, AppError> {
     }
 
     Ok(text)
-}
\ No newline at end of file
+}
+
+/// DOCX a texto con marcas markdown: un párrafo por línea, estilos `Heading N`/`Title`
+/// como `#`, párrafos numerados/viñetas como `-` y runs en negrita como `**`.
+fn extract_markdown_from_docx(bytes: &[u8]) -> Result<String, AppError> {
+    let xml_content = read_docx_xml(bytes)?;
+    let parser = EventReader::from_str(&xml_content);
+
+    let mut text = String::new();
+    let mut paragraph = String::new();
+    let mut heading_level: Option<usize> = None;
+    let mut is_list_item = false;
+    let mut run = String::new();
+    let mut run_bold = false;
+    let mut in_run_props = false;
+
+    for e in parser {
+        match e {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => match name.local_name.as_str() {
+                "p" => {
+                    paragraph.clear();
+                    heading_level = None;
+                    is_list_item = false;
+                },
+                "pStyle" => {
+                    let style = attributes.iter().find(|a| a.name.local_name == "val").map(|a| a.value.as_str()).unwrap_or("");
+                    heading_level = heading_level_from_style(style);
+                },
+                "numPr" => is_list_item = true,
+                "r" => {
+                    run.clear();
+                    run_bold = false;
+                },
+                "rPr" => in_run_props = true,
+                "b" if in_run_props => {
+                    // <w:b w:val="0"/> desactiva explícitamente la negrita
+                    run_bold = !attributes.iter().any(|a| a.name.local_name == "val" && (a.value == "0" || a.value == "false"));
+                },
+                "tab" => run.push('\t'),
+                "br" => run.push('\n'),
+                _ => {}
+            },
+            Ok(XmlEvent::Characters(s)) => run.push_str(&s),
+            Ok(XmlEvent::EndElement { name }) => match name.local_name.as_str() {
+                "rPr" => in_run_props = false,
+                "r" => {
+                    // En títulos la negrita no aporta señal
+                    if run_bold && heading_level.is_none() && !run.trim().is_empty() {
+                        paragraph.push_str(&format!("**{}**", run.trim()));
+                        if run.ends_with(' ') {
+                            paragraph.push(' ');
+                        }
+                    } else {
+                        paragraph.push_str(&run);
+                    }
+                    run.clear();
+                },
+                "p" => {
+                    let content = paragraph.trim();
+                    if !content.is_empty() {
+                        match (heading_level, is_list_item) {
+                            (Some(level), _) => text.push_str(&format!("{} {}", "#".repeat(level), content)),
+                            (None, true) => text.push_str(&format!("- {}", content)),
+                            (None, false) => text.push_str(content),
+                        }
+                    }
+                    text.push('\n');
+                },
+                _ => {}
+            },
+            Err(e) => return Err(AppError::ParseError(format!("XML Error: {}", e))),
+            _ => {}
+        }
+    }
+
+    Ok(text)
+}
+
+/// `Title` -> 1, `Heading1`/`Heading 2`/`heading3` -> nivel (máx. 6).
+fn heading_level_from_style(style: &str) -> Option<usize> {
+    let lower = style.to_lowercase();
+    if lower == "title" {
+        return Some(1);
+    }
+    let level = lower.strip_prefix("heading")?.trim().parse::<usize>().ok()?;
+    Some(level.clamp(1, 6))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// DOCX mínimo con `body` como contenido de `<w:body>`.
+    fn docx(body: &str) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("word/document.xml", zip::write::FileOptions::default()).unwrap();
+        write!(zip, r#"<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#, body).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// XLSX mínimo: cada hoja es `(nombre, filas)`; con `filas = None` la hoja es un gráfico.
+    fn xlsx(sheets: &[(&str, Option<&[&[&str]]>)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default();
+        let mut entries = String::new();
+        let mut rels = String::new();
+        for (i, (name, rows)) in sheets.iter().enumerate() {
+            let id = i + 1;
+            entries.push_str(&format!(r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#, name, id, id));
+            let Some(rows) = rows else {
+                rels.push_str(&format!(r#"<Relationship Id="rId{}" Target="chartsheets/sheet{}.xml"/>"#, id, id));
+                continue;
+            };
+            rels.push_str(&format!(r#"<Relationship Id="rId{}" Target="worksheets/sheet{}.xml"/>"#, id, id));
+            let mut data = String::new();
+            for (r, row) in rows.iter().enumerate() {
+                data.push_str(&format!(r#"<row r="{}">"#, r + 1));
+                for (c, value) in row.iter().enumerate() {
+                    let cell = format!("{}{}", (b'A' + c as u8) as char, r + 1);
+                    data.push_str(&format!(r#"<c r="{}" t="inlineStr"><is><t>{}</t></is></c>"#, cell, value));
+                }
+                data.push_str("</row>");
+            }
+            zip.start_file(format!("xl/worksheets/sheet{}.xml", id), options).unwrap();
+            write!(zip, r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{}</sheetData></worksheet>"#, data).unwrap();
+        }
+        zip.start_file("xl/workbook.xml", options).unwrap();
+        write!(zip, r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>{}</sheets></workbook>"#, entries).unwrap();
+        zip.start_file("xl/_rels/workbook.xml.rels", options).unwrap();
+        write!(zip, r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{}</Relationships>"#, rels).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn heading_styles_map_to_markdown_levels() {
+        assert_eq!(heading_level_from_style("Title"), Some(1));
+        assert_eq!(heading_level_from_style("Heading2"), Some(2));
+        assert_eq!(heading_level_from_style("heading 3"), Some(3));
+        assert_eq!(heading_level_from_style("Heading9"), Some(6));
+        assert_eq!(heading_level_from_style("Normal"), None);
+    }
+
+    #[test]
+    fn docx_headings_lists_and_bold_become_markdown() {
+        let bytes = docx(concat!(
+            r#"<w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:rPr><w:b/></w:rPr><w:t>Historia</w:t></w:r></w:p>"#,
+            r#"<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/></w:numPr></w:pPr><w:r><w:t>Construida en el siglo III</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:t xml:space="preserve">La </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t xml:space="preserve">Muralla </w:t></w:r>"#,
+            r#"<w:r><w:rPr><w:b w:val="0"/></w:rPr><w:t>rodea Lugo</w:t></w:r></w:p>"#,
+        ));
+
+        let text = parse_text_from_bytes("muralla.docx", &bytes, ParseOptions { preserve_formatting: true, ..Default::default() }).unwrap();
+
+        assert_eq!(text, "## Historia\n- Construida en el siglo III\nLa **Muralla** rodea Lugo\n");
+    }
+
+    #[test]
+    fn without_the_option_docx_stays_plain_text() {
+        let bytes = docx(r#"<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Historia</w:t></w:r></w:p>"#);
+        let text = parse_text_from_bytes("muralla.docx", &bytes, ParseOptions::default()).unwrap();
+        assert!(!text.contains('#'));
+        assert!(text.contains("Historia"));
+    }
+
+    /// PDF de una página con `text` en su capa de texto.
+    fn pdf(text: &str) -> Vec<u8> {
+        use lopdf::{dictionary, Object, Stream, content::{Content, Operation}};
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Courier" });
+        let resources_id = doc.add_object(dictionary! { "Font" => dictionary! { "F1" => font_id } });
+        let content = Content { operations: vec![
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), 24.into()]),
+            Operation::new("Td", vec![100.into(), 600.into()]),
+            Operation::new("Tj", vec![Object::string_literal(text)]),
+            Operation::new("ET", vec![]),
+        ] };
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+        let page_id = doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "Contents" => content_id });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn a_pdf_with_enough_native_text_skips_ocr() {
+        let options = ParseOptions { ocr_min_chars: Some(5), ..Default::default() };
+        let (text, source) = parse_document("muralla.pdf", &pdf("La Muralla de Lugo"), options).unwrap();
+        assert!(text.contains("La Muralla de Lugo"));
+        assert_eq!(source, TextSource::Native);
+    }
+
+    #[cfg(not(feature = "ocr"))]
+    #[test]
+    fn without_the_ocr_feature_a_short_pdf_keeps_its_native_text() {
+        let options = ParseOptions { ocr_min_chars: Some(1000), ..Default::default() };
+        let (text, source) = parse_document("muralla.pdf", &pdf("Lugo"), options).unwrap();
+        assert!(text.contains("Lugo"));
+        assert_eq!(source, TextSource::Native);
+    }
+
+    #[test]
+    fn pdf_bullets_are_normalised_to_dashes() {
+        let text = "Puertas:\n  • Porta Miñá\n◦Porta Nova\nTexto normal";
+        assert_eq!(normalize_pdf_bullets(text), "Puertas:\n- Porta Miñá\n- Porta Nova\nTexto normal");
+    }
+
+    #[test]
+    fn csv_rows_carry_their_column_names() {
+        let csv = "Puerta, Siglo ,\nMiñá,III,extra\nNova,,\n,,\n";
+        let text = parse_text_from_bytes("puertas.csv", csv.as_bytes(), ParseOptions::default()).unwrap();
+        // Celdas vacías omitidas; cabecera en blanco -> `colN`; filas sin datos descartadas
+        assert_eq!(text, "Puerta: Miñá; Siglo: III; col3: extra\nPuerta: Nova\n");
+    }
+
+    #[test]
+    fn worksheets_become_tab_separated_sections() {
+        let bytes = xlsx(&[
+            ("Puertas", Some(&[&["Nombre", "Siglo"], &["Miñá", "III"], &["", ""]])),
+            ("Torres", Some(&[&["Total", "85"]])),
+        ]);
+        let text = parse_text_from_bytes("muralla.xlsx", &bytes, ParseOptions::default()).unwrap();
+        assert_eq!(text, "## Puertas\nNombre\tSiglo\nMiñá\tIII\n\n## Torres\nTotal\t85\n\n");
+    }
+
+    #[test]
+    fn chart_sheets_are_skipped() {
+        let bytes = xlsx(&[("Gráfico", None), ("Datos", Some(&[&["Lugo"]]))]);
+        let text = parse_text_from_bytes("muralla.xlsx", &bytes, ParseOptions::default()).unwrap();
+        assert_eq!(text, "## Datos\nLugo\n\n");
+    }
+
+    #[test]
+    fn a_workbook_without_worksheets_is_rejected() {
+        let bytes = xlsx(&[("Gráfico", None)]);
+        let err = parse_text_from_bytes("muralla.xlsx", &bytes, ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, AppError::ValidationError(ref msg) if msg.contains("no data worksheets")), "{:?}", err);
+    }
+
+    #[test]
+    fn unsupported_extensions_are_rejected() {
+        let err = parse_text_from_bytes("muralla.odt", b"...", ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, AppError::ValidationError(ref msg) if msg.contains(".odt")), "{:?}", err);
+    }
+}
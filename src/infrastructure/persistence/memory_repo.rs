@@ -0,0 +1,962 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
+use crate::domain::{
+    ports::KGRepository,
+    models::{DocumentDeletion, DocumentInput, DocumentSummary, EntityChunk, EntityExportRow, ExportRecord, ExportedEntity, ExportedNode, ExportedRelation, ExtractionProvenance, KnowledgeExtraction, GraphDataResponse, GraphDelta, GraphRelation, GraphFilter, GraphLimit, TraversalDirection, VisNode, VisEdge, HybridContext, InferredRelation, EmbeddingRecord, StaleChunk, TimelineEvent, CategoryCount, EntityDegree, EntityMatch, GraphStats, VectorSimilarity, EmbeddingSlot, MAX_CONTEXT_ENTITIES_PER_CHUNK, MAX_CONTEXT_HOPS},
+    errors::AppError
+};
+use super::neo4j_repo::{dedupe_relations, EntityMatching, DEFAULT_EDGE_CONFIDENCE};
+
+/// Chunk guardado por `save_chunk`.
+#[derive(Debug, Clone)]
+pub struct StoredChunk {
+    pub document_id: Uuid,
+    pub id: Uuid,
+    pub content: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Lo que ha recibido el repositorio (los tests lo inspeccionan tras la llamada).
+#[derive(Debug, Default)]
+pub struct MemoryState {
+    pub documents: Vec<(Uuid, DocumentInput)>,
+    pub chunks: Vec<StoredChunk>,
+    pub graphs: Vec<(Uuid, KnowledgeExtraction)>,
+    /// Instante (epoch ms) de cada `save_graph`, en el mismo orden que `graphs`
+    pub graph_times: Vec<i64>,
+    /// Procedencia de la extracción por chunk (la última `save_graph` con `provenance`)
+    pub provenance: HashMap<Uuid, ExtractionProvenance>,
+    /// JSON de la extracción guardado por chunk (`save_chunk_extraction`)
+    pub extractions: HashMap<Uuid, String>,
+    /// Dimensión y función de similitud de los índices vectoriales creados
+    pub indexes: Vec<(usize, VectorSimilarity)>,
+    /// Índices vectoriales de los slots de embeddings creados
+    pub slot_indexes: Vec<EmbeddingSlot>,
+    /// Vectores de los slots por (chunk, slot)
+    pub slot_embeddings: HashMap<(Uuid, String), Vec<f32>>,
+    /// Dimensión del embedding y slot de cada `find_hybrid_context`
+    pub vector_queries: Vec<(usize, Option<String>)>,
+    /// Respuesta de `find_hybrid_context`
+    pub contexts: Vec<HybridContext>,
+    /// Respuesta de `find_keyword_context`
+    pub keyword_contexts: Vec<HybridContext>,
+    pub inferred: Vec<InferredRelation>,
+    /// Instante (epoch ms) de cada relación inferida, en el mismo orden que `inferred`
+    pub inferred_times: Vec<i64>,
+    /// Puntuaciones de `save_centrality`
+    pub centrality: HashMap<String, f64>,
+    pub resets: usize,
+}
+
+/// `KGRepository` en memoria para los tests de servicios y handlers (sin Neo4j).
+/// Las consultas que ningún test necesita devuelven resultados vacíos.
+#[derive(Default)]
+pub struct MemoryRepo {
+    state: Mutex<MemoryState>,
+    /// Simula una caída de Neo4j: todas las operaciones fallan con `DatabaseError`
+    down: AtomicBool,
+}
+
+impl MemoryRepo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fragmentos que devuelve la búsqueda vectorial.
+    pub fn with_contexts(self, contexts: Vec<HybridContext>) -> Self {
+        self.state().contexts = contexts;
+        self
+    }
+
+    /// Fragmentos que devuelve la búsqueda por términos.
+    pub fn with_keyword_contexts(self, contexts: Vec<HybridContext>) -> Self {
+        self.state().keyword_contexts = contexts;
+        self
+    }
+
+    pub fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+    }
+
+    fn check(&self) -> Result<(), AppError> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(AppError::DatabaseError("Neo4j unavailable".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl KGRepository for MemoryRepo {
+    async fn save_document(&self, id: Uuid, document: &DocumentInput) -> Result<(), AppError> {
+        self.check()?;
+        self.state().documents.push((id, document.clone()));
+        Ok(())
+    }
+
+    async fn save_chunk(&self, document_id: Uuid, id: Uuid, content: &str, embedding: Vec<f32>) -> Result<(), AppError> {
+        self.check()?;
+        self.state().chunks.push(StoredChunk { document_id, id, content: content.to_string(), embedding });
+        Ok(())
+    }
+
+    async fn list_documents(&self, metadata_filter: &HashMap<String, String>) -> Result<Vec<DocumentSummary>, AppError> {
+        self.check()?;
+        // Como en Neo4j: un valor escalar es una lista de uno y el filtro busca el valor en ella
+        let as_string = |value: &serde_json::Value| match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let matches = |metadata: &HashMap<String, serde_json::Value>| metadata_filter.iter().all(|(key, wanted)| {
+            match metadata.get(key) {
+                Some(serde_json::Value::Array(items)) => items.iter().any(|item| &as_string(item) == wanted),
+                Some(value) => &as_string(value) == wanted,
+                None => false,
+            }
+        });
+        let state = self.state();
+        Ok(state.documents.iter().rev()
+            .filter(|(_, document)| matches(&document.metadata))
+            .map(|(id, document)| DocumentSummary {
+                id: id.to_string(),
+                name: document.name.clone(),
+                created_at: String::new(),
+                content_type: document.content_type.clone(),
+                char_count: document.char_count,
+                chunk_count: state.chunks.iter().filter(|c| c.document_id == *id).count(),
+                metadata: document.metadata.clone(),
+            })
+            .collect())
+    }
+
+    async fn get_document_graph(&self, doc_group_id: Uuid) -> Result<GraphDataResponse, AppError> {
+        self.check()?;
+        // Entidades de las extracciones de sus chunks; relaciones de cualquier extracción (en Neo4j
+        // son aristas globales entre entidades) con ambos extremos dentro, más las inferidas
+        let state = self.state();
+        if !state.documents.iter().any(|(id, _)| *id == doc_group_id) {
+            return Err(AppError::NotFound(format!("Document {}", doc_group_id)));
+        }
+        let chunk_ids: Vec<Uuid> = state.chunks.iter().filter(|c| c.document_id == doc_group_id).map(|c| c.id).collect();
+        let mut nodes: Vec<VisNode> = Vec::new();
+        for entity in state.graphs.iter().filter(|(id, _)| chunk_ids.contains(id)).flat_map(|(_, data)| &data.entities) {
+            if !nodes.iter().any(|n| n.id == entity.name) {
+                nodes.push(VisNode {
+                    id: entity.name.clone(),
+                    label: entity.name.clone(),
+                    group: entity.category.clone(),
+                    centrality: state.centrality.get(&entity.name).copied(),
+                });
+            }
+        }
+        let inside = |name: &str| nodes.iter().any(|n| n.id == name);
+        let mut edges: Vec<VisEdge> = Vec::new();
+        for (chunk_id, data) in &state.graphs {
+            for r in data.relations.iter().filter(|r| inside(&r.source) && inside(&r.target)) {
+                let chunk_id = chunk_id.to_string();
+                match edges.iter_mut().find(|e| e.from == r.source && e.to == r.target && e.label == r.relation_type) {
+                    Some(edge) if edge.sources.contains(&chunk_id) => {},
+                    Some(edge) => edge.sources.push(chunk_id),
+                    None => edges.push(VisEdge {
+                        from: r.source.clone(),
+                        to: r.target.clone(),
+                        label: r.relation_type.clone(),
+                        sources: vec![chunk_id],
+                        confidence: r.confidence.map(f64::from),
+                        reasoning: None,
+                        inferred: false,
+                    }),
+                }
+            }
+        }
+        for r in state.inferred.iter().filter(|r| inside(&r.source) && inside(&r.target)) {
+            edges.push(VisEdge {
+                from: r.source.clone(),
+                to: r.target.clone(),
+                label: r.relation.clone(),
+                sources: Vec::new(),
+                confidence: r.confidence.map(f64::from),
+                reasoning: Some(r.reasoning.clone()),
+                inferred: true,
+            });
+        }
+        Ok(GraphDataResponse { nodes, edges, limits_reached: Vec::new() })
+    }
+
+    async fn delete_document(&self, doc_group_id: Uuid) -> Result<DocumentDeletion, AppError> {
+        self.check()?;
+        let mut state = self.state();
+        let Some(position) = state.documents.iter().position(|(id, _)| *id == doc_group_id) else {
+            return Err(AppError::NotFound(format!("Document {}", doc_group_id)));
+        };
+        state.documents.remove(position);
+
+        let deleted: Vec<Uuid> = state.chunks.iter().filter(|c| c.document_id == doc_group_id).map(|c| c.id).collect();
+        state.chunks.retain(|c| c.document_id != doc_group_id);
+        for chunk_id in &deleted {
+            state.provenance.remove(chunk_id);
+        }
+
+        // Las extracciones van por chunk: al quitar las de los chunks borrados desaparecen sus menciones
+        let mentioned = |graphs: &[(Uuid, KnowledgeExtraction)], keep: bool| -> Vec<String> {
+            let mut names: Vec<String> = graphs.iter()
+                .filter(|(chunk_id, _)| deleted.contains(chunk_id) != keep)
+                .flat_map(|(_, data)| data.entities.iter().map(|e| e.name.clone()))
+                .collect();
+            names.sort();
+            names.dedup();
+            names
+        };
+        let before = mentioned(&state.graphs, false);
+        let remaining = mentioned(&state.graphs, true);
+        let times = std::mem::take(&mut state.graph_times);
+        let graphs = std::mem::take(&mut state.graphs);
+        for ((chunk_id, data), time) in graphs.into_iter().zip(times) {
+            if !deleted.contains(&chunk_id) {
+                state.graphs.push((chunk_id, data));
+                state.graph_times.push(time);
+            }
+        }
+
+        Ok(DocumentDeletion {
+            document_id: doc_group_id.to_string(),
+            chunks_deleted: deleted.len(),
+            entities_deleted: before.iter().filter(|name| !remaining.contains(name)).count(),
+        })
+    }
+
+    async fn save_graph(&self, chunk_id: Uuid, mut data: KnowledgeExtraction, provenance: Option<&ExtractionProvenance>) -> Result<(), AppError> {
+        self.check()?;
+        // Como Neo4jRepo por defecto: las relaciones repetidas en la extracción se guardan una vez
+        data.relations = dedupe_relations(data.relations, EntityMatching::default());
+        let mut state = self.state();
+        if let Some(provenance) = provenance {
+            state.provenance.insert(chunk_id, provenance.clone());
+        }
+        state.graphs.push((chunk_id, data));
+        state.graph_times.push(now_millis());
+        Ok(())
+    }
+
+    async fn save_chunk_extraction(&self, chunk_id: Uuid, extraction_json: &str) -> Result<(), AppError> {
+        self.check()?;
+        let mut state = self.state();
+        // Como el MATCH de Neo4j: sin chunk no se guarda nada
+        if state.chunks.iter().any(|c| c.id == chunk_id) {
+            state.extractions.insert(chunk_id, extraction_json.to_string());
+        }
+        Ok(())
+    }
+
+    async fn reset_database(&self) -> Result<usize, AppError> {
+        self.check()?;
+        let mut state = self.state();
+        // Solo se cuentan los nodos que guarda este repositorio: documentos y chunks
+        let deleted = state.documents.len() + state.chunks.len();
+        let contexts = std::mem::take(&mut state.contexts);
+        let keyword_contexts = std::mem::take(&mut state.keyword_contexts);
+        let resets = state.resets + 1;
+        *state = MemoryState { contexts, keyword_contexts, resets, ..MemoryState::default() };
+        Ok(deleted)
+    }
+
+    async fn create_indexes(&self, dim: usize, similarity: VectorSimilarity) -> Result<(), AppError> {
+        self.check()?;
+        self.state().indexes.push((dim, similarity));
+        Ok(())
+    }
+
+    async fn create_embedding_slot_index(&self, slot: &EmbeddingSlot, _similarity: VectorSimilarity) -> Result<(), AppError> {
+        self.check()?;
+        self.state().slot_indexes.push(slot.clone());
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.check()?;
+        Ok(())
+    }
+
+    async fn get_full_graph(&self, filter: &GraphFilter) -> Result<GraphDataResponse, AppError> {
+        self.check()?;
+        // Entidades y relaciones de los `save_graph` recibidos más las inferidas; `min_degree`
+        // cuenta las relaciones de cada entidad y `min_confidence` trata las relaciones sin
+        // confianza como seguras, como en Neo4j. `limit` recorta solo las aristas
+        let state = self.state();
+        let min_degree = filter.min_degree.unwrap_or(0);
+        let degree = |name: &str| state.graphs.iter()
+            .flat_map(|(_, data)| &data.relations)
+            .filter(|r| r.source == name || r.target == name)
+            .count()
+            + state.inferred.iter().filter(|r| r.source == name || r.target == name).count();
+        let confident = |r: &&GraphRelation| filter.min_confidence
+            .is_none_or(|min| r.confidence.map_or(DEFAULT_EDGE_CONFIDENCE, f64::from) >= min);
+        let mut nodes: Vec<VisNode> = Vec::new();
+        let mut edges: Vec<VisEdge> = Vec::new();
+        for (chunk_id, data) in &state.graphs {
+            for entity in &data.entities {
+                if degree(&entity.name) >= min_degree && !nodes.iter().any(|n| n.id == entity.name) {
+                    nodes.push(VisNode {
+                        id: entity.name.clone(),
+                        label: entity.name.clone(),
+                        group: entity.category.clone(),
+                        centrality: state.centrality.get(&entity.name).copied(),
+                    });
+                }
+            }
+            for r in data.relations.iter()
+                .filter(|r| degree(&r.source) >= min_degree && degree(&r.target) >= min_degree)
+                .filter(confident)
+            {
+                // Una arista por relación; cada chunk que la afirma se acumula en `sources`
+                let chunk_id = chunk_id.to_string();
+                match edges.iter_mut().find(|e| e.from == r.source && e.to == r.target && e.label == r.relation_type) {
+                    Some(edge) if edge.sources.contains(&chunk_id) => {},
+                    Some(edge) => edge.sources.push(chunk_id),
+                    None => edges.push(VisEdge {
+                        from: r.source.clone(),
+                        to: r.target.clone(),
+                        label: r.relation_type.clone(),
+                        sources: vec![chunk_id],
+                        confidence: r.confidence.map(f64::from),
+                        reasoning: None,
+                        inferred: false,
+                    }),
+                }
+            }
+        }
+        let is_entity = |name: &str| state.graphs.iter().any(|(_, data)| data.entities.iter().any(|e| e.name == name));
+        for r in state.inferred.iter()
+            .filter(|r| is_entity(&r.source) && is_entity(&r.target))
+            .filter(|r| degree(&r.source) >= min_degree && degree(&r.target) >= min_degree)
+            .filter(|r| filter.min_confidence.is_none_or(|min| r.confidence.map_or(DEFAULT_EDGE_CONFIDENCE, f64::from) >= min))
+        {
+            edges.push(VisEdge {
+                from: r.source.clone(),
+                to: r.target.clone(),
+                label: r.relation.clone(),
+                sources: Vec::new(),
+                confidence: r.confidence.map(f64::from),
+                reasoning: Some(r.reasoning.clone()),
+                inferred: true,
+            });
+        }
+        let (max_nodes, max_edges) = (filter.node_limit(), filter.edge_limit());
+        let mut limits_reached = Vec::new();
+        if nodes.len() > max_nodes {
+            nodes.truncate(max_nodes);
+            edges.retain(|e| nodes.iter().any(|n| n.id == e.from) && nodes.iter().any(|n| n.id == e.to));
+            limits_reached.push(GraphLimit::MaxNodes);
+        }
+        if edges.len() > max_edges {
+            edges.truncate(max_edges);
+            limits_reached.push(GraphLimit::MaxEdges);
+        }
+        Ok(GraphDataResponse { nodes, edges, limits_reached })
+    }
+
+    async fn stream_graph_records(&self, include_chunks: bool, tx: mpsc::Sender<ExportRecord>) -> Result<usize, AppError> {
+        self.check()?;
+        // Una entidad por nombre (categoría + atributos) y una relación por (origen, destino, tipo);
+        // se preparan con el lock tomado y se envían después
+        let records: Vec<ExportRecord> = {
+            let state = self.state();
+            let mut entities: Vec<ExportedEntity> = Vec::new();
+            let mut relations: Vec<ExportedRelation> = Vec::new();
+            for (_, data) in &state.graphs {
+                for entity in &data.entities {
+                    if entities.iter().any(|e| e.name == entity.name) {
+                        continue;
+                    }
+                    let mut properties = entity.attributes.clone();
+                    properties.insert("category".to_string(), serde_json::json!(entity.category));
+                    entities.push(ExportedEntity { name: entity.name.clone(), properties });
+                }
+                for r in &data.relations {
+                    if relations.iter().any(|e| e.source == r.source && e.target == r.target && e.relation_type == r.relation_type) {
+                        continue;
+                    }
+                    let properties = r.confidence.map(|c| ("confidence".to_string(), serde_json::json!(c))).into_iter().collect();
+                    relations.push(ExportedRelation {
+                        source: r.source.clone(),
+                        target: r.target.clone(),
+                        relation_type: r.relation_type.clone(),
+                        properties,
+                    });
+                }
+            }
+            let mut records: Vec<ExportRecord> = entities.into_iter().map(ExportRecord::Entity)
+                .chain(relations.into_iter().map(ExportRecord::Relation))
+                .collect();
+
+            if include_chunks {
+                records.extend(state.documents.iter().map(|(id, document)| ExportRecord::Document(ExportedNode {
+                    id: id.to_string(),
+                    document_id: None,
+                    properties: HashMap::from([("name".to_string(), serde_json::json!(document.name))]),
+                })));
+                records.extend(state.chunks.iter().map(|chunk| ExportRecord::Chunk(ExportedNode {
+                    id: chunk.id.to_string(),
+                    document_id: Some(chunk.document_id.to_string()),
+                    properties: HashMap::from([("content".to_string(), serde_json::json!(chunk.content))]),
+                })));
+                for (chunk_id, data) in &state.graphs {
+                    for entity in &data.entities {
+                        records.push(ExportRecord::Mention { chunk_id: chunk_id.to_string(), entity: entity.name.clone() });
+                    }
+                }
+            }
+            records
+        };
+        let mut sent = 0;
+        for record in records {
+            if tx.send(record).await.is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    async fn get_graph_since(&self, since_millis: i64) -> Result<GraphDelta, AppError> {
+        self.check()?;
+        // Como `created_at` en Neo4j: cuenta la primera vez que se guardó cada entidad o relación
+        let timestamp = now_millis();
+        let state = self.state();
+        let mut seen_nodes: Vec<&str> = Vec::new();
+        let mut seen_edges: Vec<(&str, &str, &str)> = Vec::new();
+        let mut nodes: Vec<VisNode> = Vec::new();
+        let mut edges: Vec<VisEdge> = Vec::new();
+        let node = |name: &str, group: &str| VisNode {
+            id: name.to_string(),
+            label: name.to_string(),
+            group: group.to_string(),
+            centrality: state.centrality.get(name).copied(),
+        };
+        let category = |name: &str| state.graphs.iter()
+            .flat_map(|(_, data)| &data.entities)
+            .find(|e| e.name == name)
+            .map_or_else(|| "Concept".to_string(), |e| e.category.clone());
+        for ((chunk_id, data), created_at) in state.graphs.iter().zip(&state.graph_times) {
+            let is_new = *created_at > since_millis;
+            for entity in &data.entities {
+                if seen_nodes.contains(&entity.name.as_str()) {
+                    continue;
+                }
+                seen_nodes.push(&entity.name);
+                if is_new && !nodes.iter().any(|n| n.id == entity.name) {
+                    nodes.push(node(&entity.name, &entity.category));
+                }
+            }
+            for r in &data.relations {
+                let key = (r.source.as_str(), r.target.as_str(), r.relation_type.as_str());
+                if seen_edges.contains(&key) {
+                    continue;
+                }
+                seen_edges.push(key);
+                if !is_new {
+                    continue;
+                }
+                for name in [&r.source, &r.target] {
+                    if !nodes.iter().any(|n| &n.id == name) {
+                        nodes.push(node(name, &category(name)));
+                    }
+                }
+                edges.push(VisEdge {
+                    from: r.source.clone(),
+                    to: r.target.clone(),
+                    label: r.relation_type.clone(),
+                    sources: vec![chunk_id.to_string()],
+                    confidence: r.confidence.map(f64::from),
+                    reasoning: None,
+                    inferred: false,
+                });
+            }
+        }
+        Ok(GraphDelta { nodes, edges, timestamp })
+    }
+
+    async fn find_hybrid_context(&self, embedding: Vec<f32>, limit: usize, hops: usize, min_score: Option<f64>, slot: Option<&EmbeddingSlot>) -> Result<Vec<HybridContext>, AppError> {
+        self.check()?;
+        self.state().vector_queries.push((embedding.len(), slot.map(|s| s.name.clone())));
+        let state = self.state();
+        let neighbours = |name: &str| -> Vec<String> {
+            state.graphs.iter()
+                .flat_map(|(_, data)| &data.relations)
+                .filter_map(|r| if r.source == name { Some(r.target.clone()) } else if r.target == name { Some(r.source.clone()) } else { None })
+                .collect()
+        };
+        // Como en Neo4j: las mencionadas primero y después las alcanzables, salto a salto y con tope
+        // Los `limit` más parecidos, como el índice vectorial, y después el filtro y el orden por similitud
+        let mut contexts: Vec<HybridContext> = state.contexts.iter()
+            .take(limit)
+            .filter(|c| min_score.is_none_or(|min| c.score >= min))
+            .cloned()
+            .collect();
+        contexts.sort_by(|a, b| b.score.total_cmp(&a.score));
+        for context in &mut contexts {
+            let mut frontier = context.connected_entities.clone();
+            for _ in 1..hops.clamp(1, MAX_CONTEXT_HOPS) {
+                let mut next = Vec::new();
+                for name in frontier.iter().flat_map(|name| neighbours(name)) {
+                    if !context.connected_entities.contains(&name) && !next.contains(&name) {
+                        next.push(name);
+                    }
+                }
+                context.connected_entities.extend(next.iter().cloned());
+                frontier = next;
+            }
+            context.connected_entities.truncate(MAX_CONTEXT_ENTITIES_PER_CHUNK);
+        }
+        Ok(contexts)
+    }
+
+    async fn find_keyword_context(&self, _text: &str, limit: usize) -> Result<Vec<HybridContext>, AppError> {
+        self.check()?;
+        Ok(self.state().keyword_contexts.iter().take(limit).cloned().collect())
+    }
+
+    async fn get_concept_neighborhood(&self, concept_name: &str, direction: TraversalDirection) -> Result<GraphDataResponse, AppError> {
+        self.check()?;
+        // El concepto y sus vecinos directos en el sentido pedido
+        let state = self.state();
+        let node = |name: &str| {
+            let group = state.graphs.iter()
+                .flat_map(|(_, data)| &data.entities)
+                .find(|e| e.name == name)
+                .map(|e| e.category.clone())
+                .unwrap_or_default();
+            VisNode { id: name.to_string(), label: name.to_string(), group, centrality: state.centrality.get(name).copied() }
+        };
+        let mut nodes: Vec<VisNode> = Vec::new();
+        let mut edges: Vec<VisEdge> = Vec::new();
+        for (chunk_id, data) in &state.graphs {
+            for r in &data.relations {
+                let outgoing = r.source == concept_name && direction != TraversalDirection::In;
+                let incoming = r.target == concept_name && direction != TraversalDirection::Out;
+                if !outgoing && !incoming {
+                    continue;
+                }
+                for name in [&r.source, &r.target] {
+                    if !nodes.iter().any(|n| &n.id == name) {
+                        nodes.push(node(name));
+                    }
+                }
+                edges.push(VisEdge {
+                    from: r.source.clone(),
+                    to: r.target.clone(),
+                    label: r.relation_type.clone(),
+                    sources: vec![chunk_id.to_string()],
+                    confidence: r.confidence.map(f64::from),
+                    reasoning: None,
+                    inferred: false,
+                });
+            }
+        }
+        Ok(GraphDataResponse { nodes, edges, limits_reached: Vec::new() })
+    }
+
+    async fn list_chunks_needing_embedding(&self, dim: usize, force: bool) -> Result<Vec<(String, String)>, AppError> {
+        self.check()?;
+        Ok(self.state().chunks.iter()
+            .filter(|c| force || c.embedding.len() != dim)
+            .map(|c| (c.id.to_string(), c.content.clone()))
+            .collect())
+    }
+
+    async fn update_chunk_embedding(&self, chunk_id: &str, embedding: Vec<f32>) -> Result<(), AppError> {
+        self.check()?;
+        if let Some(chunk) = self.state().chunks.iter_mut().find(|c| c.id.to_string() == chunk_id) {
+            chunk.embedding = embedding;
+        }
+        Ok(())
+    }
+
+    async fn save_chunk_slot_embedding(&self, chunk_id: Uuid, slot: &EmbeddingSlot, embedding: Vec<f32>) -> Result<(), AppError> {
+        self.check()?;
+        self.state().slot_embeddings.insert((chunk_id, slot.name.clone()), embedding);
+        Ok(())
+    }
+
+    async fn export_embeddings(&self) -> Result<Vec<EmbeddingRecord>, AppError> {
+        self.check()?;
+        Ok(self.state().chunks.iter()
+            .map(|c| EmbeddingRecord {
+                chunk_id: c.id.to_string(),
+                content_hash: EmbeddingRecord::hash_content(&c.content),
+                embedding: c.embedding.clone(),
+            })
+            .collect())
+    }
+
+    async fn list_categories(&self) -> Result<Vec<CategoryCount>, AppError> {
+        self.check()?;
+        // Una entidad por nombre con la última categoría guardada, como el MERGE de Neo4j
+        let mut categories_by_name: HashMap<&str, &str> = HashMap::new();
+        let state = self.state();
+        for entity in state.graphs.iter().flat_map(|(_, data)| &data.entities) {
+            categories_by_name.insert(&entity.name, &entity.category);
+        }
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for category in categories_by_name.into_values() {
+            *counts.entry(category).or_default() += 1;
+        }
+        let mut categories: Vec<CategoryCount> = counts.into_iter()
+            .map(|(category, count)| CategoryCount { category: category.to_string(), count })
+            .collect();
+        categories.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.category.cmp(&b.category)));
+        Ok(categories)
+    }
+
+    async fn get_stats(&self) -> Result<GraphStats, AppError> {
+        self.check()?;
+        // Relaciones únicas por (origen, tipo, destino) como el MERGE de Neo4j, más las inferidas
+        let state = self.state();
+        let mut entities: Vec<&str> = Vec::new();
+        for entity in state.graphs.iter().flat_map(|(_, data)| &data.entities) {
+            if !entities.contains(&entity.name.as_str()) {
+                entities.push(&entity.name);
+            }
+        }
+        let mut relations: Vec<(&str, &str, &str)> = Vec::new();
+        for r in state.graphs.iter().flat_map(|(_, data)| &data.relations) {
+            let key = (r.source.as_str(), r.relation_type.as_str(), r.target.as_str());
+            if entities.contains(&key.0) && entities.contains(&key.2) && !relations.contains(&key) {
+                relations.push(key);
+            }
+        }
+        let inferred: Vec<(&str, &str, &str)> = state.inferred.iter()
+            .filter(|r| entities.contains(&r.source.as_str()) && entities.contains(&r.target.as_str()))
+            .map(|r| (r.source.as_str(), r.relation.as_str(), r.target.as_str()))
+            .collect();
+
+        let mut top_entities: Vec<EntityDegree> = entities.iter()
+            .map(|name| EntityDegree {
+                name: name.to_string(),
+                degree: relations.iter().chain(&inferred).filter(|(s, _, t)| s == name || t == name).count(),
+            })
+            .filter(|e| e.degree > 0)
+            .collect();
+        top_entities.sort_by(|a, b| b.degree.cmp(&a.degree).then_with(|| a.name.cmp(&b.name)));
+        top_entities.truncate(10);
+
+        Ok(GraphStats {
+            entities: entities.len(),
+            relations: relations.len() + inferred.len(),
+            inferred_relations: inferred.len(),
+            chunks: state.chunks.len(),
+            top_entities,
+        })
+    }
+
+    async fn list_entity_names(&self, limit: usize) -> Result<Vec<String>, AppError> {
+        self.check()?;
+        let state = self.state();
+        let mut names: Vec<(String, usize)> = Vec::new();
+        for entity in state.graphs.iter().flat_map(|(_, data)| &data.entities) {
+            if !names.iter().any(|(n, _)| n == &entity.name) {
+                let degree = state.graphs.iter()
+                    .flat_map(|(_, data)| &data.relations)
+                    .filter(|r| r.source == entity.name || r.target == entity.name)
+                    .count();
+                names.push((entity.name.clone(), degree));
+            }
+        }
+        names.sort_by_key(|(_, degree)| std::cmp::Reverse(*degree));
+        Ok(names.into_iter().take(limit).map(|(name, _)| name).collect())
+    }
+
+    async fn search_entities(&self, text: &str, limit: usize) -> Result<Vec<EntityMatch>, AppError> {
+        self.check()?;
+        // Como el índice full-text: cada palabra buscada es prefijo de alguna palabra del nombre
+        let terms: Vec<String> = text.split_whitespace().map(str::to_lowercase).collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let state = self.state();
+        let mut matches: Vec<EntityMatch> = Vec::new();
+        for entity in state.graphs.iter().flat_map(|(_, data)| &data.entities) {
+            let name = entity.name.to_lowercase();
+            let words: Vec<&str> = name.split_whitespace().collect();
+            let found = terms.iter().all(|term| words.iter().any(|word| word.starts_with(term.as_str())));
+            if found && !matches.iter().any(|m| m.name == entity.name) {
+                let degree = state.graphs.iter()
+                    .flat_map(|(_, data)| &data.relations)
+                    .filter(|r| r.source == entity.name || r.target == entity.name)
+                    .count();
+                matches.push(EntityMatch { name: entity.name.clone(), category: entity.category.clone(), degree });
+            }
+        }
+        matches.sort_by(|a, b| b.degree.cmp(&a.degree).then_with(|| a.name.cmp(&b.name)));
+        matches.truncate(limit);
+        Ok(matches)
+    }
+
+    async fn find_chunks_by_entity(&self, name: &str, limit: usize) -> Result<Vec<EntityChunk>, AppError> {
+        self.check()?;
+        // MENTIONS = la entidad aparece en el `save_graph` de ese chunk
+        let state = self.state();
+        let mentions = |chunk_id: &Uuid| state.graphs.iter()
+            .any(|(id, data)| id == chunk_id && data.entities.iter().any(|e| e.name == name));
+        Ok(state.chunks.iter()
+            .filter(|c| mentions(&c.id))
+            .take(limit)
+            .map(|c| EntityChunk {
+                chunk_id: c.id.to_string(),
+                document_id: Some(c.document_id.to_string()),
+                preview: c.content.chars().take(150).collect(),
+                content: c.content.clone(),
+            })
+            .collect())
+    }
+
+    async fn stream_entities(&self, limit: Option<usize>, tx: mpsc::Sender<EntityExportRow>) -> Result<usize, AppError> {
+        self.check()?;
+        // Las filas se preparan con el lock tomado y se envían después (el envío puede esperar)
+        let rows: Vec<EntityExportRow> = {
+            let state = self.state();
+            let mut relations: Vec<(&str, &str, &str)> = Vec::new();
+            for r in state.graphs.iter().flat_map(|(_, data)| &data.relations) {
+                let key = (r.source.as_str(), r.target.as_str(), r.relation_type.as_str());
+                if !relations.contains(&key) {
+                    relations.push(key);
+                }
+            }
+            let mut rows: Vec<EntityExportRow> = Vec::new();
+            for entity in state.graphs.iter().flat_map(|(_, data)| &data.entities) {
+                if !rows.iter().any(|row| row.name == entity.name) {
+                    rows.push(EntityExportRow {
+                        name: entity.name.clone(),
+                        category: entity.category.clone(),
+                        degree: relations.iter().filter(|(s, t, _)| *s == entity.name || *t == entity.name).count() as i64,
+                        centrality: state.centrality.get(&entity.name).copied(),
+                    });
+                }
+            }
+            rows.truncate(limit.unwrap_or(usize::MAX));
+            rows
+        };
+        let mut sent = 0;
+        for row in rows {
+            if tx.send(row).await.is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    async fn merge_entities(&self, canonical: &str, duplicates: &[String]) -> Result<usize, AppError> {
+        self.check()?;
+        // Las relaciones de los duplicados pasan a la canónica; `get_full_graph` las une con
+        // las que ya tenía, combinando sus fuentes
+        let mut state = self.state();
+        if !state.graphs.iter().flat_map(|(_, data)| &data.entities).any(|e| e.name == canonical) {
+            return Err(AppError::ValidationError(format!("Canonical entity not found: {}", canonical)));
+        }
+        let duplicates: Vec<&String> = duplicates.iter().filter(|d| d.as_str() != canonical).collect();
+        for (_, data) in state.graphs.iter_mut() {
+            data.entities.retain(|e| !duplicates.contains(&&e.name));
+            for relation in data.relations.iter_mut() {
+                for end in [&mut relation.source, &mut relation.target] {
+                    if duplicates.contains(&&*end) {
+                        *end = canonical.to_string();
+                    }
+                }
+            }
+        }
+        Ok(duplicates.len())
+    }
+
+    async fn get_entity_graph(&self) -> Result<(Vec<String>, Vec<(String, String)>), AppError> {
+        self.check()?;
+        let state = self.state();
+        let mut names: Vec<String> = Vec::new();
+        for entity in state.graphs.iter().flat_map(|(_, data)| &data.entities) {
+            if !names.contains(&entity.name) {
+                names.push(entity.name.clone());
+            }
+        }
+        let edges = state.graphs.iter()
+            .flat_map(|(_, data)| &data.relations)
+            .map(|r| (r.source.clone(), r.target.clone()))
+            .collect();
+        Ok((names, edges))
+    }
+
+    async fn save_centrality(&self, scores: &HashMap<String, f64>) -> Result<(), AppError> {
+        self.check()?;
+        self.state().centrality.extend(scores.iter().map(|(name, score)| (name.clone(), *score)));
+        Ok(())
+    }
+
+    async fn get_timeline(&self) -> Result<(Vec<TimelineEvent>, Vec<(String, String)>), AppError> {
+        self.check()?;
+        // Como el MERGE por nombre de Neo4j: un suceso por nombre, la última fecha conocida y
+        // solo participantes que existen como entidad
+        let state = self.state();
+        let entity_names: Vec<&str> = state.graphs.iter()
+            .flat_map(|(_, data)| data.entities.iter().map(|e| e.name.as_str()))
+            .collect();
+        let mut events: Vec<TimelineEvent> = Vec::new();
+        for event in state.graphs.iter().flat_map(|(_, data)| &data.events) {
+            let index = match events.iter().position(|e| e.name == event.name) {
+                Some(index) => index,
+                None => {
+                    events.push(TimelineEvent { name: event.name.clone(), date: None, participants: Vec::new() });
+                    events.len() - 1
+                }
+            };
+            let timeline_event = &mut events[index];
+            if event.date.is_some() {
+                timeline_event.date = event.date.clone();
+            }
+            for participant in &event.participants {
+                if entity_names.contains(&participant.as_str()) && !timeline_event.participants.contains(participant) {
+                    timeline_event.participants.push(participant.clone());
+                }
+            }
+        }
+        let mut order: Vec<(String, String)> = Vec::new();
+        for relation in state.graphs.iter().flat_map(|(_, data)| &data.temporal_relations) {
+            let pair = (relation.before.clone(), relation.after.clone());
+            let known = |name: &str| events.iter().any(|e| e.name == name);
+            if pair.0 != pair.1 && known(&pair.0) && known(&pair.1) && !order.contains(&pair) {
+                order.push(pair);
+            }
+        }
+        Ok((events, order))
+    }
+
+    async fn find_stale_chunks(&self, model: Option<&str>, prompt_version: &str, include_unversioned: bool, limit: usize) -> Result<(usize, Vec<StaleChunk>), AppError> {
+        self.check()?;
+        let state = self.state();
+        let stale: Vec<StaleChunk> = state.chunks.iter()
+            .filter_map(|chunk| {
+                let provenance = state.provenance.get(&chunk.id);
+                let is_stale = match provenance {
+                    Some(p) => p.prompt_version != prompt_version || model.is_some_and(|m| p.model != m),
+                    None => include_unversioned,
+                };
+                is_stale.then(|| StaleChunk {
+                    chunk_id: chunk.id.to_string(),
+                    document_id: Some(chunk.document_id.to_string()),
+                    extraction_model: provenance.map(|p| p.model.clone()),
+                    extraction_prompt_version: provenance.map(|p| p.prompt_version.clone()),
+                    extracted_at: None,
+                })
+            })
+            .collect();
+        let total = stale.len();
+        Ok((total, stale.into_iter().take(limit).collect()))
+    }
+
+    async fn list_stored_extractions(&self) -> Result<(Vec<(String, String)>, usize), AppError> {
+        self.check()?;
+        let state = self.state();
+        let mut ids: Vec<Uuid> = state.chunks.iter().map(|c| c.id).collect();
+        ids.sort_by_key(|id| id.to_string());
+        let stored: Vec<(String, String)> = ids.iter()
+            .filter_map(|id| state.extractions.get(id).map(|json| (id.to_string(), json.clone())))
+            .collect();
+        let missing = ids.len() - stored.len();
+        Ok((stored, missing))
+    }
+
+    async fn clear_extracted_graph(&self) -> Result<usize, AppError> {
+        self.check()?;
+        let mut state = self.state();
+        let mut names: Vec<String> = state.graphs.iter()
+            .flat_map(|(_, data)| {
+                data.entities.iter().map(|e| format!("entity:{}", e.name))
+                    .chain(data.relations.iter().flat_map(|r| [format!("entity:{}", r.source), format!("entity:{}", r.target)]))
+                    .chain(data.events.iter().map(|ev| format!("event:{}", ev.name)))
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        // Las relaciones inferidas y la centralidad viven en las entidades: desaparecen con ellas
+        state.graphs.clear();
+        state.graph_times.clear();
+        state.inferred.clear();
+        state.inferred_times.clear();
+        state.centrality.clear();
+        Ok(names.len())
+    }
+
+    async fn prune_orphan_entities(&self) -> Result<usize, AppError> {
+        self.check()?;
+        // Como en Neo4j, MENTIONS solo existe si el chunk de la extracción está guardado
+        let mut state = self.state();
+        let state = &mut *state;
+        let connected: Vec<String> = state.graphs.iter()
+            .flat_map(|(chunk_id, data)| {
+                let mentioned = state.chunks.iter().any(|c| c.id == *chunk_id);
+                data.entities.iter().filter(move |_| mentioned).map(|e| e.name.clone())
+                    .chain(data.relations.iter().flat_map(|r| [r.source.clone(), r.target.clone()]))
+            })
+            .chain(state.inferred.iter().flat_map(|r| [r.source.clone(), r.target.clone()]))
+            .collect();
+        let mut pruned: Vec<String> = Vec::new();
+        for (_, data) in state.graphs.iter_mut() {
+            data.entities.retain(|e| {
+                let orphan = !connected.contains(&e.name);
+                if orphan && !pruned.contains(&e.name) {
+                    pruned.push(e.name.clone());
+                }
+                !orphan
+            });
+        }
+        Ok(pruned.len())
+    }
+
+    async fn get_graph_context_for_reasoning(&self, limit: usize) -> Result<String, AppError> {
+        self.check()?;
+        // Mismo formato de triplas que Neo4j, en orden de guardado
+        let context: String = self.state().graphs.iter()
+            .flat_map(|(_, data)| &data.relations)
+            .take(limit)
+            .map(|r| format!("({}) -[{}]-> ({})\n", r.source, r.relation_type, r.target))
+            .collect();
+        if context.is_empty() {
+            return Ok("El grafo está vacío.".to_string());
+        }
+        Ok(context)
+    }
+
+    async fn save_inferred_relations(&self, relations: Vec<InferredRelation>) -> Result<(), AppError> {
+        self.check()?;
+        let mut state = self.state();
+        let now = now_millis();
+        state.inferred_times.extend(relations.iter().map(|_| now));
+        state.inferred.extend(relations);
+        Ok(())
+    }
+
+    async fn delete_inferred_relations_older_than(&self, max_age: std::time::Duration) -> Result<usize, AppError> {
+        self.check()?;
+        let cutoff = now_millis() - max_age.as_millis() as i64;
+        let mut state = self.state();
+        let state = &mut *state;
+        let before = state.inferred.len();
+        let mut times = state.inferred_times.iter();
+        state.inferred.retain(|_| times.next().is_some_and(|t| *t >= cutoff));
+        state.inferred_times.retain(|t| *t >= cutoff);
+        Ok(before - state.inferred.len())
+    }
+}
@@ -1 +1,3 @@
-pub mod neo4j_repo;
\ No newline at end of file
+pub mod neo4j_repo;
+#[cfg(test)]
+pub mod memory_repo;
\ No newline at end of file
@@ -1,48 +1,615 @@
 use async_trait::async_trait;
-use neo4rs::{Graph, query};
+use neo4rs::{BoltType, Graph, query};
 use uuid::Uuid;
+use serde::Deserialize;
 use std::sync::Arc;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::{mpsc, Semaphore};
 use crate::domain::{
     ports::KGRepository, 
-    models::{KnowledgeExtraction, GraphDataResponse, VisNode, VisEdge, HybridContext, InferredRelation}, 
+    models::{DocumentDeletion, DocumentInput, DocumentSummary, EntityChunk, EntityExportRow, ExportRecord, ExportedEntity, ExportedNode, ExportedRelation, ExtractionProvenance, KnowledgeExtraction, GraphDataResponse, GraphRelation, GraphDelta, GraphFilter, GraphLimit, TraversalDirection, VisNode, VisEdge, HybridContext, InferredRelation, EmbeddingRecord, StaleChunk, TimelineEvent, CategoryCount, EntityDegree, EntityMatch, GraphStats, VectorSimilarity, EmbeddingSlot, MAX_CONTEXT_ENTITIES_PER_CHUNK, MAX_CONTEXT_HOPS}, 
     errors::AppError
 };
 
+/// Límite por defecto de transacciones simultáneas contra Neo4j.
+pub const DEFAULT_MAX_CONCURRENT_TXNS: usize = 8;
+
+/// Confianza asumida para relaciones sin propiedad `confidence` (no se filtran).
+pub const DEFAULT_EDGE_CONFIDENCE: f64 = 1.0;
+
+/// Voto de categoría: cada extracción suma uno a la categoría que le da a la entidad
+/// (`category_names` / `category_counts`, listas paralelas) y `e.category` pasa a ser la
+/// más votada; en empate se queda la que llegó antes.
+const CATEGORY_VOTE_CYPHER: &str = "MERGE (e:Entity {name: $name}) \
+     ON CREATE SET e.name_key = toLower($name), e.created_at = datetime() \
+     SET e += $attributes \
+     WITH e, \
+          coalesce(e.category_names, CASE WHEN e.category IS NULL THEN [] ELSE [e.category] END) AS names, \
+          coalesce(e.category_counts, CASE WHEN e.category IS NULL THEN [] ELSE [1] END) AS counts \
+     WITH e, \
+          CASE WHEN $category IN names THEN names ELSE names + $category END AS names, \
+          CASE WHEN $category IN names \
+               THEN [i IN range(0, size(counts) - 1) | CASE WHEN names[i] = $category THEN counts[i] + 1 ELSE counts[i] END] \
+               ELSE counts + 1 END AS counts \
+     WITH e, names, counts, \
+          reduce(best = 0, i IN range(0, size(counts) - 1) | CASE WHEN counts[i] > counts[best] THEN i ELSE best END) AS best \
+     SET e.category_names = names, e.category_counts = counts, e.category = names[best]";
+
+/// Cómo se comparan los nombres de entidad al fusionarlas en `save_graph`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntityMatching {
+    /// Nombre exacto: "NASA" y "nasa" son entidades distintas (códigos, siglas...)
+    #[default]
+    CaseSensitive,
+    /// Sin distinguir mayúsculas; el nombre visible es la grafía vista primero
+    CaseInsensitive,
+}
+
+impl std::str::FromStr for EntityMatching {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "case_sensitive" | "sensitive" => Ok(Self::CaseSensitive),
+            "case_insensitive" | "insensitive" => Ok(Self::CaseInsensitive),
+            other => Err(format!("Unknown entity matching mode: {}", other)),
+        }
+    }
+}
+
+/// Sinónimos de tipos de relación (`ESTABLISHED` -> `FOUNDED`): se aplican al guardar
+/// para que las variantes de distintos documentos acaben en el mismo tipo.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelationAliases(HashMap<String, String>);
+
+impl RelationAliases {
+    /// Tipo canónico ya normalizado (`established` -> `FOUNDED`); sin alias, el propio tipo.
+    pub fn canonical(&self, relation_type: &str) -> String {
+        let label = relation_label(relation_type);
+        self.0.get(&label).cloned().unwrap_or(label)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Formato `ESTABLISHED=FOUNDED,CREATED_BY=FOUNDED` (se normalizan como los tipos guardados).
+impl std::str::FromStr for RelationAliases {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut aliases = HashMap::new();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (alias, canonical) = pair.split_once('=')
+                .ok_or_else(|| format!("Invalid relation alias '{}' (expected ALIAS=CANONICAL)", pair))?;
+            if alias.trim().is_empty() || canonical.trim().is_empty() {
+                return Err(format!("Invalid relation alias '{}' (expected ALIAS=CANONICAL)", pair));
+            }
+            let (alias, canonical) = (relation_label(alias), relation_label(canonical));
+            if alias != canonical {
+                aliases.insert(alias, canonical);
+            }
+        }
+        Ok(Self(aliases))
+    }
+}
+
+impl EntityMatching {
+    /// Clave normalizada con la que se identifica una entidad.
+    pub fn key(self, name: &str) -> String {
+        match self {
+            Self::CaseSensitive => name.to_string(),
+            Self::CaseInsensitive => name.to_lowercase(),
+        }
+    }
+}
+
 pub struct Neo4jRepo {
     graph: Arc<Graph>,
+    // Permisos para abrir transacciones (evita agotar el pool de conexiones)
+    txn_permits: Arc<Semaphore>,
+    // Confianza de las relaciones antiguas/sin valor al filtrar por `min_confidence`
+    default_confidence: f64,
+    // Comparación de nombres al fusionar entidades
+    entity_matching: EntityMatching,
+    // Categoría por mayoría entre extracciones (si no, se queda la primera)
+    category_voting: bool,
+    // Una sola MERGE por relación repetida dentro de la misma extracción
+    dedupe_relations: bool,
+    // Crear como nodo mínimo el extremo de una relación que no existe (si no, la relación se descarta avisando)
+    create_missing_endpoints: bool,
+    // Tipos de relación equivalentes que se guardan con su nombre canónico
+    relation_aliases: RelationAliases,
+    // Ontología: tipos de relación admitidos (None = cualquiera) y qué hacer con el resto
+    allowed_relations: Option<HashSet<String>>,
+    unknown_relations: UnknownRelation,
+    // Comprobar que el embedding de la consulta tiene la dimensión del índice vectorial
+    validate_query_dim: bool,
+    // Dimensión del índice `chunk_embeddings` leída de Neo4j (None = aún no consultada)
+    index_dim: std::sync::RwLock<Option<usize>>,
 }
 
 impl Neo4jRepo {
     pub fn new(graph: Arc<Graph>) -> Self {
-        Self { graph }
+        Self {
+            graph,
+            txn_permits: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_TXNS)),
+            default_confidence: DEFAULT_EDGE_CONFIDENCE,
+            entity_matching: EntityMatching::default(),
+            category_voting: true,
+            dedupe_relations: true,
+            create_missing_endpoints: false,
+            relation_aliases: RelationAliases::default(),
+            allowed_relations: None,
+            unknown_relations: UnknownRelation::default(),
+            validate_query_dim: true,
+            index_dim: std::sync::RwLock::new(None),
+        }
+    }
+
+    /// Limita el número de transacciones abiertas a la vez (mínimo 1).
+    pub fn with_max_concurrent_txns(mut self, limit: usize) -> Self {
+        let limit = limit.max(1);
+        tracing::info!("🔒 Neo4j: máximo {} transacciones concurrentes", limit);
+        self.txn_permits = Arc::new(Semaphore::new(limit));
+        self
+    }
+
+    /// Confianza que se asume para relaciones sin `confidence` al filtrar el grafo.
+    pub fn with_default_confidence(mut self, confidence: f64) -> Self {
+        self.default_confidence = confidence;
+        self
+    }
+
+    /// Modo de comparación de nombres al fusionar entidades (sensible o no a mayúsculas).
+    pub fn with_entity_matching(mut self, matching: EntityMatching) -> Self {
+        self.entity_matching = matching;
+        self
+    }
+
+    /// Categoría de cada entidad por mayoría de votos entre chunks (`false`: la primera vista).
+    pub fn with_category_voting(mut self, enabled: bool) -> Self {
+        self.category_voting = enabled;
+        self
+    }
+
+    /// Descarta las relaciones repetidas dentro de una misma extracción antes de guardarla.
+    pub fn with_relation_dedupe(mut self, enabled: bool) -> Self {
+        self.dedupe_relations = enabled;
+        self
+    }
+
+    /// Relaciones cuyo origen/destino no está en la extracción ni en el grafo: por defecto se
+    /// descartan (con aviso en el log); activado, se crea la entidad mínima (`auto_created`).
+    pub fn with_missing_endpoint_creation(mut self, enabled: bool) -> Self {
+        self.create_missing_endpoints = enabled;
+        self
+    }
+
+    /// Guarda las relaciones extraídas e inferidas con el tipo canónico de su alias.
+    pub fn with_relation_aliases(mut self, aliases: RelationAliases) -> Self {
+        self.relation_aliases = aliases;
+        self
+    }
+
+    /// Limita los tipos de relación que guarda `save_graph` (tras aplicar los alias); los demás
+    /// se guardan como `RELATED_TO` o se descartan según `unknown`. Un conjunto vacío no limita nada.
+    pub fn with_allowed_relations(mut self, types: impl IntoIterator<Item = String>, unknown: UnknownRelation) -> Self {
+        let allowed: HashSet<String> = types.into_iter().map(|t| relation_label(&t)).collect();
+        self.allowed_relations = (!allowed.is_empty()).then_some(allowed);
+        self.unknown_relations = unknown;
+        self
+    }
+
+    /// Aplica la lista de tipos permitidos (si la hay) a relaciones ya canonizadas por los alias.
+    fn restrict_relations(&self, chunk_id: Uuid, relations: &mut Vec<GraphRelation>) {
+        let Some(allowed) = &self.allowed_relations else { return };
+        relations.retain_mut(|rel| {
+            if allowed.contains(&rel.relation_type) {
+                return true;
+            }
+            match self.unknown_relations {
+                UnknownRelation::Generic => {
+                    tracing::debug!("🏷️ Chunk {}: relación {} fuera de la ontología, se guarda como {}", chunk_id, rel.relation_type, GENERIC_RELATION);
+                    rel.relation_type = GENERIC_RELATION.to_string();
+                    true
+                },
+                UnknownRelation::Reject => {
+                    tracing::warn!(
+                        "🚫 Chunk {}: relación {} -[{}]-> {} descartada (tipo no permitido)",
+                        chunk_id, rel.source, rel.relation_type, rel.target
+                    );
+                    false
+                },
+            }
+        });
+    }
+
+    /// Antes de cada búsqueda vectorial compara la dimensión del embedding con la del índice y,
+    /// si no coinciden, devuelve un error que nombra ambas en lugar del error de Neo4j.
+    pub fn with_query_dim_validation(mut self, enabled: bool) -> Self {
+        self.validate_query_dim = enabled;
+        self
+    }
+
+    /// Dimensión configurada en el índice vectorial (None si el índice no existe).
+    /// Se lee una vez y se guarda; `create_indexes` la invalida.
+    async fn vector_index_dim(&self) -> Result<Option<usize>, AppError> {
+        if let Some(dim) = *self.index_dim.read().unwrap_or_else(|e| e.into_inner()) {
+            return Ok(Some(dim));
+        }
+
+        let q = query(
+            "SHOW INDEXES YIELD name, options \
+             WHERE name = 'chunk_embeddings' \
+             RETURN options['indexConfig']['vector.dimensions'] as dim"
+        );
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let dim = match stream.next().await {
+            Ok(Some(row)) => row.get::<i64>("dim").ok().map(|d| d as usize),
+            _ => None,
+        };
+        if dim.is_some() {
+            *self.index_dim.write().unwrap_or_else(|e| e.into_inner()) = dim;
+        }
+        Ok(dim)
+    }
+
+    /// `IF NOT EXISTS` conserva un índice creado con otra función de similitud:
+    /// se avisa para que no se use en silencio (hay que borrar el índice para cambiarla).
+    async fn warn_on_similarity_mismatch(&self, expected: VectorSimilarity) {
+        let q = query(
+            "SHOW INDEXES YIELD name, options \
+             WHERE name = 'chunk_embeddings' \
+             RETURN options['indexConfig']['vector.similarity_function'] as similarity"
+        );
+        let Ok(mut stream) = self.graph.execute(q).await else { return };
+        let Ok(Some(row)) = stream.next().await else { return };
+        let Ok(actual) = row.get::<String>("similarity") else { return };
+
+        if !actual.eq_ignore_ascii_case(expected.as_neo4j()) {
+            tracing::warn!(
+                "⚠️ The vector index 'chunk_embeddings' uses '{}' similarity but '{}' is configured; \
+                 drop the index (DROP INDEX chunk_embeddings) to apply the new function",
+                actual.to_lowercase(), expected.as_neo4j()
+            );
+        }
+    }
+
+    /// Nombres de `names` que ya existen como `:Entity`.
+    async fn existing_entity_names(&self, names: &[String]) -> Result<HashSet<String>, AppError> {
+        let q = query("MATCH (e:Entity) WHERE e.name IN $names RETURN e.name as name")
+            .param("names", names.to_vec());
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut existing = HashSet::new();
+        while let Ok(Some(row)) = stream.next().await {
+            if let Ok(name) = row.get::<String>("name") {
+                existing.insert(name);
+            }
+        }
+        Ok(existing)
+    }
+
+    /// Nombre canónico para cada clave normalizada de la extracción: el ya guardado en
+    /// Neo4j si existe y, si no, la primera grafía que aparece en `names`.
+    async fn canonical_names(&self, names: &[&str]) -> Result<HashMap<String, String>, AppError> {
+        let keys: Vec<String> = names.iter().map(|n| self.entity_matching.key(n)).collect();
+
+        let mut canonical = HashMap::new();
+        if self.entity_matching == EntityMatching::CaseInsensitive {
+            let q = query("MATCH (e:Entity) WHERE e.name_key IN $keys RETURN e.name_key as key, e.name as name")
+                .param("keys", keys.clone());
+            let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            while let Ok(Some(row)) = stream.next().await {
+                if let (Ok(key), Ok(name)) = (row.get::<String>("key"), row.get::<String>("name")) {
+                    canonical.entry(key).or_insert(name);
+                }
+            }
+        }
+
+        for (key, name) in keys.into_iter().zip(names) {
+            canonical.entry(key).or_insert_with(|| name.to_string());
+        }
+        Ok(canonical)
+    }
+
+    /// Recorre el resultado de `q` enviando cada fila convertida por `tx`.
+    /// Devuelve `false` si el receptor se cerró (el cliente cortó la descarga).
+    async fn forward_records(
+        &self,
+        q: neo4rs::Query,
+        tx: &mpsc::Sender<ExportRecord>,
+        sent: &mut usize,
+        to_record: impl Fn(neo4rs::Row) -> Option<ExportRecord>,
+    ) -> Result<bool, AppError> {
+        let q = q.param("temporal", TEMPORAL_PROPERTIES.to_vec());
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        loop {
+            let row = match stream.next().await {
+                Ok(Some(row)) => row,
+                Ok(None) => return Ok(true),
+                Err(e) => return Err(AppError::DatabaseError(e.to_string())),
+            };
+            let Some(record) = to_record(row) else { continue };
+            if tx.send(record).await.is_err() {
+                return Ok(false);
+            }
+            *sent += 1;
+        }
+    }
+}
+
+/// Propiedades de `:Entity` que escribe el propio backend: un atributo extraído con ese
+/// nombre las pisaría, así que se descarta.
+const RESERVED_ENTITY_PROPERTIES: &[&str] = &["name", "category", "category_names", "category_counts", "name_key", "centrality", "created_at", "auto_created"];
+
+/// Vecino devuelto por la consulta de vecindario o de subgrafo de documento (mapa Cypher).
+#[derive(Debug, Deserialize)]
+struct NeighborRow {
+    name: String,
+    category: String,
+    centrality: Option<f64>,
+}
+
+/// Relación devuelta por la consulta de vecindario (mapa Cypher).
+#[derive(Debug, Deserialize)]
+struct EdgeRow {
+    from: String,
+    to: String,
+    label: String,
+    sources: Vec<String>,
+    confidence: Option<f64>,
+    reasoning: Option<String>,
+    inferred: bool,
+}
+
+/// Propiedades temporales conocidas: se exportan como texto ISO-8601 (`datetime(...)` al importar).
+pub const TEMPORAL_PROPERTIES: &[&str] = &["created_at", "extracted_at"];
+
+/// Prefijo de las propiedades de metadatos en `:Document` (evita chocar con id/name/...).
+const METADATA_PREFIX: &str = "meta_";
+
+/// Metadatos como propiedades filtrables: cada valor se guarda como lista de strings
+/// (un escalar es una lista de uno), así `$valor IN d.meta_x` sirve para ambos casos.
+fn metadata_properties(metadata: &HashMap<String, serde_json::Value>) -> HashMap<String, BoltType> {
+    metadata.iter()
+        .map(|(key, value)| {
+            let values: Vec<String> = match value {
+                serde_json::Value::Array(items) => items.iter().map(metadata_value_to_string).collect(),
+                other => vec![metadata_value_to_string(other)],
+            };
+            (format!("{}{}", METADATA_PREFIX, key), BoltType::from(values))
+        })
+        .collect()
+}
+
+fn metadata_value_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Escapa los caracteres especiales de la sintaxis Lucene para buscar texto literal.
+fn escape_lucene(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "+-&|!(){}[]^\"~*?:\\/".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Consulta Lucene de autocompletado: cada palabra como prefijo y todas obligatorias
+/// (`new yo` -> `new* AND yo*`). Los comodines no pasan por el analizador: se pasan a minúsculas aquí.
+fn prefix_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("{}*", escape_lucene(&word.to_lowercase())))
+        .collect::<Vec<_>>()
+        .join(" AND ")
+}
+
+/// Tipo genérico para relaciones fuera de la lista permitida (ALLOWED_RELATION_TYPES).
+pub const GENERIC_RELATION: &str = "RELATED_TO";
+
+/// Tipo de relación tal como se guarda en Neo4j (`works for` -> `WORKS_FOR`).
+/// Se interpola en el Cypher, así que solo quedan `[A-Z_]`: las vocales acentuadas y la Ñ
+/// pierden la tilde y cualquier otro carácter (comillas, llaves, `]`...) pasa a `_`.
+fn relation_label(relation_type: &str) -> String {
+    let mut label = String::with_capacity(relation_type.len());
+    for c in relation_type.chars().flat_map(char::to_uppercase) {
+        let c = match c {
+            'Á' | 'À' | 'Ä' | 'Â' => 'A',
+            'É' | 'È' | 'Ë' | 'Ê' => 'E',
+            'Í' | 'Ì' | 'Ï' | 'Î' => 'I',
+            'Ó' | 'Ò' | 'Ö' | 'Ô' => 'O',
+            'Ú' | 'Ù' | 'Ü' | 'Û' => 'U',
+            'Ñ' => 'N',
+            'Ç' => 'C',
+            'A'..='Z' => c,
+            _ => '_',
+        };
+        // Sin guiones bajos repetidos ni al principio
+        if c != '_' || !(label.is_empty() || label.ends_with('_')) {
+            label.push(c);
+        }
+    }
+    let label = label.trim_end_matches('_');
+    if label.is_empty() { GENERIC_RELATION.to_string() } else { label.to_string() }
+}
+
+/// Qué hacer en `save_graph` con una relación cuyo tipo no está en la lista permitida.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownRelation {
+    /// Se guarda como `RELATED_TO`
+    #[default]
+    Generic,
+    /// Se descarta (con aviso en el log)
+    Reject,
+}
+
+impl std::str::FromStr for UnknownRelation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "generic" | "related_to" => Ok(Self::Generic),
+            "reject" | "drop" => Ok(Self::Reject),
+            other => Err(format!("Unknown relation mode: {} (expected generic or reject)", other)),
+        }
+    }
+}
+
+/// Quita las relaciones repetidas dentro de una misma extracción (mismo origen, destino y tipo
+/// normalizados), conservando la primera y la confianza más alta. La acumulación entre chunks
+/// distintos no cambia: cada chunk sigue sumándose a `r.sources`.
+pub fn dedupe_relations(relations: Vec<GraphRelation>, matching: EntityMatching) -> Vec<GraphRelation> {
+    let mut position: HashMap<(String, String, String), usize> = HashMap::new();
+    let mut unique: Vec<GraphRelation> = Vec::with_capacity(relations.len());
+
+    for rel in relations {
+        let key = (matching.key(&rel.source), matching.key(&rel.target), relation_label(&rel.relation_type));
+        match position.get(&key) {
+            Some(&i) => {
+                let kept = &mut unique[i];
+                kept.confidence = match (kept.confidence, rel.confidence) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+            },
+            None => {
+                position.insert(key, unique.len());
+                unique.push(rel);
+            },
+        }
+    }
+    unique
+}
+
+/// Convierte los atributos tipados de una entidad en propiedades Neo4j.
+/// Se ignoran nulos y claves reservadas; las estructuras anidadas y las listas que Neo4j no
+/// admite como propiedad (tipos mezclados, nulos) se guardan como texto JSON.
+fn entity_properties(attributes: &HashMap<String, serde_json::Value>) -> HashMap<String, BoltType> {
+    attributes.iter()
+        .filter(|(key, _)| !RESERVED_ENTITY_PROPERTIES.contains(&key.as_str()))
+        .filter_map(|(key, value)| {
+            let prop = match value {
+                serde_json::Value::Null => return None,
+                serde_json::Value::Object(_) => BoltType::from(value.to_string()),
+                serde_json::Value::Array(items) => homogeneous_list(items).unwrap_or_else(|| BoltType::from(value.to_string())),
+                other => BoltType::try_from(other.clone()).ok()?,
+            };
+            Some((key.clone(), prop))
+        })
+        .collect()
+}
+
+/// Lista de un solo tipo (texto, enteros, números o booleanos), la única que Neo4j guarda como
+/// propiedad; enteros y decimales juntos pasan a decimales. `None` si no es homogénea.
+fn homogeneous_list(items: &[serde_json::Value]) -> Option<BoltType> {
+    if items.iter().all(|i| i.is_string()) {
+        Some(BoltType::from(items.iter().filter_map(|i| i.as_str().map(str::to_string)).collect::<Vec<String>>()))
+    } else if items.iter().all(|i| i.is_i64()) {
+        Some(BoltType::from(items.iter().filter_map(serde_json::Value::as_i64).collect::<Vec<i64>>()))
+    } else if items.iter().all(|i| i.is_number()) {
+        Some(BoltType::from(items.iter().filter_map(serde_json::Value::as_f64).collect::<Vec<f64>>()))
+    } else if items.iter().all(|i| i.is_boolean()) {
+        Some(BoltType::from(items.iter().filter_map(serde_json::Value::as_bool).collect::<Vec<bool>>()))
+    } else {
+        None
     }
 }
 
 #[async_trait]
 impl KGRepository for Neo4jRepo {
-    async fn create_indexes(&self, dim: usize) -> Result<(), AppError> {
+    async fn create_indexes(&self, dim: usize, similarity: VectorSimilarity) -> Result<(), AppError> {
         let q = format!(
             "CREATE VECTOR INDEX chunk_embeddings IF NOT EXISTS FOR (c:DocumentChunk) ON (c.embedding) \
-             OPTIONS {{indexConfig: {{ `vector.dimensions`: {}, `vector.similarity_function`: 'cosine' }} }}", 
-            dim
+             OPTIONS {{indexConfig: {{ `vector.dimensions`: {}, `vector.similarity_function`: '{}' }} }}", 
+            dim, similarity.as_neo4j()
         );
         self.graph.run(query(&q)).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        // IF NOT EXISTS no cambia un índice previo: la dimensión real se vuelve a leer
+        *self.index_dim.write().unwrap_or_else(|e| e.into_inner()) = None;
+        self.warn_on_similarity_mismatch(similarity).await;
         
         self.graph.run(query("CREATE CONSTRAINT entity_name IF NOT EXISTS FOR (e:Entity) REQUIRE e.name IS UNIQUE")).await
             .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        self.graph.run(query("CREATE FULLTEXT INDEX chunk_fulltext IF NOT EXISTS FOR (c:DocumentChunk) ON EACH [c.content]")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        // Búsqueda / autocompletado de entidades por nombre
+        self.graph.run(query("CREATE FULLTEXT INDEX entity_name_fulltext IF NOT EXISTS FOR (e:Entity) ON EACH [e.name]")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        // Clave en minúsculas para el modo sin distinción de mayúsculas (se rellena en entidades antiguas)
+        self.graph.run(query("CREATE INDEX entity_name_key IF NOT EXISTS FOR (e:Entity) ON (e.name_key)")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        self.graph.run(query("MATCH (e:Entity) WHERE e.name_key IS NULL SET e.name_key = toLower(e.name)")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
             
         Ok(())
     }
 
-    async fn reset_database(&self) -> Result<(), AppError> {
-        self.graph.run(query("MATCH (n) DETACH DELETE n")).await
+    async fn ping(&self) -> Result<(), AppError> {
+        self.graph.run(query("RETURN 1")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    async fn create_embedding_slot_index(&self, slot: &EmbeddingSlot, similarity: VectorSimilarity) -> Result<(), AppError> {
+        let q = format!(
+            "CREATE VECTOR INDEX {} IF NOT EXISTS FOR (c:DocumentChunk) ON (c.{}) \
+             OPTIONS {{indexConfig: {{ `vector.dimensions`: {}, `vector.similarity_function`: '{}' }} }}",
+            slot.index_name(), slot.property(), slot.dim, similarity.as_neo4j()
+        );
+        self.graph.run(query(&q)).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn reset_database(&self) -> Result<usize, AppError> {
+        let mut stream = self.graph.execute(query("MATCH (n) DETACH DELETE n RETURN count(n) as deleted")).await
             .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let deleted = match stream.next().await {
+            Ok(Some(row)) => row.get::<i64>("deleted").unwrap_or(0),
+            _ => 0,
+        };
+        Ok(deleted as usize)
+    }
+
+    async fn save_document(&self, id: Uuid, document: &DocumentInput) -> Result<(), AppError> {
+        // `metadata` (JSON) conserva los valores originales; `meta_*` sirve para filtrar
+        let metadata_json = serde_json::to_string(&document.metadata)
+            .map_err(|e| AppError::ParseError(e.to_string()))?;
+        let q = query(
+            "CREATE (d:Document {id: $id, name: $name, created_at: datetime(), metadata: $metadata, \
+                                 content_type: $content_type, char_count: $char_count}) \
+             SET d += $properties"
+        )
+            .param("id", id.to_string())
+            .param("name", document.name.as_str())
+            .param("content_type", document.content_type.clone())
+            .param("char_count", document.char_count as i64)
+            .param("metadata", metadata_json)
+            .param("properties", metadata_properties(&document.metadata));
+
+        self.graph.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         Ok(())
     }
 
-    async fn save_chunk(&self, id: Uuid, content: &str, embedding: Vec<f32>) -> Result<(), AppError> {
-        let q = query("CREATE (c:DocumentChunk {id: $id, content: $content, embedding: $embedding})")
+    async fn save_chunk(&self, document_id: Uuid, id: Uuid, content: &str, embedding: Vec<f32>) -> Result<(), AppError> {
+        let q = query(
+            "MERGE (d:Document {id: $doc_id}) \
+             CREATE (d)-[:HAS_CHUNK]->(c:DocumentChunk {id: $id, content: $content, embedding: $embedding})"
+        )
+            .param("doc_id", document_id.to_string())
             .param("id", id.to_string())
             .param("content", content)
             .param("embedding", embedding);
@@ -51,25 +618,149 @@ impl KGRepository for Neo4jRepo {
         Ok(())
     }
 
-    async fn save_graph(&self, chunk_id: Uuid, data: KnowledgeExtraction) -> Result<(), AppError> {
+    async fn save_graph(&self, chunk_id: Uuid, mut data: KnowledgeExtraction, provenance: Option<&ExtractionProvenance>) -> Result<(), AppError> {
+        // Reescribe los nombres a su forma canónica antes de hacer MERGE por `name`
+        let mentioned: Vec<&str> = data.entities.iter().map(|e| e.name.as_str())
+            .chain(data.relations.iter().flat_map(|r| [r.source.as_str(), r.target.as_str()]))
+            .chain(data.events.iter().flat_map(|ev| ev.participants.iter().map(String::as_str)))
+            .collect();
+        let canonical = self.canonical_names(&mentioned).await?;
+        let resolve = |name: &mut String| {
+            if let Some(c) = canonical.get(&self.entity_matching.key(name)) {
+                name.clone_from(c);
+            }
+        };
+        for entity in &mut data.entities {
+            resolve(&mut entity.name);
+        }
+        for rel in &mut data.relations {
+            resolve(&mut rel.source);
+            resolve(&mut rel.target);
+            rel.relation_type = self.relation_aliases.canonical(&rel.relation_type);
+        }
+        for participant in data.events.iter_mut().flat_map(|ev| ev.participants.iter_mut()) {
+            resolve(participant);
+        }
+        self.restrict_relations(chunk_id, &mut data.relations);
+        if self.dedupe_relations {
+            let before = data.relations.len();
+            data.relations = dedupe_relations(data.relations, self.entity_matching);
+            if data.relations.len() < before {
+                tracing::debug!("🧹 Chunk {}: {} relaciones repetidas descartadas", chunk_id, before - data.relations.len());
+            }
+        }
+
+        // El MATCH de una relación con un extremo inexistente no crea nada: se detecta aquí
+        let listed: HashSet<&str> = data.entities.iter().map(|e| e.name.as_str()).collect();
+        let mut unlisted: Vec<String> = data.relations.iter()
+            .flat_map(|r| [&r.source, &r.target])
+            .filter(|name| !listed.contains(name.as_str()))
+            .cloned()
+            .collect();
+        unlisted.sort();
+        unlisted.dedup();
+        let missing: Vec<String> = if unlisted.is_empty() {
+            Vec::new()
+        } else {
+            let existing = self.existing_entity_names(&unlisted).await?;
+            unlisted.into_iter().filter(|name| !existing.contains(name)).collect()
+        };
+        if !missing.is_empty() && !self.create_missing_endpoints {
+            data.relations.retain(|rel| {
+                let dropped = missing.contains(&rel.source) || missing.contains(&rel.target);
+                if dropped {
+                    tracing::warn!(
+                        "🕳️ Chunk {}: relación {} -[{}]-> {} descartada (extremo no extraído ni existente)",
+                        chunk_id, rel.source, rel.relation_type, rel.target
+                    );
+                }
+                !dropped
+            });
+        }
+
+        let _permit = self.txn_permits.acquire().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         let mut txn = self.graph.start_txn().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
+        if self.create_missing_endpoints && !missing.is_empty() {
+            tracing::info!("🧩 Chunk {}: {} entidades creadas para extremos de relación no extraídos", chunk_id, missing.len());
+            let q = query(
+                "UNWIND $names AS name \
+                 MERGE (e:Entity {name: name}) \
+                 ON CREATE SET e.category = 'Concept', e.name_key = toLower(name), e.created_at = datetime(), e.auto_created = true"
+            ).param("names", missing.clone());
+            txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        }
+
+        let entity_cypher = if self.category_voting {
+            CATEGORY_VOTE_CYPHER
+        } else {
+            "MERGE (e:Entity {name: $name}) ON CREATE SET e.category = $category, e.name_key = toLower($name), e.created_at = datetime() SET e += $attributes"
+        };
         for entity in &data.entities {
-            let q = query("MERGE (e:Entity {name: $name}) ON CREATE SET e.category = $category")
+            let q = query(entity_cypher)
                 .param("name", entity.name.as_str())
-                .param("category", entity.category.as_str());
+                .param("category", entity.category.as_str())
+                .param("attributes", entity_properties(&entity.attributes));
             txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         }
 
         for rel in data.relations {
+            // Cada chunk que afirma la relación se acumula en r.sources (sin duplicados) y suma 1 a
+            // r.weight; volver a guardar el mismo chunk (re-extracción, rebuild) no la infla
             let cypher = format!(
                 "MATCH (a:Entity {{name: $source}}), (b:Entity {{name: $target}}) \
-                 MERGE (a)-[:{}]->(b)", 
-                rel.relation_type.replace(" ", "_").to_uppercase() 
+                 MERGE (a)-[r:{}]->(b) \
+                 ON CREATE SET r.created_at = datetime(), r.weight = 1 \
+                 ON MATCH SET r.weight = CASE WHEN $cid IN coalesce(r.sources, []) \
+                                              THEN r.weight ELSE coalesce(r.weight, 1) + 1 END \
+                 SET r.sources = CASE WHEN $cid IN coalesce(r.sources, []) \
+                                      THEN r.sources ELSE coalesce(r.sources, []) + $cid END, \
+                     r.confidence = CASE WHEN $confidence IS NULL OR $confidence < coalesce(r.confidence, 0.0) \
+                                         THEN r.confidence ELSE $confidence END", 
+                relation_label(&rel.relation_type)
             );
+            // Entre varios chunks se conserva la confianza más alta
             let q = query(&cypher)
                 .param("source", rel.source.as_str())
-                .param("target", rel.target.as_str());
+                .param("target", rel.target.as_str())
+                .param("cid", chunk_id.to_string())
+                .param("confidence", rel.confidence.map(f64::from));
+            txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        }
+
+        // Sucesos: el chunk los menciona y se enlazan con las entidades que intervienen
+        for event in &data.events {
+            let q = query(
+                "MERGE (ev:Event {name: $name}) \
+                 ON CREATE SET ev.created_at = datetime() \
+                 SET ev.date = coalesce($date, ev.date) \
+                 WITH ev \
+                 MATCH (c:DocumentChunk {id: $cid}) \
+                 MERGE (c)-[:MENTIONS]->(ev) \
+                 WITH ev \
+                 UNWIND $participants AS participant \
+                 MATCH (e:Entity {name: participant}) \
+                 MERGE (ev)-[:INVOLVES]->(e)"
+            )
+                .param("name", event.name.as_str())
+                .param("date", event.date.clone())
+                .param("cid", chunk_id.to_string())
+                .param("participants", event.participants.clone());
+            txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        }
+
+        for order in &data.temporal_relations {
+            let q = query(
+                "MATCH (a:Event {name: $before}), (b:Event {name: $after}) \
+                 WHERE a <> b \
+                 MERGE (a)-[r:BEFORE]->(b) \
+                 ON CREATE SET r.created_at = datetime() \
+                 SET r.sources = CASE WHEN $cid IN coalesce(r.sources, []) \
+                                      THEN r.sources ELSE coalesce(r.sources, []) + $cid END"
+            )
+                .param("before", order.before.as_str())
+                .param("after", order.after.as_str())
+                .param("cid", chunk_id.to_string());
             txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         }
 
@@ -77,57 +768,292 @@ impl KGRepository for Neo4jRepo {
                             WHERE e.name IN $names \
                             MERGE (c)-[:MENTIONS]->(e)");
         
-        let names: Vec<String> = data.entities.into_iter().map(|e| e.name).collect();
+        let names: Vec<String> = data.entities.into_iter().map(|e| e.name)
+            .chain(missing.into_iter().filter(|_| self.create_missing_endpoints))
+            .collect();
         txn.run(q_link.param("cid", chunk_id.to_string()).param("names", names)).await
             .map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
+        if let Some(provenance) = provenance {
+            let q_provenance = query(
+                "MATCH (c:DocumentChunk {id: $cid}) \
+                 SET c.extraction_model = $model, c.extraction_prompt_version = $version, c.extracted_at = datetime()"
+            )
+                .param("cid", chunk_id.to_string())
+                .param("model", provenance.model.as_str())
+                .param("version", provenance.prompt_version.as_str());
+            txn.run(q_provenance).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        }
+
         txn.commit().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         Ok(())
     }
 
-    async fn get_full_graph(&self) -> Result<GraphDataResponse, AppError> {
+    async fn save_chunk_extraction(&self, chunk_id: Uuid, extraction_json: &str) -> Result<(), AppError> {
+        let q = query("MATCH (c:DocumentChunk {id: $id}) SET c.extraction_json = $json")
+            .param("id", chunk_id.to_string())
+            .param("json", extraction_json);
+        self.graph.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn get_full_graph(&self, filter: &GraphFilter) -> Result<GraphDataResponse, AppError> {
+        // El grado cuenta solo relaciones entre entidades (no MENTIONS de chunks)
         let q = query(
             "MATCH (n:Entity)-[r]->(m:Entity) \
-             RETURN n.name, n.category, type(r), m.name, m.category \
-             LIMIT 1000"
-        );
+             WHERE ($min_degree = 0 OR \
+                   (COUNT { (n)--(:Entity) } >= $min_degree AND COUNT { (m)--(:Entity) } >= $min_degree)) \
+               AND ($min_confidence IS NULL OR coalesce(r.confidence, $default_confidence) >= $min_confidence) \
+             RETURN n.name, n.category, type(r), m.name, m.category, coalesce(r.sources, []) as sources, \
+                    r.confidence as confidence, n.centrality as n_centrality, m.centrality as m_centrality, \
+                    r.reasoning as reasoning, coalesce(r.is_ai_generated, false) as inferred \
+             LIMIT $limit"
+        )
+        // Una fila de más para saber si el límite de relaciones ha recortado el grafo
+        .param("limit", filter.edge_limit() as i64 + 1)
+        .param("min_degree", filter.min_degree.unwrap_or(0) as i64)
+        .param("min_confidence", filter.min_confidence)
+        .param("default_confidence", self.default_confidence);
         
         let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
         let mut nodes_vec = Vec::new();
         let mut edges_vec = Vec::new();
         let mut unique_nodes = HashSet::new(); 
+        let (max_nodes, max_edges) = (filter.node_limit(), filter.edge_limit());
+        let mut limits_reached = Vec::new();
 
         while let Ok(Some(row)) = stream.next().await {
+            if edges_vec.len() == max_edges {
+                limits_reached.push(GraphLimit::MaxEdges);
+                break;
+            }
             let n_name: String = row.get("n.name").unwrap_or_else(|_| "Unknown".to_string());
             let n_cat: String = row.get("n.category").unwrap_or_else(|_| "Concept".to_string());
             let r_type: String = row.get("type(r)").unwrap_or_else(|_| "RELATED".to_string());
             let m_name: String = row.get("m.name").unwrap_or_else(|_| "Unknown".to_string());
             let m_cat: String = row.get("m.category").unwrap_or_else(|_| "Concept".to_string());
+            let sources: Vec<String> = row.get("sources").unwrap_or_default();
+            let confidence: Option<f64> = row.get("confidence").unwrap_or_default();
+            let n_centrality: Option<f64> = row.get("n_centrality").unwrap_or_default();
+            let m_centrality: Option<f64> = row.get("m_centrality").unwrap_or_default();
+            let reasoning: Option<String> = row.get("reasoning").unwrap_or_default();
+            let inferred: bool = row.get("inferred").unwrap_or_default();
+
+            // Sin hueco para nodos nuevos solo entran relaciones entre entidades ya incluidas
+            let new_nodes = usize::from(!unique_nodes.contains(&n_name))
+                + usize::from(m_name != n_name && !unique_nodes.contains(&m_name));
+            if nodes_vec.len() + new_nodes > max_nodes {
+                if !limits_reached.contains(&GraphLimit::MaxNodes) {
+                    limits_reached.push(GraphLimit::MaxNodes);
+                }
+                continue;
+            }
 
             if unique_nodes.insert(n_name.clone()) {
-                nodes_vec.push(VisNode { id: n_name.clone(), label: n_name.clone(), group: n_cat });
+                nodes_vec.push(VisNode { id: n_name.clone(), label: n_name.clone(), group: n_cat, centrality: n_centrality });
             }
             if unique_nodes.insert(m_name.clone()) {
-                nodes_vec.push(VisNode { id: m_name.clone(), label: m_name.clone(), group: m_cat });
+                nodes_vec.push(VisNode { id: m_name.clone(), label: m_name.clone(), group: m_cat, centrality: m_centrality });
             }
 
-            edges_vec.push(VisEdge { from: n_name, to: m_name, label: r_type });
+            edges_vec.push(VisEdge { from: n_name, to: m_name, label: r_type, sources, confidence, reasoning, inferred });
         }
 
-        Ok(GraphDataResponse { nodes: nodes_vec, edges: edges_vec })
+        Ok(GraphDataResponse { nodes: nodes_vec, edges: edges_vec, limits_reached })
     }
 
-    async fn find_hybrid_context(&self, embedding: Vec<f32>, limit: usize) -> Result<Vec<HybridContext>, AppError> {
-        let q_str = format!(
-            "CALL db.index.vector.queryNodes('chunk_embeddings', {}, $embedding) \
-             YIELD node as chunk, score \
-             MATCH (chunk)-[:MENTIONS]->(e:Entity) \
-             RETURN chunk.id as id, chunk.content as content, collect(DISTINCT e.name) as entities", 
-            limit
+    async fn stream_graph_records(&self, include_chunks: bool, tx: mpsc::Sender<ExportRecord>) -> Result<usize, AppError> {
+        // Las fechas no tienen equivalente JSON: se pasan a texto en la propia consulta
+        let props = |var: &str, skip: &str| format!(
+            "[k IN keys({v}) WHERE NOT k IN {skip} | [k, CASE WHEN k IN $temporal THEN toString({v}[k]) ELSE {v}[k] END]]",
+            v = var, skip = skip
         );
+        let pairs = |row: &neo4rs::Row| -> HashMap<String, serde_json::Value> {
+            row.get::<Vec<(String, serde_json::Value)>>("props").unwrap_or_default().into_iter().collect()
+        };
+        let mut sent = 0;
+
+        // Sin ORDER BY: ordenar obligaría a Neo4j a materializar todo el resultado
+        let q = query(&format!("MATCH (e:Entity) RETURN e.name as name, {} as props", props("e", "['name']")));
+        let open = self.forward_records(q, &tx, &mut sent, |row| {
+            Some(ExportRecord::Entity(ExportedEntity { name: row.get("name").ok()?, properties: pairs(&row) }))
+        }).await?;
+        if !open { return Ok(sent); }
+
+        let q = query(&format!(
+            "MATCH (a:Entity)-[r]->(b:Entity) RETURN a.name as source, b.name as target, type(r) as rel, {} as props",
+            props("r", "[]")
+        ));
+        let open = self.forward_records(q, &tx, &mut sent, |row| {
+            Some(ExportRecord::Relation(ExportedRelation {
+                source: row.get("source").ok()?,
+                target: row.get("target").ok()?,
+                relation_type: row.get("rel").ok()?,
+                properties: pairs(&row),
+            }))
+        }).await?;
+        if !open || !include_chunks { return Ok(sent); }
+
+        let q = query(&format!("MATCH (d:Document) RETURN d.id as id, {} as props", props("d", "['id']")));
+        let open = self.forward_records(q, &tx, &mut sent, |row| {
+            Some(ExportRecord::Document(ExportedNode { id: row.get("id").ok()?, document_id: None, properties: pairs(&row) }))
+        }).await?;
+        if !open { return Ok(sent); }
+
+        // Los embeddings no se exportan (dependen del modelo): tras importar, POST /api/admin/reembed
+        let q = query(&format!(
+            "MATCH (c:DocumentChunk) OPTIONAL MATCH (d:Document)-[:HAS_CHUNK]->(c) \
+             RETURN c.id as id, d.id as document_id, {} as props",
+            props("c", "['id', 'embedding']")
+        ));
+        let open = self.forward_records(q, &tx, &mut sent, |row| {
+            Some(ExportRecord::Chunk(ExportedNode {
+                id: row.get("id").ok()?,
+                document_id: row.get("document_id").unwrap_or_default(),
+                properties: pairs(&row),
+            }))
+        }).await?;
+        if !open { return Ok(sent); }
+
+        let q = query("MATCH (c:DocumentChunk)-[:MENTIONS]->(e:Entity) RETURN c.id as chunk_id, e.name as entity");
+        self.forward_records(q, &tx, &mut sent, |row| {
+            Some(ExportRecord::Mention { chunk_id: row.get("chunk_id").ok()?, entity: row.get("entity").ok()? })
+        }).await?;
+
+        Ok(sent)
+    }
+
+    async fn get_graph_since(&self, since_millis: i64) -> Result<GraphDelta, AppError> {
+        // Marca de tiempo del propio Neo4j: el cursor no depende del reloj de este servidor
+        let mut stream = self.graph.execute(query("RETURN timestamp() as now")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let timestamp = match stream.next().await {
+            Ok(Some(row)) => row.get::<i64>("now").map_err(|e| AppError::DatabaseError(e.to_string()))?,
+            _ => return Err(AppError::DatabaseError("Could not read server timestamp".to_string())),
+        };
+
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        let mut unique_nodes = HashSet::new();
+
+        // 1. Relaciones nuevas (con sus dos extremos, para que el cliente pueda dibujarlas)
+        let q_edges = query(
+            "MATCH (n:Entity)-[r]->(m:Entity) \
+             WHERE r.created_at > datetime({epochMillis: $since}) \
+             RETURN n.name, n.category, type(r), m.name, m.category, coalesce(r.sources, []) as sources, \
+                    r.confidence as confidence, n.centrality as n_centrality, m.centrality as m_centrality, \
+                    r.reasoning as reasoning, coalesce(r.is_ai_generated, false) as inferred"
+        ).param("since", since_millis);
+        let mut stream = self.graph.execute(q_edges).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        while let Ok(Some(row)) = stream.next().await {
+            let n_name: String = row.get("n.name").unwrap_or_else(|_| "Unknown".to_string());
+            let n_cat: String = row.get("n.category").unwrap_or_else(|_| "Concept".to_string());
+            let r_type: String = row.get("type(r)").unwrap_or_else(|_| "RELATED".to_string());
+            let m_name: String = row.get("m.name").unwrap_or_else(|_| "Unknown".to_string());
+            let m_cat: String = row.get("m.category").unwrap_or_else(|_| "Concept".to_string());
+            let sources: Vec<String> = row.get("sources").unwrap_or_default();
+            let confidence: Option<f64> = row.get("confidence").unwrap_or_default();
+            let n_centrality: Option<f64> = row.get("n_centrality").unwrap_or_default();
+            let m_centrality: Option<f64> = row.get("m_centrality").unwrap_or_default();
+            let reasoning: Option<String> = row.get("reasoning").unwrap_or_default();
+            let inferred: bool = row.get("inferred").unwrap_or_default();
+
+            if unique_nodes.insert(n_name.clone()) {
+                nodes.push(VisNode { id: n_name.clone(), label: n_name.clone(), group: n_cat, centrality: n_centrality });
+            }
+            if unique_nodes.insert(m_name.clone()) {
+                nodes.push(VisNode { id: m_name.clone(), label: m_name.clone(), group: m_cat, centrality: m_centrality });
+            }
+
+            edges.push(VisEdge { from: n_name, to: m_name, label: r_type, sources, confidence, reasoning, inferred });
+        }
+
+        // 2. Entidades nuevas aunque aún no tengan relaciones
+        let q_nodes = query(
+            "MATCH (e:Entity) WHERE e.created_at > datetime({epochMillis: $since}) \
+             RETURN e.name as name, e.category as category, e.centrality as centrality"
+        ).param("since", since_millis);
+        let mut stream = self.graph.execute(q_nodes).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
-        let q = query(&q_str).param("embedding", embedding);
+        while let Ok(Some(row)) = stream.next().await {
+            let Ok(name) = row.get::<String>("name") else { continue };
+            if unique_nodes.insert(name.clone()) {
+                let group: String = row.get("category").unwrap_or_else(|_| "Concept".to_string());
+                let centrality: Option<f64> = row.get("centrality").unwrap_or_default();
+                nodes.push(VisNode { id: name.clone(), label: name, group, centrality });
+            }
+        }
+
+        Ok(GraphDelta { nodes, edges, timestamp })
+    }
+
+    async fn find_hybrid_context(&self, embedding: Vec<f32>, limit: usize, hops: usize, min_score: Option<f64>, slot: Option<&EmbeddingSlot>) -> Result<Vec<HybridContext>, AppError> {
+        let hops = hops.clamp(1, MAX_CONTEXT_HOPS);
+        let index = match slot {
+            Some(slot) => {
+                if embedding.len() != slot.dim {
+                    return Err(AppError::ConfigError(format!(
+                        "Query embedding has {} dimensions but embedding slot '{}' expects {}",
+                        embedding.len(), slot.name, slot.dim
+                    )));
+                }
+                slot.index_name()
+            },
+            None => "chunk_embeddings".to_string(),
+        };
+        if self.validate_query_dim && slot.is_none() {
+            if let Some(index_dim) = self.vector_index_dim().await? {
+                if embedding.len() != index_dim {
+                    return Err(AppError::ConfigError(format!(
+                        "Query embedding has {} dimensions but vector index 'chunk_embeddings' expects {}. \
+                         The embedding model changed without re-creating the index: reset the database or \
+                         restore the previous AI_EMBEDDING_MODEL / AI_EMBEDDING_DIM",
+                        embedding.len(), index_dim
+                    )));
+                }
+            }
+        }
+        let q_str = if hops == 1 {
+            format!(
+                "CALL db.index.vector.queryNodes('{}', {}, $embedding) \
+                 YIELD node as chunk, score \
+                 WHERE score >= $min_score \
+                 MATCH (chunk)-[:MENTIONS]->(e:Entity) \
+                 RETURN chunk.id as id, chunk.content as content, score, collect(DISTINCT e.name) as entities, \
+                        coalesce(max(e.centrality), 0.0) as centrality \
+                 ORDER BY score DESC",
+                index, limit
+            )
+        } else {
+            // Solo caminos entre entidades (no a través de otros chunks) y con LIMIT antes de
+            // agregar: en nodos muy conectados los caminos crecen exponencialmente con los saltos
+            format!(
+                "CALL db.index.vector.queryNodes('{}', {}, $embedding) \
+                 YIELD node as chunk, score \
+                 WHERE score >= $min_score \
+                 MATCH (chunk)-[:MENTIONS]->(e:Entity) \
+                 WITH chunk, score, collect(DISTINCT e) as direct, coalesce(max(e.centrality), 0.0) as centrality \
+                 CALL {{ \
+                     WITH direct \
+                     UNWIND direct as d \
+                     MATCH p = (d)-[*1..{}]-(related:Entity) \
+                     WHERE all(n IN nodes(p) WHERE n:Entity) AND NOT related IN direct \
+                     WITH DISTINCT related LIMIT $max_entities \
+                     RETURN collect(related.name) as expanded \
+                 }} \
+                 RETURN chunk.id as id, chunk.content as content, score, centrality, \
+                        ([x IN direct | x.name] + expanded)[..$max_entities] as entities \
+                 ORDER BY score DESC",
+                index, limit, hops
+            )
+        };
+
+        let q = query(&q_str)
+            .param("embedding", embedding)
+            .param("min_score", min_score.unwrap_or(0.0))
+            .param("max_entities", MAX_CONTEXT_ENTITIES_PER_CHUNK as i64);
         let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
         let mut results = Vec::new();
@@ -135,101 +1061,690 @@ impl KGRepository for Neo4jRepo {
             let id: String = row.get("id").unwrap_or_else(|_| "unk".to_string());
             let content: String = row.get("content").unwrap_or_default();
             let entities: Vec<String> = row.get("entities").unwrap_or_default();
+            let score: f64 = row.get("score").unwrap_or_default();
+            let entity_centrality: f64 = row.get("centrality").unwrap_or_default();
 
             results.push(HybridContext {
                 chunk_id: id,
                 content,
                 connected_entities: entities,
+                score,
+                entity_centrality,
             });
         }
         
         Ok(results)
     }
     
-    // --- IMPLEMENTACIÓN: VECINDARIO DE CONCEPTO (Deep Dive) ---
+    async fn find_keyword_context(&self, text: &str, limit: usize) -> Result<Vec<HybridContext>, AppError> {
+        let terms = escape_lucene(text.trim());
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
 
-    async fn get_concept_neighborhood(&self, concept_name: &str) -> Result<GraphDataResponse, AppError> {
-        // Busca el nodo central y todas las relaciones (entrantes o salientes) directas
         let q = query(
-            "MATCH (center:Entity {name: $name})-[r]-(neighbor:Entity)
-             RETURN center.name, center.category, type(r) as rel, startNode(r) = center as is_source, neighbor.name, neighbor.category
-             LIMIT 100"
-        ).param("name", concept_name);
+            "CALL db.index.fulltext.queryNodes('chunk_fulltext', $terms, {limit: $limit}) \
+             YIELD node as chunk, score \
+             OPTIONAL MATCH (chunk)-[:MENTIONS]->(e:Entity) \
+             RETURN chunk.id as id, chunk.content as content, score, collect(DISTINCT e.name) as entities, \
+                    coalesce(max(e.centrality), 0.0) as centrality \
+             ORDER BY score DESC"
+        ).param("terms", terms).param("limit", limit as i64);
 
         let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
-        let mut nodes_vec = Vec::new();
-        let mut edges_vec = Vec::new();
-        let mut unique_nodes = HashSet::new();
+        let mut results = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            results.push(HybridContext {
+                chunk_id: row.get("id").unwrap_or_else(|_| "unk".to_string()),
+                content: row.get("content").unwrap_or_default(),
+                connected_entities: row.get("entities").unwrap_or_default(),
+                score: row.get("score").unwrap_or_default(),
+                entity_centrality: row.get("centrality").unwrap_or_default(),
+            });
+        }
 
-        let mut relations_found = false;
+        Ok(results)
+    }
+    
+    // --- IMPLEMENTACIÓN: VECINDARIO DE CONCEPTO (Deep Dive) ---
 
-        while let Ok(Some(row)) = stream.next().await {
-            relations_found = true;
-            
-            let c_name: String = row.get("center.name").unwrap_or_default();
-            let c_cat: String = row.get("center.category").unwrap_or_else(|_| "Concept".to_string());
-            let rel_type: String = row.get("rel").unwrap_or_default();
-            let is_source: bool = row.get("is_source").unwrap_or(true);
-            let n_name: String = row.get("neighbor.name").unwrap_or_default();
-            let n_cat: String = row.get("neighbor.category").unwrap_or_else(|_| "Concept".to_string());
-
-            // Añadir/Actualizar nodo central
-            if unique_nodes.insert(c_name.clone()) {
-                 nodes_vec.push(VisNode { id: c_name.clone(), label: c_name.clone(), group: c_cat });
-            }
+    async fn get_concept_neighborhood(&self, concept_name: &str, direction: TraversalDirection) -> Result<GraphDataResponse, AppError> {
+        // Busca el nodo central y sus relaciones directas en el sentido pedido
+        let pattern = match direction {
+            TraversalDirection::Out => "-[r]->",
+            TraversalDirection::In => "<-[r]-",
+            TraversalDirection::Both => "-[r]-",
+        };
+        // Deduplicación en Cypher: cada vecino y cada relación aparecen una sola vez
+        // (con `Both`, un bucle center->center se emparejaría dos veces)
+        let q = query(&format!(
+            "MATCH (center:Entity {{name: $name}})
+             OPTIONAL MATCH (center){}(neighbor:Entity)
+             WITH center, r, neighbor LIMIT 100
+             WITH center, collect(DISTINCT r) as rels, collect(DISTINCT neighbor) as neighbors
+             RETURN center.name as name, coalesce(center.category, 'Concept') as category, center.centrality as centrality,
+                    [n IN neighbors WHERE n <> center |
+                        {{name: n.name, category: coalesce(n.category, 'Concept'), centrality: n.centrality}}] as neighbors,
+                    [rel IN rels |
+                        {{from: startNode(rel).name, to: endNode(rel).name, label: type(rel),
+                          sources: coalesce(rel.sources, []), confidence: rel.confidence,
+                          reasoning: rel.reasoning, inferred: coalesce(rel.is_ai_generated, false)}}] as edges",
+            pattern
+        )).param("name", concept_name);
 
-            // Añadir nodo vecino
-            if unique_nodes.insert(n_name.clone()) {
-                nodes_vec.push(VisNode { id: n_name.clone(), label: n_name.clone(), group: n_cat });
-            }
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
-            // Definir dirección
-            let (from, to) = if is_source {
-                (c_name.clone(), n_name.clone())
-            } else {
-                (n_name.clone(), c_name.clone())
-            };
+        // Una sola fila (o ninguna si el concepto no existe)
+        let Ok(Some(row)) = stream.next().await else {
+            return Ok(GraphDataResponse { nodes: Vec::new(), edges: Vec::new(), limits_reached: Vec::new() });
+        };
 
-            edges_vec.push(VisEdge { from, to, label: rel_type });
-        }
-        
-        // Fallback: Si no hay relaciones, al menos devolvemos el nodo central
-        if !relations_found {
-             let q_fallback = query("MATCH (center:Entity {name: $name}) RETURN center.name, center.category")
-                .param("name", concept_name);
-             let mut stream_fallback = self.graph.execute(q_fallback).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
-             if let Ok(Some(row)) = stream_fallback.next().await {
-                let name: String = row.get("center.name").unwrap_or_default();
-                let cat: String = row.get("center.category").unwrap_or_else(|_| "Concept".to_string());
-                nodes_vec.push(VisNode { id: name.clone(), label: name, group: cat });
-             }
-        }
+        let name: String = row.get("name").unwrap_or_default();
+        let mut nodes_vec = vec![VisNode {
+            id: name.clone(),
+            label: name,
+            group: row.get("category").unwrap_or_else(|_| "Concept".to_string()),
+            centrality: row.get("centrality").unwrap_or_default(),
+        }];
 
-        // Limpiar duplicados de nodos (si se insertó dos veces en el loop principal o fallback)
-        nodes_vec.sort_by(|a, b| a.id.cmp(&b.id));
-        nodes_vec.dedup_by(|a, b| a.id == b.id);
+        let neighbors: Vec<NeighborRow> = row.get("neighbors").unwrap_or_default();
+        nodes_vec.extend(neighbors.into_iter().map(|n| VisNode {
+            id: n.name.clone(),
+            label: n.name,
+            group: n.category,
+            centrality: n.centrality,
+        }));
 
-        Ok(GraphDataResponse { nodes: nodes_vec, edges: edges_vec })
+        let edges: Vec<EdgeRow> = row.get("edges").unwrap_or_default();
+        let edges_vec = edges.into_iter()
+            .map(|e| VisEdge {
+                from: e.from, to: e.to, label: e.label, sources: e.sources,
+                confidence: e.confidence, reasoning: e.reasoning, inferred: e.inferred,
+            })
+            .collect();
+
+        Ok(GraphDataResponse { nodes: nodes_vec, edges: edges_vec, limits_reached: Vec::new() })
     }
     
-    // --- MÉTODOS DE RAZONAMIENTO (EXISTENTES) ---
-
-    async fn get_graph_context_for_reasoning(&self, limit: usize) -> Result<String, AppError> {
-        // Obtenemos las relaciones más "densas" para dar contexto
+    async fn get_document_graph(&self, doc_group_id: Uuid) -> Result<GraphDataResponse, AppError> {
+        // Documento -> chunks -> MENTIONS -> entidades; solo las relaciones con ambos extremos dentro
         let q = query(
-            "MATCH (n:Entity)-[r]->(m:Entity) 
-             WITH n, r, m, count(n) as degree 
-             ORDER BY degree DESC 
-             LIMIT $limit 
-             RETURN n.name, type(r), m.name"
-        ).param("limit", limit as i64);
+            "MATCH (d:Document {id: $id}) \
+             OPTIONAL MATCH (d)-[:HAS_CHUNK]->(:DocumentChunk)-[:MENTIONS]->(e:Entity) \
+             WITH collect(DISTINCT e) as entities \
+             CALL { \
+                 WITH entities \
+                 UNWIND entities as a \
+                 MATCH (a)-[rel]->(b:Entity) \
+                 WHERE b IN entities \
+                 RETURN collect(DISTINCT rel) as rels \
+             } \
+             RETURN [n IN entities | {name: n.name, category: coalesce(n.category, 'Concept'), centrality: n.centrality}] as nodes, \
+                    [rel IN rels | \
+                        {from: startNode(rel).name, to: endNode(rel).name, label: type(rel), \
+                         sources: coalesce(rel.sources, []), confidence: rel.confidence, \
+                         reasoning: rel.reasoning, inferred: coalesce(rel.is_ai_generated, false)}] as edges"
+        ).param("id", doc_group_id.to_string());
 
         let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
-        let mut context = String::new();
+        let row = match stream.next().await {
+            Ok(Some(row)) => row,
+            Ok(None) => return Err(AppError::NotFound(format!("Document {}", doc_group_id))),
+            Err(e) => return Err(AppError::DatabaseError(e.to_string())),
+        };
 
-        while let Ok(Some(row)) = stream.next().await {
-            let n: String = row.get("n.name").unwrap_or_default();
+        let nodes: Vec<NeighborRow> = row.get("nodes").unwrap_or_default();
+        let edges: Vec<EdgeRow> = row.get("edges").unwrap_or_default();
+
+        Ok(GraphDataResponse {
+            nodes: nodes.into_iter()
+                .map(|n| VisNode { id: n.name.clone(), label: n.name, group: n.category, centrality: n.centrality })
+                .collect(),
+            edges: edges.into_iter()
+                .map(|e| VisEdge {
+                    from: e.from, to: e.to, label: e.label, sources: e.sources,
+                    confidence: e.confidence, reasoning: e.reasoning, inferred: e.inferred,
+                })
+                .collect(),
+            limits_reached: Vec::new(),
+        })
+    }
+
+    async fn list_documents(&self, metadata_filter: &HashMap<String, String>) -> Result<Vec<DocumentSummary>, AppError> {
+        let filter: HashMap<String, String> = metadata_filter.iter()
+            .map(|(key, value)| (format!("{}{}", METADATA_PREFIX, key), value.clone()))
+            .collect();
+
+        let q = query(
+            "MATCH (d:Document) \
+             WHERE all(k IN keys($filter) WHERE $filter[k] IN coalesce(d[k], [])) \
+             RETURN d.id as id, coalesce(d.name, '') as name, toString(d.created_at) as created_at, \
+                    d.content_type as content_type, coalesce(d.char_count, 0) as char_count, \
+                    coalesce(d.metadata, '{}') as metadata, COUNT { (d)-[:HAS_CHUNK]->() } as chunk_count \
+             ORDER BY d.created_at DESC"
+        ).param("filter", filter);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut documents = Vec::new();
+
+        while let Ok(Some(row)) = stream.next().await {
+            let metadata_json: String = row.get("metadata").unwrap_or_default();
+            documents.push(DocumentSummary {
+                id: row.get("id").unwrap_or_default(),
+                name: row.get("name").unwrap_or_default(),
+                created_at: row.get("created_at").unwrap_or_default(),
+                content_type: row.get("content_type").unwrap_or_default(),
+                char_count: row.get::<i64>("char_count").unwrap_or(0) as usize,
+                chunk_count: row.get::<i64>("chunk_count").unwrap_or(0) as usize,
+                metadata: serde_json::from_str(&metadata_json).unwrap_or_default(),
+            });
+        }
+
+        Ok(documents)
+    }
+
+    async fn delete_document(&self, doc_group_id: Uuid) -> Result<DocumentDeletion, AppError> {
+        let id = doc_group_id.to_string();
+
+        // 1. Chunks del documento y entidades que no menciona ningún otro chunk
+        let q_scope = query(
+            "MATCH (d:Document {id: $id}) \
+             OPTIONAL MATCH (d)-[:HAS_CHUNK]->(c:DocumentChunk) \
+             WITH d, collect(c) AS chunks \
+             OPTIONAL MATCH (d)-[:HAS_CHUNK]->(:DocumentChunk)-[:MENTIONS]->(e:Entity) \
+             WITH chunks, collect(DISTINCT e) AS mentioned \
+             RETURN size(chunks) AS chunks, \
+                    [e IN mentioned WHERE NOT EXISTS { \
+                        MATCH (other:DocumentChunk)-[:MENTIONS]->(e) WHERE NOT other IN chunks \
+                    } | e.name] AS orphans"
+        ).param("id", id.as_str());
+        let mut stream = self.graph.execute(q_scope).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let (chunks_deleted, orphans) = match stream.next().await {
+            Ok(Some(row)) => (
+                row.get::<i64>("chunks").unwrap_or(0) as usize,
+                row.get::<Vec<String>>("orphans").unwrap_or_default(),
+            ),
+            Ok(None) => return Err(AppError::NotFound(format!("Document {}", id))),
+            Err(e) => return Err(AppError::DatabaseError(e.to_string())),
+        };
+
+        // 2. Borrado en una transacción; las huérfanas se comprueban de nuevo por si otra ingesta las mencionó
+        let _permit = self.txn_permits.acquire().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut txn = self.graph.start_txn().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let q_chunks = query(
+            "MATCH (d:Document {id: $id}) \
+             OPTIONAL MATCH (d)-[:HAS_CHUNK]->(c:DocumentChunk) \
+             DETACH DELETE c, d"
+        ).param("id", id.as_str());
+        txn.run(q_chunks).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        let q_orphans = query(
+            "MATCH (e:Entity) WHERE e.name IN $names AND NOT EXISTS { (:DocumentChunk)-[:MENTIONS]->(e) } \
+             DETACH DELETE e"
+        ).param("names", orphans.clone());
+        txn.run(q_orphans).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        txn.commit().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        Ok(DocumentDeletion { document_id: id, chunks_deleted, entities_deleted: orphans.len() })
+    }
+
+    async fn list_chunks_needing_embedding(&self, dim: usize, force: bool) -> Result<Vec<(String, String)>, AppError> {
+        let q = query(
+            "MATCH (c:DocumentChunk) \
+             WHERE $force OR c.embedding IS NULL OR size(c.embedding) <> $dim \
+             RETURN c.id as id, c.content as content"
+        )
+            .param("force", force)
+            .param("dim", dim as i64);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut chunks = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            if let (Ok(id), Ok(content)) = (row.get::<String>("id"), row.get::<String>("content")) {
+                chunks.push((id, content));
+            }
+        }
+        Ok(chunks)
+    }
+
+    async fn list_stored_extractions(&self) -> Result<(Vec<(String, String)>, usize), AppError> {
+        let q = query(
+            "MATCH (c:DocumentChunk) \
+             RETURN c.id as id, c.extraction_json as json \
+             ORDER BY c.id"
+        );
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut stored = Vec::new();
+        let mut missing = 0;
+        while let Ok(Some(row)) = stream.next().await {
+            match (row.get::<String>("id"), row.get::<Option<String>>("json")) {
+                (Ok(id), Ok(Some(json))) => stored.push((id, json)),
+                _ => missing += 1,
+            }
+        }
+        Ok((stored, missing))
+    }
+
+    async fn clear_extracted_graph(&self) -> Result<usize, AppError> {
+        let q = query(
+            "MATCH (n) WHERE n:Entity OR n:Event \
+             DETACH DELETE n \
+             RETURN count(n) as deleted"
+        );
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let deleted = match stream.next().await {
+            Ok(Some(row)) => row.get::<i64>("deleted").unwrap_or(0),
+            _ => 0,
+        };
+        Ok(deleted as usize)
+    }
+
+    async fn update_chunk_embedding(&self, chunk_id: &str, embedding: Vec<f32>) -> Result<(), AppError> {
+        let q = query("MATCH (c:DocumentChunk {id: $id}) SET c.embedding = $embedding")
+            .param("id", chunk_id)
+            .param("embedding", embedding);
+        self.graph.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn save_chunk_slot_embedding(&self, chunk_id: Uuid, slot: &EmbeddingSlot, embedding: Vec<f32>) -> Result<(), AppError> {
+        // El nombre del slot se valida al leer EMBEDDING_SLOTS ([a-z0-9_]): se puede interpolar
+        let q = query(&format!("MATCH (c:DocumentChunk {{id: $id}}) SET c.{} = $embedding", slot.property()))
+            .param("id", chunk_id.to_string())
+            .param("embedding", embedding);
+        self.graph.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn export_embeddings(&self) -> Result<Vec<EmbeddingRecord>, AppError> {
+        let q = query(
+            "MATCH (c:DocumentChunk) WHERE c.embedding IS NOT NULL \
+             RETURN c.id as id, c.content as content, c.embedding as embedding"
+        );
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        let mut records = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            let content: String = row.get("content").unwrap_or_default();
+            records.push(EmbeddingRecord {
+                chunk_id: row.get("id").unwrap_or_default(),
+                content_hash: EmbeddingRecord::hash_content(&content),
+                embedding: row.get("embedding").unwrap_or_default(),
+            });
+        }
+        Ok(records)
+    }
+
+    // --- RESOLUCIÓN DE ENTIDADES ---
+
+    async fn list_entity_names(&self, limit: usize) -> Result<Vec<String>, AppError> {
+        let q = query(
+            "MATCH (e:Entity) \
+             RETURN e.name as name \
+             ORDER BY COUNT { (e)--(:Entity) } DESC \
+             LIMIT $limit"
+        ).param("limit", limit as i64);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut names = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            if let Ok(name) = row.get::<String>("name") {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    async fn search_entities(&self, text: &str, limit: usize) -> Result<Vec<EntityMatch>, AppError> {
+        let terms = prefix_query(text);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Se piden más candidatos que resultados: el orden final es por grado, no por relevancia textual
+        let q = query(
+            "CALL db.index.fulltext.queryNodes('entity_name_fulltext', $terms, {limit: $candidates}) \
+             YIELD node as e, score \
+             RETURN e.name as name, coalesce(e.category, 'Concept') as category, \
+                    COUNT { (e)--(:Entity) } as degree \
+             ORDER BY degree DESC, score DESC, name \
+             LIMIT $limit"
+        )
+            .param("terms", terms)
+            .param("candidates", (limit * 5) as i64)
+            .param("limit", limit as i64);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut matches = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            if let (Ok(name), Ok(degree)) = (row.get::<String>("name"), row.get::<i64>("degree")) {
+                matches.push(EntityMatch {
+                    name,
+                    category: row.get("category").unwrap_or_else(|_| "Concept".to_string()),
+                    degree: degree as usize,
+                });
+            }
+        }
+        Ok(matches)
+    }
+
+    async fn find_chunks_by_entity(&self, name: &str, limit: usize) -> Result<Vec<EntityChunk>, AppError> {
+        let q = query(
+            "MATCH (c:DocumentChunk)-[:MENTIONS]->(:Entity {name: $name}) \
+             OPTIONAL MATCH (d:Document)-[:HAS_CHUNK]->(c) \
+             RETURN c.id as id, c.content as content, d.id as document_id \
+             LIMIT $limit"
+        ).param("name", name).param("limit", limit as i64);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut chunks = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            let content: String = row.get("content").unwrap_or_default();
+            let preview = match content.char_indices().nth(150) {
+                Some((cut, _)) => format!("{}...", &content[..cut]),
+                None => content.clone(),
+            };
+            chunks.push(EntityChunk {
+                chunk_id: row.get("id").unwrap_or_else(|_| "unk".to_string()),
+                document_id: row.get("document_id").unwrap_or_default(),
+                preview,
+                content,
+            });
+        }
+        Ok(chunks)
+    }
+
+    async fn stream_entities(&self, limit: Option<usize>, tx: mpsc::Sender<EntityExportRow>) -> Result<usize, AppError> {
+        // Sin ORDER BY: ordenar obligaría a Neo4j a materializar todo el resultado
+        let cypher = format!(
+            "MATCH (e:Entity) \
+             RETURN e.name as name, coalesce(e.category, 'Concept') as category, \
+                    COUNT {{ (e)--(:Entity) }} as degree, e.centrality as centrality{}",
+            limit.map(|n| format!(" LIMIT {}", n)).unwrap_or_default()
+        );
+
+        let mut stream = self.graph.execute(query(&cypher)).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut sent = 0;
+        loop {
+            let row = match stream.next().await {
+                Ok(Some(row)) => row,
+                Ok(None) => break,
+                Err(e) => return Err(AppError::DatabaseError(e.to_string())),
+            };
+            let Ok(name) = row.get::<String>("name") else { continue };
+            let entity = EntityExportRow {
+                name,
+                category: row.get("category").unwrap_or_else(|_| "Concept".to_string()),
+                degree: row.get("degree").unwrap_or_default(),
+                centrality: row.get("centrality").unwrap_or_default(),
+            };
+            if tx.send(entity).await.is_err() {
+                // El cliente cortó la descarga
+                break;
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    async fn merge_entities(&self, canonical: &str, duplicates: &[String]) -> Result<usize, AppError> {
+        // Sin entidad canónica no fusionamos: borraríamos los duplicados sin re-enlazar nada
+        let q_exists = query("MATCH (c:Entity {name: $canonical}) RETURN count(c) as n").param("canonical", canonical);
+        let mut stream = self.graph.execute(q_exists).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let exists = match stream.next().await {
+            Ok(Some(row)) => row.get::<i64>("n").unwrap_or(0) > 0,
+            _ => false,
+        };
+        if !exists {
+            return Err(AppError::ValidationError(format!("Canonical entity not found: {}", canonical)));
+        }
+
+        let _permit = self.txn_permits.acquire().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut merged = 0;
+
+        for dup in duplicates.iter().filter(|d| d.as_str() != canonical) {
+            // 1. Leer las relaciones del duplicado (sin APOC no hay tipos dinámicos en Cypher)
+            let q_rels = query(
+                "MATCH (c:Entity {name: $canonical}) \
+                 MATCH (d:Entity {name: $dup})-[r]-(o) \
+                 WHERE o <> c \
+                 RETURN type(r) as rel, startNode(r) = d as outgoing, elementId(o) as other, properties(r) as props"
+            ).param("canonical", canonical).param("dup", dup.as_str());
+
+            let mut stream = self.graph.execute(q_rels).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            let mut rels = Vec::new();
+            while let Ok(Some(row)) = stream.next().await {
+                let rel: String = row.get("rel").unwrap_or_default();
+                let outgoing: bool = row.get("outgoing").unwrap_or(true);
+                let other: String = row.get("other").unwrap_or_default();
+                let props: BoltType = row.get("props").unwrap_or_else(|_| BoltType::from(HashMap::<String, BoltType>::new()));
+                rels.push((rel, outgoing, other, props));
+            }
+
+            // 2. Recrear las relaciones sobre la canónica y borrar el duplicado
+            let mut txn = self.graph.start_txn().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            for (rel, outgoing, other, props) in rels {
+                let rel_type = rel.replace('`', "");
+                let pattern = if outgoing {
+                    format!("(c)-[nr:`{}`]->(o)", rel_type)
+                } else {
+                    format!("(o)-[nr:`{}`]->(c)", rel_type)
+                };
+                // `+=` pisaría las fuentes de la relación superviviente: se combinan sin duplicados,
+                // y el peso vuelve a ser el número de chunks distintos que la afirman
+                let cypher = format!(
+                    "MATCH (c:Entity {{name: $canonical}}), (o) WHERE elementId(o) = $other \
+                     MERGE {} \
+                     WITH nr, reduce(acc = [], s IN coalesce(nr.sources, []) + coalesce($props.sources, []) | \
+                                     CASE WHEN s IN acc THEN acc ELSE acc + s END) AS all_sources \
+                     SET nr += $props \
+                     SET nr.sources = CASE WHEN size(all_sources) = 0 THEN null ELSE all_sources END, \
+                         nr.weight = CASE WHEN size(all_sources) = 0 THEN nr.weight ELSE size(all_sources) END",
+                    pattern
+                );
+                let q = query(&cypher)
+                    .param("canonical", canonical)
+                    .param("other", other)
+                    .param("props", props);
+                txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            }
+
+            txn.run(query("MATCH (d:Entity {name: $dup}) DETACH DELETE d").param("dup", dup.as_str())).await
+                .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            txn.commit().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            merged += 1;
+        }
+
+        Ok(merged)
+    }
+
+    // --- CENTRALIDAD ---
+
+    async fn get_entity_graph(&self) -> Result<(Vec<String>, Vec<(String, String)>), AppError> {
+        let mut nodes = Vec::new();
+        let mut stream = self.graph.execute(query("MATCH (e:Entity) RETURN e.name as name")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        while let Ok(Some(row)) = stream.next().await {
+            if let Ok(name) = row.get::<String>("name") {
+                nodes.push(name);
+            }
+        }
+
+        let mut edges = Vec::new();
+        let mut stream = self.graph.execute(query("MATCH (a:Entity)-[]->(b:Entity) RETURN a.name as source, b.name as target")).await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        while let Ok(Some(row)) = stream.next().await {
+            if let (Ok(source), Ok(target)) = (row.get::<String>("source"), row.get::<String>("target")) {
+                edges.push((source, target));
+            }
+        }
+
+        Ok((nodes, edges))
+    }
+
+    async fn save_centrality(&self, scores: &HashMap<String, f64>) -> Result<(), AppError> {
+        let _permit = self.txn_permits.acquire().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut txn = self.graph.start_txn().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        // Lotes con UNWIND para no lanzar una consulta por entidad
+        let rows: Vec<serde_json::Value> = scores.iter()
+            .map(|(name, score)| serde_json::json!({ "name": name, "score": score }))
+            .collect();
+
+        for batch in rows.chunks(1000) {
+            let batch = BoltType::try_from(serde_json::Value::Array(batch.to_vec()))
+                .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            let q = query("UNWIND $rows AS row MATCH (e:Entity {name: row.name}) SET e.centrality = row.score")
+                .param("rows", batch);
+            txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        }
+
+        txn.commit().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn list_categories(&self) -> Result<Vec<CategoryCount>, AppError> {
+        let q = query(
+            "MATCH (e:Entity) \
+             RETURN coalesce(e.category, 'Concept') as category, count(*) as count \
+             ORDER BY count DESC, category"
+        );
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut categories = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            if let (Ok(category), Ok(count)) = (row.get::<String>("category"), row.get::<i64>("count")) {
+                categories.push(CategoryCount { category, count: count as usize });
+            }
+        }
+        Ok(categories)
+    }
+
+    async fn get_stats(&self) -> Result<GraphStats, AppError> {
+        // Un solo RETURN con subconsultas COUNT: siempre devuelve una fila, también con la base vacía
+        let q_counts = query(
+            "RETURN COUNT { (:Entity) } as entities, \
+                    COUNT { (:Entity)-[]->(:Entity) } as relations, \
+                    COUNT { (:Entity)-[r]->(:Entity) WHERE r.is_ai_generated = true } as inferred, \
+                    COUNT { (:DocumentChunk) } as chunks"
+        );
+        let mut stream = self.graph.execute(q_counts).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut stats = GraphStats::default();
+        if let Ok(Some(row)) = stream.next().await {
+            stats.entities = row.get::<i64>("entities").unwrap_or(0) as usize;
+            stats.relations = row.get::<i64>("relations").unwrap_or(0) as usize;
+            stats.inferred_relations = row.get::<i64>("inferred").unwrap_or(0) as usize;
+            stats.chunks = row.get::<i64>("chunks").unwrap_or(0) as usize;
+        }
+
+        let q_top = query(
+            "MATCH (e:Entity) \
+             WITH e, COUNT { (e)--(:Entity) } as degree \
+             WHERE degree > 0 \
+             RETURN e.name as name, degree \
+             ORDER BY degree DESC, name \
+             LIMIT 10"
+        );
+        let mut stream = self.graph.execute(q_top).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        while let Ok(Some(row)) = stream.next().await {
+            if let (Ok(name), Ok(degree)) = (row.get::<String>("name"), row.get::<i64>("degree")) {
+                stats.top_entities.push(EntityDegree { name, degree: degree as usize });
+            }
+        }
+
+        Ok(stats)
+    }
+
+    async fn get_timeline(&self) -> Result<(Vec<TimelineEvent>, Vec<(String, String)>), AppError> {
+        let q_events = query(
+            "MATCH (ev:Event) \
+             OPTIONAL MATCH (ev)-[:INVOLVES]->(e:Entity) \
+             RETURN ev.name as name, ev.date as date, collect(e.name) as participants"
+        );
+        let mut stream = self.graph.execute(q_events).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut events = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            let Ok(name) = row.get::<String>("name") else { continue };
+            events.push(TimelineEvent {
+                name,
+                date: row.get("date").unwrap_or_default(),
+                participants: row.get("participants").unwrap_or_default(),
+            });
+        }
+
+        let q_order = query("MATCH (a:Event)-[:BEFORE]->(b:Event) RETURN a.name as before, b.name as after");
+        let mut stream = self.graph.execute(q_order).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut order = Vec::new();
+        while let Ok(Some(row)) = stream.next().await {
+            if let (Ok(before), Ok(after)) = (row.get::<String>("before"), row.get::<String>("after")) {
+                order.push((before, after));
+            }
+        }
+
+        Ok((events, order))
+    }
+
+    async fn find_stale_chunks(&self, model: Option<&str>, prompt_version: &str, include_unversioned: bool, limit: usize) -> Result<(usize, Vec<StaleChunk>), AppError> {
+        let q = query(
+            "MATCH (c:DocumentChunk) \
+             WHERE CASE WHEN c.extraction_prompt_version IS NULL THEN $include_unversioned \
+                        ELSE c.extraction_prompt_version <> $version \
+                             OR ($model IS NOT NULL AND coalesce(c.extraction_model, '') <> $model) END \
+             OPTIONAL MATCH (d:Document)-[:HAS_CHUNK]->(c) \
+             WITH c, d ORDER BY c.extracted_at \
+             WITH count(c) AS total, collect({ \
+                 chunk_id: c.id, document_id: d.id, extraction_model: c.extraction_model, \
+                 extraction_prompt_version: c.extraction_prompt_version, extracted_at: toString(c.extracted_at) \
+             })[..$limit] AS chunks \
+             RETURN total, chunks"
+        )
+            .param("version", prompt_version)
+            .param("model", model)
+            .param("include_unversioned", include_unversioned)
+            .param("limit", limit as i64);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let Some(row) = stream.next().await.map_err(|e| AppError::DatabaseError(e.to_string()))? else {
+            return Ok((0, Vec::new()));
+        };
+        let total = row.get::<i64>("total").unwrap_or(0) as usize;
+        let chunks = row.get::<Vec<StaleChunk>>("chunks").unwrap_or_default();
+        Ok((total, chunks))
+    }
+
+    async fn prune_orphan_entities(&self) -> Result<usize, AppError> {
+        let q = query(
+            "MATCH (e:Entity) WHERE NOT EXISTS { (e)--() } \
+             WITH collect(e) AS orphans \
+             FOREACH (o IN orphans | DELETE o) \
+             RETURN size(orphans) AS pruned"
+        );
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        match stream.next().await {
+            Ok(Some(row)) => Ok(row.get::<i64>("pruned").unwrap_or(0) as usize),
+            Ok(None) => Ok(0),
+            Err(e) => Err(AppError::DatabaseError(e.to_string())),
+        }
+    }
+
+    // --- MÉTODOS DE RAZONAMIENTO (EXISTENTES) ---
+
+    async fn get_graph_context_for_reasoning(&self, limit: usize) -> Result<String, AppError> {
+        // Obtenemos las relaciones más "densas" para dar contexto
+        let q = query(
+            "MATCH (n:Entity)-[r]->(m:Entity) 
+             WITH n, r, m, count(n) as degree 
+             ORDER BY degree DESC 
+             LIMIT $limit 
+             RETURN n.name, type(r), m.name"
+        ).param("limit", limit as i64);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let mut context = String::new();
+
+        while let Ok(Some(row)) = stream.next().await {
+            let n: String = row.get("n.name").unwrap_or_default();
             let r: String = row.get("type(r)").unwrap_or_default();
             let m: String = row.get("m.name").unwrap_or_default();
             context.push_str(&format!("({}) -[{}]-> ({})\n", n, r, m));
@@ -242,20 +1757,26 @@ impl KGRepository for Neo4jRepo {
     }
 
     async fn save_inferred_relations(&self, relations: Vec<InferredRelation>) -> Result<(), AppError> {
+        let _permit = self.txn_permits.acquire().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         let mut txn = self.graph.start_txn().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
         for rel in relations {
             let cypher = format!(
                 "MATCH (a:Entity {{name: $source}}), (b:Entity {{name: $target}}) \
                  MERGE (a)-[r:INFERRED_{}]->(b) \
-                 ON CREATE SET r.reasoning = $reasoning, r.is_ai_generated = true",
-                rel.relation.replace(" ", "_").to_uppercase()
+                 ON CREATE SET r.reasoning = $reasoning, r.is_ai_generated = true, r.confidence = $confidence, \
+                               r.inference_type = $inference_type, r.confidence_level = $confidence_level, \
+                               r.created_at = datetime()",
+                self.relation_aliases.canonical(&rel.relation)
             );
             
             let q = query(&cypher)
                 .param("source", rel.source)
                 .param("target", rel.target)
-                .param("reasoning", rel.reasoning);
+                .param("reasoning", rel.reasoning)
+                .param("inference_type", rel.inference_type.map(|t| t.as_str()))
+                .param("confidence_level", rel.confidence_level.map(|l| l.as_str()))
+                .param("confidence", rel.confidence.map(f64::from));
                 
             txn.run(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         }
@@ -263,4 +1784,594 @@ impl KGRepository for Neo4jRepo {
         txn.commit().await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    async fn delete_inferred_relations_older_than(&self, max_age: std::time::Duration) -> Result<usize, AppError> {
+        // Las inferidas antes de guardar `created_at` empiezan a contar desde ahora
+        self.graph.run(query(
+            "MATCH (:Entity)-[r]->(:Entity) WHERE r.is_ai_generated = true AND r.created_at IS NULL \
+             SET r.created_at = datetime()"
+        )).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        let q = query(
+            "MATCH (:Entity)-[r]->(:Entity) \
+             WHERE r.is_ai_generated = true AND r.created_at < datetime() - duration({seconds: $seconds}) \
+             DELETE r \
+             RETURN count(*) as removed"
+        ).param("seconds", max_age.as_secs() as i64);
+
+        let mut stream = self.graph.execute(q).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let removed = match stream.next().await {
+            Ok(Some(row)) => row.get::<i64>("removed").unwrap_or(0),
+            _ => 0,
+        };
+        Ok(removed as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{GraphEntity, GraphRelation};
+    use std::time::Duration;
+
+    /// Repositorio contra un puerto sin servidor: el pool de neo4rs no conecta hasta la primera consulta.
+    async fn offline_repo() -> Neo4jRepo {
+        let graph = Graph::new("127.0.0.1:1", "neo4j", "test").await.expect("the pool is created lazily");
+        Neo4jRepo::new(Arc::new(graph))
+    }
+
+    /// Neo4j real para los tests con `#[ignore]` (`cargo test -- --ignored`): NEO4J_TEST_URI,
+    /// NEO4J_TEST_USER y NEO4J_TEST_PASS. Usan nombres únicos y no vacían la base de datos.
+    async fn live_repo() -> Neo4jRepo {
+        let var = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} is required for the Neo4j tests", name));
+        let graph = Graph::new(var("NEO4J_TEST_URI"), var("NEO4J_TEST_USER"), var("NEO4J_TEST_PASS")).await.unwrap();
+        Neo4jRepo::new(Arc::new(graph))
+    }
+
+    /// Columna `value` de la primera fila de `cypher` (con `$name` como parámetro).
+    async fn fetch_value<T: serde::de::DeserializeOwned>(repo: &Neo4jRepo, cypher: &str, name: &str) -> Option<T> {
+        let mut stream = repo.graph.execute(query(cypher).param("name", name)).await.unwrap();
+        stream.next().await.unwrap().and_then(|row| row.get::<T>("value").ok())
+    }
+
+    fn extraction(names: &[&str]) -> KnowledgeExtraction {
+        KnowledgeExtraction {
+            entities: names.iter()
+                .map(|name| GraphEntity { name: name.to_string(), category: "Person".to_string(), attributes: HashMap::new() })
+                .collect(),
+            relations: Vec::new(),
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn save_graph_calls_serialize_with_a_single_txn_permit() {
+        let repo = Arc::new(offline_repo().await.with_max_concurrent_txns(1));
+        // Otra transacción en curso ocupa el único permiso
+        let held = repo.txn_permits.clone().acquire_owned().await.unwrap();
+
+        let first = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.save_graph(Uuid::new_v4(), extraction(&["Ada Lovelace"]), None).await }
+        });
+        let second = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.save_graph(Uuid::new_v4(), extraction(&["Charles Babbage"]), None).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!first.is_finished() && !second.is_finished(), "no transaction may start while the permit is taken");
+
+        // Al liberarlo avanzan de uno en uno (y fallan al conectar: no hay servidor)
+        drop(held);
+        for call in [first, second] {
+            let result = tokio::time::timeout(Duration::from_secs(10), call).await
+                .expect("save_graph should proceed once the permit is free")
+                .unwrap();
+            assert!(matches!(result, Err(AppError::DatabaseError(_))));
+        }
+        assert_eq!(repo.txn_permits.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_query_embedding_with_the_wrong_dimension_is_rejected_before_searching() {
+        let repo = offline_repo().await;
+        *repo.index_dim.write().unwrap() = Some(8);
+
+        let result = repo.find_hybrid_context(vec![0.1; 4], 5, 1, None, None).await;
+        let Err(AppError::ConfigError(message)) = result else { panic!("expected ConfigError, got {:?}", result) };
+        assert!(message.contains("has 4 dimensions") && message.contains("expects 8"));
+    }
+
+    #[test]
+    fn entity_properties_keep_typed_values_and_skip_reserved_keys() {
+        let mut attributes: HashMap<String, serde_json::Value> = serde_json::from_value(serde_json::json!({
+            "date": "2024-01-15",
+            "amount": 50000,
+            "aliases": ["ACME", "Acme Corp"],
+            "ratios": [1, 2.5],
+            "mixed": [1, "dos", null],
+            "address": { "city": "Barcelona" },
+            "missing": null
+        })).unwrap();
+        for reserved in RESERVED_ENTITY_PROPERTIES {
+            attributes.insert(reserved.to_string(), serde_json::json!("sobrescrito"));
+        }
+
+        let props = entity_properties(&attributes);
+
+        assert_eq!(props.get("date"), Some(&BoltType::from("2024-01-15")));
+        assert_eq!(props.get("amount"), Some(&BoltType::from(50000_i64)));
+        assert_eq!(props.get("aliases"), Some(&BoltType::from(vec!["ACME".to_string(), "Acme Corp".to_string()])));
+        for reserved in RESERVED_ENTITY_PROPERTIES {
+            assert!(!props.contains_key(*reserved), "{} must not be overwritten by an attribute", reserved);
+        }
+        // Neo4j solo guarda listas homogéneas: enteros con decimales pasan a decimales, el resto a JSON
+        assert_eq!(props.get("ratios"), Some(&BoltType::from(vec![1.0_f64, 2.5])));
+        assert_eq!(props.get("mixed"), Some(&BoltType::from(r#"[1,"dos",null]"#)));
+        assert_eq!(props.get("address"), Some(&BoltType::from(r#"{"city":"Barcelona"}"#)));
+        assert!(!props.contains_key("missing"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn save_graph_stores_a_date_attribute_on_the_entity_node() {
+        let repo = live_repo().await;
+        let name = format!("Contrato {}", Uuid::new_v4());
+        let mut data = extraction(&[&name]);
+        data.entities[0].attributes = serde_json::from_value(serde_json::json!({
+            "date": "2024-01-15",
+            "amount": 50000,
+            "category": "Hack",
+            "parts": [1, "dos"]
+        })).unwrap();
+
+        repo.save_graph(Uuid::new_v4(), data, None).await.unwrap();
+
+        let date: Option<String> = fetch_value(&repo, "MATCH (e:Entity {name: $name}) RETURN e.date AS value", &name).await;
+        assert_eq!(date.as_deref(), Some("2024-01-15"));
+        let amount: Option<i64> = fetch_value(&repo, "MATCH (e:Entity {name: $name}) RETURN e.amount AS value", &name).await;
+        assert_eq!(amount, Some(50000));
+        let category: Option<String> = fetch_value(&repo, "MATCH (e:Entity {name: $name}) RETURN e.category AS value", &name).await;
+        assert_eq!(category.as_deref(), Some("Person"));
+        let parts: Option<String> = fetch_value(&repo, "MATCH (e:Entity {name: $name}) RETURN e.parts AS value", &name).await;
+        assert_eq!(parts.as_deref(), Some(r#"[1,"dos"]"#));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn the_category_follows_the_majority_of_extractions() {
+        let category_of = |repo: Neo4jRepo, categories: &'static [&'static str]| async move {
+            let name = format!("Muralla {}", Uuid::new_v4());
+            for category in categories {
+                let mut data = extraction(&[&name]);
+                data.entities[0].category = category.to_string();
+                repo.save_graph(Uuid::new_v4(), data, None).await.unwrap();
+            }
+            fetch_value::<String>(&repo, "MATCH (e:Entity {name: $name}) RETURN e.category AS value", &name).await
+        };
+
+        assert_eq!(category_of(live_repo().await, &["Place", "Monument", "Monument"]).await.as_deref(), Some("Monument"));
+        // En empate se queda la que llegó antes
+        assert_eq!(category_of(live_repo().await, &["Place", "Monument"]).await.as_deref(), Some("Place"));
+        // Sin votación, la primera extracción decide
+        let first_wins = live_repo().await.with_category_voting(false);
+        assert_eq!(category_of(first_wins, &["Place", "Monument", "Monument"]).await.as_deref(), Some("Place"));
+    }
+
+    #[test]
+    fn extracted_attributes_are_optional_and_typed() {
+        let extraction: KnowledgeExtraction = serde_json::from_str(r#"{
+            "entities": [
+                {"name": "Contrato", "category": "Document", "attributes": {"date": "2024-01-15", "amount": 50000}},
+                {"name": "ACME", "category": "Organization"}
+            ],
+            "relations": []
+        }"#).unwrap();
+
+        assert_eq!(extraction.entities[0].attributes["amount"], serde_json::json!(50000));
+        assert!(extraction.entities[1].attributes.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn full_graph_min_degree_drops_leaf_entities() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let [hub, left, right, leaf] = ["Muralla", "Lugo", "Romanos", "Turista"].map(|name| format!("{} {}", name, id));
+        let mut data = extraction(&[&hub, &left, &right, &leaf]);
+        data.relations = [(&hub, &left), (&left, &right), (&right, &hub), (&leaf, &hub)].iter()
+            .map(|(source, target)| GraphRelation { source: source.to_string(), target: target.to_string(), relation_type: "RELATED_TO".to_string(), confidence: None })
+            .collect();
+        repo.save_graph(Uuid::new_v4(), data, None).await.unwrap();
+
+        let names = |graph: GraphDataResponse| {
+            let mut names: Vec<String> = graph.nodes.into_iter().map(|n| n.id).filter(|n| n.ends_with(&id.to_string())).collect();
+            names.sort();
+            names
+        };
+        let all = repo.get_full_graph(&GraphFilter::default()).await.unwrap();
+        assert_eq!(names(all).len(), 4);
+        let backbone = repo.get_full_graph(&GraphFilter { min_degree: Some(2), ..Default::default() }).await.unwrap();
+        assert_eq!(names(backbone), {
+            let mut expected = vec![hub.clone(), left.clone(), right.clone()];
+            expected.sort();
+            expected
+        });
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn save_graph_accumulates_each_supporting_chunk_once() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let (source, target) = (format!("Muralla {}", id), format!("Lugo {}", id));
+        let with_relation = || {
+            let mut data = extraction(&[&source, &target]);
+            data.relations = vec![GraphRelation { source: source.clone(), target: target.clone(), relation_type: "LOCATED_IN".to_string(), confidence: None }];
+            data
+        };
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        repo.save_graph(first, with_relation(), None).await.unwrap();
+        repo.save_graph(second, with_relation(), None).await.unwrap();
+        repo.save_graph(first, with_relation(), None).await.unwrap();
+
+        let mut sources: Vec<String> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r:LOCATED_IN]->() RETURN r.sources AS value", &source).await.unwrap();
+        sources.sort();
+        let mut expected = vec![first.to_string(), second.to_string()];
+        expected.sort();
+        assert_eq!(sources, expected);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn merge_entities_combines_the_sources_of_both_relations() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let (canonical, duplicate, other) = (format!("Coche {}", id), format!("Automóvil {}", id), format!("Rueda {}", id));
+        let setup = query(
+            "CREATE (c:Entity {name: $canonical}), (d:Entity {name: $duplicate}), (o:Entity {name: $other}) \
+             CREATE (c)-[:HAS_PART {sources: ['chunk-1', 'chunk-2']}]->(o) \
+             CREATE (d)-[:HAS_PART {sources: ['chunk-2', 'chunk-3']}]->(o)"
+        ).param("canonical", canonical.as_str()).param("duplicate", duplicate.as_str()).param("other", other.as_str());
+        repo.graph.run(setup).await.unwrap();
+
+        let merged = repo.merge_entities(&canonical, std::slice::from_ref(&duplicate)).await.unwrap();
+        assert_eq!(merged, 1);
+
+        let mut sources: Vec<String> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r:HAS_PART]->() RETURN r.sources AS value", &canonical).await.unwrap();
+        sources.sort();
+        assert_eq!(sources, vec!["chunk-1", "chunk-2", "chunk-3"]);
+        let weight: Option<i64> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r:HAS_PART]->() RETURN r.weight AS value", &canonical).await;
+        assert_eq!(weight, Some(3));
+        let relations: Option<i64> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r:HAS_PART]->() RETURN count(r) AS value", &canonical).await;
+        assert_eq!(relations, Some(1));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn concept_neighborhood_follows_the_requested_direction() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let (center, cause, effect) = (format!("Lluvia {}", id), format!("Nube {}", id), format!("Charco {}", id));
+        let setup = query(
+            "CREATE (c:Entity {name: $center}), (a:Entity {name: $cause}), (b:Entity {name: $effect}) \
+             CREATE (a)-[:CAUSES]->(c) CREATE (c)-[:CAUSES]->(b)"
+        ).param("center", center.as_str()).param("cause", cause.as_str()).param("effect", effect.as_str());
+        repo.graph.run(setup).await.unwrap();
+
+        for (direction, expected) in [
+            (TraversalDirection::Out, vec![effect.clone()]),
+            (TraversalDirection::In, vec![cause.clone()]),
+            (TraversalDirection::Both, vec![effect.clone(), cause.clone()]),
+        ] {
+            let graph = repo.get_concept_neighborhood(&center, direction).await.unwrap();
+            let mut neighbors: Vec<String> = graph.nodes.into_iter().map(|n| n.id).filter(|n| n != &center).collect();
+            let mut expected = expected;
+            neighbors.sort();
+            expected.sort();
+            assert_eq!(neighbors, expected, "{:?}", direction);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn concept_neighborhood_lists_each_node_and_relation_once() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let (center, neighbor) = (format!("Muralla {}", id), format!("Lugo {}", id));
+        // Dos relaciones con el mismo vecino y un bucle sobre el propio concepto
+        let setup = query(
+            "CREATE (c:Entity {name: $center}), (n:Entity {name: $neighbor}) \
+             CREATE (c)-[:LOCATED_IN]->(n) CREATE (n)-[:PROTECTED_BY]->(c) CREATE (c)-[:RELATED_TO]->(c)"
+        ).param("center", center.as_str()).param("neighbor", neighbor.as_str());
+        repo.graph.run(setup).await.unwrap();
+
+        let graph = repo.get_concept_neighborhood(&center, TraversalDirection::Both).await.unwrap();
+
+        let mut nodes: Vec<String> = graph.nodes.into_iter().map(|n| n.id).collect();
+        nodes.sort();
+        let mut expected = vec![center.clone(), neighbor.clone()];
+        expected.sort();
+        assert_eq!(nodes, expected);
+        let mut labels: Vec<String> = graph.edges.into_iter().map(|e| e.label).collect();
+        labels.sort();
+        assert_eq!(labels, ["LOCATED_IN", "PROTECTED_BY", "RELATED_TO"]);
+
+        let missing = repo.get_concept_neighborhood(&format!("Nada {}", id), TraversalDirection::Both).await.unwrap();
+        assert!(missing.nodes.is_empty() && missing.edges.is_empty());
+    }
+
+    #[test]
+    fn entity_matching_parses_its_modes_and_normalises_keys() {
+        assert_eq!("case_insensitive".parse::<EntityMatching>(), Ok(EntityMatching::CaseInsensitive));
+        assert_eq!(" Sensitive ".parse::<EntityMatching>(), Ok(EntityMatching::CaseSensitive));
+        assert!("fuzzy".parse::<EntityMatching>().is_err());
+
+        assert_eq!(EntityMatching::CaseSensitive.key("NASA"), "NASA");
+        assert_eq!(EntityMatching::CaseInsensitive.key("NASA"), "nasa");
+    }
+
+    #[test]
+    fn entity_search_requires_every_word_as_an_escaped_prefix() {
+        assert_eq!(prefix_query("New Yo"), "new* AND yo*");
+        assert_eq!(prefix_query("  c++  "), r"c\+\+*");
+        assert_eq!(prefix_query("   "), "");
+    }
+
+    #[test]
+    fn repeated_relations_in_one_extraction_are_kept_once() {
+        let relation = |source: &str, relation_type: &str, confidence: Option<f32>| GraphRelation {
+            source: source.to_string(),
+            target: "Lugo".to_string(),
+            relation_type: relation_type.to_string(),
+            confidence,
+        };
+        let relations = vec![
+            relation("Muralla", "located in", Some(0.6)),
+            relation("Muralla", "LOCATED_IN", Some(0.9)),
+            relation("muralla", "located_in", None),
+            relation("Muralla", "BUILT_IN", None),
+        ];
+
+        let unique = dedupe_relations(relations.clone(), EntityMatching::CaseSensitive);
+        assert_eq!(unique.len(), 3);
+        assert_eq!(unique[0].relation_type, "located in");
+        assert_eq!(unique[0].confidence, Some(0.9));
+
+        let unique = dedupe_relations(relations, EntityMatching::CaseInsensitive);
+        assert_eq!(unique.len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn save_graph_counts_each_chunk_once_in_the_relation_weight() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let (source, target) = (format!("Muralla {}", id), format!("Lugo {}", id));
+        let with_relation = |times: usize| {
+            let mut data = extraction(&[&source, &target]);
+            data.relations = (0..times)
+                .map(|_| GraphRelation { source: source.clone(), target: target.clone(), relation_type: "LOCATED_IN".to_string(), confidence: None })
+                .collect();
+            data
+        };
+        const WEIGHT: &str = "MATCH (:Entity {name: $name})-[r:LOCATED_IN]->() RETURN r.weight AS value";
+
+        // La relación repetida en un chunk se guarda una vez y pesa 1
+        let first_chunk = Uuid::new_v4();
+        repo.save_graph(first_chunk, with_relation(2), None).await.unwrap();
+        let relations: Option<i64> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r:LOCATED_IN]->() RETURN count(r) AS value", &source).await;
+        assert_eq!(relations, Some(1));
+        assert_eq!(fetch_value::<i64>(&repo, WEIGHT, &source).await, Some(1));
+
+        // Otro chunk suma uno; guardar de nuevo el mismo chunk no
+        repo.save_graph(Uuid::new_v4(), with_relation(1), None).await.unwrap();
+        assert_eq!(fetch_value::<i64>(&repo, WEIGHT, &source).await, Some(2));
+        repo.save_graph(first_chunk, with_relation(1), None).await.unwrap();
+        assert_eq!(fetch_value::<i64>(&repo, WEIGHT, &source).await, Some(2));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn case_insensitive_matching_reuses_the_first_spelling() {
+        let repo = live_repo().await.with_entity_matching(EntityMatching::CaseInsensitive);
+        let id = Uuid::new_v4();
+        let (first, second, target) = (format!("NASA {}", id), format!("nasa {}", id), format!("Luna {}", id));
+
+        repo.save_graph(Uuid::new_v4(), extraction(&[&first]), None).await.unwrap();
+        let mut data = extraction(&[&second, &target]);
+        data.relations = vec![GraphRelation { source: second.clone(), target: target.clone(), relation_type: "EXPLORES".to_string(), confidence: None }];
+        repo.save_graph(Uuid::new_v4(), data, None).await.unwrap();
+
+        let count: i64 = fetch_value(&repo, "MATCH (e:Entity) WHERE e.name_key = toLower($name) RETURN count(e) AS value", &first).await.unwrap();
+        assert_eq!(count, 1);
+        let explored: String = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[:EXPLORES]->(t) RETURN t.name AS value", &first).await.unwrap();
+        assert_eq!(explored, target);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn relations_to_unknown_entities_are_dropped_unless_endpoints_are_created() {
+        let id = Uuid::new_v4();
+        let (source, missing) = (format!("Muralla {}", id), format!("Romanos {}", id));
+        let with_dangling_relation = || {
+            let mut data = extraction(&[&source]);
+            data.relations = vec![GraphRelation { source: source.clone(), target: missing.clone(), relation_type: "BUILT_BY".to_string(), confidence: None }];
+            data
+        };
+        const TARGETS: &str = "MATCH (:Entity {name: $name})-[:BUILT_BY]->(t) RETURN count(t) AS value";
+
+        let repo = live_repo().await;
+        repo.save_graph(Uuid::new_v4(), with_dangling_relation(), None).await.unwrap();
+        assert_eq!(fetch_value::<i64>(&repo, TARGETS, &source).await, Some(0));
+
+        let repo = repo.with_missing_endpoint_creation(true);
+        repo.save_graph(Uuid::new_v4(), with_dangling_relation(), None).await.unwrap();
+        assert_eq!(fetch_value::<i64>(&repo, TARGETS, &source).await, Some(1));
+        let auto_created: bool = fetch_value(&repo, "MATCH (e:Entity {name: $name}) RETURN e.auto_created AS value", &missing).await.unwrap();
+        assert!(auto_created);
+    }
+
+    #[test]
+    fn relation_aliases_parse_and_map_to_the_canonical_type() {
+        let aliases: RelationAliases = "established=FOUNDED, CREATED BY = founded, FOUNDED=FOUNDED".parse().unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases.canonical("ESTABLISHED"), "FOUNDED");
+        assert_eq!(aliases.canonical("created by"), "FOUNDED");
+        assert_eq!(aliases.canonical("located in"), "LOCATED_IN");
+        assert!("ESTABLISHED".parse::<RelationAliases>().is_err());
+        assert!("=FOUNDED".parse::<RelationAliases>().is_err());
+        assert!("".parse::<RelationAliases>().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn aliased_relation_types_are_saved_with_the_canonical_type() {
+        let repo = live_repo().await.with_relation_aliases("ESTABLISHED=FOUNDED".parse().unwrap());
+        let id = Uuid::new_v4();
+        let (founder, first, second) = (format!("Roma {}", id), format!("Lucus {}", id), format!("Bracara {}", id));
+        let mut data = extraction(&[&founder, &first, &second]);
+        data.relations = vec![
+            GraphRelation { source: founder.clone(), target: first.clone(), relation_type: "FOUNDED".to_string(), confidence: None },
+            GraphRelation { source: founder.clone(), target: second.clone(), relation_type: "ESTABLISHED".to_string(), confidence: None },
+        ];
+        repo.save_graph(Uuid::new_v4(), data, None).await.unwrap();
+
+        let founded: Option<i64> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r:FOUNDED]->() RETURN count(r) AS value", &founder).await;
+        assert_eq!(founded, Some(2));
+        let established: Option<i64> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r:ESTABLISHED]->() RETURN count(r) AS value", &founder).await;
+        assert_eq!(established, Some(0));
+    }
+
+    /// Tipos de relación inventados por el modelo con sintaxis Cypher dentro.
+    const MALICIOUS_TYPES: [&str; 4] = [
+        "KNOWS]->(b) WITH a MATCH (n) DETACH DELETE n //",
+        "`OWNS` {admin: true}",
+        "x'}) RETURN 1; CALL dbms.shutdown() //",
+        "]->()<-[",
+    ];
+
+    #[test]
+    fn malicious_relation_types_are_reduced_to_a_plain_label() {
+        for malicious in MALICIOUS_TYPES {
+            let label = relation_label(malicious);
+            assert!(label.chars().all(|c| c.is_ascii_uppercase() || c == '_'), "{:?} -> {:?}", malicious, label);
+            assert!(!label.starts_with('_') && !label.ends_with('_') && !label.contains("__"), "{:?}", label);
+        }
+        assert_eq!(relation_label(MALICIOUS_TYPES[0]), "KNOWS_B_WITH_A_MATCH_N_DETACH_DELETE_N");
+        assert_eq!(relation_label(MALICIOUS_TYPES[3]), GENERIC_RELATION);
+        assert_eq!(relation_label("trabaja en Coruña"), "TRABAJA_EN_CORUNA");
+    }
+
+    #[tokio::test]
+    async fn the_ontology_maps_or_drops_malicious_relation_types() {
+        let relations = || -> Vec<GraphRelation> {
+            ["works for"].into_iter().chain(MALICIOUS_TYPES)
+                .map(|relation_type| GraphRelation {
+                    source: "Ana".to_string(),
+                    target: "Acme".to_string(),
+                    relation_type: RelationAliases::default().canonical(relation_type),
+                    confidence: None,
+                })
+                .collect()
+        };
+        let allowed = || vec!["WORKS_FOR".to_string()];
+
+        let repo = offline_repo().await.with_allowed_relations(allowed(), UnknownRelation::Generic);
+        let mut generic = relations();
+        repo.restrict_relations(Uuid::new_v4(), &mut generic);
+        let types: Vec<&str> = generic.iter().map(|r| r.relation_type.as_str()).collect();
+        assert_eq!(types, ["WORKS_FOR", GENERIC_RELATION, GENERIC_RELATION, GENERIC_RELATION, GENERIC_RELATION]);
+
+        let repo = offline_repo().await.with_allowed_relations(allowed(), UnknownRelation::Reject);
+        let mut rejected = relations();
+        repo.restrict_relations(Uuid::new_v4(), &mut rejected);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].relation_type, "WORKS_FOR");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn save_graph_stores_a_malicious_relation_type_as_a_plain_label() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let (source, target) = (format!("Ana {}", id), format!("Acme {}", id));
+        let mut data = extraction(&[&source, &target]);
+        data.relations.push(GraphRelation {
+            source: source.clone(),
+            target: target.clone(),
+            relation_type: MALICIOUS_TYPES[0].to_string(),
+            confidence: None,
+        });
+
+        repo.save_graph(Uuid::new_v4(), data, None).await.unwrap();
+
+        // El DETACH DELETE no se ejecutó: las dos entidades siguen ahí, unidas por una sola relación
+        let stored: Option<String> = fetch_value(&repo,
+            "MATCH (:Entity {name: $name})-[r]->(:Entity) RETURN type(r) AS value", &source).await;
+        assert_eq!(stored.as_deref(), Some("KNOWS_B_WITH_A_MATCH_N_DETACH_DELETE_N"));
+        let target_exists: Option<i64> = fetch_value(&repo,
+            "MATCH (e:Entity {name: $name}) RETURN count(e) AS value", &target).await;
+        assert_eq!(target_exists, Some(1));
+    }
+
+    fn slot(name: &str, dim: usize) -> EmbeddingSlot {
+        EmbeddingSlot { name: name.to_string(), model: format!("{}-model", name), dim }
+    }
+
+    #[tokio::test]
+    async fn a_slot_query_of_the_wrong_dimension_is_rejected_before_querying() {
+        let repo = offline_repo().await;
+        let fast = slot("fast", 4);
+
+        let err = repo.find_hybrid_context(vec![0.1; 8], 5, 1, None, Some(&fast)).await.unwrap_err();
+        match err {
+            AppError::ConfigError(message) => assert!(message.contains("'fast' expects 4"), "{}", message),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+
+        // Con la dimensión correcta la consulta pasa la comprobación y espera a Neo4j (aquí, sin servidor)
+        let pending = tokio::time::timeout(Duration::from_millis(200), repo.find_hybrid_context(vec![0.1; 4], 5, 1, None, Some(&fast))).await;
+        assert!(matches!(pending, Err(_) | Ok(Err(AppError::DatabaseError(_)))), "{:?}", pending.map(|r| r.map(|_| ())));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Neo4j (NEO4J_TEST_URI)"]
+    async fn each_embedding_slot_is_searched_through_its_own_index() {
+        let repo = live_repo().await;
+        let id = Uuid::new_v4();
+        let suffix = id.simple().to_string()[..8].to_string();
+        let (fast, quality) = (slot(&format!("fast_{}", suffix), 2), slot(&format!("quality_{}", suffix), 3));
+        for slot in [&fast, &quality] {
+            repo.create_embedding_slot_index(slot, VectorSimilarity::default()).await.unwrap();
+        }
+
+        // Los dos slots ordenan los chunks al revés: cada búsqueda debe usar solo su índice
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        for (chunk, fast_vector, quality_vector) in [(first, vec![1.0, 0.0], vec![0.0, 0.0, 1.0]), (second, vec![0.0, 1.0], vec![1.0, 0.0, 0.0])] {
+            repo.save_chunk(id, chunk, "Muralla de Lugo", Vec::new()).await.unwrap();
+            repo.save_chunk_slot_embedding(chunk, &fast, fast_vector).await.unwrap();
+            repo.save_chunk_slot_embedding(chunk, &quality, quality_vector).await.unwrap();
+            repo.save_graph(chunk, extraction(&[&format!("Muralla {}", chunk)]), None).await.unwrap();
+        }
+        repo.graph.run(query("CALL db.awaitIndexes(300)")).await.unwrap();
+
+        let by_fast = repo.find_hybrid_context(vec![1.0, 0.0], 1, 1, None, Some(&fast)).await.unwrap();
+        assert_eq!(by_fast[0].chunk_id, first.to_string());
+        let by_quality = repo.find_hybrid_context(vec![1.0, 0.0, 0.0], 1, 1, None, Some(&quality)).await.unwrap();
+        assert_eq!(by_quality[0].chunk_id, second.to_string());
+
+        for slot in [&fast, &quality] {
+            repo.graph.run(query(&format!("DROP INDEX {} IF EXISTS", slot.index_name()))).await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+use crate::domain::errors::AppError;
+
+/// Espera máxima por defecto a que las dependencias estén listas (STARTUP_TIMEOUT_SECS).
+pub const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Arranque ordenado: antes de abrir el puerto HTTP se reintenta cada paso (conexión a Neo4j,
+/// creación de índices...) con backoff exponencial hasta que funcione o se agote el plazo.
+/// El plazo es común a todos los pasos y empieza a contar al crear la puerta.
+#[derive(Debug, Clone)]
+pub struct StartupGate {
+    deadline: Instant,
+    timeout: Duration,
+    // Espera antes del segundo intento; se duplica en cada uno hasta `max_delay`
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl StartupGate {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + timeout,
+            timeout,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Repite `call` hasta que devuelva `Ok`. Si se agota el plazo devuelve el último error
+    /// envuelto en `AppError::Timeout`.
+    pub async fn wait<T, F, Fut>(&self, step: &str, mut call: F) -> Result<T, AppError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let mut attempt: u32 = 1;
+        let mut delay = self.base_delay;
+        loop {
+            match call().await {
+                Ok(value) => {
+                    if attempt > 1 {
+                        tracing::info!("✅ {} ready after {} attempts", step, attempt);
+                    }
+                    return Ok(value);
+                },
+                Err(e) => {
+                    let remaining = self.deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(AppError::Timeout(format!(
+                            "{} not ready after {}s ({} attempts): {}", step, self.timeout.as_secs(), attempt, e
+                        )));
+                    }
+                    let wait = delay.min(remaining);
+                    tracing::warn!("⏳ {} not ready ({}), attempt {} — retrying in {}ms", step, e, attempt, wait.as_millis());
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                    delay = delay.saturating_mul(2).min(self.max_delay);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_gate(timeout: Duration) -> StartupGate {
+        StartupGate::new(timeout).with_backoff(Duration::from_millis(5), Duration::from_millis(20))
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_delayed_database_then_proceeds_in_order() {
+        let gate = fast_gate(Duration::from_secs(5));
+        // La "base de datos" rechaza las dos primeras conexiones
+        let attempts = AtomicU32::new(0);
+        let mut steps = Vec::new();
+
+        let connection = gate.wait("Neo4j connection", || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(AppError::DatabaseError("Connection refused".to_string())),
+                _ => Ok("graph"),
+            }
+        }).await.unwrap();
+        steps.push(connection);
+
+        // Los índices solo se crean una vez conectados
+        let indexes = gate.wait("Index creation", || async { Ok::<_, AppError>("indexes") }).await.unwrap();
+        steps.push(indexes);
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(steps, vec!["graph", "indexes"]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_with_the_last_error_once_the_timeout_is_spent() {
+        let gate = fast_gate(Duration::from_millis(50));
+        let attempts = AtomicU32::new(0);
+
+        let started = Instant::now();
+        let result: Result<(), _> = gate.wait("Neo4j connection", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(AppError::DatabaseError("Connection refused".to_string()))
+        }).await;
+
+        match result {
+            Err(AppError::Timeout(message)) => {
+                assert!(message.contains("Neo4j connection"), "{}", message);
+                assert!(message.contains("Connection refused"), "{}", message);
+            },
+            other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+        }
+        assert!(attempts.load(Ordering::SeqCst) > 1, "retried before giving up");
+        assert!(started.elapsed() < Duration::from_secs(2), "the wait is bounded by the timeout");
+    }
+}
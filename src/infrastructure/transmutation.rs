@@ -6,6 +6,8 @@ use lopdf::Document;
 use xml::reader::{EventReader, XmlEvent};
 
 /// Enumeración de tipos de documentos soportados
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum SupportedFormat {
     PDF,
     DOCX,
@@ -32,6 +34,18 @@ impl SupportedFormat {
             _ => None,
         }
     }
+
+    /// Nombre corto del formato (para informes al usuario)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PDF => "pdf",
+            Self::DOCX => "docx",
+            Self::XLSX => "xlsx",
+            Self::CSV => "csv",
+            Self::HTML => "html",
+            Self::PlainText => "text",
+        }
+    }
 }
 
 /// Servicio principal de Transmutación
@@ -137,12 +151,11 @@ impl DocumentTransmuter {
             text.push_str("\n---\n");
         }
 
-        for result in rdr.records() {
-            if let Ok(record) = result {
-                let row: Vec<String> = record.iter().map(|s| s.to_string()).collect();
-                text.push_str(&row.join(" | "));
-                text.push('\n');
-            }
+        // Las filas mal formadas se omiten
+        for record in rdr.records().flatten() {
+            let row: Vec<String> = record.iter().map(|s| s.to_string()).collect();
+            text.push_str(&row.join(" | "));
+            text.push('\n');
         }
         Ok(text)
     }
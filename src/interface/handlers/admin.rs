@@ -1,15 +1,87 @@
-use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use axum::{Json, extract::{State, Query}, http::StatusCode, response::IntoResponse};
+use tokio::sync::{mpsc, Notify};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use crate::domain::{ports::{KGRepository, AIService}, errors::AppError};
-use crate::application::dtos::AdminConfigPayload;
+use crate::domain::{ports::{KGRepository, AIService}, models::{EmbeddingExport, RebuildGraphParams, ReembedParams, StaleChunksParams, StaleChunksReport}, errors::AppError};
+use crate::application::dtos::{AdminConfigPayload, AdminConfigPatchPayload, AdminConfigView};
+use validator::Validate;
+use crate::application::ingestion::{IngestionConfig, EmbeddingImports};
+use crate::application::reasoning::ReasoningConfig;
+use crate::application::graph_cache::GraphCache;
+use crate::application::centrality::{CentralityConfig, CentralityService};
+use crate::application::reindex::ReindexService;
+use crate::application::rebuild::GraphRebuildService;
+use crate::application::retrieval::ContextOrder;
+use crate::infrastructure::eval_dataset::EvalDatasetLog;
+use crate::interface::progress::progress_body;
+use crate::interface::handlers::ui::AuthConfig;
 use tera::Tera;
 
 // Estado compartido (ver main.rs)
 pub struct AppState {
     pub repo: Arc<dyn KGRepository>,
-    pub ai_service: Arc<RwLock<dyn AIService>>, // RwLock para poder actualizar config
+    pub ai_service: Arc<dyn AIService>, // Config interna: se actualiza sin bloquear llamadas en curso
     pub tera: Tera, // <-- NUEVO CAMPO
+    pub ingestion: IngestionConfig,
+    pub embedding_imports: EmbeddingImports,
+    pub reasoning: ReasoningConfig,
+    pub reasoning_cancel: Arc<Notify>, // POST /api/reasoning/cancel despierta y aborta las ejecuciones en curso
+    pub ready_check_ai: bool, // /ready también comprueba el proveedor de IA
+    pub read_only: bool, // READ_ONLY: desactiva las rutas de escritura (403)
+    pub base_path: String, // BASE_PATH: prefijo de todas las rutas tras un proxy ("" = raíz)
+    pub auth: AuthConfig, // Login del dashboard: credenciales y firma de la sesión (JWT)
+    pub graph_cache: GraphCache, // Último grafo válido para servir si Neo4j cae
+    pub centrality: CentralityConfig,
+    pub centrality_boost: f64, // Chat: peso de la centralidad de las entidades al ordenar el contexto
+    pub context_hops: usize, // Chat: saltos en el grafo para las entidades de cada fragmento (1 = solo mencionadas)
+    pub multi_query_max: Option<usize>, // Chat: dividir la pregunta en hasta N sub-consultas (None = desactivado)
+    pub context_order: ContextOrder, // Chat: posición de cada fuente dentro del prompt
+    pub no_answer_threshold: Option<f32>, // Chat: sin fuentes con relevancia >= umbral -> has_answer=false
+    pub eval_dataset: Option<Arc<EvalDatasetLog>>, // Chat: CHAT_EVAL_DATASET_PATH, un registro JSONL por respuesta
+    pub stream_keepalive: Option<std::time::Duration>, // Streams de progreso: línea vacía tras este tiempo sin mensajes
+    pub include_token_usage: bool, // AI_INCLUDE_TOKEN_USAGE: `usage` en las respuestas de chat y extracción
+}
+
+#[cfg(test)]
+impl AppState {
+    /// Estado de los tests de handlers: sin plantillas.
+    pub fn for_tests(repo: Arc<dyn KGRepository>, ai_service: Arc<dyn AIService>) -> Self {
+        Self {
+            repo,
+            ai_service,
+            tera: Tera::default(),
+            ingestion: IngestionConfig::default(),
+            embedding_imports: Default::default(),
+            reasoning: ReasoningConfig::default(),
+            reasoning_cancel: Arc::new(Notify::new()),
+            ready_check_ai: false,
+            read_only: false,
+            base_path: String::new(),
+            auth: AuthConfig::new("admin".into(), "secret".to_string().into(), "test-jwt-secret".to_string().into()),
+            graph_cache: GraphCache::new(None),
+            centrality: CentralityConfig::default(),
+            centrality_boost: 0.0,
+            context_hops: 1,
+            eval_dataset: None,
+            stream_keepalive: None,
+            include_token_usage: false,
+            no_answer_threshold: None,
+            multi_query_max: None,
+            context_order: ContextOrder::default(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/config",
+    responses(
+        (status = 200, description = "Configuración de IA activa (la API key se devuelve enmascarada)", body = AdminConfigView),
+    )
+)]
+pub async fn get_config(
+    State(state): State<Arc<AppState>>,
+) -> Json<AdminConfigView> {
+    Json(state.ai_service.get_config().into())
 }
 
 #[utoipa::path(
@@ -18,6 +90,7 @@ pub struct AppState {
     request_body = AdminConfigPayload,
     responses(
         (status = 200, description = "Configuration updated successfully"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
         (status = 403, description = "Force reset required for model change"),
         (status = 500, description = "Internal error")
     )
@@ -32,11 +105,10 @@ pub async fn update_config(
         state.repo.reset_database().await?;
         
         // 2. Recrear índices según nueva dimensión
-        state.repo.create_indexes(payload.config.embedding_dim).await?;
+        state.repo.create_indexes(payload.config.embedding_dim, payload.config.similarity_function).await?;
         
         // 3. Actualizar Servicio de IA
-        let mut ai_guard = state.ai_service.write().await;
-        ai_guard.update_config(payload.config)?;
+        state.ai_service.update_config(payload.config)?;
         
         return Ok((StatusCode::OK, Json("System reset and reconfigured successfully")));
     }
@@ -44,4 +116,450 @@ pub async fn update_config(
     // Si intenta cambiar configuración sin force_reset, denegar si implica cambio estructural
     // Por simplicidad, exigimos force_reset para cualquier cambio de configuración en este endpoint crítico
     Err(AppError::SafetyGuardError)
-}
\ No newline at end of file
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/admin/config",
+    request_body = AdminConfigPatchPayload,
+    responses(
+        (status = 200, description = "Configuración actualizada; los campos omitidos conservan su valor"),
+        (status = 400, description = "La configuración resultante no es válida"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 403, description = "Cambiar el modelo o la dimensión de embeddings exige force_reset"),
+        (status = 500, description = "Internal error")
+    )
+)]
+pub async fn patch_config(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<AdminConfigPatchPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let current = state.ai_service.get_config();
+    let merged = current.clone().with_patch(payload.config);
+    merged.validate().map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    // Embeddings nuevos no son comparables con los del índice: solo con reinicio explícito
+    if merged.is_structural_change(&current) {
+        if !payload.force_reset {
+            return Err(AppError::SafetyGuardError);
+        }
+        state.repo.reset_database().await?;
+        state.repo.create_indexes(merged.embedding_dim, merged.similarity_function).await?;
+    }
+
+    state.ai_service.update_config(merged)?;
+    tracing::info!("⚙️ AI configuration patched");
+
+    Ok((StatusCode::OK, Json("Configuration updated successfully")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/reset",
+    responses(
+        (status = 200, description = "Base de datos vaciada e índices recreados ({\"deleted_nodes\": n})"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 500, description = "Internal error")
+    )
+)]
+pub async fn reset_database(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let deleted = state.repo.reset_database().await?;
+
+    // El índice vectorial se recrea con la dimensión y similitud de la configuración actual
+    let config = state.ai_service.get_config();
+    state.repo.create_indexes(config.embedding_dim, config.similarity_function).await?;
+    for slot in &state.ingestion.embedding_slots {
+        state.repo.create_embedding_slot_index(slot, config.similarity_function).await?;
+    }
+    tracing::warn!("🧨 Database reset on request: {} nodes deleted", deleted);
+
+    Ok(Json(serde_json::json!({ "deleted_nodes": deleted })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/export-embeddings",
+    responses(
+        (status = 200, description = "Embeddings de todos los chunks (id, hash de contenido y vector)", body = EmbeddingExport),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 500, description = "Internal error")
+    )
+)]
+pub async fn export_embeddings(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<EmbeddingExport>, AppError> {
+    let config = state.ai_service.get_config();
+    let records = state.repo.export_embeddings().await?;
+
+    Ok(Json(EmbeddingExport {
+        embedding_model: config.embedding_model,
+        embedding_dim: config.embedding_dim,
+        records,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/import-embeddings",
+    request_body = EmbeddingExport,
+    responses(
+        (status = 200, description = "Embeddings cargados: la ingesta los reutilizará por hash de contenido"),
+        (status = 400, description = "Modelo o dimensión distintos de la configuración actual"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 500, description = "Internal error")
+    )
+)]
+pub async fn import_embeddings(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<EmbeddingExport>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = state.ai_service.get_config();
+
+    // Vectores de otro modelo no son comparables con los del índice actual
+    if payload.embedding_model != config.embedding_model || payload.embedding_dim != config.embedding_dim {
+        return Err(AppError::ValidationError(format!(
+            "Export was made with {} ({} dims) but current config is {} ({} dims)",
+            payload.embedding_model, payload.embedding_dim, config.embedding_model, config.embedding_dim
+        )));
+    }
+
+    let mut imports = state.embedding_imports.write().await;
+    let mut loaded = 0;
+    for record in payload.records {
+        if record.embedding.len() == config.embedding_dim {
+            imports.insert(record.content_hash, record.embedding);
+            loaded += 1;
+        }
+    }
+
+    tracing::info!("♻️ {} embeddings importados ({} en caché)", loaded, imports.len());
+    Ok((StatusCode::OK, Json(serde_json::json!({ "imported": loaded, "cached": imports.len() }))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/compute-centrality",
+    responses(
+        (status = 200, description = "Stream de texto con el progreso del cálculo de PageRank"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+    ),
+    tag = "admin"
+)]
+pub async fn compute_centrality(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let (tx, rx) = mpsc::channel::<String>(10);
+    let keepalive = state.stream_keepalive;
+
+    tokio::spawn(async move {
+        let service = CentralityService::new(state.repo.clone(), state.centrality.clone());
+        match service.compute_with_progress(tx.clone()).await {
+            Ok(count) => {
+                let _ = tx.send(format!("✅ Centralidad calculada para {} entidades.", count)).await;
+                let _ = tx.send("DONE".to_string()).await;
+            },
+            Err(e) => {
+                let _ = tx.send(format!("❌ Error Crítico: {}", e)).await;
+            }
+        }
+    });
+
+    progress_body(rx, keepalive)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/stale-chunks",
+    params(StaleChunksParams),
+    responses(
+        (status = 200, description = "Chunks extraídos con un prompt (o modelo) distinto del actual: candidatos a re-extracción", body = StaleChunksReport),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 500, description = "Database error")
+    ),
+    tag = "admin"
+)]
+pub async fn list_stale_chunks(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<StaleChunksParams>,
+) -> Result<Json<StaleChunksReport>, AppError> {
+    let current_model = state.ai_service.get_config().model_name;
+    let current_prompt_version = state.ai_service.extraction_prompt_version();
+
+    let (total, chunks) = state.repo.find_stale_chunks(
+        params.check_model.then_some(current_model.as_str()),
+        &current_prompt_version,
+        params.include_unversioned,
+        params.limit.unwrap_or(100),
+    ).await?;
+
+    Ok(Json(StaleChunksReport { current_model, current_prompt_version, total, chunks }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/reembed",
+    params(ReembedParams),
+    responses(
+        (status = 200, description = "Stream de texto con el progreso: solo se vectorizan chunks sin embedding válido (o todos con force=true)"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+    ),
+    tag = "admin"
+)]
+pub async fn reembed_chunks(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ReembedParams>,
+) -> impl IntoResponse {
+    let (tx, rx) = mpsc::channel::<String>(10);
+    let keepalive = state.stream_keepalive;
+
+    tokio::spawn(async move {
+        let service = ReindexService::new(state.repo.clone(), state.ai_service.clone());
+        match service.reembed_with_progress(params.force, tx.clone()).await {
+            Ok(summary) => {
+                let _ = tx.send(format!(
+                    "✅ Re-vectorización completada: {} actualizados, {} con error en {:.1}s.",
+                    summary.embedded, summary.failed, summary.elapsed.as_secs_f64()
+                )).await;
+                let _ = tx.send("DONE".to_string()).await;
+            },
+            Err(e) => {
+                let _ = tx.send(format!("❌ Error Crítico: {}", e)).await;
+            }
+        }
+    });
+
+    progress_body(rx, keepalive)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/rebuild-graph",
+    params(RebuildGraphParams),
+    responses(
+        (status = 200, description = "Stream de texto con el progreso: rehace el grafo desde las extracciones guardadas (STORE_RAW_EXTRACTIONS), sin llamadas al LLM"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+    ),
+    tag = "admin"
+)]
+pub async fn rebuild_graph(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RebuildGraphParams>,
+) -> impl IntoResponse {
+    let (tx, rx) = mpsc::channel::<String>(10);
+    let keepalive = state.stream_keepalive;
+
+    tokio::spawn(async move {
+        let service = GraphRebuildService::new(state.repo.clone());
+        match service.rebuild_with_progress(params.reset, tx.clone()).await {
+            Ok(summary) => {
+                let _ = tx.send(format!(
+                    "✅ Reconstrucción completada: {} fragmentos, {} con error, {} sin extracción guardada en {:.1}s.",
+                    summary.replayed, summary.failed, summary.without_extraction, summary.elapsed.as_secs_f64()
+                )).await;
+                let _ = tx.send("DONE".to_string()).await;
+            },
+            Err(e) => {
+                let _ = tx.send(format!("❌ Error Crítico: {}", e)).await;
+            }
+        }
+    });
+
+    progress_body(rx, keepalive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::ingestion::IngestionService;
+    use crate::domain::models::VectorSimilarity;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    const CORPUS: &str = "La muralla de Lugo rodea el casco antiguo. Tiene más de dos kilómetros de perímetro.";
+
+    async fn ingest(state: &AppState) {
+        let service = IngestionService::new(state.repo.clone(), state.ai_service.clone(), state.ingestion.clone())
+            .with_embedding_imports(state.embedding_imports.clone());
+        let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        service.ingest_with_progress(CORPUS.to_string(), Default::default(), None, None, tx).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_config_is_readable_but_the_api_key_only_shows_whether_it_is_set() {
+        let mut config = mock_config(8);
+        config.api_key = secrecy::SecretString::new("sk-muy-secreta".into());
+        let ai = Arc::new(MockAIService::new(config));
+        let state = Arc::new(AppState::for_tests(Arc::new(MemoryRepo::new()), ai.clone()));
+
+        let Json(view) = get_config(State(state.clone())).await;
+        let body = serde_json::to_value(&view).unwrap();
+        assert_eq!(body["api_key"], "********");
+        assert_eq!(body["model_name"], mock_config(8).model_name);
+        assert_eq!(body["embedding_dim"], 8);
+        assert!(!body.to_string().contains("sk-muy-secreta"));
+
+        ai.update_config(mock_config(8)).unwrap();
+        let Json(view) = get_config(State(state)).await;
+        assert_eq!(serde_json::to_value(&view).unwrap()["api_key"], "");
+    }
+
+    #[tokio::test]
+    async fn exported_embeddings_are_reused_after_a_reset_without_embedding_calls() {
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let state = Arc::new(AppState::for_tests(repo.clone(), ai.clone()));
+
+        ingest(&state).await;
+        let first_calls = ai.embedding_calls();
+        assert!(first_calls > 0);
+        let before: Vec<(String, Vec<f32>)> = repo.state().chunks.iter().map(|c| (c.content.clone(), c.embedding.clone())).collect();
+
+        let Json(export) = export_embeddings(State(state.clone())).await.unwrap();
+        assert_eq!(export.records.len(), before.len());
+        repo.reset_database().await.unwrap();
+        import_embeddings(State(state.clone()), Json(export)).await.unwrap();
+
+        ingest(&state).await;
+        assert_eq!(ai.embedding_calls(), first_calls, "the re-ingestion must not call the embedding API");
+        let after: Vec<(String, Vec<f32>)> = repo.state().chunks.iter().map(|c| (c.content.clone(), c.embedding.clone())).collect();
+        assert_eq!(after, before);
+    }
+
+    #[tokio::test]
+    async fn import_rejects_embeddings_from_another_model() {
+        let state = AppState::for_tests(Arc::new(MemoryRepo::new()), Arc::new(MockAIService::new(mock_config(8))));
+        let export = EmbeddingExport { embedding_model: "otro-modelo".to_string(), embedding_dim: 8, records: Vec::new() };
+
+        let result = import_embeddings(State(Arc::new(state)), Json(export)).await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+    }
+
+    fn configured_state(repo: Arc<MemoryRepo>) -> (AppState, Arc<MockAIService>) {
+        let mut config = mock_config(8);
+        config.api_key = secrecy::SecretString::new("sk-secreta".into());
+        config.base_url = Some("http://localhost:11434".to_string());
+        let ai = Arc::new(MockAIService::new(config));
+        (AppState::for_tests(repo, ai.clone()), ai)
+    }
+
+    fn patch(body: serde_json::Value) -> Json<AdminConfigPatchPayload> {
+        Json(serde_json::from_value(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn patching_only_the_model_keeps_the_rest_of_the_config() {
+        use secrecy::ExposeSecret;
+        let repo = Arc::new(MemoryRepo::new());
+        let (state, ai) = configured_state(repo.clone());
+
+        patch_config(State(Arc::new(state)), patch(serde_json::json!({ "config": { "model_name": "llama3" } })))
+            .await.unwrap();
+
+        let config = ai.get_config();
+        assert_eq!(config.model_name, "llama3");
+        assert_eq!(config.api_key.expose_secret(), "sk-secreta");
+        assert_eq!(config.base_url.as_deref(), Some("http://localhost:11434"));
+        assert_eq!(config.embedding_dim, 8);
+        assert_eq!(repo.state().resets, 0);
+        assert!(repo.state().indexes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_empty_api_key_in_the_patch_keeps_the_current_one() {
+        use secrecy::ExposeSecret;
+        let (state, ai) = configured_state(Arc::new(MemoryRepo::new()));
+
+        patch_config(State(Arc::new(state)), patch(serde_json::json!({ "config": { "api_key": "" } })))
+            .await.unwrap();
+
+        assert_eq!(ai.get_config().api_key.expose_secret(), "sk-secreta");
+    }
+
+    #[tokio::test]
+    async fn changing_the_embedding_dimension_requires_force_reset() {
+        let repo = Arc::new(MemoryRepo::new());
+        let (state, ai) = configured_state(repo.clone());
+        let state = Arc::new(state);
+
+        let refused = patch_config(State(state.clone()), patch(serde_json::json!({ "config": { "embedding_dim": 16 } }))).await;
+        assert!(matches!(refused, Err(AppError::SafetyGuardError)));
+        assert_eq!(ai.get_config().embedding_dim, 8);
+
+        patch_config(State(state), patch(serde_json::json!({ "config": { "embedding_dim": 16, "similarity_function": "euclidean" }, "force_reset": true })))
+            .await.unwrap();
+        assert_eq!(ai.get_config().embedding_dim, 16);
+        assert_eq!(repo.state().resets, 1);
+        assert_eq!(repo.state().indexes, vec![(16, VectorSimilarity::Euclidean)]);
+    }
+
+    #[tokio::test]
+    async fn a_patch_that_leaves_an_invalid_config_is_rejected() {
+        let (state, ai) = configured_state(Arc::new(MemoryRepo::new()));
+
+        let result = patch_config(State(Arc::new(state)), patch(serde_json::json!({ "config": { "base_url": "no es una url" } }))).await;
+
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+        assert_eq!(ai.get_config().base_url.as_deref(), Some("http://localhost:11434"));
+    }
+
+    #[tokio::test]
+    async fn stale_chunks_are_the_ones_extracted_with_another_prompt_version() {
+        use crate::domain::models::{DocumentInput, ExtractionProvenance, KnowledgeExtraction};
+        let repo = Arc::new(MemoryRepo::new());
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let current = ExtractionProvenance { model: ai.get_config().model_name, prompt_version: ai.extraction_prompt_version() };
+        let outdated = ExtractionProvenance { prompt_version: "v0-balanced".to_string(), ..current.clone() };
+
+        let document_id = uuid::Uuid::new_v4();
+        repo.save_document(document_id, &DocumentInput { name: "muralla.txt".to_string(), ..Default::default() }).await.unwrap();
+        let mut chunks = Vec::new();
+        for provenance in [&current, &outdated, &current, &outdated] {
+            let chunk_id = uuid::Uuid::new_v4();
+            repo.save_chunk(document_id, chunk_id, "La muralla rodea Lugo.", vec![0.1; 8]).await.unwrap();
+            let extraction = KnowledgeExtraction { entities: Vec::new(), relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() };
+            repo.save_graph(chunk_id, extraction, Some(provenance)).await.unwrap();
+            chunks.push(chunk_id.to_string());
+        }
+        let state = Arc::new(AppState::for_tests(repo, ai.clone()));
+
+        let params = StaleChunksParams { check_model: true, include_unversioned: true, limit: None };
+        let report = list_stale_chunks(State(state.clone()), Query(params)).await.unwrap().0;
+        assert_eq!(report.current_prompt_version, ai.extraction_prompt_version());
+        assert_eq!(report.total, 2);
+        let stale: Vec<&str> = report.chunks.iter().map(|c| c.chunk_id.as_str()).collect();
+        assert_eq!(stale, [chunks[1].as_str(), chunks[3].as_str()]);
+        assert_eq!(report.chunks[0].extraction_prompt_version.as_deref(), Some("v0-balanced"));
+        assert_eq!(report.chunks[0].document_id, Some(document_id.to_string()));
+
+        // `limit` recorta la lista pero `total` los cuenta todos
+        let params = StaleChunksParams { check_model: true, include_unversioned: true, limit: Some(1) };
+        let report = list_stale_chunks(State(state), Query(params)).await.unwrap().0;
+        assert_eq!((report.total, report.chunks.len()), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn reset_empties_the_database_and_recreates_the_indexes() {
+        let repo = Arc::new(MemoryRepo::new());
+        let mut state = AppState::for_tests(repo.clone(), Arc::new(MockAIService::new(mock_config(8))));
+        state.ingestion.embedding_slots = vec!["fast:small-embedder:4".parse().unwrap()];
+        let state = Arc::new(state);
+        ingest(&state).await;
+        let stored = {
+            let repo_state = repo.state();
+            repo_state.documents.len() + repo_state.chunks.len()
+        };
+
+        // Sin sesión no se llega aquí: lo cubre session_guard (ver middleware.rs)
+        let Json(body) = reset_database(State(state.clone())).await.unwrap();
+        assert_eq!(body["deleted_nodes"], stored);
+        let repo_state = repo.state();
+        assert_eq!(repo_state.resets, 1);
+        // El reinicio no deja la base sin índice vectorial
+        assert_eq!(repo_state.indexes, vec![(8, VectorSimilarity::Cosine)]);
+        let slots: Vec<&str> = repo_state.slot_indexes.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(slots, ["fast"]);
+    }
+}
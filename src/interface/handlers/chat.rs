@@ -1,47 +1,96 @@
 // FILE: src/interface/handlers/chat.rs
 
-use axum::{Json, extract::State};
-use std::sync::Arc;
-use rig::{
-    completion::Prompt, 
-    providers::openai::{self, OpenAIResponsesExt},
-    client::CompletionClient 
+use axum::{
+    Json, Form,
+    extract::{State, FromRequest, Request},
+    response::{IntoResponse, Response},
 };
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
-use secrecy::ExposeSecret; 
+use std::sync::Arc;
+use reqwest::header::CONTENT_TYPE;
 use crate::domain::{
-    models::{ChatRequest, ChatResponse, SourceReference}, 
+    models::{ChatRequest, ChatResponse, ChatDebugResponse, HybridContext, SourceReference}, 
+    ports::{AIService, with_usage},
     errors::AppError
 };
+use crate::application::retrieval::{retrieve_context, retrieve_multi_query, RetrievalOptions};
+use crate::application::citations::build_footnotes;
+use crate::infrastructure::eval_dataset::EvalRecord;
 use super::admin::AppState;
 
-#[utoipa::path(
-    post,
-    path = "/api/chat",
-    request_body = ChatRequest,
-    responses(
-        (status = 200, description = "Respuesta RAG Estructurada con Fuentes", body = ChatResponse),
-        (status = 500, description = "Error interno")
-    ),
-    tag = "chat"
-)]
-pub async fn chat_handler(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<ChatRequest>,
-) -> Result<Json<ChatResponse>, AppError> {
-    
-    // 1. Obtener lock de lectura del servicio IA
-    let ai_guard = state.ai_service.read().await;
+/// Cuerpo del chat aceptado como JSON o como formulario HTML
+/// (`application/x-www-form-urlencoded`), según la cabecera `Content-Type`.
+pub struct ChatPayload(pub ChatRequest);
 
-    // 2. Generar Embedding de la pregunta del usuario
-    let embedding = ai_guard.generate_embedding(&payload.message).await?;
-    
-    // 3. Recuperación Híbrida en Neo4j (Vector Search + Graph Traversals)
-    // Traemos los top 5 fragmentos más relevantes
-    let hybrid_contexts = state.repo.find_hybrid_context(embedding, 5).await?;
+impl<S: Send + Sync> FromRequest<S> for ChatPayload {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.starts_with("application/x-www-form-urlencoded"));
+
+        if is_form {
+            let Form(payload) = Form::<ChatRequest>::from_request(req, state).await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(payload))
+        } else {
+            let Json(payload) = Json::<ChatRequest>::from_request(req, state).await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(payload))
+        }
+    }
+}
+
+/// Resultado de la fase de recuperación + ensamblado de contexto.
+/// Compartido por `chat_handler` y `chat_debug_handler` para que ambos vean exactamente lo mismo.
+struct AssembledContext {
+    contexts: Vec<HybridContext>,
+    sources: Vec<SourceReference>,
+    system_prompt: String,
+}
+
+async fn assemble_context(
+    state: &AppState,
+    ai: &dyn AIService,
+    request: &ChatRequest,
+) -> Result<AssembledContext, AppError> {
+    // 1-2. Recuperación en Neo4j según la estrategia pedida (vector / keyword / hybrid)
+    // Traemos los `top_k` fragmentos más relevantes (5 si la petición no lo indica)
+    // Con CHAT_MULTI_QUERY_MAX la pregunta se divide antes en facetas (una llamada LLM más)
+    // Con CHAT_CONTEXT_HOPS > 1 cada fragmento trae también entidades a varios saltos
+    let embedding_slot = match request.embedding.as_deref() {
+        Some(name) => Some(state.ingestion.embedding_slots.iter().find(|s| s.name == name).ok_or_else(|| {
+            AppError::ValidationError(format!("Unknown embedding slot '{}' (see EMBEDDING_SLOTS)", name))
+        })?),
+        None => None,
+    };
+    let options = RetrievalOptions {
+        strategy: request.retrieval,
+        limit: request.effective_top_k(),
+        centrality_boost: state.centrality_boost,
+        hops: state.context_hops,
+        min_score: request.min_score,
+        embedding_slot,
+    };
+    let hybrid_contexts = match state.multi_query_max {
+        Some(max_subqueries) => retrieve_multi_query(
+            state.repo.as_ref(),
+            ai,
+            &request.message,
+            options,
+            max_subqueries,
+        ).await?,
+        None => retrieve_context(
+            state.repo.as_ref(),
+            ai,
+            &request.message,
+            options,
+        ).await?,
+    };
     
-    // 4. Construir Contexto Estructurado para el Prompt y para la Respuesta API
-    let mut context_text = String::new();
+    // 3. Construir Contexto Estructurado para el Prompt y para la Respuesta API
+    let mut fragments = Vec::with_capacity(hybrid_contexts.len());
     let mut sources_output = Vec::new();
 
     for (i, ctx) in hybrid_contexts.iter().enumerate() {
@@ -52,7 +101,7 @@ pub async fn chat_handler(
         let entity_list = ctx.connected_entities.join(", ");
         
         // Texto que leerá el LLM
-        context_text.push_str(&format!(
+        fragments.push(format!(
             "FUENTE [{}]:\n- Contenido: {}\n- Conceptos Relacionados: [{}]\n\n", 
             idx, clean_content, entity_list
         ));
@@ -67,13 +116,20 @@ pub async fn chat_handler(
             } else {
                 clean_content.clone()
             },
-            // Simulación de relevancia (en un sistema real vendría del score vectorial)
-            relevance: 1.0 - (i as f32 * 0.1), 
+            // Relevancia real: similitud del índice vectorial
+            relevance: ctx.score as f32, 
             concepts: ctx.connected_entities.clone(),
         });
     }
 
-    // 5. Construcción del System Prompt
+    // Los números [n] siguen la relevancia; CHAT_CONTEXT_ORDER solo cambia dónde aparece cada fuente
+    let context_text: String = state.context_order
+        .arrange(fragments.len())
+        .into_iter()
+        .map(|i| fragments[i].as_str())
+        .collect();
+
+    // 4. Construcción del System Prompt
     // Es CRÍTICO instruir al modelo sobre cómo citar.
     let system_prompt = format!(
         r#"Eres 'La Muralla', un asistente de inteligencia cognitiva avanzado que responde basándose en un Grafo de Conocimiento.
@@ -93,40 +149,378 @@ pub async fn chat_handler(
         context_text
     );
 
-    // 6. Configuración dinámica del cliente LLM (Rig + Reqwest)
-    let config = ai_guard.get_config(); 
-    let base_url = config.base_url.as_deref().unwrap_or("https://api.openai.com/v1");
-    let api_key = config.api_key.expose_secret();
+    Ok(AssembledContext {
+        contexts: hybrid_contexts,
+        sources: sources_output,
+        system_prompt,
+    })
+}
 
-    // Construcción manual del cliente HTTP para asegurar headers correctos
-    let mut headers = HeaderMap::new();
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    
-    if !api_key.is_empty() {
-        if let Ok(mut val) = HeaderValue::from_str(&format!("Bearer {}", api_key)) {
-            val.set_sensitive(true);
-            headers.insert(AUTHORIZATION, val);
+#[utoipa::path(
+    post,
+    path = "/api/chat",
+    request_body(content(
+        (ChatRequest = "application/json"),
+        (ChatRequest = "application/x-www-form-urlencoded")
+    )),
+    responses(
+        (status = 200, description = "Respuesta RAG Estructurada con Fuentes (`has_answer=false` si no hay contexto relevante)", body = ChatResponse),
+        (status = 500, description = "Error interno")
+    ),
+    tag = "chat"
+)]
+pub async fn chat_handler(
+    State(state): State<Arc<AppState>>,
+    ChatPayload(payload): ChatPayload,
+) -> Result<Json<ChatResponse>, AppError> {
+    // Tokens de todas las llamadas de la petición (sub-consultas incluidas)
+    let (response, usage) = with_usage(answer_chat(&state, payload)).await;
+    let mut response = response?;
+    if state.include_token_usage {
+        response.usage = usage;
+    }
+    Ok(Json(response))
+}
+
+async fn answer_chat(state: &AppState, payload: ChatRequest) -> Result<ChatResponse, AppError> {
+    // 1-2. Recuperación + ensamblado del contexto (ver `assemble_context`)
+    let assembled = assemble_context(state, state.ai_service.as_ref(), &payload).await?;
+
+    // Sin contexto útil el modelo solo puede inventar: respuesta tipada sin llamar al LLM
+    if let Some(threshold) = state.no_answer_threshold {
+        if assembled.sources.iter().all(|s| s.relevance < threshold) {
+            tracing::info!("🤷 Chat sin contexto suficiente (umbral {}): no se consulta al LLM", threshold);
+            record_eval(state, &payload, &assembled, "", false).await;
+            return Ok(ChatResponse {
+                has_answer: false,
+                response: String::new(),
+                sources: assembled.sources,
+                footnotes: payload.footnotes.then(Vec::new),
+                usage: None,
+            });
         }
     }
 
-    let client = openai::Client::from_parts(
-        base_url.to_string(),
-        headers,
-        reqwest::Client::new(),
-        OpenAIResponsesExt,
-    );
+    // 3-4. Generación de respuesta (mismo cliente, auditoría y circuit breaker que el resto de llamadas IA)
+    let answer = state.ai_service.generate_answer(&assembled.system_prompt, &payload.recent_history(), &payload.message).await?;
 
-    // 7. Generación de respuesta
-    let agent = client.agent(&config.model_name)
-        .preamble(&system_prompt)
-        .build();
+    record_eval(state, &payload, &assembled, &answer, true).await;
 
-    let answer = agent.prompt(&payload.message).await
-        .map_err(|e| AppError::AIError(format!("Error generando respuesta LLM: {}", e)))?;
+    // 5. Notas al pie: cada [n] de la respuesta -> chunk de origen
+    let footnotes = payload.footnotes.then(|| build_footnotes(&answer, &assembled.sources));
 
-    // 8. Retorno estructurado
-    Ok(Json(ChatResponse {
+    // 6. Retorno estructurado
+    Ok(ChatResponse {
+        has_answer: true,
         response: answer,
-        sources: sources_output,
+        sources: assembled.sources,
+        footnotes,
+        usage: None,
+    })
+}
+
+/// Añade la tupla pregunta / contextos / prompt / respuesta al dataset de evaluación, si está activo.
+/// Un fallo de escritura no debe tumbar la respuesta al usuario: solo se avisa.
+async fn record_eval(state: &AppState, request: &ChatRequest, assembled: &AssembledContext, answer: &str, has_answer: bool) {
+    let Some(log) = &state.eval_dataset else { return };
+    let record = EvalRecord::new(
+        &request.message,
+        request.recent_history(),
+        request.retrieval,
+        &assembled.contexts,
+        &assembled.system_prompt,
+        answer,
+        has_answer,
+    );
+    if let Err(e) = log.append(&record).await {
+        tracing::warn!("⚠️ No se pudo escribir en el dataset de evaluación {}: {}", log.path().display(), e);
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chat/debug",
+    request_body(content(
+        (ChatRequest = "application/json"),
+        (ChatRequest = "application/x-www-form-urlencoded")
+    )),
+    responses(
+        (status = 200, description = "Contexto recuperado y system prompt (sin llamar al LLM)", body = ChatDebugResponse),
+        (status = 500, description = "Error interno")
+    ),
+    tag = "chat"
+)]
+pub async fn chat_debug_handler(
+    State(state): State<Arc<AppState>>,
+    ChatPayload(payload): ChatPayload,
+) -> Result<Json<ChatDebugResponse>, AppError> {
+    let assembled = assemble_context(&state, state.ai_service.as_ref(), &payload).await?;
+
+    Ok(Json(ChatDebugResponse {
+        contexts: assembled.contexts,
+        system_prompt: assembled.system_prompt,
     }))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::{Request as HttpRequest, StatusCode}, response::Response, routing::post};
+    use tower::ServiceExt;
+    use crate::application::retrieval::ContextOrder;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    fn context(chunk_id: &str, content: &str, score: f64) -> HybridContext {
+        HybridContext {
+            chunk_id: chunk_id.to_string(),
+            content: content.to_string(),
+            connected_entities: vec!["La Muralla".to_string()],
+            score,
+            entity_centrality: 0.0,
+        }
+    }
+
+    async fn read_json(response: Response) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn debug_returns_contexts_and_prompt_without_completion_call() {
+        let repo = Arc::new(MemoryRepo::new().with_contexts(vec![
+            context("chunk-1", "La muralla mide\n5 km.", 0.91),
+            context("chunk-2", "Se construyó en el siglo XIV.", 0.42),
+        ]));
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let router = Router::new()
+            .route("/api/chat/debug", post(chat_debug_handler))
+            .with_state(Arc::new(AppState::for_tests(repo, ai.clone())));
+
+        let response = router.oneshot(HttpRequest::post("/api/chat/debug")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({ "message": "¿Cuánto mide la muralla?", "retrieval": "vector" }).to_string()))
+            .unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_json(response).await;
+
+        assert_eq!(body["contexts"][0]["chunk_id"], "chunk-1");
+        assert_eq!(body["contexts"][0]["score"], 0.91);
+        assert_eq!(body["contexts"][1]["chunk_id"], "chunk-2");
+        let prompt = body["system_prompt"].as_str().unwrap();
+        assert!(prompt.contains("FUENTE [1]:\n- Contenido: La muralla mide 5 km.\n- Conceptos Relacionados: [La Muralla]"));
+        assert!(prompt.contains("FUENTE [2]:\n- Contenido: Se construyó en el siglo XIV."));
+
+        // Solo el embedding de la consulta: ninguna llamada de completado
+        assert_eq!(ai.embedding_calls(), 1);
+        assert_eq!(ai.completion_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn the_context_order_moves_fragments_without_renumbering_them() {
+        let repo = Arc::new(MemoryRepo::new().with_contexts(vec![
+            context("chunk-1", "Primero.", 0.9),
+            context("chunk-2", "Segundo.", 0.8),
+            context("chunk-3", "Tercero.", 0.7),
+        ]));
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let state = AppState { context_order: ContextOrder::EdgesFirst, ..AppState::for_tests(repo, ai.clone()) };
+        let request: ChatRequest = serde_json::from_value(serde_json::json!({ "message": "muralla", "retrieval": "vector" })).unwrap();
+
+        let assembled = assemble_context(&state, ai.as_ref(), &request).await.unwrap();
+
+        let position = |label: &str| assembled.system_prompt.find(label).unwrap();
+        assert!(position("FUENTE [1]") < position("FUENTE [3]"));
+        assert!(position("FUENTE [3]") < position("FUENTE [2]"));
+        assert!(assembled.system_prompt.contains("FUENTE [3]:\n- Contenido: Tercero."));
+    }
+
+    fn debug_app() -> Router {
+        let repo = Arc::new(MemoryRepo::new().with_contexts(vec![context("chunk-1", "La muralla mide 5 km.", 0.91)]));
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        Router::new()
+            .route("/api/chat/debug", post(chat_debug_handler))
+            .with_state(Arc::new(AppState::for_tests(repo, ai)))
+    }
+
+    #[tokio::test]
+    async fn chat_accepts_the_same_query_as_json_and_as_form() {
+        let router = debug_app();
+        let as_json = HttpRequest::post("/api/chat/debug")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({ "message": "¿Cuánto mide la muralla?" }).to_string()))
+            .unwrap();
+        let as_form = HttpRequest::post("/api/chat/debug")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded; charset=utf-8")
+            .body(Body::from("message=%C2%BFCu%C3%A1nto+mide+la+muralla%3F"))
+            .unwrap();
+
+        let mut prompts = Vec::new();
+        for request in [as_json, as_form] {
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = read_json(response).await;
+            assert_eq!(body["contexts"][0]["chunk_id"], "chunk-1");
+            prompts.push(body["system_prompt"].as_str().unwrap().to_string());
+        }
+        assert_eq!(prompts[0], prompts[1]);
+    }
+
+    #[tokio::test]
+    async fn chat_rejects_an_unsupported_body() {
+        let response = debug_app().oneshot(
+            HttpRequest::post("/api/chat/debug").header(CONTENT_TYPE, "text/plain").body(Body::from("hola")).unwrap()
+        ).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn below_the_threshold_chat_answers_has_answer_false_without_calling_the_llm() {
+        let repo = Arc::new(MemoryRepo::new().with_contexts(vec![context("chunk-1", "Horario del museo.", 0.31)]));
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let mut state = AppState::for_tests(repo, ai.clone());
+        state.no_answer_threshold = Some(0.5);
+        let router = Router::new().route("/api/chat", post(chat_handler)).with_state(Arc::new(state));
+
+        let response = router.oneshot(HttpRequest::post("/api/chat")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({ "message": "¿Cuánto mide la muralla?", "retrieval": "vector", "footnotes": true }).to_string()))
+            .unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_json(response).await;
+
+        assert_eq!(body["has_answer"], false);
+        assert_eq!(body["response"], "");
+        assert_eq!(body["sources"][0]["chunk_id"], "chunk-1");
+        assert_eq!(body["footnotes"], serde_json::json!([]));
+        assert_eq!(ai.completion_calls(), 0);
+    }
+
+    #[test]
+    fn responses_without_has_answer_default_to_true() {
+        let response: ChatResponse = serde_json::from_value(serde_json::json!({ "response": "Sí [1]", "sources": [] })).unwrap();
+        assert!(response.has_answer);
+    }
+
+    #[tokio::test]
+    async fn chat_answers_through_the_ai_service() {
+        let repo = Arc::new(MemoryRepo::new().with_contexts(vec![context("chunk-1", "La muralla mide 2 km.", 0.9)]));
+        let ai = Arc::new(MockAIService::new(mock_config(8)).with_answer("¿Cuánto mide la muralla?", "Unos 2 km [1]."));
+        let router = Router::new()
+            .route("/api/chat", post(chat_handler))
+            .with_state(Arc::new(AppState::for_tests(repo, ai.clone())));
+
+        let response = router.oneshot(HttpRequest::post("/api/chat")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({ "message": "¿Cuánto mide la muralla?", "retrieval": "vector", "footnotes": true }).to_string()))
+            .unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_json(response).await;
+
+        assert_eq!(body["has_answer"], true);
+        assert_eq!(body["response"], "Unos 2 km [1].");
+        assert_eq!(body["footnotes"][0]["chunk_id"], "chunk-1");
+        assert_eq!(ai.completion_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn every_response_is_appended_to_the_evaluation_dataset() {
+        let path = std::env::temp_dir().join(format!("chat-eval-{}.jsonl", uuid::Uuid::new_v4()));
+        let repo = Arc::new(MemoryRepo::new().with_contexts(vec![context("chunk-1", "La muralla mide 2 km.", 0.9)]));
+        let ai = Arc::new(MockAIService::new(mock_config(8)).with_answer("¿Cuánto mide la muralla?", "Unos 2 km [1]."));
+        let log = Arc::new(crate::infrastructure::eval_dataset::EvalDatasetLog::new(&path));
+        let mut state = AppState::for_tests(repo.clone(), ai.clone());
+        state.eval_dataset = Some(log.clone());
+        let mut strict = AppState::for_tests(repo, ai);
+        strict.eval_dataset = Some(log);
+        strict.no_answer_threshold = Some(0.95);
+        let ask = |state: Arc<AppState>, body: serde_json::Value| async move {
+            let router = Router::new().route("/api/chat", post(chat_handler)).with_state(state);
+            let response = router.oneshot(HttpRequest::post("/api/chat")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+
+        ask(Arc::new(state), serde_json::json!({
+            "message": "¿Cuánto mide la muralla?",
+            "retrieval": "vector",
+            "history": [{ "role": "user", "content": "Hola" }, { "role": "assistant", "content": "¿En qué te ayudo?" }]
+        })).await;
+        ask(Arc::new(strict), serde_json::json!({ "message": "¿Y la catedral?", "retrieval": "vector" })).await;
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["question"], "¿Cuánto mide la muralla?");
+        assert_eq!(lines[0]["contexts"], serde_json::json!(["La muralla mide 2 km."]));
+        assert_eq!(lines[0]["retrieved"][0]["chunk_id"], "chunk-1");
+        assert_eq!(lines[0]["history"][1]["role"], "assistant");
+        assert_eq!(lines[0]["answer"], "Unos 2 km [1].");
+        assert_eq!(lines[0]["has_answer"], true);
+        assert_eq!(lines[1]["answer"], "");
+        assert_eq!(lines[1]["has_answer"], false);
+    }
+
+    #[tokio::test]
+    async fn the_requested_embedding_slot_drives_vector_retrieval() {
+        let repo = Arc::new(MemoryRepo::new().with_contexts(vec![context("chunk-1", "La muralla mide 2 km.", 0.9)]));
+        let mut state = AppState::for_tests(repo.clone(), Arc::new(MockAIService::new(mock_config(8))));
+        state.ingestion.embedding_slots = vec![
+            "fast:small-embedder:4".parse().unwrap(),
+            "quality:large-embedder:16".parse().unwrap(),
+        ];
+        let router = Router::new().route("/api/chat/debug", post(chat_debug_handler)).with_state(Arc::new(state));
+        let ask = |body: serde_json::Value| router.clone().oneshot(HttpRequest::post("/api/chat/debug")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap());
+
+        for slot in [None, Some("fast"), Some("quality")] {
+            let response = ask(serde_json::json!({ "message": "¿Cuánto mide?", "retrieval": "vector", "embedding": slot })).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        // Cada consulta se vectoriza con el modelo del slot y busca en su índice
+        assert_eq!(repo.state().vector_queries, vec![
+            (8, None),
+            (4, Some("fast".to_string())),
+            (16, Some("quality".to_string())),
+        ]);
+
+        let response = ask(serde_json::json!({ "message": "¿Cuánto mide?", "embedding": "unknown" })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn token_usage_is_returned_only_when_enabled() {
+        for include_token_usage in [false, true] {
+            let repo = Arc::new(MemoryRepo::new().with_contexts(vec![context("chunk-1", "La muralla mide 2 km.", 0.9)]));
+            let mut state = AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))));
+            state.include_token_usage = include_token_usage;
+
+            let router = Router::new().route("/api/chat", post(chat_handler)).with_state(Arc::new(state));
+            let response = router.oneshot(HttpRequest::post("/api/chat")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::json!({ "message": "¿Cuánto mide la muralla?" }).to_string()))
+                .unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = read_json(response).await;
+
+            if !include_token_usage {
+                assert!(body["usage"].is_null(), "{}", body);
+                continue;
+            }
+            // El mock cuenta una palabra por token
+            let usage = &body["usage"];
+            let answer_words = body["response"].as_str().unwrap().split_whitespace().count() as u64;
+            assert_eq!(usage["completion_tokens"], answer_words);
+            assert!(usage["prompt_tokens"].as_u64().unwrap() > 0);
+            assert_eq!(usage["total_tokens"].as_u64().unwrap(), usage["prompt_tokens"].as_u64().unwrap() + answer_words);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use axum::{Json, extract::{State, Path, Query}};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::domain::{models::{DocumentDeletion, DocumentSummary, GraphDataResponse}, errors::AppError};
+use super::admin::AppState;
+
+#[utoipa::path(
+    get,
+    path = "/api/documents",
+    params(
+        ("filter" = Option<HashMap<String, String>>, Query, style = Form, explode,
+         description = "Cada par clave=valor filtra por metadatos (ej. ?author=X&tags=legal)")
+    ),
+    responses(
+        (status = 200, description = "Documentos ingestados que cumplen todos los filtros", body = Vec<DocumentSummary>),
+        (status = 500, description = "Database error")
+    ),
+    tag = "documents"
+)]
+pub async fn list_documents(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<DocumentSummary>>, AppError> {
+    let documents = state.repo.list_documents(&filter).await?;
+    Ok(Json(documents))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/documents/{id}",
+    params(("id" = String, Path, description = "ID del documento (devuelto por la ingesta)")),
+    responses(
+        (status = 200, description = "Documento, chunks y entidades que solo él mencionaba, borrados", body = DocumentDeletion),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 404, description = "Documento no encontrado"),
+        (status = 500, description = "Database error")
+    ),
+    tag = "documents"
+)]
+pub async fn delete_document(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DocumentDeletion>, AppError> {
+    let deletion = state.repo.delete_document(id).await?;
+    tracing::info!(
+        "🗑️ Documento {} borrado: {} chunks, {} entidades huérfanas",
+        deletion.document_id, deletion.chunks_deleted, deletion.entities_deleted
+    );
+    Ok(Json(deletion))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/documents/{id}/graph",
+    params(("id" = String, Path, description = "ID del documento (devuelto por la ingesta)")),
+    responses(
+        (status = 200, description = "Entidades mencionadas por el documento y las relaciones entre ellas", body = GraphDataResponse),
+        (status = 404, description = "Documento no encontrado"),
+        (status = 500, description = "Database error")
+    ),
+    tag = "documents"
+)]
+pub async fn get_document_graph(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<GraphDataResponse>, AppError> {
+    Ok(Json(state.repo.get_document_graph(id).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::{Request, StatusCode}, routing::{delete, get}};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+    use crate::domain::{models::{DocumentInput, GraphEntity, GraphRelation, KnowledgeExtraction}, ports::KGRepository};
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    async fn names(uri: &str) -> Vec<String> {
+        let repo = Arc::new(MemoryRepo::new());
+        for (name, metadata) in [
+            ("contrato.pdf", serde_json::json!({ "author": "Ana", "tags": ["legal", "2024"] })),
+            ("informe.pdf", serde_json::json!({ "author": "Luis", "tags": "legal" })),
+            ("notas.txt", serde_json::json!({ "author": "Ana", "year": 2023 })),
+        ] {
+            let document = DocumentInput { name: name.to_string(), metadata: serde_json::from_value(metadata).unwrap(), ..Default::default() };
+            repo.save_document(Uuid::new_v4(), &document).await.unwrap();
+        }
+        let router = Router::new()
+            .route("/api/documents", get(list_documents))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let response = router.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let documents: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let mut names: Vec<String> = documents.as_array().unwrap().iter()
+            .map(|d| d["name"].as_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn documents_are_filtered_by_every_metadata_pair() {
+        assert_eq!(names("/api/documents").await, ["contrato.pdf", "informe.pdf", "notas.txt"]);
+        assert_eq!(names("/api/documents?tags=legal").await, ["contrato.pdf", "informe.pdf"]);
+        assert_eq!(names("/api/documents?author=Ana&tags=legal").await, ["contrato.pdf"]);
+        assert_eq!(names("/api/documents?year=2023").await, ["notas.txt"]);
+        assert!(names("/api/documents?author=Nadie").await.is_empty());
+    }
+
+    /// Guarda un documento de un chunk que menciona `entities`.
+    async fn ingest(repo: &MemoryRepo, name: &str, entities: &[&str]) -> Uuid {
+        let (document_id, chunk_id) = (Uuid::new_v4(), Uuid::new_v4());
+        repo.save_document(document_id, &DocumentInput { name: name.to_string(), ..Default::default() }).await.unwrap();
+        repo.save_chunk(document_id, chunk_id, "La muralla de Lugo", vec![0.1; 8]).await.unwrap();
+        let entities = entities.iter()
+            .map(|name| GraphEntity { name: name.to_string(), category: "Place".to_string(), attributes: Default::default() })
+            .collect();
+        repo.save_graph(chunk_id, KnowledgeExtraction { entities, relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+        document_id
+    }
+
+    #[tokio::test]
+    async fn deleting_a_document_removes_its_chunks_and_only_its_own_entities() {
+        let repo = Arc::new(MemoryRepo::new());
+        let deleted = ingest(&repo, "muralla.txt", &["Muralla", "Lugo"]).await;
+        let kept = ingest(&repo, "lugo.txt", &["Lugo"]).await;
+        let router = Router::new()
+            .route("/api/documents/{id}", delete(delete_document))
+            .with_state(Arc::new(AppState::for_tests(repo.clone(), Arc::new(MockAIService::new(mock_config(8))))));
+        let uri = format!("/api/documents/{}", deleted);
+
+        let response = router.clone().oneshot(Request::delete(&uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let deletion: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(deletion, serde_json::json!({ "document_id": deleted.to_string(), "chunks_deleted": 1, "entities_deleted": 1 }));
+
+        {
+            let state = repo.state();
+            assert_eq!(state.documents.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![kept]);
+            assert!(state.chunks.iter().all(|c| c.document_id == kept));
+            assert_eq!(state.graphs.len(), 1);
+        }
+
+        // Borrado dos veces: ya no existe
+        let response = router.oneshot(Request::delete(&uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn a_document_graph_holds_its_entities_and_only_the_relations_among_them() {
+        let repo = Arc::new(MemoryRepo::new());
+        let muralla = ingest(&repo, "muralla.txt", &["Muralla", "Lugo"]).await;
+        ingest(&repo, "catedral.txt", &["Catedral", "Lugo"]).await;
+        let relation = |source: &str, target: &str| GraphRelation { source: source.to_string(), target: target.to_string(), relation_type: "LOCATED_IN".to_string(), confidence: None };
+        let relations = vec![relation("Muralla", "Lugo"), relation("Catedral", "Lugo")];
+        repo.save_graph(Uuid::new_v4(), KnowledgeExtraction { entities: Vec::new(), relations, events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+        let router = Router::new()
+            .route("/api/documents/{id}/graph", get(get_document_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let response = router.clone().oneshot(Request::get(format!("/api/documents/{}/graph", muralla)).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let nodes: Vec<&str> = graph["nodes"].as_array().unwrap().iter().map(|n| n["id"].as_str().unwrap()).collect();
+        assert_eq!(nodes, ["Muralla", "Lugo"]);
+        assert_eq!(graph["edges"].as_array().unwrap().len(), 1);
+        assert_eq!(graph["edges"][0]["from"], "Muralla");
+
+        let response = router.oneshot(Request::get(format!("/api/documents/{}/graph", Uuid::new_v4())).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,363 @@
+use axum::{
+    Json,
+    extract::{State, Path, Query},
+    body::{Body, Bytes},
+    http::header,
+    response::IntoResponse,
+};
+use std::sync::Arc;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use validator::Validate;
+use crate::application::entity_resolution::{EntityResolutionService, DEFAULT_DEDUP_THRESHOLD, DEFAULT_DEDUP_LIMIT};
+use crate::domain::{
+    models::{DedupParams, EntityChunk, EntityChunksParams, EntityExportParams, EntityExportRow, EntityMatch, EntitySearchParams, MergeProposal, MergeEntitiesRequest, DEFAULT_ENTITY_SEARCH_LIMIT, MAX_ENTITY_SEARCH_LIMIT},
+    errors::AppError
+};
+use super::admin::AppState;
+
+#[utoipa::path(
+    post,
+    path = "/api/entities/dedup",
+    params(DedupParams),
+    responses(
+        (status = 200, description = "Propuestas de fusión por similitud semántica (para revisión)", body = Vec<MergeProposal>),
+        (status = 400, description = "Umbral fuera de rango"),
+        (status = 500, description = "Error interno")
+    ),
+    tag = "entities"
+)]
+pub async fn propose_entity_merges(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DedupParams>,
+) -> Result<Json<Vec<MergeProposal>>, AppError> {
+    let threshold = params.threshold.unwrap_or(DEFAULT_DEDUP_THRESHOLD);
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(AppError::ValidationError("threshold must be between 0.0 and 1.0".to_string()));
+    }
+
+    let service = EntityResolutionService::new(state.repo.clone(), state.ai_service.clone());
+    let proposals = service.propose_merges(threshold, params.limit.unwrap_or(DEFAULT_DEDUP_LIMIT)).await?;
+
+    Ok(Json(proposals))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/entities/merge",
+    request_body = MergeEntitiesRequest,
+    responses(
+        (status = 200, description = "Entidades fusionadas en la canónica"),
+        (status = 400, description = "Petición inválida o entidad canónica inexistente"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 500, description = "Error interno")
+    ),
+    tag = "entities"
+)]
+pub async fn merge_entities(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<MergeEntitiesRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    payload.validate().map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let merged = state.repo.merge_entities(&payload.canonical, &payload.duplicates).await?;
+
+    Ok(Json(json!({
+        "canonical": payload.canonical,
+        "merged": merged
+    })))
+}
+
+/// Campo CSV entrecomillado solo cuando hace falta (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_line(row: &EntityExportRow) -> String {
+    format!(
+        "{},{},{},{}\n",
+        csv_field(&row.name),
+        csv_field(&row.category),
+        row.degree,
+        row.centrality.map(|c| c.to_string()).unwrap_or_default()
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/entities/export",
+    params(EntityExportParams),
+    responses(
+        (status = 200, description = "CSV name,category,degree,centrality (en streaming)", content_type = "text/csv", body = String)
+    ),
+    tag = "entities"
+)]
+pub async fn export_entities(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EntityExportParams>,
+) -> impl IntoResponse {
+    let (out_tx, out_rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(64);
+
+    tokio::spawn(async move {
+        if out_tx.send(Ok(Bytes::from_static(b"name,category,degree,centrality\n"))).await.is_err() {
+            return;
+        }
+
+        // Las filas pasan del cursor de Neo4j al cuerpo de la respuesta sin acumularse
+        // (si el cliente se va, al soltar `row_rx` el repositorio deja de leer)
+        let (row_tx, mut row_rx) = mpsc::channel::<EntityExportRow>(256);
+        let body_tx = out_tx.clone();
+        let forward = async move {
+            while let Some(row) = row_rx.recv().await {
+                if body_tx.send(Ok(Bytes::from(csv_line(&row)))).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (result, _) = tokio::join!(state.repo.stream_entities(params.limit, row_tx), forward);
+
+        match result {
+            Ok(count) => tracing::info!("📤 Exportadas {} entidades (CSV)", count),
+            Err(e) => {
+                // Un error a mitad de descarga corta el cuerpo: el cliente no recibe un CSV incompleto como válido
+                tracing::error!("❌ Entity export failed: {}", e);
+                let _ = out_tx.send(Err(std::io::Error::other(e.to_string()))).await;
+            }
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"entities.csv\""),
+        ],
+        Body::from_stream(ReceiverStream::new(out_rx)),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/entities/search",
+    params(EntitySearchParams),
+    responses(
+        (status = 200, description = "Entidades cuyo nombre coincide, de mayor a menor grado (vacío si `q` está vacío)", body = Vec<EntityMatch>),
+        (status = 500, description = "Database error")
+    ),
+    tag = "entities"
+)]
+pub async fn search_entities(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EntitySearchParams>,
+) -> Result<Json<Vec<EntityMatch>>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_ENTITY_SEARCH_LIMIT).clamp(1, MAX_ENTITY_SEARCH_LIMIT);
+    Ok(Json(state.repo.search_entities(&params.q, limit).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/entities/{name}/chunks",
+    params(
+        ("name" = String, Path, description = "Nombre exacto de la entidad"),
+        EntityChunksParams
+    ),
+    responses(
+        (status = 200, description = "Fragmentos de origen que mencionan la entidad (vacío si no existe)", body = Vec<EntityChunk>),
+        (status = 500, description = "Database error")
+    ),
+    tag = "entities"
+)]
+pub async fn get_entity_chunks(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(params): Query<EntityChunksParams>,
+) -> Result<Json<Vec<EntityChunk>>, AppError> {
+    let chunks = state.repo.find_chunks_by_entity(&name, params.limit.unwrap_or(50)).await?;
+    Ok(Json(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::{Request, StatusCode, header::CONTENT_TYPE}, routing::{get, post}};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+    use crate::domain::{models::{GraphEntity, GraphFilter, GraphRelation, KnowledgeExtraction}, ports::KGRepository};
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+    use crate::interface::handlers::graph;
+
+    fn app(repo: Arc<MemoryRepo>) -> Router {
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        Router::new()
+            .route("/api/entities/merge", post(merge_entities))
+            .route("/api/graph", get(graph::get_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, ai)))
+    }
+
+    fn merge_request(body: serde_json::Value) -> Request<Body> {
+        Request::post("/api/entities/merge")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// "Coche" y "Automóvil" afirman la misma relación con "Rueda" desde chunks que se solapan.
+    async fn seeded_repo() -> (Arc<MemoryRepo>, [Uuid; 3]) {
+        let repo = Arc::new(MemoryRepo::new());
+        let chunks = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let has_part = |source: &str| KnowledgeExtraction {
+            entities: [source, "Rueda"].iter()
+                .map(|name| GraphEntity { name: name.to_string(), category: "Concept".to_string(), attributes: Default::default() })
+                .collect(),
+            relations: vec![GraphRelation { source: source.to_string(), target: "Rueda".to_string(), relation_type: "HAS_PART".to_string(), confidence: None }],
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        };
+        repo.save_graph(chunks[0], has_part("Coche"), None).await.unwrap();
+        repo.save_graph(chunks[1], has_part("Coche"), None).await.unwrap();
+        repo.save_graph(chunks[1], has_part("Automóvil"), None).await.unwrap();
+        repo.save_graph(chunks[2], has_part("Automóvil"), None).await.unwrap();
+        (repo, chunks)
+    }
+
+    #[tokio::test]
+    async fn merge_moves_relations_to_the_canonical_and_combines_their_sources() {
+        let (repo, chunks) = seeded_repo().await;
+
+        let response = app(repo.clone()).oneshot(merge_request(serde_json::json!({
+            "canonical": "Coche",
+            "duplicates": ["Automóvil"]
+        }))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["merged"], 1);
+
+        let graph = repo.get_full_graph(&GraphFilter::default()).await.unwrap();
+        assert!(graph.nodes.iter().all(|n| n.id != "Automóvil"));
+        assert_eq!(graph.edges.len(), 1);
+        let mut sources = graph.edges[0].sources.clone();
+        sources.sort();
+        let mut expected: Vec<String> = chunks.iter().map(Uuid::to_string).collect();
+        expected.sort();
+        assert_eq!(sources, expected);
+    }
+
+    #[tokio::test]
+    async fn merge_rejects_an_unknown_canonical_and_an_empty_list() {
+        let (repo, _) = seeded_repo().await;
+
+        let unknown = app(repo.clone()).oneshot(merge_request(serde_json::json!({
+            "canonical": "Bicicleta",
+            "duplicates": ["Coche"]
+        }))).await.unwrap();
+        assert_eq!(unknown.status(), StatusCode::BAD_REQUEST);
+
+        let empty = app(repo).oneshot(merge_request(serde_json::json!({
+            "canonical": "Coche",
+            "duplicates": []
+        }))).await.unwrap();
+        assert_eq!(empty.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn export(repo: Arc<MemoryRepo>, uri: &str) -> (StatusCode, Option<String>, String) {
+        let response = Router::new()
+            .route("/api/entities/export", get(export_entities))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))))
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await.unwrap();
+        let content_type = response.headers().get(CONTENT_TYPE).map(|v| v.to_str().unwrap().to_string());
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, content_type, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("Lugo"), "Lugo");
+        assert_eq!(csv_field("Lugo, Galicia"), "\"Lugo, Galicia\"");
+        assert_eq!(csv_field("El \"Cristo\""), "\"El \"\"Cristo\"\"\"");
+    }
+
+    #[tokio::test]
+    async fn export_streams_one_csv_row_per_entity() {
+        let (repo, _) = seeded_repo().await;
+        repo.save_centrality(&[("Rueda".to_string(), 0.5)].into_iter().collect()).await.unwrap();
+
+        let (status, content_type, body) = export(repo, "/api/entities/export").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(content_type.unwrap().starts_with("text/csv"));
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines, ["name,category,degree,centrality", "Coche,Concept,1,", "Rueda,Concept,2,0.5", "Automóvil,Concept,1,"]);
+    }
+
+    #[tokio::test]
+    async fn export_honours_the_limit() {
+        let (repo, _) = seeded_repo().await;
+        let (_, _, body) = export(repo, "/api/entities/export?limit=1").await;
+        assert_eq!(body.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn entity_chunks_lists_the_chunks_that_mention_it() {
+        let (repo, chunks) = seeded_repo().await;
+        let document = Uuid::new_v4();
+        for (i, chunk) in chunks.iter().enumerate() {
+            repo.save_chunk(document, *chunk, &format!("Fragmento {}", i), Vec::new()).await.unwrap();
+        }
+        let router = Router::new()
+            .route("/api/entities/{name}/chunks", get(get_entity_chunks))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let get_json = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<Vec<serde_json::Value>>(&bytes).unwrap()
+            }
+        };
+
+        // "Coche" aparece en los chunks 0 y 1; "Automóvil" en el 1 y 2
+        let coche = get_json("/api/entities/Coche/chunks").await;
+        let ids: Vec<&str> = coche.iter().map(|c| c["chunk_id"].as_str().unwrap()).collect();
+        assert_eq!(ids, [chunks[0].to_string(), chunks[1].to_string()]);
+        assert_eq!(coche[0]["document_id"], document.to_string());
+        assert_eq!(coche[0]["content"], "Fragmento 0");
+
+        assert_eq!(get_json("/api/entities/Autom%C3%B3vil/chunks?limit=1").await.len(), 1);
+        assert!(get_json("/api/entities/Bicicleta/chunks").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_matches_word_prefixes_and_ranks_by_degree() {
+        let (repo, _) = seeded_repo().await;
+        let entity = |name: &str| GraphEntity { name: name.to_string(), category: "Concept".to_string(), attributes: Default::default() };
+        let extraction = KnowledgeExtraction { entities: vec![entity("Rueda de repuesto"), entity("Coche eléctrico")], relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() };
+        repo.save_graph(Uuid::new_v4(), extraction, None).await.unwrap();
+        let router = Router::new()
+            .route("/api/entities/search", get(search_entities))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+        let search = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<Vec<serde_json::Value>>(&bytes).unwrap()
+                    .iter().map(|m| m["name"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(search("/api/entities/search?q=rue").await, ["Rueda", "Rueda de repuesto"]);
+        assert_eq!(search("/api/entities/search?q=coche%20ELÉ").await, ["Coche eléctrico"]);
+        assert_eq!(search("/api/entities/search?q=c&limit=1").await, ["Coche"]);
+        assert!(search("/api/entities/search?q=").await.is_empty());
+    }
+}
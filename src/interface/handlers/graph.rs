@@ -1,32 +1,79 @@
-use axum::{Json, extract::{State, Path}};
+use axum::{Json, body::{Body, Bytes}, extract::{State, Path, Query}, http::{header, HeaderValue}, response::{IntoResponse, Response}};
 use std::sync::Arc;
-use crate::domain::{models::GraphDataResponse, errors::AppError};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use crate::domain::{models::{CategoryCount, GraphDataResponse, GraphStats, ExportRecord, GraphDelta, GraphSinceParams, GraphExportFormat, GraphExportParams, GraphFilter, NeighborhoodParams, TimelineEvent}, errors::AppError};
+use crate::application::graph_export::{cypher_preamble, cypher_statement, to_dot};
+use crate::application::timeline::order_events;
+use crate::infrastructure::persistence::neo4j_repo::TEMPORAL_PROPERTIES;
 use super::admin::AppState;
 
 #[utoipa::path(
     get,
     path = "/api/graph",
+    params(GraphFilter),
     responses(
-        (status = 200, description = "Retrieve full graph for visualization", body = GraphDataResponse),
+        (status = 200, description = "Retrieve full graph for visualization (`X-Graph-Stale: true` + `Age` if served from cache)", body = GraphDataResponse),
+        (status = 400, description = "min_confidence fuera de rango"),
         (status = 500, description = "Database error")
     ),
     tag = "visualization"
 )]
 pub async fn get_graph(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<GraphDataResponse>, AppError> {
-    
-    // Llamada al repositorio para el grafo completo
-    let graph_data = state.repo.get_full_graph().await?;
+    Query(filter): Query<GraphFilter>,
+) -> Result<Response, AppError> {
+    if filter.min_confidence.is_some_and(|c| !(0.0..=1.0).contains(&c)) {
+        return Err(AppError::ValidationError("min_confidence must be between 0.0 and 1.0".to_string()));
+    }
     
-    Ok(Json(graph_data))
+    // Llamada al repositorio para el grafo completo (con filtros opcionales)
+    match state.repo.get_full_graph(&filter).await {
+        Ok(graph_data) => {
+            state.graph_cache.store(&filter, &graph_data).await;
+            Ok(Json(graph_data).into_response())
+        },
+        Err(e) => {
+            // Failover: última copia válida, marcada como obsoleta
+            let Some((cached, age)) = state.graph_cache.last_good(&filter).await else {
+                return Err(e);
+            };
+            tracing::warn!("⚠️ Serving cached graph ({}s old) after DB error: {}", age.as_secs(), e);
+
+            let mut response = Json(cached).into_response();
+            let headers = response.headers_mut();
+            headers.insert("X-Graph-Stale", HeaderValue::from_static("true"));
+            headers.insert("Age", HeaderValue::from(age.as_secs()));
+            Ok(response)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/graph/since",
+    params(GraphSinceParams),
+    responses(
+        (status = 200, description = "Nodos y relaciones creados después de `timestamp` (polling incremental)", body = GraphDelta),
+        (status = 500, description = "Database error")
+    ),
+    tag = "visualization"
+)]
+pub async fn get_graph_since(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GraphSinceParams>,
+) -> Result<Json<GraphDelta>, AppError> {
+    // Los datos anteriores a la marca `created_at` no aparecen nunca en el delta
+    let delta = state.repo.get_graph_since(params.timestamp).await?;
+    Ok(Json(delta))
 }
 
 #[utoipa::path(
     get,
     path = "/api/graph/concept/{name}",
     params(
-        ("name" = String, Path, description = "Concept Entity Name to explore")
+        ("name" = String, Path, description = "Concept Entity Name to explore"),
+        NeighborhoodParams
     ),
     responses(
         (status = 200, description = "Sub-graph neighborhood for specific concept", body = GraphDataResponse),
@@ -37,10 +84,614 @@ pub async fn get_graph(
 pub async fn get_concept_neighborhood(
     State(state): State<Arc<AppState>>,
     Path(name): Path<String>,
+    Query(params): Query<NeighborhoodParams>,
 ) -> Result<Json<GraphDataResponse>, AppError> {
     
-    // Llamada al repositorio para obtener el nodo y sus vecinos (Requiere implementación en Repo)
-    let graph_data = state.repo.get_concept_neighborhood(&name).await?;
+    // Llamada al repositorio para obtener el nodo y sus vecinos en el sentido pedido
+    let graph_data = state.repo.get_concept_neighborhood(&name, params.direction).await?;
     
     Ok(Json(graph_data))
-}
\ No newline at end of file
+}
+
+
+/// Script Cypher en streaming: con `include_chunks` (texto de todos los fragmentos) no cabría en memoria.
+fn stream_cypher_export(state: Arc<AppState>, include_chunks: bool) -> ReceiverStream<Result<Bytes, std::io::Error>> {
+    let (out_tx, out_rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(64);
+
+    tokio::spawn(async move {
+        if out_tx.send(Ok(Bytes::from(cypher_preamble(include_chunks)))).await.is_err() {
+            return;
+        }
+
+        let (record_tx, mut record_rx) = mpsc::channel::<ExportRecord>(256);
+        let body_tx = out_tx.clone();
+        let forward = async move {
+            while let Some(record) = record_rx.recv().await {
+                if body_tx.send(Ok(Bytes::from(cypher_statement(&record, TEMPORAL_PROPERTIES)))).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (result, _) = tokio::join!(state.repo.stream_graph_records(include_chunks, record_tx), forward);
+
+        match result {
+            Ok(count) => tracing::info!("📤 Exportados {} elementos del grafo (Cypher, fragmentos: {})", count, include_chunks),
+            Err(e) => {
+                // Cortar el cuerpo: un script a medias no debe parecer una copia completa
+                tracing::error!("❌ Graph export failed: {}", e);
+                let _ = out_tx.send(Err(std::io::Error::other(e.to_string()))).await;
+            }
+        }
+    });
+
+    ReceiverStream::new(out_rx)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/graph/export",
+    params(GraphExportParams, GraphFilter),
+    responses(
+        (status = 200, description = "Grafo serializado (DOT: `dot -Tpng grafo.dot -o grafo.png`; Cypher en streaming: `cypher-shell -f grafo.cypher`)", content_type = "text/vnd.graphviz", body = String),
+        (status = 400, description = "`include_chunks` solo está disponible con `format=cypher`"),
+        (status = 500, description = "Database error")
+    ),
+    tag = "visualization"
+)]
+pub async fn export_graph(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GraphExportParams>,
+    Query(filter): Query<GraphFilter>,
+) -> Result<Response, AppError> {
+    let response = match params.format {
+        GraphExportFormat::Dot if params.include_chunks => {
+            return Err(AppError::ValidationError("include_chunks is only supported with format=cypher".to_string()));
+        },
+        GraphExportFormat::Dot => {
+            let graph_data = state.repo.get_full_graph(&filter).await?;
+            (
+                [(header::CONTENT_TYPE, "text/vnd.graphviz; charset=utf-8")],
+                to_dot(&graph_data),
+            ).into_response()
+        },
+        // Copia completa: ignora los filtros de vista y el límite de /api/graph
+        GraphExportFormat::Cypher => (
+            [
+                (header::CONTENT_TYPE, "application/x-cypher-query; charset=utf-8"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"lamuralla-graph.cypher\""),
+            ],
+            Body::from_stream(stream_cypher_export(state, params.include_chunks)),
+        ).into_response(),
+    };
+
+    Ok(response)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/timeline",
+    responses(
+        (status = 200, description = "Sucesos en orden cronológico (aristas BEFORE y, entre sucesos sin orden, fecha)", body = Vec<TimelineEvent>),
+        (status = 500, description = "Database error")
+    ),
+    tag = "visualization"
+)]
+pub async fn get_timeline(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<TimelineEvent>>, AppError> {
+    let (events, before) = state.repo.get_timeline().await?;
+    Ok(Json(order_events(events, &before)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/categories",
+    responses(
+        (status = 200, description = "Categorías de entidad presentes en el grafo con su número de entidades", body = Vec<CategoryCount>),
+        (status = 500, description = "Database error")
+    ),
+    tag = "visualization"
+)]
+pub async fn list_categories(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<CategoryCount>>, AppError> {
+    Ok(Json(state.repo.list_categories().await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    responses(
+        (status = 200, description = "Recuentos del grafo y entidades con más relaciones", body = GraphStats),
+        (status = 500, description = "Database error")
+    ),
+    tag = "visualization"
+)]
+pub async fn get_stats(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<GraphStats>, AppError> {
+    Ok(Json(state.repo.get_stats().await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::{Request, StatusCode}, routing::get};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+    use crate::domain::{models::{GraphEntity, GraphEvent, GraphRelation, KnowledgeExtraction, TemporalRelation}, ports::KGRepository};
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    fn relation(source: &str, target: &str) -> GraphRelation {
+        GraphRelation { source: source.to_string(), target: target.to_string(), relation_type: "RELATED_TO".to_string(), confidence: None }
+    }
+
+    /// Triángulo Muralla-Lugo-Romanos (grado 2 o más) y una hoja Turista (grado 1).
+    async fn app() -> Router {
+        let repo = Arc::new(MemoryRepo::new());
+        let entities = ["Muralla", "Lugo", "Romanos", "Turista"].iter()
+            .map(|name| GraphEntity { name: name.to_string(), category: "Concept".to_string(), attributes: Default::default() })
+            .collect();
+        let relations = vec![relation("Muralla", "Lugo"), relation("Lugo", "Romanos"), relation("Romanos", "Muralla"), relation("Turista", "Muralla")];
+        repo.save_graph(Uuid::new_v4(), KnowledgeExtraction { entities, relations, events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        Router::new()
+            .route("/api/graph", get(get_graph))
+            .route("/api/graph/concept/{name}", get(get_concept_neighborhood))
+            .route("/api/graph/export", get(export_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, ai)))
+    }
+
+    async fn node_ids(router: Router, uri: &str) -> Vec<String> {
+        let response = router.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let mut ids: Vec<String> = graph["nodes"].as_array().unwrap().iter()
+            .map(|n| n["id"].as_str().unwrap().to_string())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn min_degree_keeps_only_the_backbone() {
+        assert_eq!(node_ids(app().await, "/api/graph").await, ["Lugo", "Muralla", "Romanos", "Turista"]);
+        assert_eq!(node_ids(app().await, "/api/graph?min_degree=2").await, ["Lugo", "Muralla", "Romanos"]);
+    }
+
+    #[tokio::test]
+    async fn edges_list_every_chunk_that_states_the_relation() {
+        let repo = Arc::new(MemoryRepo::new());
+        let extraction = || KnowledgeExtraction {
+            entities: ["Muralla", "Lugo"].iter()
+                .map(|name| GraphEntity { name: name.to_string(), category: "Concept".to_string(), attributes: Default::default() })
+                .collect(),
+            relations: vec![relation("Muralla", "Lugo")],
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        };
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        repo.save_graph(first, extraction(), None).await.unwrap();
+        repo.save_graph(second, extraction(), None).await.unwrap();
+        repo.save_graph(first, extraction(), None).await.unwrap();
+
+        let ai = Arc::new(MockAIService::new(mock_config(8)));
+        let router = Router::new()
+            .route("/api/graph", get(get_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, ai)));
+        let response = router.oneshot(Request::get("/api/graph").body(Body::empty()).unwrap()).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(graph["edges"].as_array().unwrap().len(), 1);
+        assert_eq!(graph["edges"][0]["sources"], serde_json::json!([first.to_string(), second.to_string()]));
+    }
+
+    #[tokio::test]
+    async fn neighborhood_follows_the_requested_direction() {
+        // Muralla -> Lugo; Romanos -> Muralla; Turista -> Muralla
+        assert_eq!(node_ids(app().await, "/api/graph/concept/Muralla?direction=out").await, ["Lugo", "Muralla"]);
+        assert_eq!(node_ids(app().await, "/api/graph/concept/Muralla?direction=in").await, ["Muralla", "Romanos", "Turista"]);
+        assert_eq!(node_ids(app().await, "/api/graph/concept/Muralla").await, ["Lugo", "Muralla", "Romanos", "Turista"]);
+    }
+
+    #[tokio::test]
+    async fn neighborhood_rejects_an_unknown_direction() {
+        let response = app().await
+            .oneshot(Request::get("/api/graph/concept/Muralla?direction=sideways").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn state_with_graph(cache_ttl: Option<std::time::Duration>) -> (Arc<MemoryRepo>, Arc<AppState>) {
+        let repo = Arc::new(MemoryRepo::new());
+        let entity = GraphEntity { name: "Muralla de Lugo".to_string(), category: "Monument".to_string(), attributes: Default::default() };
+        repo.save_graph(Uuid::new_v4(), KnowledgeExtraction { entities: vec![entity], relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+
+        let mut state = AppState::for_tests(repo.clone(), Arc::new(MockAIService::new(mock_config(8))));
+        state.graph_cache = crate::application::graph_cache::GraphCache::new(cache_ttl);
+        (repo, Arc::new(state))
+    }
+
+    async fn response_node_ids(response: Response) -> Vec<String> {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        graph["nodes"].as_array().unwrap().iter().map(|n| n["id"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn a_db_outage_serves_the_last_good_graph_flagged_as_stale() {
+        let (repo, state) = state_with_graph(Some(std::time::Duration::from_secs(60))).await;
+
+        let live = get_graph(State(state.clone()), Query(GraphFilter::default())).await.unwrap();
+        assert!(live.headers().get("X-Graph-Stale").is_none());
+        assert_eq!(response_node_ids(live).await, vec!["Muralla de Lugo"]);
+
+        repo.set_down(true);
+        let cached = get_graph(State(state), Query(GraphFilter::default())).await.unwrap();
+        assert_eq!(cached.headers().get("X-Graph-Stale").unwrap(), "true");
+        assert!(cached.headers().get("Age").is_some());
+        assert_eq!(response_node_ids(cached).await, vec!["Muralla de Lugo"]);
+    }
+
+    #[tokio::test]
+    async fn without_the_cache_a_db_outage_is_an_error() {
+        let (repo, state) = state_with_graph(None).await;
+        get_graph(State(state.clone()), Query(GraphFilter::default())).await.unwrap();
+
+        repo.set_down(true);
+        let result = get_graph(State(state), Query(GraphFilter::default())).await;
+        assert!(matches!(result, Err(AppError::DatabaseError(_))));
+    }
+
+    #[tokio::test]
+    async fn the_cached_copy_expires_with_its_ttl() {
+        let (repo, state) = state_with_graph(Some(std::time::Duration::ZERO)).await;
+        get_graph(State(state.clone()), Query(GraphFilter::default())).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        repo.set_down(true);
+        let result = get_graph(State(state), Query(GraphFilter::default())).await;
+        assert!(matches!(result, Err(AppError::DatabaseError(_))));
+    }
+
+    #[tokio::test]
+    async fn min_confidence_hides_weak_relations_but_keeps_unscored_ones() {
+        let repo = Arc::new(MemoryRepo::new());
+        let entities = ["Muralla", "Lugo", "Romanos", "Turista"].iter()
+            .map(|name| GraphEntity { name: name.to_string(), category: "Concept".to_string(), attributes: Default::default() })
+            .collect();
+        let relations = vec![
+            GraphRelation { confidence: Some(0.9), ..relation("Muralla", "Lugo") },
+            GraphRelation { confidence: Some(0.3), ..relation("Romanos", "Muralla") },
+            relation("Turista", "Muralla"),
+        ];
+        repo.save_graph(Uuid::new_v4(), KnowledgeExtraction { entities, relations, events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+        let router = Router::new()
+            .route("/api/graph", get(get_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let response = router.oneshot(Request::get("/api/graph?min_confidence=0.5").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let edges: Vec<(&str, Option<f64>)> = graph["edges"].as_array().unwrap().iter()
+            .map(|e| (e["from"].as_str().unwrap(), e["confidence"].as_f64()))
+            .collect();
+        assert_eq!(edges, [("Muralla", Some(0.9f32 as f64)), ("Turista", None)]);
+    }
+
+    #[tokio::test]
+    async fn min_confidence_out_of_range_is_rejected() {
+        let response = app().await
+            .oneshot(Request::get("/api/graph?min_confidence=1.5").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn export_serves_dot_with_the_graphviz_content_type() {
+        let response = app().await
+            .oneshot(Request::get("/api/graph/export?format=dot&min_degree=2").body(Body::empty()).unwrap())
+            .await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/vnd.graphviz; charset=utf-8");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let dot = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(dot.contains(r#""Muralla" -> "Lugo""#));
+        assert!(!dot.contains("Turista"));
+    }
+
+    #[tokio::test]
+    async fn export_as_cypher_is_a_full_download_that_ignores_the_view_filters() {
+        let response = app().await
+            .oneshot(Request::get("/api/graph/export?format=cypher&min_degree=2").body(Body::empty()).unwrap())
+            .await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-cypher-query; charset=utf-8");
+        assert!(response.headers()[header::CONTENT_DISPOSITION].to_str().unwrap().contains("lamuralla-graph.cypher"));
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let script = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(script.matches("MERGE (e:Entity").count(), 4);
+        assert_eq!(script.matches("MERGE (a)-[r:`RELATED_TO`]->(b)").count(), 4);
+        assert!(script.contains("MERGE (e:Entity {name: 'Turista'}) SET e += {`category`: 'Concept'};"));
+        assert!(!script.contains("DocumentChunk"));
+    }
+
+    #[tokio::test]
+    async fn include_chunks_adds_documents_chunks_and_mentions() {
+        let repo = Arc::new(MemoryRepo::new());
+        let document_id = Uuid::new_v4();
+        let chunk_id = Uuid::new_v4();
+        repo.save_document(document_id, &crate::domain::models::DocumentInput { name: "muralla.txt".to_string(), ..Default::default() }).await.unwrap();
+        repo.save_chunk(document_id, chunk_id, "La muralla rodea Lugo.", vec![0.1; 8]).await.unwrap();
+        let entity = GraphEntity { name: "Muralla".to_string(), category: "Monument".to_string(), attributes: Default::default() };
+        repo.save_graph(chunk_id, KnowledgeExtraction { entities: vec![entity], relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+        let router = Router::new()
+            .route("/api/graph/export", get(export_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let response = router.clone()
+            .oneshot(Request::get("/api/graph/export?format=cypher&include_chunks=true").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let script = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(script.contains(&format!("MERGE (d:Document {{id: '{}'}}) SET d += {{`name`: 'muralla.txt'}};", document_id)));
+        assert!(script.contains(&format!("MERGE (c:DocumentChunk {{id: '{}'}}) SET c += {{`content`: 'La muralla rodea Lugo.'}}", chunk_id)));
+        assert!(script.contains(&format!("MATCH (c:DocumentChunk {{id: '{}'}}), (e:Entity {{name: 'Muralla'}}) MERGE (c)-[:MENTIONS]->(e);", chunk_id)));
+        assert!(!script.contains("`embedding`"));
+
+        // DOT no tiene fragmentos que exportar
+        let response = router
+            .oneshot(Request::get("/api/graph/export?format=dot&include_chunks=true").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn a_relation_repeated_in_one_extraction_counts_once_per_chunk() {
+        let repo = Arc::new(MemoryRepo::new());
+        let with_relation = |times: usize| KnowledgeExtraction {
+            entities: ["Muralla", "Lugo"].iter()
+                .map(|name| GraphEntity { name: name.to_string(), category: "Place".to_string(), attributes: Default::default() })
+                .collect(),
+            relations: (0..times).map(|_| relation("Muralla", "Lugo")).collect(),
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        };
+        let weight = |repo: Arc<MemoryRepo>| async move {
+            let graph = repo.get_full_graph(&GraphFilter::default()).await.unwrap();
+            assert_eq!(graph.edges.len(), 1);
+            graph.edges[0].sources.len()
+        };
+
+        let first_chunk = Uuid::new_v4();
+        repo.save_graph(first_chunk, with_relation(2), None).await.unwrap();
+        assert_eq!(repo.state().graphs[0].1.relations.len(), 1);
+        assert_eq!(weight(repo.clone()).await, 1);
+
+        // Otro chunk suma uno; guardar de nuevo el mismo chunk no
+        repo.save_graph(Uuid::new_v4(), with_relation(1), None).await.unwrap();
+        assert_eq!(weight(repo.clone()).await, 2);
+        repo.save_graph(first_chunk, with_relation(3), None).await.unwrap();
+        assert_eq!(weight(repo).await, 2);
+    }
+
+    #[tokio::test]
+    async fn since_returns_only_what_was_created_after_the_cursor() {
+        let (repo, state) = state_with_graph(None).await;
+        let before = get_graph_since(State(state.clone()), Query(GraphSinceParams { timestamp: 0 })).await.unwrap().0;
+        assert_eq!(before.nodes.len(), 1);
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        // Nueva entidad Lugo y relación hacia la ya existente Muralla de Lugo
+        let entity = GraphEntity { name: "Lugo".to_string(), category: "City".to_string(), attributes: Default::default() };
+        let relations = vec![relation("Lugo", "Muralla de Lugo")];
+        repo.save_graph(Uuid::new_v4(), KnowledgeExtraction { entities: vec![entity], relations, events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+
+        let delta = get_graph_since(State(state.clone()), Query(GraphSinceParams { timestamp: before.timestamp })).await.unwrap().0;
+        let mut ids: Vec<&str> = delta.nodes.iter().map(|n| n.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, ["Lugo", "Muralla de Lugo"]);
+        assert_eq!(delta.edges.len(), 1);
+        assert_eq!((delta.edges[0].from.as_str(), delta.edges[0].to.as_str()), ("Lugo", "Muralla de Lugo"));
+
+        let empty = get_graph_since(State(state), Query(GraphSinceParams { timestamp: delta.timestamp })).await.unwrap().0;
+        assert!(empty.nodes.is_empty() && empty.edges.is_empty());
+    }
+
+    #[tokio::test]
+    async fn since_requires_a_numeric_timestamp() {
+        let (_, state) = state_with_graph(None).await;
+        let router = Router::new().route("/api/graph/since", get(get_graph_since)).with_state(state);
+        for uri in ["/api/graph/since", "/api/graph/since?timestamp=ayer"] {
+            let response = router.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn timeline_follows_the_before_edges_across_extractions() {
+        let repo = Arc::new(MemoryRepo::new());
+        let lugo = GraphEntity { name: "Lugo".to_string(), category: "City".to_string(), attributes: Default::default() };
+        let event = |name: &str, date: Option<&str>, participants: &[&str]| GraphEvent {
+            name: name.to_string(),
+            date: date.map(str::to_string),
+            participants: participants.iter().map(|p| p.to_string()).collect(),
+        };
+        let first = KnowledgeExtraction {
+            entities: vec![lugo],
+            relations: Vec::new(),
+            events: vec![event("Construcción de la muralla", Some("0263"), &["Lugo", "Desconocida"]), event("Fundación", None, &[])],
+            temporal_relations: vec![TemporalRelation { before: "Fundación".to_string(), after: "Construcción de la muralla".to_string() }],
+        };
+        let second = KnowledgeExtraction {
+            entities: Vec::new(),
+            relations: Vec::new(),
+            events: vec![event("Fundación", Some("-0025"), &[]), event("Patrimonio de la Humanidad", Some("2000"), &[])],
+            temporal_relations: Vec::new(),
+        };
+        repo.save_graph(Uuid::new_v4(), first, None).await.unwrap();
+        repo.save_graph(Uuid::new_v4(), second, None).await.unwrap();
+        let router = Router::new()
+            .route("/api/timeline", get(get_timeline))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let response = router.oneshot(Request::get("/api/timeline").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let timeline: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let names: Vec<&str> = timeline.as_array().unwrap().iter().map(|e| e["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["Fundación", "Construcción de la muralla", "Patrimonio de la Humanidad"]);
+        assert_eq!(timeline[0]["date"], "-0025");
+        // Solo participantes que existen como entidad
+        assert_eq!(timeline[1]["participants"], serde_json::json!(["Lugo"]));
+    }
+
+    #[tokio::test]
+    async fn edges_flag_inferred_relations_with_their_reasoning() {
+        let repo = Arc::new(MemoryRepo::new());
+        let entities = ["Muralla", "Lugo", "Romanos"].iter()
+            .map(|name| GraphEntity { name: name.to_string(), category: "Concept".to_string(), attributes: Default::default() })
+            .collect();
+        let data = KnowledgeExtraction { entities, relations: vec![relation("Muralla", "Lugo")], events: Vec::new(), temporal_relations: Vec::new() };
+        repo.save_graph(Uuid::new_v4(), data, None).await.unwrap();
+        repo.save_inferred_relations(vec![crate::domain::models::InferredRelation {
+            source: "Romanos".to_string(),
+            target: "Lugo".to_string(),
+            relation: "FOUNDED".to_string(),
+            reasoning: "Construyeron su muralla".to_string(),
+            inference_type: None,
+            confidence_level: None,
+            confidence: Some(0.8),
+        }]).await.unwrap();
+        let router = Router::new()
+            .route("/api/graph", get(get_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let response = router.clone().oneshot(Request::get("/api/graph").body(Body::empty()).unwrap()).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let edges = graph["edges"].as_array().unwrap();
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0]["inferred"], false);
+        assert!(edges[0].get("reasoning").is_none());
+        assert_eq!(edges[1]["inferred"], true);
+        assert_eq!(edges[1]["reasoning"], "Construyeron su muralla");
+
+        let response = router.oneshot(Request::get("/api/graph?max_edges=1").body(Body::empty()).unwrap()).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(graph["edges"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn each_limit_is_reported_only_when_it_trims_the_graph() {
+        let repo = Arc::new(MemoryRepo::new());
+        let entities = ["Muralla", "Lugo", "Romanos"].iter()
+            .map(|name| GraphEntity { name: name.to_string(), category: "Concept".to_string(), attributes: Default::default() })
+            .collect();
+        let relations = vec![relation("Muralla", "Lugo"), relation("Romanos", "Lugo")];
+        repo.save_graph(Uuid::new_v4(), KnowledgeExtraction { entities, relations, events: Vec::new(), temporal_relations: Vec::new() }, None).await.unwrap();
+        let router = Router::new()
+            .route("/api/graph", get(get_graph))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+        let graph = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+            }
+        };
+
+        let full = graph("/api/graph").await;
+        assert!(full.get("limits_reached").is_none());
+
+        let by_nodes = graph("/api/graph?max_nodes=1").await;
+        assert_eq!(by_nodes["limits_reached"], serde_json::json!(["max_nodes"]));
+        assert_eq!(by_nodes["nodes"].as_array().unwrap().len(), 1);
+        assert!(by_nodes["edges"].as_array().unwrap().is_empty());
+
+        let by_edges = graph("/api/graph?max_edges=1").await;
+        assert_eq!(by_edges["limits_reached"], serde_json::json!(["max_edges"]));
+        assert_eq!(by_edges["edges"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn categories_are_counted_once_per_entity_most_frequent_first() {
+        let repo = Arc::new(MemoryRepo::new());
+        let entity = |name: &str, category: &str| GraphEntity { name: name.to_string(), category: category.to_string(), attributes: Default::default() };
+        let extraction = |entities| KnowledgeExtraction { entities, relations: Vec::new(), events: Vec::new(), temporal_relations: Vec::new() };
+        repo.save_graph(Uuid::new_v4(), extraction(vec![entity("Lugo", "Place"), entity("Muralla", "Monument"), entity("Catedral", "Monument")]), None).await.unwrap();
+        repo.save_graph(Uuid::new_v4(), extraction(vec![entity("Muralla", "Monument"), entity("Galicia", "Place"), entity("Romanos", "Person")]), None).await.unwrap();
+        let router = Router::new()
+            .route("/api/categories", get(list_categories))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+
+        let response = router.oneshot(Request::get("/api/categories").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let categories: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(categories, serde_json::json!([
+            { "category": "Monument", "count": 2 },
+            { "category": "Place", "count": 2 },
+            { "category": "Person", "count": 1 },
+        ]));
+    }
+
+    #[tokio::test]
+    async fn stats_count_the_graph_and_rank_entities_by_relations() {
+        let repo = Arc::new(MemoryRepo::new());
+        let router = |repo: Arc<MemoryRepo>| Router::new()
+            .route("/api/stats", get(get_stats))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))))));
+        let stats = |router: Router| async move {
+            let response = router.oneshot(Request::get("/api/stats").body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        // Con la base vacía también responde, con ceros
+        let empty = stats(router(repo.clone())).await;
+        assert_eq!(empty, serde_json::json!({ "entities": 0, "relations": 0, "inferred_relations": 0, "chunks": 0, "top_entities": [] }));
+
+        let entity = |name: &str| GraphEntity { name: name.to_string(), category: "Place".to_string(), attributes: Default::default() };
+        let relation = |source: &str, target: &str| GraphRelation { source: source.to_string(), target: target.to_string(), relation_type: "PART_OF".to_string(), confidence: None };
+        let (document_id, chunk_id) = (Uuid::new_v4(), Uuid::new_v4());
+        repo.save_chunk(document_id, chunk_id, "La muralla de Lugo, en Galicia", vec![0.1; 8]).await.unwrap();
+        repo.save_graph(chunk_id, KnowledgeExtraction {
+            entities: vec![entity("Muralla"), entity("Lugo"), entity("Galicia"), entity("Catedral")],
+            relations: vec![relation("Muralla", "Lugo"), relation("Lugo", "Galicia"), relation("Muralla", "Lugo")],
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        }, None).await.unwrap();
+        repo.save_inferred_relations(vec![crate::domain::models::InferredRelation {
+            source: "Muralla".to_string(),
+            target: "Galicia".to_string(),
+            relation: "LOCATED_IN".to_string(),
+            reasoning: "transitividad".to_string(),
+            inference_type: None,
+            confidence_level: None,
+            confidence: None,
+        }]).await.unwrap();
+
+        let stats = stats(router(repo)).await;
+        assert_eq!(stats["entities"], 4);
+        assert_eq!(stats["relations"], 3);
+        assert_eq!(stats["inferred_relations"], 1);
+        assert_eq!(stats["chunks"], 1);
+        assert_eq!(stats["top_entities"], serde_json::json!([
+            { "name": "Galicia", "degree": 2 },
+            { "name": "Lugo", "degree": 2 },
+            { "name": "Muralla", "degree": 2 },
+        ]));
+    }
+}
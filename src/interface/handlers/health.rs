@@ -0,0 +1,104 @@
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::domain::models::{DependencyStatus, ReadinessReport};
+use crate::interface::handlers::admin::AppState;
+
+/// Tiempo máximo para cada comprobación de dependencia.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Todas las dependencias responden", body = ReadinessReport),
+        (status = 503, description = "Alguna dependencia no responde", body = ReadinessReport)
+    )
+)]
+pub async fn readiness(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let mut dependencies = Vec::new();
+
+    // 1. Neo4j
+    let neo4j = tokio::time::timeout(CHECK_TIMEOUT, state.repo.ping()).await;
+    dependencies.push(match neo4j {
+        Ok(Ok(())) => DependencyStatus::ok("neo4j"),
+        Ok(Err(e)) => DependencyStatus::failed("neo4j", e.to_string()),
+        Err(_) => DependencyStatus::failed("neo4j", "timeout".to_string()),
+    });
+
+    // 2. Proveedor de IA (opcional: cuesta una llamada a la API)
+    if state.ready_check_ai {
+        let provider = tokio::time::timeout(CHECK_TIMEOUT, state.ai_service.check_connectivity()).await;
+        dependencies.push(match provider {
+            Ok(Ok(())) => DependencyStatus::ok("ai_provider"),
+            Ok(Err(e)) => DependencyStatus::failed("ai_provider", e.to_string()),
+            Err(_) => DependencyStatus::failed("ai_provider", "timeout".to_string()),
+        });
+    }
+
+    let ready = dependencies.iter().all(|d| d.ok);
+    for dep in dependencies.iter().filter(|d| !d.ok) {
+        tracing::warn!("⚠️ Readiness check failed for {}: {}", dep.name, dep.detail.as_deref().unwrap_or(""));
+    }
+
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(ReadinessReport { ready, dependencies }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::ai::mock::mock_config;
+    use crate::infrastructure::ai::rig_client::RigAIService;
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    /// Proveedor local que rechaza la clave con 401, como una API key revocada.
+    async fn rejecting_provider() -> String {
+        let app = axum::Router::new().fallback(|| async {
+            (StatusCode::UNAUTHORIZED, Json(serde_json::json!({ "error": { "message": "Invalid API key" } })))
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base_url
+    }
+
+    async fn ready(ready_check_ai: bool) -> (StatusCode, serde_json::Value) {
+        let mut config = mock_config(8);
+        config.api_key = secrecy::SecretString::new("revoked".into());
+        config.auth_scheme = "query:key".parse().unwrap();
+        config.base_url = Some(rejecting_provider().await);
+        let mut state = AppState::for_tests(Arc::new(MemoryRepo::new()), Arc::new(RigAIService::new(config)));
+        state.ready_check_ai = ready_check_ai;
+
+        let response = readiness(State(Arc::new(state))).await.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn a_rejected_provider_key_makes_ready_report_the_ai_provider() {
+        let (status, report) = ready(true).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["ready"], false);
+        let dependencies = report["dependencies"].as_array().unwrap();
+        assert_eq!(dependencies[0]["name"], "neo4j");
+        assert_eq!(dependencies[0]["ok"], true);
+        assert_eq!(dependencies[1]["name"], "ai_provider");
+        assert_eq!(dependencies[1]["ok"], false);
+        assert!(dependencies[1]["detail"].as_str().unwrap().contains("Invalid API key"));
+    }
+
+    #[tokio::test]
+    async fn the_provider_is_not_called_unless_the_check_is_enabled() {
+        let (status, report) = ready(false).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["dependencies"].as_array().unwrap().len(), 1);
+    }
+}
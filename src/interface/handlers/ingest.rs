@@ -1,14 +1,21 @@
 use axum::{
-    extract::{State, Multipart},
+    Json,
+    extract::{State, Multipart, Query},
     response::IntoResponse,
-    body::{Body, Bytes}, 
 };
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
-use tokio_stream::StreamExt;
+use validator::Validate;
 use crate::application::ingestion::IngestionService;
-use crate::infrastructure::parsing::parse_text_from_bytes; // E0432 CORREGIDO
+use crate::domain::{
+    models::{DocumentInput, ExtractionPreview, ExtractionPreviewParams, ExtractionPreviewRequest, FileValidationParams, FileValidationReport, IngestionPlan},
+    ports::with_usage,
+    errors::AppError
+};
+use crate::infrastructure::parsing::{parse_document, parse_text_from_bytes, ParseOptions, TextSource}; // E0432 CORREGIDO
+use crate::infrastructure::transmutation::{DocumentTransmuter, SupportedFormat};
+use crate::interface::progress::progress_body;
 use super::admin::AppState;
 
 #[utoipa::path(
@@ -16,10 +23,14 @@ use super::admin::AppState;
     path = "/api/ingest",
     request_body(
         content_type = "multipart/form-data", 
-        description = "Sube un archivo (PDF/DOCX/TXT) en el campo 'file' o texto plano en 'content'",
+        description = "Sube un archivo (PDF/DOCX/TXT) en el campo 'file' o texto plano en 'content'. \
+                       Opcional: 'max_chunks' limita los fragmentos procesados, 'token_budget' los tokens \
+                       estimados que puede gastar el documento y 'metadata' \
+                       (objeto JSON, ej. {\"author\": \"X\", \"tags\": [\"a\"]}) se guarda en el documento.",
     ),
     responses(
         (status = 200, description = "Stream de texto con el progreso del proceso"),
+        (status = 401, description = "Sin sesión válida del dashboard"),
         (status = 500, description = "Error interno del servidor")
     ),
     tag = "ingestion" // Añadimos el tag para utoipa
@@ -31,6 +42,7 @@ pub async fn ingest_document(
 
     // Creamos un canal para streaming de logs
     let (tx, rx) = mpsc::channel::<String>(10);
+    let keepalive = state.stream_keepalive;
     let tx_inner = tx.clone();
 
     // Lanzamos el proceso en background
@@ -39,12 +51,17 @@ pub async fn ingest_document(
         let mut content = String::new();
         // Variable renombrada a 'file_label' y usada para logging, eliminando la advertencia.
         let mut file_label = String::from("Text Input"); 
+        let mut max_chunks: Option<usize> = None;
+        let mut token_budget: Option<usize> = None;
+        let mut metadata = HashMap::new();
+        let mut content_type: Option<String> = None;
 
         while let Ok(Some(field)) = multipart.next_field().await {
             if let Some(name) = field.name() {
                 if name == "file" {
                     // 1. Obtener nombre y notificar
                     file_label = field.file_name().unwrap_or("file").to_string();
+                    content_type = field.content_type().map(str::to_string);
                     let _ = tx_inner.send(format!("📂 Leyendo archivo: {}...", file_label)).await;
                     
                     // 2. Obtener bytes del archivo
@@ -53,8 +70,17 @@ pub async fn ingest_document(
                     match bytes_result {
                         Ok(bytes) => {
                              let _ = tx_inner.send("📄 Parseando contenido...".to_string()).await;
-                             match parse_text_from_bytes(&file_label, &bytes) {
-                                Ok(text) => content = text,
+                             let options = ParseOptions {
+                                 preserve_formatting: state.ingestion.preserve_formatting,
+                                 ocr_min_chars: state.ingestion.ocr_min_chars,
+                             };
+                             match parse_document(&file_label, &bytes, options) {
+                                Ok((text, source)) => {
+                                    if source == TextSource::Ocr {
+                                        let _ = tx_inner.send("🔍 PDF sin capa de texto: OCR fallback engaged.".to_string()).await;
+                                    }
+                                    content = text;
+                                },
                                 Err(e) => {
                                     let _ = tx_inner.send(format!("❌ Error parseando: {}", e)).await;
                                     return;
@@ -67,11 +93,32 @@ pub async fn ingest_document(
                             return;
                         }
                     }
+                } else if name == "metadata" {
+                    if let Ok(value) = field.text().await {
+                        if !value.trim().is_empty() {
+                            match serde_json::from_str::<HashMap<String, serde_json::Value>>(&value) {
+                                Ok(parsed) => metadata = parsed,
+                                Err(e) => {
+                                    let _ = tx_inner.send(format!("❌ Error: 'metadata' debe ser un objeto JSON: {}", e)).await;
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                } else if name == "max_chunks" {
+                    if let Ok(value) = field.text().await {
+                        max_chunks = value.trim().parse::<usize>().ok();
+                    }
+                } else if name == "token_budget" {
+                    if let Ok(value) = field.text().await {
+                        token_budget = value.trim().parse::<usize>().ok();
+                    }
                 } else if name == "content" {
                      if let Ok(text) = field.text().await {
                         if !text.is_empty() {
                             content = text;
                             file_label = "Texto Plano".to_string(); // Actualizamos la etiqueta para el log
+                            content_type = Some("text/plain".to_string());
                             let _ = tx_inner.send("📝 Recibido texto directo...".to_string()).await;
                         }
                      }
@@ -85,9 +132,17 @@ pub async fn ingest_document(
         }
 
         // 2. Iniciar Servicio
-        let service = IngestionService::new(state.repo.clone(), state.ai_service.clone());
+        let service = IngestionService::new(state.repo.clone(), state.ai_service.clone(), state.ingestion.clone())
+            .with_embedding_imports(state.embedding_imports.clone());
 
-        match service.ingest_with_progress(content, tx_inner.clone()).await {
+        let document = DocumentInput {
+            name: file_label,
+            metadata,
+            content_type,
+            char_count: content.chars().count(),
+        };
+
+        match service.ingest_with_progress(content, document, max_chunks, token_budget, tx_inner.clone()).await {
             Ok(_) => {
                 let _ = tx_inner.send("DONE".to_string()).await;
             },
@@ -97,10 +152,326 @@ pub async fn ingest_document(
         }
     });
 
-    // Convertimos el Receiver en un Stream compatible con Axum Body
-    let stream = ReceiverStream::new(rx).map(|msg| {
-        Ok::<_, std::io::Error>(Bytes::from(format!("{}\n", msg))) 
-    });
+    // Convertimos el Receiver en un Stream compatible con Axum Body (con keepalive si está configurado)
+    progress_body(rx, keepalive)
+}
+
+
+#[utoipa::path(
+    post,
+    path = "/api/extract",
+    params(ExtractionPreviewParams),
+    request_body = ExtractionPreviewRequest,
+    responses(
+        (status = 200, description = "Entidades y relaciones extraídas (no se guarda nada)", body = ExtractionPreview),
+        (status = 400, description = "Contenido demasiado corto"),
+        (status = 500, description = "Error del proveedor o de parseo")
+    ),
+    tag = "ingestion"
+)]
+pub async fn preview_extraction(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ExtractionPreviewParams>,
+    Json(payload): Json<ExtractionPreviewRequest>,
+) -> Result<Json<ExtractionPreview>, AppError> {
+    payload.validate().map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let (result, usage) = with_usage(state.ai_service.extract_knowledge_raw(&payload.content)).await;
+    let (extraction, raw) = result?;
+
+    Ok(Json(ExtractionPreview {
+        extraction,
+        // La respuesta literal puede repetir contenido sensible: solo bajo petición
+        raw_response: params.raw.then_some(raw),
+        usage: usage.filter(|_| state.include_token_usage),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/ingest/validate",
+    params(FileValidationParams),
+    request_body(
+        content_type = "multipart/form-data",
+        description = "Archivo a comprobar en el campo 'file'. Solo se convierte a texto: sin embeddings, extracción ni escritura en la base de datos.",
+    ),
+    responses(
+        (status = 200, description = "Resultado de la conversión (valid=false si el archivo no se puede leer)", body = FileValidationReport),
+        (status = 400, description = "Falta el campo 'file'")
+    ),
+    tag = "ingestion"
+)]
+pub async fn validate_document(
+    Query(params): Query<FileValidationParams>,
+    mut multipart: Multipart,
+) -> Result<Json<FileValidationReport>, AppError> {
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| AppError::ValidationError(format!("Invalid multipart body: {}", e)))? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let filename = field.file_name().unwrap_or("file").to_string();
+        let bytes = field.bytes().await
+            .map_err(|e| AppError::ValidationError(format!("Failed to read 'file': {}", e)))?;
+        let format = SupportedFormat::from_filename(&filename).map(|f| f.as_str().to_string());
+
+        let report = match DocumentTransmuter::transmute(&filename, &bytes) {
+            Ok(text) => FileValidationReport {
+                valid: true,
+                filename,
+                format,
+                char_count: text.chars().count(),
+                preview: text.chars().take(params.preview_chars).collect(),
+                error: None,
+            },
+            Err(e) => FileValidationReport {
+                valid: false,
+                filename,
+                format,
+                char_count: 0,
+                preview: String::new(),
+                error: Some(e.to_string()),
+            },
+        };
+        return Ok(Json(report));
+    }
+
+    Err(AppError::ValidationError("Missing 'file' field".to_string()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/ingest/plan",
+    request_body(
+        content_type = "multipart/form-data",
+        description = "Mismo formulario que /api/ingest ('file' o 'content', 'max_chunks' opcional). \
+                       Solo convierte y trocea: sin embeddings, extracción ni escritura en la base de datos.",
+    ),
+    responses(
+        (status = 200, description = "Fragmentos, tokens estimados y llamadas a la IA que supondría la ingesta", body = IngestionPlan),
+        (status = 400, description = "Archivo ilegible o contenido vacío")
+    ),
+    tag = "ingestion"
+)]
+pub async fn plan_ingestion(
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<Json<IngestionPlan>, AppError> {
+    let mut content = String::new();
+    let mut file_label = String::from("Text Input");
+    let mut max_chunks: Option<usize> = None;
+
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| AppError::ValidationError(format!("Invalid multipart body: {}", e)))? {
+        match field.name() {
+            Some("file") => {
+                file_label = field.file_name().unwrap_or("file").to_string();
+                let bytes = field.bytes().await
+                    .map_err(|e| AppError::ValidationError(format!("Failed to read 'file': {}", e)))?;
+                let options = ParseOptions {
+                    preserve_formatting: state.ingestion.preserve_formatting,
+                    ocr_min_chars: state.ingestion.ocr_min_chars,
+                };
+                content = parse_text_from_bytes(&file_label, &bytes, options)?;
+            },
+            Some("content") => {
+                if let Ok(text) = field.text().await {
+                    if !text.is_empty() {
+                        content = text;
+                        file_label = "Texto Plano".to_string();
+                    }
+                }
+            },
+            Some("max_chunks") => {
+                if let Ok(value) = field.text().await {
+                    max_chunks = value.trim().parse::<usize>().ok();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if content.trim().len() < 5 {
+        return Err(AppError::ValidationError("Contenido vacío o muy corto".to_string()));
+    }
+
+    let service = IngestionService::new(state.repo.clone(), state.ai_service.clone(), state.ingestion.clone())
+        .with_embedding_imports(state.embedding_imports.clone());
+
+    Ok(Json(service.plan(&content, file_label, max_chunks).await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::{Request, StatusCode}, routing::post};
+    use tower::ServiceExt;
+    use crate::domain::models::{GraphEntity, KnowledgeExtraction};
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    const TEXT: &str = "La Muralla de Lugo rodea el casco antiguo.";
+
+    fn app(repo: Arc<MemoryRepo>) -> Router {
+        let extraction = KnowledgeExtraction {
+            entities: vec![GraphEntity { name: "Muralla de Lugo".to_string(), category: "Monument".to_string(), attributes: Default::default() }],
+            relations: Vec::new(),
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        };
+        let ai = Arc::new(MockAIService::new(mock_config(8)).with_extraction(TEXT, extraction));
+        Router::new()
+            .route("/api/extract", post(preview_extraction))
+            .with_state(Arc::new(AppState::for_tests(repo, ai)))
+    }
+
+    async fn preview(router: Router, uri: &str, content: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::json!({ "content": content }).to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn preview_returns_the_extraction_without_saving_it() {
+        let repo = Arc::new(MemoryRepo::new());
+        let (status, body) = preview(app(repo.clone()), "/api/extract", TEXT).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["extraction"]["entities"][0]["name"], "Muralla de Lugo");
+        assert!(body.get("raw_response").is_none());
+        assert!(repo.state().graphs.is_empty());
+        assert!(repo.state().chunks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn raw_response_is_included_only_on_request() {
+        let (status, body) = preview(app(Arc::new(MemoryRepo::new())), "/api/extract?raw=true", TEXT).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let raw: KnowledgeExtraction = serde_json::from_str(body["raw_response"].as_str().unwrap()).unwrap();
+        assert_eq!(raw.entities[0].name, "Muralla de Lugo");
+    }
+
+    #[tokio::test]
+    async fn preview_rejects_short_content() {
+        let (status, _) = preview(app(Arc::new(MemoryRepo::new())), "/api/extract", "corto").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// Cuerpo multipart con un único campo `name` (con nombre de archivo si se indica).
+    fn multipart(name: &str, filename: Option<&str>, content: &str) -> Request<Body> {
+        let disposition = match filename {
+            Some(filename) => format!("form-data; name=\"{}\"; filename=\"{}\"", name, filename),
+            None => format!("form-data; name=\"{}\"", name),
+        };
+        let body = format!("--X\r\nContent-Disposition: {}\r\n\r\n{}\r\n--X--\r\n", disposition, content);
+        Request::post("/api/ingest/validate?preview_chars=12")
+            .header("content-type", "multipart/form-data; boundary=X")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn validate(request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let router = Router::new().route("/api/ingest/validate", post(validate_document));
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn validation_reports_the_extracted_text() {
+        let (status, report) = validate(multipart("file", Some("murallas.csv"), "nombre,ciudad\nMuralla,Lugo\n")).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["valid"], true);
+        assert_eq!(report["format"], "csv");
+        assert_eq!(report["char_count"], "nombre | ciudad\n---\nMuralla | Lugo\n".chars().count());
+        assert_eq!(report["preview"], "nombre | ciu");
+        assert!(report.get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn an_unreadable_file_is_reported_as_invalid() {
+        let (status, report) = validate(multipart("file", Some("plano.dwg"), "binario")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["valid"], false);
+        assert!(report["format"].is_null());
+        assert!(report["error"].as_str().unwrap().contains("plano.dwg"));
+
+        let (_, report) = validate(multipart("file", Some("roto.pdf"), "no es un pdf")).await;
+        assert_eq!((report["valid"].as_bool(), report["format"].as_str()), (Some(false), Some("pdf")));
+    }
+
+    #[tokio::test]
+    async fn validation_requires_the_file_field() {
+        let (status, _) = validate(multipart("content", None, "texto suelto")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    fn form(fields: &[(&str, &str)]) -> Request<Body> {
+        let mut body = String::new();
+        for (name, value) in fields {
+            body.push_str(&format!("--limite\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", name, value));
+        }
+        body.push_str("--limite--\r\n");
+        Request::post("/api/ingest/plan")
+            .header("content-type", "multipart/form-data; boundary=limite")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_plan_reports_the_chunks_without_ingesting() {
+        let repo = Arc::new(MemoryRepo::new());
+        let router = Router::new()
+            .route("/api/ingest/plan", post(plan_ingestion))
+            .with_state(Arc::new(AppState::for_tests(repo.clone(), Arc::new(MockAIService::new(mock_config(8))))));
+        let long_text = "La Muralla de Lugo rodea el casco antiguo. ".repeat(100);
+
+        let response = router.clone().oneshot(form(&[("content", &long_text), ("max_chunks", "1")])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let plan: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(plan["document"], "Texto Plano");
+        assert!(plan["chunk_count"].as_u64().unwrap() > 1);
+        assert_eq!(plan["processed_chunks"], 1);
+        assert_eq!((plan["embedding_calls"].as_u64(), plan["extraction_calls"].as_u64()), (Some(1), Some(1)));
+        assert!(repo.state().chunks.is_empty());
+
+        let response = router.oneshot(form(&[("content", "hola")])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    const CONTENT: &str = "Ada Lovelace trabajó con Charles Babbage en Londres.";
+
+    async fn preview_with_usage(include_token_usage: bool) -> ExtractionPreview {
+        let mut state = AppState::for_tests(Arc::new(MemoryRepo::new()), Arc::new(MockAIService::new(mock_config(8))));
+        state.include_token_usage = include_token_usage;
+        let Json(preview) = preview_extraction(
+            State(Arc::new(state)),
+            Query(ExtractionPreviewParams { raw: true }),
+            Json(ExtractionPreviewRequest { content: CONTENT.to_string() }),
+        ).await.unwrap();
+        preview
+    }
+
+    #[tokio::test]
+    async fn extraction_preview_includes_token_usage_only_when_enabled() {
+        assert_eq!(preview_with_usage(false).await.usage, None);
 
-    Body::from_stream(stream)
-}
\ No newline at end of file
+        let preview = preview_with_usage(true).await;
+        // El mock cuenta una palabra por token: el fragmento es el prompt y el JSON la respuesta
+        let raw_words = preview.raw_response.as_deref().unwrap().split_whitespace().count() as u64;
+        let usage = preview.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, CONTENT.split_whitespace().count() as u64);
+        assert_eq!(usage.completion_tokens, raw_words);
+        assert_eq!(usage.total_tokens, usage.prompt_tokens + raw_words);
+    }
+}
@@ -3,4 +3,7 @@ pub mod ingest;
 pub mod graph;
 pub mod ui;
 pub mod chat;
-pub mod reasoning; // <-- NUEVO
\ No newline at end of file
+pub mod reasoning; // <-- NUEVO
+pub mod entities;
+pub mod health;
+pub mod documents;
\ No newline at end of file
@@ -1,23 +1,128 @@
-use axum::{Json, extract::State};
+use axum::{Json, extract::{Query, State}, response::{IntoResponse, Response}};
 use std::sync::Arc;
+use std::time::Duration;
 use crate::application::reasoning::ReasoningService;
-use crate::domain::models::InferredRelation;
+use crate::domain::models::{ExpireInferredParams, InferredRelation, ReasoningRunParams};
 use crate::domain::errors::AppError;
 use super::admin::AppState;
 
 #[utoipa::path(
     post,
     path = "/api/reasoning/run",
+    params(ReasoningRunParams),
     responses(
-        (status = 200, description = "Knowledge consolidated", body = Vec<InferredRelation>)
+        (status = 200, description = "Knowledge consolidated (con explain=true, un ReasoningExplanation con contexto y prompt)", body = Vec<InferredRelation>),
+        (status = 401, description = "Sin sesión válida del dashboard"),
+        (status = 409, description = "Cancelada con /api/reasoning/cancel (no se guardó nada)"),
+        (status = 504, description = "Superado REASONING_TIMEOUT_SECS (no se guardó nada)")
     )
 )]
 pub async fn run_reasoning(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<InferredRelation>>, AppError> {
+    Query(params): Query<ReasoningRunParams>,
+) -> Result<Response, AppError> {
     
-    let service = ReasoningService::new(state.repo.clone(), state.ai_service.clone());
-    let new_relations = service.infer_new_knowledge().await?;
-    
-    Ok(Json(new_relations))
-}
\ No newline at end of file
+    let service = ReasoningService::new(state.repo.clone(), state.ai_service.clone(), state.reasoning.clone())
+        .with_cancellation(state.reasoning_cancel.clone());
+    let run = service.infer_with_explanation().await?;
+
+    // Sin explain se mantiene la respuesta de siempre (array de relaciones)
+    if params.explain {
+        Ok(Json(run).into_response())
+    } else {
+        Ok(Json(run.new_relations).into_response())
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/reasoning/expire",
+    params(ExpireInferredParams),
+    responses(
+        (status = 200, description = "Relaciones inferidas caducadas y borradas ({\"expired\": n})"),
+        (status = 400, description = "Sin antigüedad máxima configurada ni indicada"),
+        (status = 401, description = "Sin sesión válida del dashboard")
+    ),
+    tag = "reasoning"
+)]
+pub async fn expire_inferred_relations(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ExpireInferredParams>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let service = ReasoningService::new(state.repo.clone(), state.ai_service.clone(), state.reasoning.clone());
+    let max_age = params.max_age_hours.map(|h| Duration::from_secs(h * 3600));
+    let expired = service.expire_inferred(max_age).await?;
+
+    Ok(Json(serde_json::json!({ "expired": expired })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/reasoning/cancel",
+    responses(
+        (status = 200, description = "Ejecuciones de razonamiento en curso abortadas"),
+        (status = 401, description = "Sin sesión válida del dashboard")
+    ),
+    tag = "reasoning"
+)]
+pub async fn cancel_reasoning(
+    State(state): State<Arc<AppState>>,
+) -> Json<serde_json::Value> {
+    state.reasoning_cancel.notify_waiters();
+    tracing::warn!("🛑 Reasoning runs cancelled on request");
+    Json(serde_json::json!({ "cancelled": true }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::{Request, StatusCode}, routing::post};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+    use crate::domain::{models::{GraphEntity, GraphRelation, InferenceResult, KnowledgeExtraction}, ports::KGRepository};
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    #[tokio::test]
+    async fn explain_returns_the_context_and_prompt_next_to_the_relations() {
+        let repo = Arc::new(MemoryRepo::new());
+        let entity = |name: &str| GraphEntity { name: name.to_string(), category: "Place".to_string(), attributes: Default::default() };
+        repo.save_graph(Uuid::new_v4(), KnowledgeExtraction {
+            entities: vec![entity("Muralla"), entity("Lugo")],
+            relations: vec![GraphRelation { source: "Muralla".to_string(), target: "Lugo".to_string(), relation_type: "LOCATED_IN".to_string(), confidence: None }],
+            events: Vec::new(),
+            temporal_relations: Vec::new(),
+        }, None).await.unwrap();
+        let inferred = InferredRelation {
+            source: "Muralla".to_string(),
+            target: "Galicia".to_string(),
+            relation: "LOCATED_IN".to_string(),
+            reasoning: "transitividad".to_string(),
+            inference_type: None,
+            confidence_level: None,
+            confidence: Some(0.9),
+        };
+        let ai = MockAIService::new(mock_config(8)).with_inference(InferenceResult { new_relations: vec![inferred] });
+        let router = Router::new()
+            .route("/api/reasoning/run", post(run_reasoning))
+            .with_state(Arc::new(AppState::for_tests(repo, Arc::new(ai))));
+        let run = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(Request::post(uri).body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+            }
+        };
+
+        // Sin explain, el array de siempre
+        let plain = run("/api/reasoning/run").await;
+        assert_eq!(plain[0]["target"], "Galicia");
+
+        let explained = run("/api/reasoning/run?explain=true").await;
+        assert_eq!(explained["new_relations"][0]["target"], "Galicia");
+        assert_eq!(explained["context"], "(Muralla) -[LOCATED_IN]-> (Lugo)\n");
+        assert!(explained["prompt"].as_str().unwrap().contains("(Muralla) -[LOCATED_IN]-> (Lugo)"));
+    }
+}
@@ -4,30 +4,120 @@ use axum::{
     http::{StatusCode, header},
 };
 use std::sync::Arc;
-use tera::{Context, Tera}; // <--- CORRECCIÓN: AÑADIDO 'Tera' AQUÍ
-use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tera::{Context, Tera};
+use serde::{Deserialize, Serialize};
+use secrecy::{ExposeSecret, SecretString};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use subtle::ConstantTimeEq;
 use crate::interface::handlers::admin::AppState;
 
-// Credentials for deployment
-const USERNAME: &str = "propileno";
-const PASSWORD: &str = "propileno24";
 const SESSION_COOKIE: &str = "lamuralla_auth";
 
+/// Duración de la sesión si no se configura AUTH_SESSION_TTL_SECS.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(3600);
+
+/// Credenciales del dashboard y clave de firma de la sesión (se leen del entorno en main.rs).
+#[derive(Clone)]
+pub struct AuthConfig {
+    pub username: String,
+    pub password: SecretString,
+    /// Clave HS256 de los JWT de sesión
+    pub jwt_secret: SecretString,
+    pub session_ttl: Duration,
+}
+
+/// Claims del JWT de sesión.
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionClaims {
+    sub: String,
+    iat: u64,
+    exp: u64,
+}
+
+impl AuthConfig {
+    pub fn new(username: String, password: SecretString, jwt_secret: SecretString) -> Self {
+        Self { username, password, jwt_secret, session_ttl: DEFAULT_SESSION_TTL }
+    }
+
+    pub fn with_session_ttl(mut self, ttl: Duration) -> Self {
+        self.session_ttl = ttl;
+        self
+    }
+
+    /// Compara usuario y contraseña en tiempo constante (sin cortocircuito entre ambos).
+    fn credentials_match(&self, payload: &AuthPayload) -> bool {
+        let username = payload.username.as_bytes().ct_eq(self.username.as_bytes());
+        let password = payload.password.as_bytes().ct_eq(self.password.expose_secret().as_bytes());
+        (username & password).into()
+    }
+
+    /// Firma un JWT para `subject` que caduca tras `session_ttl`.
+    pub(crate) fn issue_token(&self, subject: &str) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let claims = SessionClaims { sub: subject.to_string(), iat: now, exp: now + self.session_ttl.as_secs() };
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(self.jwt_secret.expose_secret().as_bytes()),
+        )
+    }
+
+    /// Comprueba firma y caducidad del token.
+    fn verify_token(&self, token: &str) -> bool {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        jsonwebtoken::decode::<SessionClaims>(
+            token,
+            &DecodingKey::from_secret(self.jwt_secret.expose_secret().as_bytes()),
+            &validation,
+        ).is_ok()
+    }
+}
+
+/// Valor de la cookie `name` en la cabecera `Cookie` (coincidencia exacta del nombre).
+fn cookie_value<'a>(cookie_header: &'a str, name: &str) -> Option<&'a str> {
+    cookie_header.split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Normaliza `BASE_PATH`: `/lamuralla/` o `lamuralla` -> `/lamuralla`; vacío o `/` -> `""` (raíz).
+pub fn normalize_base_path(raw: &str) -> String {
+    let trimmed = raw.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// Ruta absoluta de la aplicación bajo `base_path` (la raíz anidada es `/lamuralla`, sin barra final).
+pub fn app_url(base_path: &str, path: &str) -> String {
+    match (base_path.is_empty(), path) {
+        (false, "/") => base_path.to_string(),
+        _ => format!("{}{}", base_path, path),
+    }
+}
+
 #[derive(Deserialize)]
 pub struct AuthPayload {
     username: String,
     password: String,
 }
 
-pub async fn render_login() -> impl IntoResponse {
-    // La instancia de Tera se crea aquí temporalmente para renderizar el login
-    // ya que no requiere el estado de la aplicación.
-    let tera = match Tera::new("templates/**/*.html") {
-        Ok(t) => t,
-        Err(e) => return Html(format!("<h1>Error loading templates: {}</h1>", e)).into_response(),
-    };
+/// Compila todas las plantillas que casan con `glob` una sola vez, en el arranque:
+/// un error de sintaxis se informa aquí en lugar de en cada petición.
+pub fn load_templates(glob: &str) -> Result<Tera, tera::Error> {
+    Tera::new(glob)
+}
 
-    match tera.render("login.html", &Context::new()) {
+pub async fn render_login(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    // Plantillas ya compiladas en el arranque (un error de sintaxis detiene el servidor allí)
+    let mut ctx = Context::new();
+    ctx.insert("base_path", &state.base_path);
+    match state.tera.render("login.html", &ctx) {
         Ok(html) => Html(html).into_response(),
         Err(err) => Html(format!("<h1>Error rendering template</h1><p>{}</p>", err)).into_response(),
     }
@@ -38,18 +128,28 @@ pub async fn authenticate(
     Form(payload): Form<AuthPayload>,
 ) -> impl IntoResponse {
     
-    if payload.username == USERNAME && payload.password == PASSWORD {
-        // En un entorno de producción, esto debería ser un token JWT o una cookie con sesión segura.
-        // Aquí usamos una cookie simple como "sesión" para el ejercicio.
-        let cookie_value = format!("{}=valid; Path=/; Max-Age={}; HttpOnly; SameSite=Strict", SESSION_COOKIE, 3600); // 1 hora
+    if state.auth.credentials_match(&payload) {
+        // Sesión: JWT firmado con AUTH_JWT_SECRET que caduca con la cookie
+        let token = match state.auth.issue_token(&payload.username) {
+            Ok(token) => token,
+            Err(e) => {
+                tracing::error!("❌ Could not sign session token: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        let cookie_value = format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+            SESSION_COOKIE, token, state.auth.session_ttl.as_secs()
+        );
         
-        let mut response = Redirect::to("/dashboard").into_response();
+        let mut response = Redirect::to(&app_url(&state.base_path, "/dashboard")).into_response();
         response.headers_mut().insert(header::SET_COOKIE, header::HeaderValue::from_str(&cookie_value).unwrap());
         response
     } else {
         // Renderizar página de login con mensaje de error
         let mut ctx = Context::new();
         ctx.insert("error", &true);
+        ctx.insert("base_path", &state.base_path);
         match state.tera.render("login.html", &ctx) {
              Ok(html) => (StatusCode::UNAUTHORIZED, Html(html)).into_response(),
              Err(err) => Html(format!("<h1>Error rendering template</h1><p>{}</p>", err)).into_response(),
@@ -57,13 +157,13 @@ pub async fn authenticate(
     }
 }
 
-pub async fn auth_guard(headers: header::HeaderMap) -> Result<(), StatusCode> {
-    // Comprueba la existencia de la cookie de autenticación
+pub fn auth_guard(auth: &AuthConfig, headers: &header::HeaderMap) -> Result<(), StatusCode> {
+    // Valida firma y caducidad del JWT de la cookie de sesión
     let cookie_header = headers.get(header::COOKIE)
         .and_then(|h| h.to_str().ok())
         .unwrap_or("");
     
-    if cookie_header.contains(&format!("{}={}", SESSION_COOKIE, "valid")) {
+    if cookie_value(cookie_header, SESSION_COOKIE).is_some_and(|token| auth.verify_token(token)) {
         Ok(())
     } else {
         // Si no está autenticado, redirige al login
@@ -71,26 +171,184 @@ pub async fn auth_guard(headers: header::HeaderMap) -> Result<(), StatusCode> {
     }
 }
 
+/// Cierra la sesión: borra la cookie en el navegador y vuelve al login.
+pub async fn logout(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let cookie_value = format!("{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict", SESSION_COOKIE);
+    let mut response = Redirect::to(&app_url(&state.base_path, "/")).into_response();
+    response.headers_mut().insert(header::SET_COOKIE, header::HeaderValue::from_str(&cookie_value).unwrap());
+    response
+}
+
 // Envuelve el render_dashboard original con el guard
 pub async fn render_dashboard_guarded(
     headers: header::HeaderMap, 
     State(state): State<Arc<AppState>>
 ) -> impl IntoResponse {
     // 1. Ejecutar el guard de autenticación
-    if let Err(_) = auth_guard(headers).await {
-        return Redirect::to("/").into_response();
+    if auth_guard(&state.auth, &headers).is_err() {
+        return Redirect::to(&app_url(&state.base_path, "/")).into_response();
     }
     
     // 2. Si pasa, renderiza el dashboard
-    let _ai_guard = state.ai_service.read().await;
     let mut ctx = Context::new();
+    let config = state.ai_service.get_config();
     ctx.insert("config", &serde_json::json!({
-        "model_name": "gpt-4o",
-        "embedding_dim": 1536
+        "model_name": config.model_name,
+        "embedding_dim": config.embedding_dim
     }));
+    ctx.insert("base_path", &state.base_path);
 
     match state.tera.render("dashboard.html", &ctx) {
         Ok(html) => Html(html).into_response(),
         Err(err) => Html(format!("<h1>Error rendering template</h1><p>{}</p>", err)).into_response(),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    fn state(base_path: &str) -> Arc<AppState> {
+        let state = AppState::for_tests(Arc::new(MemoryRepo::new()), Arc::new(MockAIService::new(mock_config(8))));
+        Arc::new(AppState { base_path: base_path.to_string(), ..state })
+    }
+
+    fn location(response: axum::response::Response) -> String {
+        response.headers()[header::LOCATION].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn the_base_path_is_normalized() {
+        assert_eq!(normalize_base_path("/lamuralla/"), "/lamuralla");
+        assert_eq!(normalize_base_path(" lamuralla "), "/lamuralla");
+        assert_eq!(normalize_base_path("/apps/lamuralla"), "/apps/lamuralla");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(normalize_base_path(""), "");
+    }
+
+    #[test]
+    fn app_urls_hang_from_the_base_path() {
+        assert_eq!(app_url("", "/"), "/");
+        assert_eq!(app_url("", "/dashboard"), "/dashboard");
+        assert_eq!(app_url("/lamuralla", "/"), "/lamuralla");
+        assert_eq!(app_url("/lamuralla", "/dashboard"), "/lamuralla/dashboard");
+    }
+
+    #[tokio::test]
+    async fn redirects_stay_under_the_base_path() {
+        let login = AuthPayload { username: "admin".into(), password: "secret".into() };
+        let response = authenticate(State(state("/lamuralla")), Form(login)).await.into_response();
+        assert_eq!(location(response), "/lamuralla/dashboard");
+
+        let response = render_dashboard_guarded(header::HeaderMap::new(), State(state("/lamuralla"))).await.into_response();
+        assert_eq!(location(response), "/lamuralla");
+    }
+
+    fn auth() -> AuthConfig {
+        AuthConfig::new("admin".into(), "secret".to_string().into(), "test-jwt-secret".to_string().into())
+    }
+
+    fn cookie_headers(cookie: &str) -> header::HeaderMap {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(header::COOKIE, header::HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn auth_guard_accepts_only_signed_unexpired_tokens() {
+        let auth = auth();
+        let token = auth.issue_token("admin").unwrap();
+        assert!(auth_guard(&auth, &cookie_headers(&format!("theme=dark; {}={}", SESSION_COOKIE, token))).is_ok());
+
+        // La cookie estática antigua y un token firmado con otra clave no valen
+        assert!(auth_guard(&auth, &cookie_headers(&format!("{}=valid", SESSION_COOKIE))).is_err());
+        let forged = AuthConfig::new("admin".into(), "secret".to_string().into(), "other".to_string().into())
+            .issue_token("admin").unwrap();
+        assert!(auth_guard(&auth, &cookie_headers(&format!("{}={}", SESSION_COOKIE, forged))).is_err());
+
+        let expired = auth.clone().with_session_ttl(Duration::ZERO).issue_token("admin").unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(auth_guard(&auth, &cookie_headers(&format!("{}={}", SESSION_COOKIE, expired))).is_err());
+        assert!(auth_guard(&auth, &header::HeaderMap::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn login_checks_env_credentials_and_sets_a_jwt_cookie() {
+        let wrong = authenticate(State(state("")), Form(AuthPayload { username: "admin".into(), password: "nope".into() }))
+            .await.into_response();
+        assert!(wrong.headers().get(header::SET_COOKIE).is_none());
+
+        let ok = authenticate(State(state("")), Form(AuthPayload { username: "admin".into(), password: "secret".into() }))
+            .await.into_response();
+        let cookie = ok.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+        let token = cookie_value(&cookie, SESSION_COOKIE).unwrap();
+        assert!(auth().verify_token(token));
+        assert!(cookie.contains("Max-Age=3600"));
+        assert_eq!(location(ok), "/dashboard");
+    }
+
+    #[test]
+    fn credentials_must_match_both_fields() {
+        let auth = auth();
+        let payload = |username: &str, password: &str| AuthPayload { username: username.into(), password: password.into() };
+        assert!(auth.credentials_match(&payload("admin", "secret")));
+        assert!(!auth.credentials_match(&payload("root", "secret")));
+        assert!(!auth.credentials_match(&payload("admin", "secret2")));
+        assert!(!auth.credentials_match(&payload("", "")));
+    }
+
+    #[tokio::test]
+    async fn logout_clears_the_session_cookie() {
+        let response = logout(State(state("/lamuralla"))).await.into_response();
+        assert_eq!(
+            response.headers()[header::SET_COOKIE],
+            "lamuralla_auth=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict"
+        );
+        assert_eq!(location(response), "/lamuralla");
+    }
+
+    fn template_dir(files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("templates-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn the_bundled_templates_compile() {
+        let tera = load_templates(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*.html")).unwrap();
+        for name in ["login.html", "dashboard.html"] {
+            assert!(tera.get_template_names().any(|t| t == name), "{} missing", name);
+        }
+    }
+
+    #[test]
+    fn a_broken_template_fails_at_load_time() {
+        let dir = template_dir(&[("login.html", "<h1>{{ base_path </h1>{% if %}")]);
+        let result = load_templates(&format!("{}/**/*.html", dir.display()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err(), "syntax errors are reported once, at startup");
+    }
+
+    #[tokio::test]
+    async fn render_login_uses_the_templates_loaded_at_startup() {
+        let dir = template_dir(&[("login.html", "cached login at {{ base_path | safe }}")]);
+        let tera = load_templates(&format!("{}/**/*.html", dir.display())).unwrap();
+        // Sin ficheros en disco: si render_login releyera las plantillas, fallaría
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut state = AppState::for_tests(Arc::new(MemoryRepo::new()), Arc::new(MockAIService::new(mock_config(8))));
+        state.tera = tera;
+        state.base_path = "/lamuralla".to_string();
+
+        let response = render_login(State(Arc::new(state))).await.into_response();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(String::from_utf8(body.to_vec()).unwrap(), "cached login at /lamuralla");
+    }
+}
@@ -0,0 +1,286 @@
+use axum::{
+    extract::{DefaultBodyLimit, Request, State},
+    middleware::{self, Next},
+    response::Response,
+    routing::{delete, get, post},
+    Router,
+};
+use std::sync::Arc;
+use crate::domain::errors::AppError;
+use crate::interface::handlers::admin::{self, AppState};
+use crate::interface::handlers::ui::{self, auth_guard};
+use crate::interface::handlers::{ingest, graph, chat, reasoning, entities, health, documents};
+
+/// Bloquea las rutas de escritura (ingesta, razonamiento, admin, fusiones) en modo solo lectura.
+/// Se aplica con `route_layer` únicamente sobre esas rutas.
+pub async fn read_only_guard(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if state.read_only {
+        tracing::warn!("🔒 Blocked {} {} (read-only mode)", request.method(), request.uri().path());
+        return Err(AppError::ReadOnlyMode);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Exige la sesión del dashboard (401 sin ella) en las rutas de administración y en las
+/// de escritura. Se aplica con `route_layer` sobre esas rutas.
+pub async fn session_guard(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if auth_guard(&state.auth, request.headers()).is_err() {
+        tracing::warn!("🔐 Blocked {} {} (no dashboard session)", request.method(), request.uri().path());
+        return Err(AppError::Unauthorized);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Rutas de la API y del dashboard, anidadas bajo `base_path` si no es la raíz.
+/// Swagger y las capas de trazas/CORS se añaden en main.rs.
+pub fn build_router(state: Arc<AppState>, base_path: &str) -> Router {
+    // Rutas de escritura: bloqueadas con 403 en modo solo lectura
+    let mutation_routes = Router::new()
+        .route("/api/admin/config", post(admin::update_config).patch(admin::patch_config))
+        .route("/api/admin/reset", post(admin::reset_database))
+        .route("/api/admin/rebuild-graph", post(admin::rebuild_graph))
+        .route("/api/admin/export-embeddings", get(admin::export_embeddings))
+        .route(
+            "/api/admin/import-embeddings",
+            // Los volcados de embeddings superan con creces el límite por defecto de 2 MB
+            post(admin::import_embeddings).layer(DefaultBodyLimit::max(512 * 1024 * 1024)),
+        )
+        .route("/api/admin/compute-centrality", post(admin::compute_centrality))
+        .route("/api/admin/reembed", post(admin::reembed_chunks))
+        .route("/api/ingest", post(ingest::ingest_document))
+        .route("/api/reasoning/run", post(reasoning::run_reasoning))
+        .route("/api/reasoning/expire", post(reasoning::expire_inferred_relations))
+        .route("/api/reasoning/cancel", post(reasoning::cancel_reasoning))
+        .route("/api/entities/merge", post(entities::merge_entities))
+        .route("/api/documents/{id}", delete(documents::delete_document))
+        .route_layer(middleware::from_fn_with_state(state.clone(), read_only_guard));
+
+    // Administración y escrituras: solo con sesión del dashboard (401)
+    let admin_routes = Router::new()
+        .merge(mutation_routes)
+        // Solo lectura (fuera de read_only_guard), pero como el resto de /api/admin exige sesión
+        .route("/api/admin/stale-chunks", get(admin::list_stale_chunks))
+        .route_layer(middleware::from_fn_with_state(state.clone(), session_guard));
+
+    let routes = Router::new()
+        // Salud
+        .route("/ready", get(health::readiness))
+
+        // Endpoints API
+        .merge(admin_routes)
+        .route("/api/extract", post(ingest::preview_extraction))
+        .route("/api/ingest/validate", post(ingest::validate_document))
+        .route("/api/ingest/plan", post(ingest::plan_ingestion))
+        .route("/api/documents", get(documents::list_documents))
+        .route("/api/documents/{id}/graph", get(documents::get_document_graph))
+        .route("/api/graph", get(graph::get_graph))
+        .route("/api/graph/export", get(graph::export_graph))
+        .route("/api/graph/since", get(graph::get_graph_since))
+        .route("/api/timeline", get(graph::get_timeline))
+        .route("/api/categories", get(graph::list_categories))
+        .route("/api/stats", get(graph::get_stats))
+        // Lectura de la configuración: disponible también en modo solo lectura (POST/PATCH están arriba)
+        .route("/api/admin/config", get(admin::get_config))
+        .route("/api/graph/concept/{name}", get(graph::get_concept_neighborhood))
+        .route("/api/chat", post(chat::chat_handler))
+        .route("/api/chat/debug", post(chat::chat_debug_handler))
+        .route("/api/entities/dedup", post(entities::propose_entity_merges))
+        .route("/api/entities/export", get(entities::export_entities))
+        .route("/api/entities/search", get(entities::search_entities))
+        .route("/api/entities/{name}/chunks", get(entities::get_entity_chunks))
+
+        // UI
+        .route("/", get(ui::render_login).post(ui::authenticate))
+        .route("/dashboard", get(ui::render_dashboard_guarded))
+        .route("/logout", get(ui::logout))
+        .with_state(state);
+
+    if base_path.is_empty() { routes } else { Router::new().nest(base_path, routes) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{header::{CONTENT_TYPE, COOKIE, SET_COOKIE}, StatusCode}};
+    use tower::ServiceExt;
+    use crate::infrastructure::ai::mock::{mock_config, MockAIService};
+    use crate::infrastructure::persistence::memory_repo::MemoryRepo;
+
+    /// El router de main.rs sobre `repo` (login en `/`).
+    fn app_with(repo: Arc<MemoryRepo>, read_only: bool) -> Router {
+        let mut state = AppState::for_tests(repo, Arc::new(MockAIService::new(mock_config(8))));
+        state.read_only = read_only;
+        build_router(Arc::new(state), "")
+    }
+
+    fn app(read_only: bool) -> Router {
+        app_with(Arc::new(MemoryRepo::new()), read_only)
+    }
+
+    fn ingest_request(cookie: &str) -> Request {
+        let body = "--X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"muralla.txt\"\r\n\r\nLa muralla de Lugo.\r\n--X--\r\n";
+        Request::post("/api/ingest")
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .header(COOKIE, cookie)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn chat_request() -> Request {
+        Request::post("/api/chat/debug")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"message": "¿Cuánto mide la muralla?"}"#))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_only_mode_blocks_ingest_but_keeps_chat() {
+        let app = app(true);
+        let cookie = login(&app).await;
+
+        let ingest = app.clone().oneshot(ingest_request(&cookie)).await.unwrap();
+        assert_eq!(ingest.status(), StatusCode::FORBIDDEN);
+        let chat = app.oneshot(chat_request()).await.unwrap();
+        assert_eq!(chat.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn ingest_is_allowed_outside_read_only_mode() {
+        let app = app(false);
+        let cookie = login(&app).await;
+
+        let ingest = app.oneshot(ingest_request(&cookie)).await.unwrap();
+        assert_eq!(ingest.status(), StatusCode::OK);
+    }
+
+    fn admin_app(repo: Arc<MemoryRepo>) -> Router {
+        app_with(repo, false)
+    }
+
+    /// Peticiones destructivas: reinicio con cambio de modelo, reconfiguración completa, rebuild con reset,
+    /// volcado/carga de embeddings, PageRank y re-vectorización forzada.
+    fn destructive_requests(cookie: Option<&str>) -> Vec<Request> {
+        let mut config = serde_json::to_value(mock_config(8)).unwrap();
+        config["api_key"] = "sk-new".into();
+        let requests = [
+            ("PATCH", "/api/admin/config", serde_json::json!({ "config": { "embedding_model": "otro", "embedding_dim": 16 }, "force_reset": true })),
+            ("POST", "/api/admin/config", serde_json::json!({ "config": config, "force_reset": true })),
+            ("POST", "/api/admin/reset", serde_json::json!({})),
+            ("POST", "/api/admin/rebuild-graph?reset=true", serde_json::json!({})),
+            ("GET", "/api/admin/export-embeddings", serde_json::json!({})),
+            ("POST", "/api/admin/import-embeddings", serde_json::json!({ "embedding_model": "mock-embed", "embedding_dim": 8, "records": [] })),
+            ("POST", "/api/admin/compute-centrality", serde_json::json!({})),
+            ("POST", "/api/admin/reembed?force=true", serde_json::json!({})),
+        ];
+        requests.into_iter()
+            .map(|(method, uri, body)| {
+                let mut request = Request::builder().method(method).uri(uri).header(CONTENT_TYPE, "application/json");
+                if let Some(cookie) = cookie {
+                    request = request.header(COOKIE, cookie);
+                }
+                request.body(Body::from(body.to_string())).unwrap()
+            })
+            .collect()
+    }
+
+    async fn login(app: &Router) -> String {
+        let response = app.clone().oneshot(
+            Request::post("/")
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("username=admin&password=secret"))
+                .unwrap()
+        ).await.unwrap();
+        let set_cookie = response.headers().get(SET_COOKIE).unwrap().to_str().unwrap();
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn destructive_admin_routes_require_a_dashboard_session() {
+        let repo = Arc::new(MemoryRepo::new());
+        let app = admin_app(repo.clone());
+
+        for request in destructive_requests(None) {
+            let uri = request.uri().to_string();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+        for request in destructive_requests(Some("lamuralla_auth=valid")) {
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "a forged cookie is not a session");
+        }
+        assert_eq!(repo.state().resets, 0);
+    }
+
+    #[tokio::test]
+    async fn a_dashboard_session_unlocks_the_admin_routes() {
+        let repo = Arc::new(MemoryRepo::new());
+        let app = admin_app(repo.clone());
+        let cookie = login(&app).await;
+
+        for request in destructive_requests(Some(&cookie)) {
+            let uri = request.uri().to_string();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn every_admin_and_mutation_route_requires_a_session() {
+        let repo = Arc::new(MemoryRepo::new());
+        let app = admin_app(repo.clone());
+        let routes = [
+            ("POST", "/api/admin/config".to_string()),
+            ("PATCH", "/api/admin/config".to_string()),
+            ("POST", "/api/admin/reset".to_string()),
+            ("POST", "/api/admin/rebuild-graph".to_string()),
+            ("GET", "/api/admin/export-embeddings".to_string()),
+            ("POST", "/api/admin/import-embeddings".to_string()),
+            ("POST", "/api/admin/compute-centrality".to_string()),
+            ("POST", "/api/admin/reembed".to_string()),
+            ("GET", "/api/admin/stale-chunks".to_string()),
+            ("POST", "/api/ingest".to_string()),
+            ("POST", "/api/reasoning/run".to_string()),
+            ("POST", "/api/reasoning/expire".to_string()),
+            ("POST", "/api/reasoning/cancel".to_string()),
+            ("POST", "/api/entities/merge".to_string()),
+            ("DELETE", format!("/api/documents/{}", uuid::Uuid::new_v4())),
+        ];
+
+        for (method, uri) in routes {
+            let request = Request::builder().method(method).uri(&uri).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+        }
+        assert_eq!(repo.state().resets, 0);
+
+        // La lectura de la configuración sigue abierta
+        let config = app.oneshot(Request::get("/api/admin/config").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(config.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn stale_chunks_need_a_session_even_in_read_only_mode() {
+        let app = app(true);
+        let request = |cookie: Option<&str>| {
+            let mut request = Request::get("/api/admin/stale-chunks");
+            if let Some(cookie) = cookie {
+                request = request.header(COOKIE, cookie);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let anonymous = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+        let cookie = login(&app).await;
+        let with_session = app.oneshot(request(Some(&cookie))).await.unwrap();
+        assert_eq!(with_session.status(), StatusCode::OK);
+    }
+}
@@ -1,3 +1,4 @@
 pub mod handlers;
-// pub mod middleware; // Descomentar si creaste middleware.rs
+pub mod middleware;
+pub mod progress;
 // pub mod api; // Descomentar si creaste api.rs
\ No newline at end of file
@@ -0,0 +1,82 @@
+//! Respuestas de progreso en texto (una línea por mensaje) compartidas por los handlers de larga duración.
+use std::time::Duration;
+use axum::body::{Body, Bytes};
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, Instant};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Línea vacía: los clientes de progreso ignoran las líneas en blanco.
+const KEEPALIVE_FRAME: &[u8] = b"\n";
+
+/// Cuerpo en streaming con un mensaje por línea. Con `keepalive`, si el canal pasa ese tiempo
+/// sin mensajes (una llamada lenta a la IA) se envía una línea vacía para que los proxies
+/// con timeout de inactividad no corten la conexión.
+pub fn progress_body(rx: mpsc::Receiver<String>, keepalive: Option<Duration>) -> Body {
+    let Some(every) = keepalive else {
+        let stream = ReceiverStream::new(rx).map(|msg| {
+            Ok::<_, std::io::Error>(Bytes::from(format!("{}\n", msg)))
+        });
+        return Body::from_stream(stream);
+    };
+
+    let (out_tx, out_rx) = mpsc::channel::<Bytes>(10);
+    tokio::spawn(async move {
+        let mut rx = rx;
+        let mut ticker = interval_at(Instant::now() + every, every);
+        loop {
+            tokio::select! {
+                msg = rx.recv() => {
+                    let Some(msg) = msg else { break };
+                    if out_tx.send(Bytes::from(format!("{}\n", msg))).await.is_err() {
+                        break;
+                    }
+                    // El intervalo cuenta desde el último mensaje real
+                    ticker.reset();
+                },
+                _ = ticker.tick() => {
+                    if out_tx.send(Bytes::from_static(KEEPALIVE_FRAME)).await.is_err() {
+                        break;
+                    }
+                },
+            }
+        }
+    });
+
+    Body::from_stream(ReceiverStream::new(out_rx).map(Ok::<_, std::io::Error>))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(body: Body) -> String {
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_message_is_one_line() {
+        let (tx, rx) = mpsc::channel(10);
+        tx.send("📂 Leyendo archivo...".to_string()).await.unwrap();
+        tx.send("DONE".to_string()).await.unwrap();
+        drop(tx);
+
+        assert_eq!(collect(progress_body(rx, None)).await, "📂 Leyendo archivo...\nDONE\n");
+    }
+
+    #[tokio::test]
+    async fn a_silent_channel_gets_blank_keepalive_lines() {
+        let (tx, rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            tx.send("DONE".to_string()).await.unwrap();
+        });
+
+        let body = collect(progress_body(rx, Some(Duration::from_millis(20)))).await;
+        let (keepalives, last) = body.split_at(body.len() - "DONE\n".len());
+        assert_eq!(last, "DONE\n");
+        assert!(!keepalives.is_empty());
+        assert!(keepalives.chars().all(|c| c == '\n'));
+    }
+}
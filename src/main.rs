@@ -4,48 +4,95 @@ mod application;
 mod infrastructure;
 mod interface;
 
-use axum::{
-    routing::{post, get}, 
-    Router, 
-    response::{Redirect, IntoResponse}, 
-}; 
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use neo4rs::Graph;
+use neo4rs::{Graph, query};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 use tower_http::trace::TraceLayer;
 use tower_http::cors::CorsLayer;
 use secrecy::SecretString;
-use tera::Tera;
 
 use crate::domain::models::*;
+use crate::domain::errors::AppError;
 use crate::domain::ports::KGRepository; 
 
 use crate::infrastructure::ai::rig_client::RigAIService;
-use crate::infrastructure::persistence::neo4j_repo::Neo4jRepo;
-use crate::interface::handlers::{admin::{self, AppState}, ingest, graph, ui, chat, reasoning}; 
+use crate::domain::ports::AIService;
+use crate::infrastructure::ai::audit::AuditConfig;
+use crate::infrastructure::ai::circuit_breaker::CircuitBreakerConfig;
+use crate::infrastructure::ai::retry::RetryConfig;
+use crate::infrastructure::startup::{StartupGate, DEFAULT_STARTUP_TIMEOUT};
+use crate::infrastructure::eval_dataset::EvalDatasetLog;
+use crate::infrastructure::persistence::neo4j_repo::{Neo4jRepo, EntityMatching, RelationAliases, UnknownRelation, DEFAULT_MAX_CONCURRENT_TXNS, DEFAULT_EDGE_CONFIDENCE};
+use crate::interface::middleware::build_router;
+use crate::interface::handlers::{admin::AppState, ui}; 
 use crate::application::dtos::*;
+use crate::application::redaction::{RedactingAIService, Redactor};
+use crate::application::ingestion::{ChunkingMode, IngestionConfig, DEFAULT_CHUNK_OVERLAP, DEFAULT_CHUNK_SIZE};
+use crate::application::reasoning::{ReasoningConfig, ReasoningService};
+use crate::application::graph_cache::GraphCache;
+use crate::application::centrality::CentralityConfig;
+use crate::application::retrieval::ContextOrder;
+use crate::application::consolidation::{ConsolidationConfig, ConsolidationService};
 
 // Documentación OpenAPI (Swagger)
 #[derive(OpenApi)]
 #[openapi(
     paths(
+        interface::handlers::admin::get_config,
         interface::handlers::admin::update_config,
+        interface::handlers::admin::list_stale_chunks,
+        interface::handlers::admin::patch_config,
+        interface::handlers::admin::reset_database,
+        interface::handlers::admin::export_embeddings,
+        interface::handlers::admin::import_embeddings,
+        interface::handlers::admin::compute_centrality,
+        interface::handlers::admin::reembed_chunks,
+        interface::handlers::admin::rebuild_graph,
         interface::handlers::ingest::ingest_document,
+        interface::handlers::ingest::preview_extraction,
+        interface::handlers::ingest::validate_document,
+        interface::handlers::ingest::plan_ingestion,
         interface::handlers::graph::get_graph,
+        interface::handlers::graph::get_graph_since,
         interface::handlers::graph::get_concept_neighborhood,
+        interface::handlers::graph::export_graph,
+        interface::handlers::graph::get_timeline,
+        interface::handlers::graph::list_categories,
+        interface::handlers::graph::get_stats,
         interface::handlers::chat::chat_handler,
-        interface::handlers::reasoning::run_reasoning
+        interface::handlers::chat::chat_debug_handler,
+        interface::handlers::reasoning::run_reasoning,
+        interface::handlers::reasoning::expire_inferred_relations,
+        interface::handlers::reasoning::cancel_reasoning,
+        interface::handlers::entities::propose_entity_merges,
+        interface::handlers::entities::merge_entities,
+        interface::handlers::entities::export_entities,
+        interface::handlers::entities::get_entity_chunks,
+        interface::handlers::entities::search_entities,
+        interface::handlers::health::readiness,
+        interface::handlers::documents::list_documents,
+        interface::handlers::documents::delete_document,
+        interface::handlers::documents::get_document_graph
     ),
     components(
         schemas(
-            AIConfig, AIProvider, 
+            AIConfig, AIProvider, ExtractionGranularity, VectorSimilarity, EmbeddingSlot,
             IngestionRequest, IngestionResponse, 
-            AdminConfigPayload,
-            VisNode, VisEdge, GraphDataResponse,
-            ChatRequest, ChatResponse, 
-            InferredRelation 
+            ExtractionPreviewRequest, ExtractionPreview, FileValidationReport, IngestionPlan, PlannedChunk, KnowledgeExtraction, GraphEntity, GraphRelation,
+            GraphEvent, TemporalRelation, TimelineEvent, CategoryCount, GraphStats, EntityDegree,
+            AdminConfigPayload, AdminConfigPatchPayload, AdminConfigView, AIConfigPatch,
+            EmbeddingExport, EmbeddingRecord,
+            VisNode, VisEdge, GraphDataResponse, GraphLimit, GraphDelta,
+            ChatRequest, ChatMessage, ChatRole, ChatResponse, TokenUsage, RetrievalStrategy, Footnote,
+            ChatDebugResponse, HybridContext,
+            InferredRelation, InferenceType, ConfidenceLevel, ReasoningExplanation,
+            MergeProposal, MergeEntitiesRequest, EntityChunk, EntityMatch,
+            ReadinessReport, DependencyStatus,
+            DocumentSummary, DocumentDeletion,
+            StaleChunk, StaleChunksReport
         )
     ),
     tags(
@@ -53,7 +100,9 @@ use crate::application::dtos::*;
         (name = "ingestion", description = "Data ingestion endpoints"),
         (name = "visualization", description = "Graph visual exploration"),
         (name = "chat", description = "Semantic GraphRAG Chat"),
-        (name = "reasoning", description = "AI Graph Enrichment")
+        (name = "reasoning", description = "AI Graph Enrichment"),
+        (name = "entities", description = "Entity resolution and lookup"),
+        (name = "documents", description = "Ingested documents and their metadata")
     )
 )]
 struct ApiDoc;
@@ -72,44 +121,267 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let model_name = std::env::var("AI_MODEL").unwrap_or_else(|_| "gpt-4o".to_string());
     let embedding_model = std::env::var("AI_EMBEDDING_MODEL").unwrap_or_else(|_| "text-embedding-3-small".to_string());
-    let embedding_dim = std::env::var("AI_EMBEDDING_DIM")
-        .unwrap_or_else(|_| "1536".to_string())
-        .parse::<usize>()
-        .expect("AI_EMBEDDING_DIM must be a number");
+    // AI_EMBEDDING_DIM manda; si falta, se deduce del modelo (solo modelos conocidos)
+    let embedding_dim = resolve_embedding_dim(&embedding_model, std::env::var("AI_EMBEDDING_DIM").ok().as_deref())
+        .unwrap_or_else(|e| panic!("{}", e));
+    tracing::info!("📐 Embedding model {} ({} dims)", embedding_model, embedding_dim);
     let base_url = std::env::var("AI_BASE_URL").ok();
+    let embedding_base_url = std::env::var("AI_EMBEDDING_BASE_URL").ok();
+    let auth_scheme = std::env::var("AI_AUTH_SCHEME")
+        .ok()
+        .map(|v| v.parse::<AuthScheme>().unwrap_or_else(|e| panic!("AI_AUTH_SCHEME: {}", e)))
+        .unwrap_or_default();
+    let json_mode = std::env::var("AI_JSON_MODE")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
 
     let provider = match provider_str.to_lowercase().as_str() {
         "ollama" => AIProvider::Ollama,
         "groq" => AIProvider::Groq,
+        "anthropic" | "claude" => AIProvider::Anthropic,
         _ => AIProvider::OpenAI,
     };
 
+    let granularity = std::env::var("AI_EXTRACTION_GRANULARITY")
+        .ok()
+        .map(|v| v.parse::<ExtractionGranularity>().expect("AI_EXTRACTION_GRANULARITY must be coarse, balanced or fine"))
+        .unwrap_or_default();
+
+    // VECTOR_SIMILARITY_FUNCTION=euclidean para modelos de embeddings no normalizados
+    let similarity_function = std::env::var("VECTOR_SIMILARITY_FUNCTION")
+        .ok()
+        .map(|v| v.parse::<VectorSimilarity>().expect("VECTOR_SIMILARITY_FUNCTION must be cosine or euclidean"))
+        .unwrap_or_default();
+
+    // EMBEDDING_SLOTS=fast:nomic-embed-text:768,large:text-embedding-3-large:3072: embeddings adicionales
+    // por chunk (mismo proveedor), cada uno con su índice; el chat elige uno con "embedding": "fast"
+    let embedding_slots: Vec<EmbeddingSlot> = std::env::var("EMBEDDING_SLOTS")
+        .map(|v| v.split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.parse::<EmbeddingSlot>().expect("EMBEDDING_SLOTS must be name:model:dim entries separated by commas"))
+            .collect())
+        .unwrap_or_default();
+
     let initial_config = AIConfig {
         provider,
         model_name,
         embedding_model,
         // CORRECCIÓN 1: Añadido .into()
         api_key: SecretString::new(api_key_str.into()), 
+        auth_scheme,
         embedding_dim,
         base_url,
+        embedding_base_url,
+        json_mode,
+        granularity,
+        similarity_function,
     };
 
     let uri = std::env::var("NEO4J_URI").expect("NEO4J_URI required in .env");
     let user = std::env::var("NEO4J_USER").expect("NEO4J_USER required in .env");
     let pass = std::env::var("NEO4J_PASS").expect("NEO4J_PASS required in .env");
     
+    // STARTUP_TIMEOUT_SECS: tiempo máximo esperando a Neo4j (p. ej. en docker-compose) antes de abrir el puerto
+    let startup_timeout = std::env::var("STARTUP_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(std::time::Duration::from_secs)
+        .unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+    let startup = StartupGate::new(startup_timeout);
+
     tracing::info!("🔌 Connecting to Neo4j at {}", uri);
-    let graph = Arc::new(Graph::new(&uri, &user, &pass).await?);
+    let graph = Arc::new(startup.wait("Neo4j connection", || async {
+        let graph = Graph::new(&uri, &user, &pass).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        // El pool puede conectarse de forma perezosa: una consulta confirma que Neo4j responde
+        graph.run(query("RETURN 1")).await.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        Ok(graph)
+    }).await?);
     
-    let repo = Arc::new(Neo4jRepo::new(graph.clone()));
+    let max_txns = std::env::var("NEO4J_MAX_CONCURRENT_TXNS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_TXNS);
+
+    // Confianza asumida para relaciones sin `confidence` al filtrar con ?min_confidence
+    let default_confidence = std::env::var("GRAPH_DEFAULT_CONFIDENCE")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(DEFAULT_EDGE_CONFIDENCE);
+
+    // ENTITY_MATCHING=case_insensitive fusiona "NASA" y "nasa" (por defecto: nombre exacto)
+    let entity_matching = std::env::var("ENTITY_MATCHING")
+        .ok()
+        .and_then(|v| v.parse::<EntityMatching>().map_err(|e| tracing::warn!("⚠️ {}", e)).ok())
+        .unwrap_or_default();
+
+    // ENTITY_CATEGORY_VOTING=false: la categoría de una entidad es la de su primera extracción
+    let category_voting = std::env::var("ENTITY_CATEGORY_VOTING")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
+
+    // DEDUPE_EXTRACTION_RELATIONS=false: se guarda cada relación tal cual la repite el modelo
+    let relation_dedupe = std::env::var("DEDUPE_EXTRACTION_RELATIONS")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
+
+    // AUTO_CREATE_RELATION_ENDPOINTS=true: crea la entidad que una relación nombra sin haberla extraído
+    // (por defecto la relación se descarta y se avisa en el log)
+    let create_missing_endpoints = std::env::var("AUTO_CREATE_RELATION_ENDPOINTS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    // RELATION_TYPE_ALIASES=ESTABLISHED=FOUNDED,CREATED_BY=FOUNDED: variantes guardadas con el tipo canónico
+    let relation_aliases = std::env::var("RELATION_TYPE_ALIASES")
+        .ok()
+        .map(|v| v.parse::<RelationAliases>().expect("RELATION_TYPE_ALIASES must be ALIAS=CANONICAL pairs separated by commas"))
+        .unwrap_or_default();
+    if !relation_aliases.is_empty() {
+        tracing::info!("🔀 {} relation type aliases loaded", relation_aliases.len());
+    }
+
+    // ALLOWED_RELATION_TYPES=FOUNDED,WORKS_FOR: ontología cerrada; el resto se guarda como RELATED_TO
+    // (UNKNOWN_RELATION_MODE=reject para descartarlas)
+    let allowed_relations: Vec<String> = std::env::var("ALLOWED_RELATION_TYPES")
+        .map(|v| v.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect())
+        .unwrap_or_default();
+    let unknown_relations = std::env::var("UNKNOWN_RELATION_MODE")
+        .ok()
+        .and_then(|v| v.parse::<UnknownRelation>().map_err(|e| tracing::warn!("⚠️ {}", e)).ok())
+        .unwrap_or_default();
+    if !allowed_relations.is_empty() {
+        tracing::info!("📚 Relation ontology: {} allowed types (others: {:?})", allowed_relations.len(), unknown_relations);
+    }
+
+    // VALIDATE_QUERY_EMBEDDING_DIM=false: no comprobar la dimensión del embedding contra el índice antes de buscar
+    let validate_query_dim = std::env::var("VALIDATE_QUERY_EMBEDDING_DIM")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
+
+    let repo = Arc::new(
+        Neo4jRepo::new(graph.clone())
+            .with_max_concurrent_txns(max_txns)
+            .with_default_confidence(default_confidence)
+            .with_entity_matching(entity_matching)
+            .with_category_voting(category_voting)
+            .with_relation_dedupe(relation_dedupe)
+            .with_missing_endpoint_creation(create_missing_endpoints)
+            .with_relation_aliases(relation_aliases)
+            .with_allowed_relations(allowed_relations, unknown_relations)
+            .with_query_dim_validation(validate_query_dim)
+    );
     
-    if let Err(e) = repo.create_indexes(embedding_dim).await {
-        tracing::warn!("⚠️ Could not ensure indexes: {}", e);
+    // Sin índices la búsqueda vectorial falla: no se sirve tráfico hasta crearlos
+    startup.wait("Neo4j indexes", || repo.create_indexes(embedding_dim, similarity_function)).await?;
+    for slot in &embedding_slots {
+        repo.create_embedding_slot_index(slot, similarity_function).await?;
+        tracing::info!("🧮 Embedding slot '{}': {} ({} dims, index {})", slot.name, slot.model, slot.dim, slot.index_name());
+    }
+
+    // Auditoría de prompts: AI_AUDIT_LOG activa el log (target `ai_audit`),
+    // AI_AUDIT_REDACT sustituye el contenido por su hash
+    let ai_audit = AuditConfig {
+        enabled: std::env::var("AI_AUDIT_LOG").map(|v| v == "true" || v == "1").unwrap_or(false),
+        redact_content: std::env::var("AI_AUDIT_REDACT").map(|v| v == "true" || v == "1").unwrap_or(false),
+    };
+
+    // Circuit breaker: AI_BREAKER_THRESHOLD fallos seguidos abren el circuito (0 = desactivado)
+    let mut breaker = CircuitBreakerConfig::default();
+    if let Some(threshold) = std::env::var("AI_BREAKER_THRESHOLD").ok().and_then(|v| v.parse::<u32>().ok()) {
+        breaker.failure_threshold = threshold;
+    }
+    if let Some(secs) = std::env::var("AI_BREAKER_COOLDOWN_SECS").ok().and_then(|v| v.parse::<u64>().ok()) {
+        breaker.cooldown = std::time::Duration::from_secs(secs);
     }
 
-    let ai_service = Arc::new(RwLock::new(RigAIService::new(initial_config)));
+    // Reintentos de errores transitorios (red, 5xx, 429): AI_MAX_RETRIES con backoff desde AI_RETRY_BASE_MS.
+    // AI_EMBEDDING_RETRIES / AI_COMPLETION_RETRIES lo ajustan por operación (p. ej. embeddings insistentes, chat rápido)
+    let mut retry = RetryConfig {
+        max_retries: 2,
+        ..RetryConfig::default()
+    };
+    if let Some(retries) = std::env::var("AI_MAX_RETRIES").ok().and_then(|v| v.parse::<u32>().ok()) {
+        retry.max_retries = retries;
+    }
+    if let Some(ms) = std::env::var("AI_RETRY_BASE_MS").ok().and_then(|v| v.parse::<u64>().ok()) {
+        retry.base_delay = std::time::Duration::from_millis(ms);
+    }
+    let retries_for = |var: &str| RetryConfig {
+        max_retries: std::env::var(var).ok().and_then(|v| v.parse::<u32>().ok()).unwrap_or(retry.max_retries),
+        ..retry.clone()
+    };
+    let (embedding_retry, completion_retry) = (retries_for("AI_EMBEDDING_RETRIES"), retries_for("AI_COMPLETION_RETRIES"));
+
+    // Embeddings degenerados (ceros / constantes) se rechazan; AI_EMBEDDING_MIN_VARIANCE endurece el umbral
+    let min_embedding_variance = std::env::var("AI_EMBEDDING_MIN_VARIANCE")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|v| *v >= 0.0)
+        .unwrap_or(0.0);
+
+    // EXTRACT_EVENTS=true: la extracción pide también sucesos y su orden (GET /api/timeline)
+    let extract_events = std::env::var("EXTRACT_EVENTS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
 
-    let tera = match Tera::new("templates/**/*.html") {
+    // EXTRACTION_TWO_STAGE=true: entidades y después relaciones entre ellas (dos llamadas por fragmento)
+    let two_stage_extraction = std::env::var("EXTRACTION_TWO_STAGE")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    // AI_EXTRACTION_PARSE_RETRIES: re-preguntar al modelo si su JSON no se puede leer (máx. 2; 0 = no)
+    let extraction_parse_retries = std::env::var("AI_EXTRACTION_PARSE_RETRIES")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    // AI_EMBEDDING_CACHE_SIZE: embeddings recientes en memoria por (modelo, texto); 0 la desactiva
+    let embedding_cache_size = std::env::var("AI_EMBEDDING_CACHE_SIZE")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1000);
+
+    // AI_MOCK_SEED: IA simulada y determinista, sin llamadas de red (feature `mock-ai`)
+    let ai_service: Arc<dyn AIService> = match mock_ai_service(&initial_config) {
+        Some(mock) => mock,
+        None => {
+            let mut service = RigAIService::new(initial_config)
+                .with_audit(ai_audit)
+                .with_circuit_breaker(breaker)
+                .with_retries(embedding_retry, completion_retry)
+                .with_min_embedding_variance(min_embedding_variance)
+                .with_extraction_parse_retries(extraction_parse_retries)
+                .with_event_extraction(extract_events)
+                .with_two_stage_extraction(two_stage_extraction);
+            if embedding_cache_size > 0 {
+                service = service.with_embedding_cache(embedding_cache_size);
+            }
+            Arc::new(service)
+        },
+    };
+
+    // PII_REDACTION=true: emails, teléfonos, IDs... se sustituyen antes de enviar texto al proveedor.
+    // PII_REDACTION_PATTERNS añade patrones ({"ETIQUETA": "regex"}); PII_STORE_ORIGINAL=false guarda los chunks redactados
+    let redactor = std::env::var("PII_REDACTION")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+        .then(|| {
+            let extra: HashMap<String, String> = std::env::var("PII_REDACTION_PATTERNS")
+                .map(|v| serde_json::from_str(&v).expect("PII_REDACTION_PATTERNS must be a JSON object of label -> regex"))
+                .unwrap_or_default();
+            Arc::new(Redactor::new(&extra).expect("PII_REDACTION_PATTERNS contains an invalid regex"))
+        });
+    let ai_service: Arc<dyn AIService> = match &redactor {
+        Some(redactor) => {
+            tracing::info!("🕶️ PII redaction enabled before AI provider calls");
+            Arc::new(RedactingAIService::new(ai_service, redactor.clone()))
+        },
+        None => ai_service,
+    };
+    let store_original_text = std::env::var("PII_STORE_ORIGINAL")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
+
+    let tera = match ui::load_templates("templates/**/*.html") {
         Ok(t) => t,
         Err(e) => {
             tracing::error!("❌ Error parsing templates: {}", e);
@@ -117,45 +389,277 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
+    let ingestion = IngestionConfig {
+        max_chunks: std::env::var("INGEST_MAX_CHUNKS").ok().and_then(|v| v.parse::<usize>().ok()),
+        ai_call_interval: std::env::var("AI_CALL_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(std::time::Duration::from_millis)
+            .unwrap_or_default(),
+        preserve_formatting: std::env::var("PARSE_PRESERVE_FORMATTING")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false),
+        // PARSE_OCR_MIN_CHARS: PDFs con menos texto se pasan por OCR (binario compilado con --features ocr; 0 = nunca)
+        ocr_min_chars: std::env::var("PARSE_OCR_MIN_CHARS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .or(Some(50))
+            .filter(|n| *n > 0),
+        // Modelo + versión de prompt + fecha en cada chunk (RECORD_EXTRACTION_PROVENANCE=false lo desactiva)
+        record_provenance: std::env::var("RECORD_EXTRACTION_PROVENANCE")
+            .map(|v| v != "false" && v != "0")
+            .unwrap_or(true),
+        // STORE_RAW_EXTRACTIONS=true: el grafo se puede reconstruir después sin volver a llamar al LLM
+        stored_text_redactor: redactor.clone().filter(|_| !store_original_text),
+        store_extractions: std::env::var("STORE_RAW_EXTRACTIONS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false),
+        // INGEST_TOKEN_BUDGET: tope de tokens estimados por documento (el campo 'token_budget' lo sobreescribe)
+        token_budget: std::env::var("INGEST_TOKEN_BUDGET").ok().and_then(|v| v.parse::<usize>().ok()),
+        embedding_slots,
+        // CHUNK_MODE=tokens: ventanas de CHUNK_MAX_TOKENS con el tokenizer del modelo (o CHUNK_TOKENIZER);
+        // por defecto, CHUNK_SIZE caracteres con CHUNK_OVERLAP de solape
+        chunking: match std::env::var("CHUNK_MODE").as_deref() {
+            Ok("tokens") => ChunkingMode::Tokens {
+                max_tokens: std::env::var("CHUNK_MAX_TOKENS").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(512),
+                overlap_tokens: std::env::var("CHUNK_OVERLAP_TOKENS").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(64),
+                tokenizer: std::env::var("CHUNK_TOKENIZER").ok(),
+            },
+            _ => ChunkingMode::Characters {
+                chunk_size: std::env::var("CHUNK_SIZE").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(DEFAULT_CHUNK_SIZE),
+                overlap: std::env::var("CHUNK_OVERLAP").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(DEFAULT_CHUNK_OVERLAP),
+            },
+        },
+    };
+
+    let reasoning = ReasoningConfig {
+        max_relations: std::env::var("REASONING_MAX_RELATIONS").ok().and_then(|v| v.parse::<usize>().ok()),
+        inferred_max_age: std::env::var("INFERRED_MAX_AGE_HOURS").ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(|h| std::time::Duration::from_secs(h * 3600)),
+        expiry_sweep_interval: std::env::var("INFERRED_SWEEP_INTERVAL_SECS").ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|s| *s > 0)
+            .map(std::time::Duration::from_secs),
+        timeout: std::env::var("REASONING_TIMEOUT_SECS").ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|s| *s > 0)
+            .map(std::time::Duration::from_secs),
+    };
+
+    // Comprobar el proveedor de IA en /ready cuesta una llamada a la API: desactivado por defecto
+    let ready_check_ai = std::env::var("READY_CHECK_AI")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    let read_only = std::env::var("READ_ONLY")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    if read_only {
+        tracing::info!("🔒 Read-only mode: ingestion, reasoning, admin and merge routes are disabled");
+    }
+
+    // BASE_PATH=/lamuralla: todas las rutas (API, UI y Swagger) cuelgan del prefijo (proxy por ruta)
+    let base_path = ui::normalize_base_path(&std::env::var("BASE_PATH").unwrap_or_default());
+
+    // Login del dashboard: credenciales y clave de firma de las sesiones (JWT HS256)
+    // Obligatorias desde que se quitaron las credenciales fijas (ver .env.example)
+    let auth_username = std::env::var("AUTH_USERNAME").expect("AUTH_USERNAME required in .env (see .env.example)");
+    let auth_password = std::env::var("AUTH_PASSWORD").expect("AUTH_PASSWORD required in .env (see .env.example)");
+    let jwt_secret = std::env::var("AUTH_JWT_SECRET").ok().filter(|s| !s.is_empty()).unwrap_or_else(|| {
+        // Sin clave fija las sesiones no sobreviven a un reinicio (ni se comparten entre réplicas)
+        tracing::warn!("⚠️ AUTH_JWT_SECRET not set: using a random key, sessions end on restart");
+        format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+    });
+    let mut auth = ui::AuthConfig::new(auth_username, SecretString::new(auth_password.into()), SecretString::new(jwt_secret.into()));
+    if let Some(ttl) = std::env::var("AUTH_SESSION_TTL_SECS").ok().and_then(|v| v.parse::<u64>().ok()).filter(|s| *s > 0) {
+        auth = auth.with_session_ttl(std::time::Duration::from_secs(ttl));
+    }
+
+    // Failover de /api/graph: sin TTL configurado no se guarda caché
+    let graph_cache_ttl = std::env::var("GRAPH_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(std::time::Duration::from_secs);
+
+    // Chat: si ninguna fuente alcanza este umbral no se llama al LLM (has_answer=false)
+    let no_answer_threshold = std::env::var("CHAT_NO_ANSWER_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse::<f32>().ok());
+
+    let mut centrality = CentralityConfig::default();
+    if let Some(damping) = std::env::var("CENTRALITY_DAMPING").ok().and_then(|v| v.parse::<f64>().ok()) {
+        centrality.damping = damping;
+    }
+    if let Some(iterations) = std::env::var("CENTRALITY_MAX_ITERATIONS").ok().and_then(|v| v.parse::<usize>().ok()) {
+        centrality.max_iterations = iterations;
+    }
+
+    // Chat: score * (1 + boost * centralidad); 0 = solo similitud
+    let centrality_boost = std::env::var("CHAT_CENTRALITY_BOOST")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(0.0);
+
+    // Chat: entidades a N saltos de las mencionadas en cada fragmento (1 = sin expansión)
+    let context_hops = std::env::var("CHAT_CONTEXT_HOPS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1)
+        .clamp(1, MAX_CONTEXT_HOPS);
+
+    // Chat multi-consulta (cuesta una llamada LLM extra por pregunta): desactivado por defecto
+    let multi_query_max = std::env::var("CHAT_MULTI_QUERY_MAX")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 1);
+
+    // CHAT_CONTEXT_ORDER=edges_first: fuentes más relevantes al principio y al final del prompt
+    let context_order = std::env::var("CHAT_CONTEXT_ORDER")
+        .ok()
+        .and_then(|v| v.parse::<ContextOrder>().map_err(|e| tracing::warn!("⚠️ {}", e)).ok())
+        .unwrap_or_default();
+
+    // CHAT_EVAL_DATASET_PATH: cada respuesta del chat se añade como línea JSONL (evaluación offline, ej. RAGAS)
+    let eval_dataset = std::env::var("CHAT_EVAL_DATASET_PATH")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .map(|path| {
+            tracing::info!("🧪 Chat evaluation dataset: {}", path);
+            Arc::new(EvalDatasetLog::new(path))
+        });
+
+    // STREAM_KEEPALIVE_SECS: línea vacía en los streams de progreso tras N s sin mensajes (0 = desactivado),
+    // para que los proxies con timeout de inactividad no corten una ingesta lenta
+    let stream_keepalive = std::env::var("STREAM_KEEPALIVE_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(15);
+    let stream_keepalive = (stream_keepalive > 0).then(|| std::time::Duration::from_secs(stream_keepalive));
+
     let app_state = Arc::new(AppState {
         repo,
         ai_service,
         tera, 
+        ingestion,
+        embedding_imports: Arc::new(RwLock::new(HashMap::new())),
+        reasoning,
+        reasoning_cancel: Arc::new(tokio::sync::Notify::new()),
+        ready_check_ai,
+        read_only,
+        base_path: base_path.clone(),
+        auth,
+        graph_cache: GraphCache::new(graph_cache_ttl),
+        centrality,
+        centrality_boost,
+        context_hops,
+        multi_query_max,
+        context_order,
+        no_answer_threshold,
+        eval_dataset,
+        stream_keepalive,
+        // AI_INCLUDE_TOKEN_USAGE=true: las respuestas de chat y /api/extract incluyen los tokens consumidos
+        include_token_usage: std::env::var("AI_INCLUDE_TOKEN_USAGE").map(|v| v == "true" || v == "1").unwrap_or(false),
     });
 
-    let app = Router::new()
+    // Barrido periódico de relaciones inferidas caducadas (necesita antigüedad e intervalo)
+    if let (Some(max_age), Some(every), false) = (app_state.reasoning.inferred_max_age, app_state.reasoning.expiry_sweep_interval, app_state.read_only) {
+        tracing::info!("⏳ Inferred relations expire after {}h (sweep every {}s)", max_age.as_secs() / 3600, every.as_secs());
+        let state = app_state.clone();
+        tokio::spawn(async move {
+            let service = ReasoningService::new(state.repo.clone(), state.ai_service.clone(), state.reasoning.clone());
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = service.expire_inferred(None).await {
+                    tracing::warn!("⚠️ Inferred relation sweep failed: {}", e);
+                }
+            }
+        });
+    }
+
+    // Mantenimiento periódico del grafo (CONSOLIDATION_INTERVAL_SECS; cada tarea se puede desactivar)
+    let consolidation_interval = std::env::var("CONSOLIDATION_INTERVAL_SECS").ok().and_then(|v| v.parse::<u64>().ok()).filter(|s| *s > 0);
+    if let (Some(secs), false) = (consolidation_interval, app_state.read_only) {
+        let flag = |name: &str, default: bool| std::env::var(name).map(|v| v == "true" || v == "1").unwrap_or(default);
+        let config = ConsolidationConfig {
+            interval: std::time::Duration::from_secs(secs),
+            prune_orphans: flag("CONSOLIDATION_PRUNE_ORPHANS", true),
+            recompute_centrality: flag("CONSOLIDATION_CENTRALITY", true),
+            run_reasoning: flag("CONSOLIDATION_REASONING", false),
+        };
+        tracing::info!(
+            "🧽 Graph consolidation every {}s (orphans: {}, centrality: {}, reasoning: {})",
+            secs, config.prune_orphans, config.recompute_centrality, config.run_reasoning
+        );
+        let state = app_state.clone();
+        tokio::spawn(async move {
+            let every = config.interval;
+            let service = ConsolidationService::new(state.repo.clone(), state.ai_service.clone(), config, state.centrality.clone(), state.reasoning.clone())
+                .with_reasoning_cancellation(state.reasoning_cancel.clone());
+            let mut ticker = tokio::time::interval(every);
+            // El primer tick es inmediato: el primer ciclo espera un intervalo completo
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let report = service.run_cycle().await;
+                tracing::info!(
+                    "🧽 Consolidation: orphans pruned {:?}, centrality updated {:?}, relations inferred {:?}",
+                    report.orphans_pruned, report.centrality_updated, report.relations_inferred
+                );
+            }
+        });
+    }
+
+    // "Try it out" de Swagger debe llamar a las rutas con prefijo
+    let mut openapi = ApiDoc::openapi();
+    if !base_path.is_empty() {
+        openapi.servers = Some(vec![utoipa::openapi::server::Server::new(base_path.clone())]);
+    }
+
+    let app = build_router(app_state, &base_path)
         // API Docs (Sintaxis correcta para utoipa 8+/axum 0.8)
         .merge(
-            SwaggerUi::new("/swagger-ui")
-                .url("/api-docs/openapi.json", ApiDoc::openapi())
+            SwaggerUi::new(format!("{}/swagger-ui", base_path))
+                .url(format!("{}/api-docs/openapi.json", base_path), openapi)
                 // CORRECCIÓN 2: Eliminado .axum_router() (ya no es necesario en v9)
         )
-
-        // Endpoints API
-        .route("/api/admin/config", post(admin::update_config))
-        .route("/api/ingest", post(ingest::ingest_document))
-        .route("/api/graph", get(graph::get_graph))
-        .route("/api/graph/concept/{name}", get(graph::get_concept_neighborhood)) 
-        .route("/api/chat", post(chat::chat_handler))
-        .route("/api/reasoning/run", post(reasoning::run_reasoning))
-        
-        // UI
-        .route("/", get(ui::render_login).post(ui::authenticate))
-        .route("/dashboard", get(ui::render_dashboard_guarded))
-        .route("/logout", get(|| async { Redirect::to("/").into_response() }))
         
         // Capas
         .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
-        .with_state(app_state);
+        .layer(CorsLayer::permissive());
 
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
     let addr = format!("0.0.0.0:{}", port);
     
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    tracing::info!("✅ Server running on http://{}", addr);
+    tracing::info!("✅ Server running on http://{}{}", addr, base_path);
     
     axum::serve(listener, app).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// `MockAIService` si AI_MOCK_SEED está definido (tests end-to-end / CI sin proveedor).
+/// AI_MOCK_FIXTURES: JSON con extracciones/respuestas grabadas por texto exacto.
+#[cfg(feature = "mock-ai")]
+fn mock_ai_service(config: &AIConfig) -> Option<Arc<dyn AIService>> {
+    use crate::infrastructure::ai::mock::MockAIService;
+
+    let seed = std::env::var("AI_MOCK_SEED").ok().and_then(|v| v.parse::<u64>().ok())?;
+    tracing::warn!("🧪 Using MockAIService (seed {}): no real AI provider calls", seed);
+    let mut mock = MockAIService::new(config.clone()).with_seed(seed);
+    if let Ok(path) = std::env::var("AI_MOCK_FIXTURES") {
+        mock = mock.with_fixtures_file(&path).expect("AI_MOCK_FIXTURES must be a valid fixtures file");
+    }
+    Some(Arc::new(mock))
+}
+
+/// Sin la feature `mock-ai` el binario siempre usa el proveedor real.
+#[cfg(not(feature = "mock-ai"))]
+fn mock_ai_service(_config: &AIConfig) -> Option<Arc<dyn AIService>> {
+    if std::env::var("AI_MOCK_SEED").is_ok() {
+        tracing::warn!("⚠️ AI_MOCK_SEED is ignored: build with `--features mock-ai` to use MockAIService");
+    }
+    None
+}